@@ -22,7 +22,7 @@ use darkfi::{
     rpc::{
         jsonrpc::{
             ErrorCode::{InternalError, InvalidParams, ServerError},
-            JsonError, JsonRequest, JsonResponse, JsonResult, JsonSubscriber,
+            JsonError, JsonId, JsonRequest, JsonResponse, JsonResult, JsonSubscriber,
         },
         util::JsonValue,
     },
@@ -390,7 +390,7 @@ impl MiningProxy {
     /// and forward it to the worker.
     /// Additionally, we will spawn background tasks for new job and
     /// keepalive notifications for this worker.
-    pub async fn stratum_login(&self, id: u16, params: JsonValue) -> JsonResult {
+    pub async fn stratum_login(&self, id: JsonId, params: JsonValue) -> JsonResult {
         let Some(params) = params.get::<HashMap<String, JsonValue>>() else {
             return JsonError::new(InvalidParams, None, id).into()
         };
@@ -590,7 +590,7 @@ impl MiningProxy {
     /// Stratum submit method
     ///
     /// The miner submits the request after a share was found.
-    pub async fn stratum_submit(&self, id: u16, params: JsonValue) -> JsonResult {
+    pub async fn stratum_submit(&self, id: JsonId, params: JsonValue) -> JsonResult {
         let Some(params) = params.get::<HashMap<String, JsonValue>>() else {
             return JsonError::new(InvalidParams, None, id).into()
         };
@@ -744,7 +744,7 @@ impl MiningProxy {
     /// Nonstandard, but widely supported protocol extension.
     /// The miner sends `keepalived` to prevent connection timeout.
     /// `darkfi-mmproxy` makes having keepalived mandatory.
-    pub async fn stratum_keepalived(&self, id: u16, params: JsonValue) -> JsonResult {
+    pub async fn stratum_keepalived(&self, id: JsonId, params: JsonValue) -> JsonResult {
         let Some(params) = params.get::<HashMap<String, JsonValue>>() else {
             return JsonError::new(InvalidParams, None, id).into()
         };
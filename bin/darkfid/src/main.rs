@@ -44,9 +44,10 @@ use darkfi::{
     net,
     net::P2pPtr,
     rpc::{
+        auth::{AccessSettings, MethodAccess},
         clock_sync::check_clock,
         jsonrpc::{ErrorCode::MethodNotFound, JsonError, JsonRequest, JsonResult},
-        server::{listen_and_serve, RequestHandler},
+        server::{listen_and_serve_with_access, RequestHandler},
     },
     system::{StoppableTask, StoppableTaskPtr},
     util::path::expand_path,
@@ -96,6 +97,14 @@ struct Args {
     /// JSON-RPC listen URL
     rpc_listen: Url,
 
+    #[structopt(long, default_value = "admin")]
+    /// Most privileged JSON-RPC methods callable without a token (public, readonly, admin)
+    rpc_access: String,
+
+    #[structopt(long)]
+    /// JSON-RPC tokens granting access, as level:token (repeatable flag)
+    rpc_auth_token: Vec<String>,
+
     #[structopt(long)]
     /// P2P accept addresses for the consensus protocol (repeatable flag)
     consensus_p2p_accept: Vec<Url>,
@@ -255,6 +264,21 @@ impl RequestHandler for Darkfid {
         }
     }
 
+    fn method_access(&self, method: &str) -> MethodAccess {
+        match method {
+            "ping" | "clock" => MethodAccess::Public,
+            "tx.simulate" | "tx.broadcast" => MethodAccess::Public,
+            "blockchain.get_slot" |
+            "blockchain.get_tx" |
+            "blockchain.last_known_slot" |
+            "blockchain.subscribe_blocks" |
+            "blockchain.subscribe_err_txs" |
+            "blockchain.lookup_zkas" => MethodAccess::Public,
+            "wallet.query_row_single" | "wallet.query_row_multi" => MethodAccess::ReadOnly,
+            _ => MethodAccess::Admin,
+        }
+    }
+
     async fn connections_mut(&self) -> MutexGuard<'_, HashSet<StoppableTaskPtr>> {
         self.rpc_connections.lock().await
     }
@@ -443,10 +467,17 @@ async fn realmain(args: Args, ex: Arc<smol::Executor<'static>>) -> Result<()> {
 
     // JSON-RPC server
     info!("Starting JSON-RPC server");
+    let rpc_settings = AccessSettings::new(&args.rpc_access, &args.rpc_auth_token)?;
     let rpc_task = StoppableTask::new();
     let darkfid_ = darkfid.clone();
     rpc_task.clone().start(
-        listen_and_serve(args.rpc_listen, darkfid.clone(), None, ex.clone()),
+        listen_and_serve_with_access(
+            args.rpc_listen,
+            darkfid.clone(),
+            None,
+            rpc_settings,
+            ex.clone(),
+        ),
         |res| async move {
             match res {
                 Ok(()) | Err(Error::RpcServerStopped) => darkfid_.stop_connections().await,
@@ -19,7 +19,7 @@
 use tinyjson::JsonValue;
 
 use darkfi::{
-    rpc::jsonrpc::{ErrorCode, JsonError, JsonResponse, JsonResult},
+    rpc::jsonrpc::{ErrorCode, JsonError, JsonId, JsonResponse, JsonResult},
     util::time::Timestamp,
 };
 
@@ -31,7 +31,7 @@ impl Darkfid {
     //
     // --> {"jsonrpc": "2.0", "method": "clock", "params": [], "id": 1}
     // <-- {"jsonrpc": "2.0", "result": "1234"}, "id": 1}
-    pub async fn misc_clock(&self, id: u16, _params: JsonValue) -> JsonResult {
+    pub async fn misc_clock(&self, id: JsonId, _params: JsonValue) -> JsonResult {
         JsonResponse::new(JsonValue::String(Timestamp::current_time().0.to_string()), id).into()
     }
 
@@ -42,7 +42,7 @@ impl Darkfid {
     //
     // --> {"jsonrpc": "2.0", "method": "sync_dnet_switch", "params": [true], "id": 42}
     // <-- {"jsonrpc": "2.0", "result": true, "id": 42}
-    pub async fn misc_sync_dnet_switch(&self, id: u16, params: JsonValue) -> JsonResult {
+    pub async fn misc_sync_dnet_switch(&self, id: JsonId, params: JsonValue) -> JsonResult {
         let params = params.get::<Vec<JsonValue>>().unwrap();
         if params.len() != 1 || !params[0].is_bool() {
             return JsonError::new(ErrorCode::InvalidParams, None, id).into()
@@ -66,7 +66,7 @@ impl Darkfid {
     //
     // --> {"jsonrpc": "2.0", "method": "consensus_dnet_switch", "params": [true], "id": 42}
     // <-- {"jsonrpc": "2.0", "result": true, "id": 42}
-    pub async fn misc_consensus_dnet_switch(&self, id: u16, params: JsonValue) -> JsonResult {
+    pub async fn misc_consensus_dnet_switch(&self, id: JsonId, params: JsonValue) -> JsonResult {
         let params = params.get::<Vec<JsonValue>>().unwrap();
         if params.len() != 1 || !params[0].is_bool() {
             return JsonError::new(ErrorCode::InvalidParams, None, id).into()
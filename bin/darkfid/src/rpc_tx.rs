@@ -21,7 +21,7 @@ use log::{error, warn};
 use tinyjson::JsonValue;
 
 use darkfi::{
-    rpc::jsonrpc::{ErrorCode::InvalidParams, JsonError, JsonResponse, JsonResult},
+    rpc::jsonrpc::{ErrorCode::InvalidParams, JsonError, JsonId, JsonResponse, JsonResult},
     tx::Transaction,
     util::encoding::base64,
 };
@@ -37,7 +37,7 @@ impl Darkfid {
     //
     // --> {"jsonrpc": "2.0", "method": "tx.simulate", "params": ["base58encodedTX"], "id": 1}
     // <-- {"jsonrpc": "2.0", "result": true, "id": 1}
-    pub async fn tx_simulate(&self, id: u16, params: JsonValue) -> JsonResult {
+    pub async fn tx_simulate(&self, id: JsonId, params: JsonValue) -> JsonResult {
         let params = params.get::<Vec<JsonValue>>().unwrap();
         if params.len() != 1 || !params[0].is_string() {
             return JsonError::new(InvalidParams, None, id).into()
@@ -93,7 +93,7 @@ impl Darkfid {
     //
     // --> {"jsonrpc": "2.0", "method": "tx.broadcast", "params": ["base58encodedTX"], "id": 1}
     // <-- {"jsonrpc": "2.0", "result": "txID...", "id": 1}
-    pub async fn tx_broadcast(&self, id: u16, params: JsonValue) -> JsonResult {
+    pub async fn tx_broadcast(&self, id: JsonId, params: JsonValue) -> JsonResult {
         let params = params.get::<Vec<JsonValue>>().unwrap();
         if params.len() != 1 || !params[0].is_string() {
             return JsonError::new(InvalidParams, None, id).into()
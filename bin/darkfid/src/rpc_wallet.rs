@@ -23,7 +23,7 @@ use serde_json::{json, Value};
 use darkfi::{
     rpc::jsonrpc::{
         ErrorCode::{InternalError, InvalidParams, ParseError},
-        JsonError, JsonResponse, JsonResult,
+        JsonError, JsonId, JsonResponse, JsonResult,
     },
     wallet::walletdb::QueryType,
 };
@@ -55,7 +55,7 @@ impl Darkfid {
     //
     // --> {"jsonrpc": "2.0", "method": "wallet.query_row_single", "params": [...], "id": 1}
     // <-- {"jsonrpc": "2.0", "result": ["va", "lu", "es", ...], "id": 1}
-    pub async fn wallet_query_row_single(&self, _id: u16, _params: JsonValue) -> JsonResult {
+    pub async fn wallet_query_row_single(&self, _id: JsonId, _params: JsonValue) -> JsonResult {
         todo!();
         /* TODO: This will be abstracted away
         // We need at least 3 params for something we want to fetch, and we want them in pairs.
@@ -208,7 +208,7 @@ impl Darkfid {
     //
     // --> {"jsonrpc": "2.0", "method": "wallet.query_row_multi", "params": [...], "id": 1}
     // <-- {"jsonrpc": "2.0", "result": [["va", "lu"], ["es", "es"], ...], "id": 1}
-    pub async fn wallet_query_row_multi(&self, _id: u16, _params: JsonValue) -> JsonResult {
+    pub async fn wallet_query_row_multi(&self, _id: JsonId, _params: JsonValue) -> JsonResult {
         todo!();
         /* TODO: This will be abstracted away
         // We need at least 3 params for something we want to fetch, and we want them in pairs.
@@ -332,7 +332,7 @@ impl Darkfid {
     //
     // --> {"jsonrpc": "2.0", "method": "wallet.exec_sql", "params": ["CREATE TABLE ..."], "id": 1}
     // <-- {"jsonrpc": "2.0", "result": true, "id": 1}
-    pub async fn wallet_exec_sql(&self, _id: u16, _params: JsonValue) -> JsonResult {
+    pub async fn wallet_exec_sql(&self, _id: JsonId, _params: JsonValue) -> JsonResult {
         todo!();
         /* TODO: This will be abstracted away
         if params.is_empty() || !params[0].is_string() {
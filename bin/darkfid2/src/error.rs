@@ -16,7 +16,7 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use darkfi::rpc::jsonrpc::{ErrorCode::ServerError, JsonError, JsonResult};
+use darkfi::rpc::jsonrpc::{ErrorCode::ServerError, JsonError, JsonId, JsonResult};
 
 /// Custom RPC errors available for darkfid.
 /// Please sort them sensefully.
@@ -53,7 +53,7 @@ fn to_tuple(e: RpcError) -> (i32, String) {
     (e as i32, msg.to_string())
 }
 
-pub fn server_error(e: RpcError, id: u16, msg: Option<&str>) -> JsonResult {
+pub fn server_error(e: RpcError, id: JsonId, msg: Option<&str>) -> JsonResult {
     let (code, default_msg) = to_tuple(e);
 
     if let Some(message) = msg {
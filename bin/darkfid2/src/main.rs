@@ -33,8 +33,11 @@ use darkfi::{
     cli_desc,
     net::{settings::SettingsOpt, P2pPtr},
     rpc::{
+        auth::AccessSettings,
+        http::{self, HttpSettings},
         jsonrpc::JsonSubscriber,
-        server::{listen_and_serve, RequestHandler},
+        metrics::serve_metrics,
+        server::{listen_and_serve_with_access, RequestHandler},
     },
     system::{StoppableTask, StoppableTaskPtr},
     util::{path::expand_path, time::TimeKeeper},
@@ -87,6 +90,30 @@ struct Args {
     /// JSON-RPC listen URL
     rpc_listen: Url,
 
+    #[structopt(long, default_value = "admin")]
+    /// Most privileged JSON-RPC methods callable without a token (public, readonly, admin)
+    rpc_access: String,
+
+    #[structopt(long)]
+    /// Optional listen URL serving JSON-RPC over HTTP and WebSocket
+    rpc_http_listen: Option<Url>,
+
+    #[structopt(long, default_value = "public")]
+    /// Most privileged methods callable over HTTP and WebSocket without a token
+    rpc_http_access: String,
+
+    #[structopt(long)]
+    /// Origins allowed to use the HTTP and WebSocket endpoint from a browser (repeatable flag)
+    rpc_http_origin: Vec<String>,
+
+    #[structopt(long)]
+    /// JSON-RPC tokens granting access, as level:token (repeatable flag)
+    rpc_auth_token: Vec<String>,
+
+    #[structopt(long)]
+    /// Optional HTTP listen URL serving metrics at /metrics
+    metrics_listen: Option<Url>,
+
     #[structopt(long, default_value = "testnet")]
     /// Blockchain network to use
     network: String,
@@ -290,10 +317,17 @@ async fn realmain(args: Args, ex: Arc<smol::Executor<'static>>) -> Result<()> {
     // task later. P2P tasks don't need this since it has its own
     // stop() function to shut down, also terminating the task we
     // created for it.
+    let rpc_settings = AccessSettings::new(&args.rpc_access, &args.rpc_auth_token)?;
     let rpc_task = StoppableTask::new();
     let darkfid_ = darkfid.clone();
     rpc_task.clone().start(
-        listen_and_serve(args.rpc_listen, darkfid.clone(), None, ex.clone()),
+        listen_and_serve_with_access(
+            args.rpc_listen,
+            darkfid.clone(),
+            None,
+            rpc_settings,
+            ex.clone(),
+        ),
         |res| async move {
             match res {
                 Ok(()) | Err(Error::RpcServerStopped) => darkfid_.stop_connections().await,
@@ -304,6 +338,55 @@ async fn realmain(args: Args, ex: Arc<smol::Executor<'static>>) -> Result<()> {
         ex.clone(),
     );
 
+    // Optional HTTP and WebSocket JSON-RPC server
+    let rpc_http_settings = HttpSettings {
+        access: AccessSettings::new(&args.rpc_http_access, &args.rpc_auth_token)?,
+        allowed_origins: args.rpc_http_origin.clone(),
+    };
+    let rpc_http_task = args.rpc_http_listen.map(|rpc_http_listen| {
+        info!(target: "darkfid", "Starting HTTP JSON-RPC server");
+        let task = StoppableTask::new();
+        let darkfid_ = darkfid.clone();
+        task.clone().start(
+            http::listen_and_serve_with_settings(
+                rpc_http_listen,
+                darkfid.clone(),
+                None,
+                rpc_http_settings,
+                ex.clone(),
+            ),
+            |res| async move {
+                match res {
+                    Ok(()) | Err(Error::RpcServerStopped) => darkfid_.stop_connections().await,
+                    Err(e) => {
+                        error!(target: "darkfid", "Failed starting HTTP JSON-RPC server: {}", e)
+                    }
+                }
+            },
+            Error::RpcServerStopped,
+            ex.clone(),
+        );
+        task
+    });
+
+    // Optional metrics endpoint
+    let metrics_task = args.metrics_listen.map(|metrics_listen| {
+        info!(target: "darkfid", "Starting metrics endpoint");
+        let task = StoppableTask::new();
+        task.clone().start(
+            serve_metrics(metrics_listen, ex.clone()),
+            |res| async move {
+                match res {
+                    Ok(()) | Err(Error::DetachedTaskStopped) => { /* Do nothing */ }
+                    Err(e) => error!(target: "darkfid", "Failed serving metrics: {}", e),
+                }
+            },
+            Error::DetachedTaskStopped,
+            ex.clone(),
+        );
+        task
+    });
+
     info!(target: "darkfid", "Starting sync P2P network");
     sync_p2p.clone().start().await?;
 
@@ -366,6 +449,16 @@ async fn realmain(args: Args, ex: Arc<smol::Executor<'static>>) -> Result<()> {
     info!(target: "darkfid", "Stopping JSON-RPC server...");
     rpc_task.stop().await;
 
+    if let Some(rpc_http_task) = rpc_http_task {
+        info!(target: "darkfid", "Stopping HTTP JSON-RPC server...");
+        rpc_http_task.stop().await;
+    }
+
+    if let Some(metrics_task) = metrics_task {
+        info!(target: "darkfid", "Stopping metrics endpoint...");
+        metrics_task.stop().await;
+    }
+
     info!(target: "darkfid", "Stopping syncing P2P network...");
     sync_p2p.stop().await;
 
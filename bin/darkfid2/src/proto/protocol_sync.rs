@@ -55,7 +55,7 @@ pub struct SyncResponse {
     pub blocks: Vec<BlockInfo>,
 }
 
-impl_p2p_message!(SyncResponse, "syncresponse");
+impl_p2p_message!(SyncResponse, "syncresponse", usize::MAX, Bulk);
 
 pub struct ProtocolSync {
     request_sub: MessageSubscription<SyncRequest>,
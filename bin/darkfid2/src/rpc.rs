@@ -25,7 +25,9 @@ use tinyjson::JsonValue;
 
 use darkfi::{
     rpc::{
-        jsonrpc::{ErrorCode, JsonError, JsonRequest, JsonResponse, JsonResult},
+        auth::MethodAccess,
+        jsonrpc::{ErrorCode, JsonError, JsonId, JsonRequest, JsonResponse, JsonResult},
+        registry::{JsonType, RpcMethod, RpcParam},
         server::RequestHandler,
     },
     system::StoppableTaskPtr,
@@ -34,6 +36,130 @@ use darkfi::{
 
 use crate::Darkfid;
 
+/// Shorthand for declaring the methods of the registry
+const fn method(
+    name: &'static str,
+    description: &'static str,
+    params: &'static [RpcParam],
+    result: JsonType,
+    access: MethodAccess,
+) -> RpcMethod {
+    RpcMethod { name, description, params, result, access }
+}
+
+/// Methods served by the darkfid JSON-RPC. Public methods are the ones
+/// that are safe to expose to wallets over the network.
+const METHODS: &[RpcMethod] = &[
+    // =====================
+    // Miscellaneous methods
+    // =====================
+    method("ping", "Reply with pong", &[], JsonType::String, MethodAccess::Public),
+    method(
+        "clock",
+        "Current system clock as a u64 timestamp",
+        &[],
+        JsonType::String,
+        MethodAccess::Public,
+    ),
+    method(
+        "sync_dnet_switch",
+        "Activate or deactivate dnet in the sync P2P stack",
+        &[RpcParam::required("enable", JsonType::Boolean, "Whether dnet should be active")],
+        JsonType::Boolean,
+        MethodAccess::Admin,
+    ),
+    method(
+        "consensus_dnet_switch",
+        "Activate or deactivate dnet in the consensus P2P stack",
+        &[RpcParam::required("enable", JsonType::Boolean, "Whether dnet should be active")],
+        JsonType::Boolean,
+        MethodAccess::Admin,
+    ),
+    // ==================
+    // Blockchain methods
+    // ==================
+    method(
+        "blockchain.get_slot",
+        "Block in the given slot, serialized and base64-encoded",
+        &[RpcParam::required("slot", JsonType::String, "Slot number, as a u64 string")],
+        JsonType::String,
+        MethodAccess::Public,
+    ),
+    method(
+        "blockchain.get_tx",
+        "Transaction with the given hash, serialized and base64-encoded",
+        &[RpcParam::required("hash", JsonType::String, "Transaction hash, hex-encoded")],
+        JsonType::String,
+        MethodAccess::Public,
+    ),
+    method(
+        "blockchain.last_known_slot",
+        "Last slot known to the node, as a u64 string",
+        &[],
+        JsonType::String,
+        MethodAccess::Public,
+    ),
+    method(
+        "blockchain.lookup_zkas",
+        "zkas namespaces and bincodes deployed by the given contract",
+        &[RpcParam::required("contract_id", JsonType::String, "Contract ID, base58-encoded")],
+        JsonType::Array,
+        MethodAccess::Public,
+    ),
+    method(
+        "blockchain.subscribe_blocks",
+        "Subscribe to notifications of new incoming blocks",
+        &[],
+        JsonType::Any,
+        MethodAccess::Public,
+    ),
+    method(
+        "blockchain.subscribe_txs",
+        "Subscribe to notifications of new incoming transactions",
+        &[],
+        JsonType::Any,
+        MethodAccess::Public,
+    ),
+    method(
+        "blockchain.subscribe_proposals",
+        "Subscribe to notifications of new incoming consensus proposals",
+        &[],
+        JsonType::Any,
+        MethodAccess::Public,
+    ),
+    // ===================
+    // Transaction methods
+    // ===================
+    method(
+        "tx.simulate",
+        "Check whether the given serialized transaction is valid",
+        &[RpcParam::required("tx", JsonType::String, "Serialized transaction, base64-encoded")],
+        JsonType::Boolean,
+        MethodAccess::Public,
+    ),
+    method(
+        "tx.broadcast",
+        "Broadcast the given serialized transaction, replying with its hash",
+        &[RpcParam::required("tx", JsonType::String, "Serialized transaction, base64-encoded")],
+        JsonType::String,
+        MethodAccess::Public,
+    ),
+    method(
+        "tx.pending",
+        "Hashes of all pending transactions",
+        &[],
+        JsonType::Array,
+        MethodAccess::ReadOnly,
+    ),
+    method(
+        "tx.clean_pending",
+        "Remove all pending transactions, replying with their hashes",
+        &[],
+        JsonType::Array,
+        MethodAccess::Admin,
+    ),
+];
+
 #[async_trait]
 impl RequestHandler for Darkfid {
     async fn handle_request(&self, req: JsonRequest) -> JsonResult {
@@ -75,7 +201,7 @@ impl RequestHandler for Darkfid {
             "tx.simulate" => return self.tx_simulate(req.id, req.params).await,
             "tx.broadcast" => return self.tx_broadcast(req.id, req.params).await,
             "tx.pending" => return self.tx_pending(req.id, req.params).await,
-            "tx.clean_pending" => return self.tx_pending(req.id, req.params).await,
+            "tx.clean_pending" => return self.tx_clean_pending(req.id, req.params).await,
 
             // ==============
             // Invalid method
@@ -84,6 +210,10 @@ impl RequestHandler for Darkfid {
         }
     }
 
+    fn methods(&self) -> &'static [RpcMethod] {
+        METHODS
+    }
+
     async fn connections_mut(&self) -> MutexGuard<'_, HashSet<StoppableTaskPtr>> {
         self.rpc_connections.lock().await
     }
@@ -95,7 +225,7 @@ impl Darkfid {
     //
     // --> {"jsonrpc": "2.0", "method": "clock", "params": [], "id": 1}
     // <-- {"jsonrpc": "2.0", "result": "1234", "id": 1}
-    async fn clock(&self, id: u16, _params: JsonValue) -> JsonResult {
+    async fn clock(&self, id: JsonId, _params: JsonValue) -> JsonResult {
         JsonResponse::new(JsonValue::String(Timestamp::current_time().0.to_string()), id).into()
     }
 
@@ -106,7 +236,7 @@ impl Darkfid {
     //
     // --> {"jsonrpc": "2.0", "method": "sync_dnet_switch", "params": [true], "id": 42}
     // <-- {"jsonrpc": "2.0", "result": true, "id": 42}
-    async fn sync_dnet_switch(&self, id: u16, params: JsonValue) -> JsonResult {
+    async fn sync_dnet_switch(&self, id: JsonId, params: JsonValue) -> JsonResult {
         let params = params.get::<Vec<JsonValue>>().unwrap();
         if params.len() != 1 || !params[0].is_bool() {
             return JsonError::new(ErrorCode::InvalidParams, None, id).into()
@@ -130,7 +260,7 @@ impl Darkfid {
     //
     // --> {"jsonrpc": "2.0", "method": "consensus_dnet_switch", "params": [true], "id": 42}
     // <-- {"jsonrpc": "2.0", "result": true, "id": 42}
-    async fn consensus_dnet_switch(&self, id: u16, params: JsonValue) -> JsonResult {
+    async fn consensus_dnet_switch(&self, id: JsonId, params: JsonValue) -> JsonResult {
         let params = params.get::<Vec<JsonValue>>().unwrap();
         if params.len() != 1 || !params[0].is_bool() {
             return JsonError::new(ErrorCode::InvalidParams, None, id).into()
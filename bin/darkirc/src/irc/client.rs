@@ -25,7 +25,7 @@ use std::{
 };
 
 use darkfi::{
-    event_graph::{proto::EventPut, Event, NULL_ID},
+    event_graph::{Event, EventGraphPtr, NULL_ID},
     system::Subscription,
     Error, Result,
 };
@@ -150,16 +150,16 @@ impl Client {
                     // in case line processing failed in any way.
                     match self.process_client_line(&line, &mut writer).await {
                         // If we got an event back, we should broadcast it.
-                        // This means we add it to our DAG, and the DAG will
-                        // handle the rest of the propagation.
-                        Ok(Some(event)) => {
+                        // This means we add it to its channel's DAG, and the
+                        // DAG will handle the rest of the propagation.
+                        Ok(Some((event, event_graph))) => {
                             // Update the last sent event.
                             let event_id = event.id();
                             *self.last_sent.write().await = event_id;
 
                             // If it fails for some reason, for now, we just note it
                             // and pass.
-                            if let Err(e) = self.server.darkirc.event_graph.dag_insert(&[event.clone()]).await {
+                            if let Err(e) = event_graph.dag_insert(&[event.clone()]).await {
                                 error!("[IRC CLIENT] Failed inserting new event to DAG: {}", e);
                             } else {
                                 // We sent this, so it should be considered seen.
@@ -169,7 +169,7 @@ impl Client {
                                 }
 
                                 // Otherwise, broadcast it
-                                self.server.darkirc.p2p.broadcast(&EventPut(event)).await;
+                                event_graph.broadcast(&event, &[]).await;
                             }
                         }
 
@@ -277,7 +277,11 @@ impl Client {
     }
 
     /// Handle the incoming line given sent by the IRC client
-    async fn process_client_line<W>(&self, line: &str, writer: &mut W) -> Result<Option<Event>>
+    async fn process_client_line<W>(
+        &self,
+        line: &str,
+        writer: &mut W,
+    ) -> Result<Option<(Event, EventGraphPtr)>>
     where
         W: AsyncWrite + Unpin,
     {
@@ -338,7 +342,8 @@ impl Client {
         }
 
         // If the command was a PRIVMSG the client sent, we need to encrypt it and
-        // create an Event to broadcast and return it from this function. So let's try.
+        // create an Event to broadcast and return it from this function, along with
+        // the DAG of the channel it belongs to. So let's try.
         // We also do not allow sending unencrypted DMs. In that case, we send a notice
         // to the client to inform them that the feature is not enabled.
 
@@ -355,17 +360,16 @@ impl Client {
                 msg: msg.to_string(),
             };
 
+            // Pick the channel's DAG before the channel name gets encrypted.
+            let event_graph = self.server.channel_event_graph(&privmsg.channel).await;
+
             // Encrypt the Privmsg if an encryption method is available.
             self.server.try_encrypt(&mut privmsg).await;
 
             // Build a DAG event and return it.
-            let event = Event::new(
-                serialize_async(&privmsg).await,
-                self.server.darkirc.event_graph.clone(),
-            )
-            .await;
+            let event = Event::new(serialize_async(&privmsg).await, event_graph.clone()).await;
 
-            return Ok(Some(event))
+            return Ok(Some((event, event_graph)))
         }
 
         Ok(None)
@@ -52,7 +52,7 @@
 
 use std::{collections::HashSet, sync::atomic::Ordering::SeqCst};
 
-use darkfi::Result;
+use darkfi::{event_graph::Event, Result};
 use darkfi_serial::deserialize_async_partial;
 use log::{error, info};
 
@@ -63,6 +63,9 @@ use super::{
     IrcChannel, SERVER_NAME,
 };
 
+/// Maximum number of archived messages replayed per DAG topic on join
+const MAX_SCROLLBACK: usize = 1000;
+
 impl Client {
     /// `ADMIN [<server>]`
     ///
@@ -316,6 +319,7 @@ impl Client {
                     topic: String::new(),
                     nicks: HashSet::from([nick.clone()]),
                     saltbox: None,
+                    dag_topic: None,
                 };
                 server_channels.insert(channel.clone(), chan);
             }
@@ -925,20 +929,73 @@ impl Client {
 
     /// Internal function that scans the DAG and returns events for
     /// given channels. Will return empty if no_history CAP is requested.
+    /// Events archived in previous rotations are included, up to
+    /// `MAX_SCROLLBACK` messages per DAG topic.
     async fn get_history(&self, channels: &HashSet<String>) -> Result<Vec<ReplyType>> {
         if channels.is_empty() || *self.caps.read().await.get("no-history").unwrap() {
             return Ok(vec![])
         }
 
-        // Fetch and order all the events from the DAG
-        let dag_events = self.server.darkirc.event_graph.order_events().await;
-
         // Here we'll hold the events in order we'll push to the client
+        let mut history = vec![];
+
+        let event_graph = &self.server.darkirc.event_graph;
+        for topic in event_graph.followed_topics().await {
+            let Some(event_graph) = event_graph.get_topic(&topic).await else { continue };
+
+            // Walk back the archive one rotation period at a time, newest
+            // first, until we have enough scrollback.
+            let mut archived = vec![];
+            for period in event_graph.archived_periods().await?.into_iter().rev() {
+                if archived.len() >= MAX_SCROLLBACK {
+                    break
+                }
+
+                let events = event_graph.archived_events(period).await?;
+                let mut period_history = self.history_replies(&events, channels).await?;
+                period_history.append(&mut archived);
+                archived = period_history;
+            }
+            let skip = archived.len().saturating_sub(MAX_SCROLLBACK);
+            history.extend(archived.into_iter().skip(skip));
+
+            // Then fetch and order all the events from the DAG
+            let mut events = vec![];
+            for event_id in event_graph.order_events().await.iter() {
+                // The event might have been pruned in the meantime
+                let Some(event) = event_graph.dag_get(event_id).await? else { continue };
+                events.push(event);
+            }
+            history.append(&mut self.history_replies(&events, channels).await?);
+        }
+
+        // Mark what we send as seen in the seen_events tree.
+        let mut replies = Vec::with_capacity(history.len());
+        for (event_id, reply) in history {
+            if let Err(e) = self.mark_seen(&event_id).await {
+                error!("[IRC CLIENT] (get_history) self.mark_seen({}) failed: {}", event_id, e);
+                return Err(e)
+            }
+            replies.push(reply);
+        }
+
+        Ok(replies)
+    }
+
+    /// Internal function that returns the unseen messages of the given
+    /// channels among the given events, along with their event IDs.
+    async fn history_replies(
+        &self,
+        events: &[Event],
+        channels: &HashSet<String>,
+    ) -> Result<Vec<(blake3::Hash, ReplyType)>> {
         let mut replies = vec![];
 
-        for event_id in dag_events.iter() {
+        for event in events.iter() {
+            let event_id = event.id();
+
             // If it was seen, skip
-            match self.is_seen(event_id).await {
+            match self.is_seen(&event_id).await {
                 Ok(true) => continue,
                 Ok(false) => {}
                 Err(e) => {
@@ -947,9 +1004,6 @@ impl Client {
                 }
             }
 
-            // Get the event from the DAG
-            let event = self.server.darkirc.event_graph.dag_get(event_id).await.unwrap().unwrap();
-
             // Try to deserialize it. (Here we skip errors)
             let Ok((mut privmsg, _)) = deserialize_async_partial(event.content()).await else {
                 continue
@@ -959,17 +1013,13 @@ impl Client {
             self.server.try_decrypt(&mut privmsg).await;
 
             // If the privmsg is intented for any of the given channels, add it as
-            // a reply and mark it as seen in the seen_events tree.
+            // a reply.
             if !channels.contains(&privmsg.channel) {
                 continue
             }
 
             let msg = format!("PRIVMSG {} :{}", privmsg.channel, privmsg.msg);
-            replies.push(ReplyType::Client((privmsg.nick, msg)));
-            if let Err(e) = self.mark_seen(event_id).await {
-                error!("[IRC CLIENT] (get_history) self.mark_seen({}) failed: {}", event_id, e);
-                return Err(e)
-            }
+            replies.push((event_id, ReplyType::Client((privmsg.nick, msg))));
         }
 
         Ok(replies)
@@ -50,6 +50,9 @@ pub struct IrcChannel {
     pub topic: String,
     pub nicks: HashSet<String>,
     pub saltbox: Option<Arc<ChaChaBox>>,
+    /// Event DAG topic this channel's messages are sent to, instead
+    /// of the default one
+    pub dag_topic: Option<String>,
 }
 
 /// IRC contact definition
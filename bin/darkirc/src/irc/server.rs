@@ -20,7 +20,7 @@ use std::{collections::HashMap, fs::File, io::BufReader, path::PathBuf, sync::Ar
 
 use async_rustls::{rustls, TlsAcceptor};
 use darkfi::{
-    event_graph::Event,
+    event_graph::{Event, EventGraphPtr},
     system::{StoppableTask, StoppableTaskPtr, Subscription},
     util::path::expand_path,
     Error, Result,
@@ -154,6 +154,13 @@ impl IrcServer {
         // Parse configured contacts
         let contacts = parse_configured_contacts(&contents)?;
 
+        // Follow the DAG topics of the configured channels
+        for channel in channels.values() {
+            if let Some(dag_topic) = &channel.dag_topic {
+                self.darkirc.follow_topic(dag_topic).await?;
+            }
+        }
+
         // FIXME: This will remove clients' joined channels. They need to stay.
         // Only if everything is fine, replace.
         *self.autojoin.write().await = autojoin;
@@ -163,6 +170,15 @@ impl IrcServer {
         Ok(())
     }
 
+    /// Return the event DAG the messages of the given channel belong to.
+    /// This is the channel's configured DAG topic, or the default DAG.
+    pub async fn channel_event_graph(&self, channel: &str) -> EventGraphPtr {
+        let event_graph = &self.darkirc.event_graph;
+        let dag_topic = self.channels.read().await.get(channel).and_then(|c| c.dag_topic.clone());
+        let Some(dag_topic) = dag_topic else { return event_graph.clone() };
+        event_graph.get_topic(&dag_topic).await.unwrap_or_else(|| event_graph.clone())
+    }
+
     /// Start accepting new IRC connections.
     pub async fn listen(self: Arc<Self>, ex: Arc<Executor<'_>>) -> Result<()> {
         loop {
@@ -196,7 +212,7 @@ impl IrcServer {
                     };
 
                     // Subscribe to incoming events and set up the connection.
-                    let incoming = self.darkirc.event_graph.event_sub.clone().subscribe().await;
+                    let incoming = self.darkirc.incoming.clone().subscribe().await;
                     if let Err(e) = self
                         .clone()
                         .process_connection(stream, peer_addr, incoming, ex.clone())
@@ -210,7 +226,7 @@ impl IrcServer {
                 // Expecting plain TCP connection
                 None => {
                     // Subscribe to incoming events and set up the connection.
-                    let incoming = self.darkirc.event_graph.event_sub.clone().subscribe().await;
+                    let incoming = self.darkirc.incoming.clone().subscribe().await;
                     if let Err(e) = self
                         .clone()
                         .process_connection(stream, peer_addr, incoming, ex.clone())
@@ -19,13 +19,14 @@ use std::{collections::HashSet, sync::Arc};
 
 use darkfi::{
     async_daemonize, cli_desc,
-    event_graph::{proto::ProtocolEventGraph, EventGraph, EventGraphPtr},
+    event_graph::{proto::ProtocolEventGraph, Event, EventGraph, EventGraphPtr, DEFAULT_TOPIC},
     net::{settings::SettingsOpt, P2p, P2pPtr, SESSION_ALL},
     rpc::{
         jsonrpc::JsonSubscriber,
+        metrics::serve_metrics,
         server::{listen_and_serve, RequestHandler},
     },
-    system::{sleep, StoppableTask, StoppableTaskPtr},
+    system::{sleep, StoppableTask, StoppableTaskPtr, Subscriber, SubscriberPtr},
     util::path::{expand_path, get_config_path},
     Error, Result,
 };
@@ -74,6 +75,10 @@ struct Args {
     /// RPC server listen address
     rpc_listen: Url,
 
+    #[structopt(long)]
+    /// Optional HTTP listen address serving metrics at /metrics
+    metrics_listen: Option<Url>,
+
     #[structopt(long, default_value = "tcp://127.0.0.1:6667")]
     /// IRC server listen address
     irc_listen: Url,
@@ -88,6 +93,10 @@ struct Args {
     /// Datastore (DB) path
     datastore: String,
 
+    #[structopt(long)]
+    /// Optional path to archive the event DAG to before it is pruned
+    dag_archive: Option<String>,
+
     /// Generate a new NaCl keypair and exit
     #[structopt(long)]
     gen_chacha_keypair: bool,
@@ -115,10 +124,14 @@ pub struct DarkIrc {
     sled: sled::Db,
     /// Event Graph instance
     event_graph: EventGraphPtr,
+    /// Events inserted into any of the DAGs we follow
+    incoming: SubscriberPtr<Event>,
     /// JSON-RPC connection tracker
     rpc_connections: Mutex<HashSet<StoppableTaskPtr>>,
     /// dnet JSON-RPC subscriber
     dnet_sub: JsonSubscriber,
+    /// Executor used to follow new DAG topics
+    executor: Arc<Executor<'static>>,
 }
 
 impl DarkIrc {
@@ -127,8 +140,45 @@ impl DarkIrc {
         sled: sled::Db,
         event_graph: EventGraphPtr,
         dnet_sub: JsonSubscriber,
+        executor: Arc<Executor<'static>>,
     ) -> Self {
-        Self { p2p, sled, event_graph, rpc_connections: Mutex::new(HashSet::new()), dnet_sub }
+        let self_ = Self {
+            p2p,
+            sled,
+            event_graph,
+            incoming: Subscriber::new(),
+            rpc_connections: Mutex::new(HashSet::new()),
+            dnet_sub,
+            executor,
+        };
+
+        self_.forward_events(&self_.event_graph);
+        self_
+    }
+
+    /// Relay the events inserted into the given DAG to `incoming`
+    fn forward_events(&self, event_graph: &EventGraphPtr) {
+        let event_sub = event_graph.event_sub.clone();
+        let incoming = self.incoming.clone();
+        self.executor
+            .spawn(async move {
+                let event_sub = event_sub.subscribe().await;
+                loop {
+                    incoming.notify(event_sub.receive().await).await;
+                }
+            })
+            .detach();
+    }
+
+    /// Start following the given DAG topic, if we aren't already
+    pub async fn follow_topic(&self, topic: &str) -> Result<()> {
+        if self.event_graph.get_topic(topic).await.is_some() {
+            return Ok(())
+        }
+
+        let event_graph = self.event_graph.add_topic(topic, 1, self.executor.clone()).await?;
+        self.forward_events(&event_graph);
+        Ok(())
     }
 }
 
@@ -183,8 +233,21 @@ async fn realmain(args: Args, ex: Arc<Executor<'static>>) -> Result<()> {
     info!("Instantiating event DAG");
     let sled_db = sled::open(datastore)?;
     let p2p = P2p::new(args.net.into(), ex.clone()).await;
-    let event_graph =
-        EventGraph::new(p2p.clone(), sled_db.clone(), "darkirc_dag", 1, ex.clone()).await?;
+    let event_graph = match args.dag_archive {
+        Some(path) => {
+            let archive_path = expand_path(&path)?;
+            EventGraph::new_with_archive(
+                p2p.clone(),
+                sled_db.clone(),
+                "darkirc_dag",
+                1,
+                archive_path,
+                ex.clone(),
+            )
+            .await?
+        }
+        None => EventGraph::new(p2p.clone(), sled_db.clone(), "darkirc_dag", 1, ex.clone()).await?,
+    };
 
     info!("Registering EventGraph P2P protocol");
     let event_graph_ = Arc::clone(&event_graph);
@@ -221,8 +284,13 @@ async fn realmain(args: Args, ex: Arc<Executor<'static>>) -> Result<()> {
     );
 
     info!("Starting JSON-RPC server");
-    let darkirc =
-        Arc::new(DarkIrc::new(p2p.clone(), sled_db.clone(), event_graph.clone(), dnet_sub));
+    let darkirc = Arc::new(DarkIrc::new(
+        p2p.clone(),
+        sled_db.clone(),
+        event_graph.clone(),
+        dnet_sub,
+        ex.clone(),
+    ));
     let darkirc_ = Arc::clone(&darkirc);
     let rpc_task = StoppableTask::new();
     rpc_task.clone().start(
@@ -237,6 +305,24 @@ async fn realmain(args: Args, ex: Arc<Executor<'static>>) -> Result<()> {
         ex.clone(),
     );
 
+    // Optional metrics endpoint
+    let metrics_task = args.metrics_listen.map(|metrics_listen| {
+        info!("Starting metrics endpoint");
+        let task = StoppableTask::new();
+        task.clone().start(
+            serve_metrics(metrics_listen, ex.clone()),
+            |res| async move {
+                match res {
+                    Ok(()) | Err(Error::DetachedTaskStopped) => { /* Do nothing */ }
+                    Err(e) => error!("Failed serving metrics: {}", e),
+                }
+            },
+            Error::DetachedTaskStopped,
+            ex.clone(),
+        );
+        task
+    });
+
     info!("Starting IRC server");
     let config_path = get_config_path(args.config, CONFIG_FILE)?;
     let irc_server = IrcServer::new(
@@ -288,6 +374,19 @@ async fn realmain(args: Args, ex: Arc<Executor<'static>>) -> Result<()> {
                 }
             }
         }
+
+        // Channels configured with their own DAG topic
+        for topic in event_graph.followed_topics().await {
+            if topic == DEFAULT_TOPIC {
+                continue
+            }
+
+            info!("Syncing event DAG of topic {}", topic);
+            let topic_graph = event_graph.get_topic(&topic).await.unwrap();
+            if let Err(e) = topic_graph.dag_sync().await {
+                error!("Failed syncing DAG of topic {}: {}", topic, e);
+            }
+        }
     }
 
     // Signal handling for graceful termination.
@@ -300,6 +399,11 @@ async fn realmain(args: Args, ex: Arc<Executor<'static>>) -> Result<()> {
 
     info!("Stopping JSON-RPC server");
     rpc_task.stop().await;
+
+    if let Some(metrics_task) = metrics_task {
+        info!("Stopping metrics endpoint");
+        metrics_task.stop().await;
+    }
     dnet_task.stop().await;
 
     info!("Stopping IRC server");
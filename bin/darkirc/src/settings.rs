@@ -149,6 +149,7 @@ pub fn parse_configured_contacts(data: &toml::Value) -> Result<HashMap<String, I
 /// [channel."#memes"]
 /// secret = "7CkVuFgwTUpJn5Sv67Q3fyEDpa28yrSeL5Hg2GqQ4jfM"
 /// topic = "Dank Memes"
+/// dag_topic = "memes"
 /// ```
 pub fn parse_configured_channels(data: &toml::Value) -> Result<HashMap<String, IrcChannel>> {
     let mut ret = HashMap::new();
@@ -158,7 +159,12 @@ pub fn parse_configured_channels(data: &toml::Value) -> Result<HashMap<String, I
     let Some(chans) = chans.as_table() else { return Err(ParseFailed("`channel` not a map")) };
 
     for (name, items) in chans {
-        let mut chan = IrcChannel { topic: String::new(), nicks: HashSet::new(), saltbox: None };
+        let mut chan = IrcChannel {
+            topic: String::new(),
+            nicks: HashSet::new(),
+            saltbox: None,
+            dag_topic: None,
+        };
 
         if let Some(topic) = items.get("topic") {
             if let Some(topic) = topic.as_str() {
@@ -169,6 +175,15 @@ pub fn parse_configured_channels(data: &toml::Value) -> Result<HashMap<String, I
             }
         }
 
+        if let Some(dag_topic) = items.get("dag_topic") {
+            if let Some(dag_topic) = dag_topic.as_str() {
+                info!("Found configured DAG topic for {}: {}", name, dag_topic);
+                chan.dag_topic = Some(dag_topic.to_string());
+            } else {
+                return Err(ParseFailed("Channel DAG topic not a string"))
+            }
+        }
+
         if let Some(secret) = items.get("secret") {
             if let Some(secret) = secret.as_str() {
                 let Ok(secret_bytes) = bs58::decode(secret).into_vec() else {
@@ -16,7 +16,7 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use darkfi::rpc::jsonrpc::{ErrorCode::ServerError, JsonError, JsonResult};
+use darkfi::rpc::jsonrpc::{ErrorCode::ServerError, JsonError, JsonId, JsonResult};
 
 pub enum RpcError {
     AmountExceedsLimit = -32107,
@@ -42,7 +42,7 @@ fn to_tuple(e: RpcError) -> (i32, String) {
     (e as i32, msg.to_string())
 }
 
-pub fn server_error(e: RpcError, id: u16) -> JsonResult {
+pub fn server_error(e: RpcError, id: JsonId) -> JsonResult {
     let (code, msg) = to_tuple(e);
     JsonError::new(ServerError(code), Some(msg), id).into()
 }
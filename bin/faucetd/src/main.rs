@@ -74,7 +74,7 @@ use darkfi::{
     rpc::{
         jsonrpc::{
             ErrorCode::{InternalError, InvalidParams, MethodNotFound},
-            JsonError, JsonRequest, JsonResponse, JsonResult,
+            JsonError, JsonId, JsonRequest, JsonResponse, JsonResult,
         },
         server::{listen_and_serve, RequestHandler},
     },
@@ -386,7 +386,7 @@ impl Faucetd {
     //
     // --> {"jsonrpc": "2.0", "method": "challenge", "params": ["1DarkFi..."], "id": 1}
     // <-- {"jsonrpc": "2.0", "result": ["0x123...", 10000], "id": 1}
-    async fn challenge(&self, id: u16, params: JsonValue) -> JsonResult {
+    async fn challenge(&self, id: JsonId, params: JsonValue) -> JsonResult {
         const N_STEPS: u64 = 2_000_000;
 
         let params = params.get::<Vec<JsonValue>>().unwrap();
@@ -445,7 +445,7 @@ impl Faucetd {
     //
     // --> {"jsonrpc": "2.0", "method": "airdrop", "params": ["1DarkFi...", 1.42, "0x123..."], "id": 1}
     // <-- {"jsonrpc": "2.0", "result": "txID", "id": 1}
-    async fn airdrop(&self, id: u16, params: JsonValue) -> JsonResult {
+    async fn airdrop(&self, id: JsonId, params: JsonValue) -> JsonResult {
         let params = params.get::<Vec<JsonValue>>().unwrap();
 
         if params.len() != 3 ||
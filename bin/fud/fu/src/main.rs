@@ -16,17 +16,19 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use std::{path::PathBuf, sync::Arc};
+
 use clap::{Parser, Subcommand};
-use log::info;
-use serde_json::json;
 use simplelog::{ColorChoice, TermLogger, TerminalMode};
+use smol::Executor;
+use tinyjson::JsonValue;
 use url::Url;
 
 use darkfi::{
     cli_desc,
     rpc::{client::RpcClient, jsonrpc::JsonRequest},
     util::cli::{get_log_config, get_log_level},
-    Result,
+    Error, Result,
 };
 
 #[derive(Parser)]
@@ -47,17 +49,40 @@ struct Args {
 
 #[derive(Subcommand)]
 enum Subcmd {
-    /// List fud folder contents
+    /// Store a local file or directory in fud and provide it to the network
+    Put {
+        /// File or directory path
+        path: PathBuf,
+    },
+
+    /// Retrieve a file or a directory tree from the fud network
+    Get {
+        /// File hash
+        file_hash: String,
+
+        /// Path to write the file or directory to, defaults to the file hash
+        output: Option<PathBuf>,
+    },
+
+    /// List the files stored by fud
     List,
 
-    /// Sync fud folder contents and signal network for record changes
-    Sync,
+    /// Pin a stored file, so it is never evicted
+    Pin {
+        /// File hash
+        file_hash: String,
+    },
 
-    /// Retrieve provided file name from the fud network
-    Get {
-        #[clap(short, long)]
-        /// File name
-        file: String,
+    /// Unpin a stored file
+    Unpin {
+        /// File hash
+        file_hash: String,
+    },
+
+    /// Remove a stored file
+    Remove {
+        /// File hash
+        file_hash: String,
     },
 }
 
@@ -66,85 +91,98 @@ struct Fu {
 }
 
 impl Fu {
-    async fn close_connection(&self) -> Result<()> {
-        self.rpc_client.close().await
+    async fn close_connection(&self) {
+        self.rpc_client.stop().await;
     }
 
-    async fn list(&self) -> Result<()> {
-        let req = JsonRequest::new("list", json!([]));
+    async fn put(&self, path: PathBuf) -> Result<()> {
+        // fud resolves paths relative to its own working directory
+        let path = std::env::current_dir()?.join(path);
+        let Some(path) = path.to_str() else {
+            return Err(Error::Custom(format!("Invalid path {:?}", path)))
+        };
+
+        let params = vec![JsonValue::String(path.to_string())];
+        let req = JsonRequest::new("put", JsonValue::Array(params));
         let rep = self.rpc_client.request(req).await?;
+        println!("{}", rep.get::<String>().unwrap());
+        Ok(())
+    }
 
-        // Extract response
-        let content = rep[0].as_array().unwrap();
-        let new = rep[1].as_array().unwrap();
-        let deleted = rep[2].as_array().unwrap();
-
-        // Print info
-        info!("----------Content-------------");
-        if content.is_empty() {
-            info!("No file records exists in DHT.");
-        } else {
-            for name in content {
-                info!("\t{}", name.as_str().unwrap());
-            }
-        }
-        info!("------------------------------");
-
-        info!("----------New files-----------");
-        if new.is_empty() {
-            info!("No new files to import.");
-        } else {
-            for name in new {
-                info!("\t{}", name.as_str().unwrap());
-            }
-        }
-        info!("------------------------------");
-
-        info!("----------Removed keys--------");
-        if deleted.is_empty() {
-            info!("No keys were removed.");
-        } else {
-            for key in deleted {
-                info!("\t{}", key.as_str().unwrap());
-            }
-        }
-        info!("------------------------------");
+    async fn get(&self, file_hash: String, output: Option<PathBuf>) -> Result<()> {
+        let output = std::env::current_dir()?.join(output.unwrap_or(file_hash.clone().into()));
+        let Some(output) = output.to_str() else {
+            return Err(Error::Custom(format!("Invalid output path {:?}", output)))
+        };
 
+        let params = vec![JsonValue::String(file_hash), JsonValue::String(output.to_string())];
+        let req = JsonRequest::new("get", JsonValue::Array(params));
+        let rep = self.rpc_client.request(req).await?;
+        println!("{}", rep.get::<String>().unwrap());
         Ok(())
     }
 
-    async fn sync(&self) -> Result<()> {
-        let req = JsonRequest::new("sync", json!([]));
-        self.rpc_client.request(req).await?;
-        info!("Daemon synced successfully!");
+    async fn list(&self) -> Result<()> {
+        let req = JsonRequest::new("list", JsonValue::Array(vec![]));
+        let rep = self.rpc_client.request(req).await?;
+
+        let files = rep.get::<Vec<JsonValue>>().unwrap();
+        if files.is_empty() {
+            println!("No files stored.");
+            return Ok(())
+        }
+
+        for file in files {
+            let file_hash = file["file_hash"].get::<String>().unwrap();
+            let chunks_total = *file["chunks_total"].get::<f64>().unwrap() as u64;
+            let chunks_done = *file["chunks_done"].get::<f64>().unwrap() as u64;
+            let size = *file["size"].get::<f64>().unwrap() as u64;
+            let pinned = if *file["pinned"].get::<bool>().unwrap() { " pinned" } else { "" };
+
+            println!(
+                "{}\t{}/{} chunks\t{} bytes{}",
+                file_hash, chunks_done, chunks_total, size, pinned
+            );
+        }
+
         Ok(())
     }
 
-    async fn get(&self, file: String) -> Result<()> {
-        let req = JsonRequest::new("get", json!([file]));
+    /// Call a method taking a file hash and returning `true` on success
+    async fn file_request(&self, method: &str, file_hash: String) -> Result<()> {
+        let req = JsonRequest::new(method, JsonValue::Array(vec![JsonValue::String(file_hash)]));
         let rep = self.rpc_client.request(req).await?;
-        let path = rep.as_str().unwrap();
-        info!("File waits you at: {}", path);
+        if !*rep.get::<bool>().unwrap() {
+            return Err(Error::Custom(format!("Failed calling {}", method)))
+        }
         Ok(())
     }
 }
 
-#[async_std::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     let args = Args::parse();
 
     let log_level = get_log_level(args.verbose);
     let log_config = get_log_config(args.verbose);
     TermLogger::init(log_level, log_config, TerminalMode::Mixed, ColorChoice::Auto)?;
 
-    let rpc_client = RpcClient::new(args.endpoint, None).await?;
-    let fu = Fu { rpc_client };
+    let executor = Arc::new(Executor::new());
+
+    smol::block_on(executor.run(async {
+        let rpc_client = RpcClient::new(args.endpoint, executor.clone()).await?;
+        let fu = Fu { rpc_client };
+
+        let result = match args.command {
+            Subcmd::Put { path } => fu.put(path).await,
+            Subcmd::Get { file_hash, output } => fu.get(file_hash, output).await,
+            Subcmd::List => fu.list().await,
+            Subcmd::Pin { file_hash } => fu.file_request("pin", file_hash).await,
+            Subcmd::Unpin { file_hash } => fu.file_request("unpin", file_hash).await,
+            Subcmd::Remove { file_hash } => fu.file_request("remove", file_hash).await,
+        };
 
-    match args.command {
-        Subcmd::List => fu.list().await,
-        Subcmd::Sync => fu.sync().await,
-        Subcmd::Get { file } => fu.get(file).await,
-    }?;
+        fu.close_connection().await;
 
-    fu.close_connection().await
+        result
+    }))
 }
@@ -0,0 +1,761 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Download manager for fud.
+//!
+//! A download looks up the providers of the file in the DHT, fetches the
+//! file metadata, and then requests the missing chunks from all providers
+//! at once. Every provider gets its own connection and worker, which takes
+//! the next chunk from a shared queue. Chunks that fail are put back in the
+//! queue so another worker can retry them with a different provider.
+//!
+//! Fetched chunks are verified against their hash before they are written
+//! to Geode, and the whole file is verified once complete. If it does not
+//! match, the metadata is dropped and fetched again from another provider.
+//! Since metadata and chunks live in Geode, the only state we have to
+//! persist is which downloads are pending. This is done with an empty file
+//! per download in the `downloads` directory, so they can be resumed after
+//! a restart.
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    sync::{
+        atomic::{AtomicBool, Ordering::SeqCst},
+        Arc,
+    },
+    time::Duration,
+};
+
+use log::{debug, error, info, warn};
+use smol::{
+    fs::{self, File},
+    future,
+    lock::{Mutex, RwLock},
+    stream::StreamExt,
+};
+use tinyjson::JsonValue;
+use url::Url;
+
+use darkfi::{
+    net::{connector::Connector, protocol::ProtocolVersion, session::Session, ChannelPtr},
+    system::{sleep, timeout::timeout, Subscriber, SubscriberPtr, Subscription},
+    Error, Result,
+};
+
+use super::{
+    proto::{
+        FudChunkNotFound, FudChunkReply, FudChunkRequest, FudFileNotFound, FudFileReply,
+        FudFileRequest,
+    },
+    Fud,
+};
+
+/// Path prefix where pending downloads are recorded
+pub const DOWNLOADS_PATH: &str = "downloads";
+
+/// Maximum number of peers we download chunks from at once
+const MAX_DOWNLOAD_PEERS: usize = 8;
+/// Time to wait for a peer to reply to a request
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Number of rounds without progress before a download fails
+const MAX_ATTEMPTS: usize = 3;
+/// Seconds to wait between rounds without progress
+const RETRY_INTERVAL: u64 = 10;
+
+/// State of a download
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DownloadState {
+    /// Fetching the file metadata
+    Metadata,
+    /// Fetching the file chunks
+    Chunks,
+    /// The file is complete and verified
+    Done,
+    /// The download failed, it is retried on the next `get` or restart
+    Failed,
+    /// The download was cancelled
+    Cancelled,
+}
+
+impl DownloadState {
+    /// Check whether the download is still running
+    pub fn is_active(&self) -> bool {
+        matches!(self, Self::Metadata | Self::Chunks)
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Metadata => "metadata",
+            Self::Chunks => "chunks",
+            Self::Done => "done",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// Progress of a download
+#[derive(Clone, Debug)]
+pub struct DownloadStatus {
+    pub state: DownloadState,
+    /// Number of chunks of the file, known once we have the metadata
+    pub chunks_total: usize,
+    /// Number of chunks available locally
+    pub chunks_done: usize,
+    /// Peers we are currently downloading from
+    pub peers: BTreeSet<Url>,
+}
+
+/// A file download, see the module documentation
+pub struct Download {
+    pub file_hash: blake3::Hash,
+    status: RwLock<DownloadStatus>,
+    cancelled: AtomicBool,
+    /// Notified with the final state once the download is finished
+    finished_sub: SubscriberPtr<DownloadState>,
+}
+
+pub type DownloadPtr = Arc<Download>;
+
+impl Download {
+    fn new(file_hash: blake3::Hash) -> DownloadPtr {
+        Arc::new(Self {
+            file_hash,
+            status: RwLock::new(DownloadStatus {
+                state: DownloadState::Metadata,
+                chunks_total: 0,
+                chunks_done: 0,
+                peers: BTreeSet::new(),
+            }),
+            cancelled: AtomicBool::new(false),
+            finished_sub: Subscriber::new(),
+        })
+    }
+
+    /// Return the current progress of the download
+    pub async fn status(&self) -> DownloadStatus {
+        self.status.read().await.clone()
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(SeqCst)
+    }
+
+    /// Set the final state of the download and notify the waiters
+    async fn finish(&self, state: DownloadState) {
+        self.status.write().await.state = state;
+        self.finished_sub.notify(state).await;
+    }
+
+    /// Return the download progress as a JSON object
+    pub async fn to_json(&self) -> JsonValue {
+        let status = self.status().await;
+        let peers = status.peers.iter().map(|p| JsonValue::String(p.to_string())).collect();
+
+        JsonValue::Object(HashMap::from([
+            ("file_hash".to_string(), JsonValue::String(self.file_hash.to_hex().to_string())),
+            ("state".to_string(), JsonValue::String(status.state.as_str().to_string())),
+            ("chunks_total".to_string(), JsonValue::Number(status.chunks_total as f64)),
+            ("chunks_done".to_string(), JsonValue::Number(status.chunks_done as f64)),
+            ("peers".to_string(), JsonValue::Array(peers)),
+        ]))
+    }
+}
+
+/// Chunks that are left to fetch in a download round
+struct ChunkQueue {
+    pending: VecDeque<blake3::Hash>,
+    /// Peers that already failed to give us a chunk
+    tried: HashMap<blake3::Hash, HashSet<Url>>,
+}
+
+impl ChunkQueue {
+    /// Take the next chunk the given peer has not failed on yet
+    fn next_for(&mut self, peer: &Url) -> Option<blake3::Hash> {
+        let pos = self
+            .pending
+            .iter()
+            .position(|chunk| !self.tried.get(chunk).is_some_and(|peers| peers.contains(peer)))?;
+
+        self.pending.remove(pos)
+    }
+
+    /// Put back a chunk the given peer failed to give us
+    fn failed(&mut self, chunk_hash: blake3::Hash, peer: &Url) {
+        self.tried.entry(chunk_hash).or_default().insert(peer.clone());
+        self.pending.push_back(chunk_hash);
+    }
+}
+
+impl Fud {
+    /// Start downloading the given file, or join the download if it is
+    /// already running. Returns a subscription notified with the final
+    /// state of the download, which has to be unsubscribed by the caller.
+    pub async fn download(&self, file_hash: blake3::Hash) -> Result<Subscription<DownloadState>> {
+        let mut downloads = self.downloads.write().await;
+        if let Some(download) = downloads.get(&file_hash) {
+            if download.status.read().await.state.is_active() {
+                return Ok(download.finished_sub.clone().subscribe().await)
+            }
+        }
+
+        // Record the download so it can be resumed after a restart
+        File::create(self.downloads_path.join(file_hash.to_hex().as_str())).await?;
+
+        info!(target: "fud::download()", "Starting download of {}", file_hash);
+        let download = Download::new(file_hash);
+        let sub = download.finished_sub.clone().subscribe().await;
+        downloads.insert(file_hash, download.clone());
+        self.download_tx.send(download).await.unwrap();
+
+        Ok(sub)
+    }
+
+    /// Cancel a running download. Returns `false` if there is no such download.
+    pub async fn cancel_download(&self, file_hash: &blake3::Hash) -> Result<bool> {
+        let downloads = self.downloads.read().await;
+        let Some(download) = downloads.get(file_hash) else { return Ok(false) };
+
+        info!(target: "fud::cancel_download()", "Cancelling download of {}", file_hash);
+        download.cancelled.store(true, SeqCst);
+        if download.status.read().await.state != DownloadState::Done {
+            download.finish(DownloadState::Cancelled).await;
+        }
+
+        let path = self.downloads_path.join(file_hash.to_hex().as_str());
+        if path.exists() {
+            fs::remove_file(path).await?;
+        }
+
+        Ok(true)
+    }
+
+    /// Resume the downloads recorded in the downloads directory
+    pub async fn resume_downloads(&self) -> Result<()> {
+        let mut entries = fs::read_dir(&self.downloads_path).await?;
+        while let Some(entry) = entries.next().await {
+            let Ok(entry) = entry else { continue };
+            let Some(file_name) = entry.file_name().to_str().map(|n| n.to_string()) else {
+                continue
+            };
+            let Ok(file_hash) = blake3::Hash::from_hex(file_name) else { continue };
+
+            info!(target: "fud::resume_downloads()", "Resuming download of {}", file_hash);
+            self.download(file_hash).await?.unsubscribe().await;
+        }
+
+        Ok(())
+    }
+
+    /// Task running a single download
+    async fn run_download(self: Arc<Self>, download: DownloadPtr) {
+        let result = self.fetch_download(&download).await;
+
+        // Hold the lock so nobody joins the download while it finishes
+        let downloads = self.downloads.read().await;
+
+        // If the download was cancelled, `cancel_download()` already
+        // finished it.
+        if download.is_cancelled() {
+            return
+        }
+
+        let state = match result {
+            Ok(()) => DownloadState::Done,
+            Err(e) => {
+                error!(
+                    target: "fud::run_download()",
+                    "Download of {} failed: {}", download.file_hash, e,
+                );
+                DownloadState::Failed
+            }
+        };
+
+        if state == DownloadState::Done {
+            info!(target: "fud::run_download()", "Download of {} finished", download.file_hash);
+
+            // Forget about the download, unless it was restarted meanwhile
+            if downloads.get(&download.file_hash).is_some_and(|d| Arc::ptr_eq(d, &download)) {
+                let path = self.downloads_path.join(download.file_hash.to_hex().as_str());
+                if let Err(e) = fs::remove_file(path).await {
+                    error!(target: "fud::run_download()", "Failed removing download: {}", e);
+                }
+            }
+
+            // We can serve the file now
+            self.dht.announce(&download.file_hash).await;
+        }
+
+        download.finish(state).await;
+        drop(downloads);
+
+        // Make room for the file we just got
+        if state == DownloadState::Done {
+            self.enforce_quota(&[download.file_hash]).await;
+        }
+    }
+
+    /// Fetch the metadata and the missing chunks of a file.
+    /// Returns early without an error if the download is cancelled.
+    async fn fetch_download(self: &Arc<Self>, download: &DownloadPtr) -> Result<()> {
+        let file_hash = download.file_hash;
+
+        // Providers that gave us metadata not matching the file
+        let mut bad_peers = HashSet::new();
+        let mut verify_attempts = 0;
+
+        loop {
+            let mut attempts = 0;
+            let mut metadata_peer = None;
+            let mut chunked_file = loop {
+                if download.is_cancelled() {
+                    return Ok(())
+                }
+
+                match self.geode.get(&file_hash).await {
+                    Ok(v) => break v,
+                    Err(Error::GeodeFileNotFound) => {}
+                    Err(e) => return Err(e),
+                }
+
+                if let Some(peer) = self.fetch_metadata(&file_hash, &bad_peers).await {
+                    metadata_peer = Some(peer);
+                    continue
+                }
+
+                attempts += 1;
+                if attempts == MAX_ATTEMPTS {
+                    return Err(Error::GeodeFileRouteNotFound)
+                }
+                sleep(RETRY_INTERVAL).await;
+            };
+
+            let mut attempts = 0;
+            loop {
+                let missing: Vec<_> =
+                    chunked_file.iter().filter(|(_, p)| p.is_none()).map(|(h, _)| *h).collect();
+
+                let chunks_total = chunked_file.iter().len();
+                let mut status = download.status.write().await;
+                status.state = DownloadState::Chunks;
+                status.chunks_total = chunks_total;
+                status.chunks_done = chunks_total - missing.len();
+                drop(status);
+
+                if missing.is_empty() {
+                    break
+                }
+
+                if download.is_cancelled() {
+                    return Ok(())
+                }
+
+                if self.fetch_chunks(download, missing).await == 0 {
+                    attempts += 1;
+                    if attempts == MAX_ATTEMPTS {
+                        return Err(Error::GeodeChunkRouteNotFound)
+                    }
+                    sleep(RETRY_INTERVAL).await;
+                } else {
+                    attempts = 0;
+                }
+
+                chunked_file = self.geode.get(&file_hash).await?;
+            }
+
+            if self.geode.verify_file(&file_hash, &chunked_file).await? {
+                return Ok(())
+            }
+
+            // Try again with metadata from another provider
+            warn!(
+                target: "fud::fetch_download()",
+                "Chunks of {} do not match the file hash, dropping its metadata", file_hash,
+            );
+            if let Some(peer) = metadata_peer {
+                bad_peers.insert(peer);
+            }
+
+            verify_attempts += 1;
+            if verify_attempts == MAX_ATTEMPTS {
+                return Err(Error::Custom(format!(
+                    "Chunks of {} do not match the file hash",
+                    file_hash
+                )))
+            }
+        }
+    }
+
+    /// Connect to a peer for fetching data from it
+    async fn connect(&self, peer: &Url) -> Result<ChannelPtr> {
+        let session_out = self.p2p.session_outbound();
+        let session_weak = Arc::downgrade(&self.p2p.session_outbound());
+
+        let connector = Connector::new(self.p2p.settings(), session_weak);
+        let (url, channel) = connector.connect(peer).await?;
+
+        let msg_subsystem = channel.message_subsystem();
+        msg_subsystem.add_dispatch::<FudFileReply>().await;
+        msg_subsystem.add_dispatch::<FudFileNotFound>().await;
+        msg_subsystem.add_dispatch::<FudChunkReply>().await;
+        msg_subsystem.add_dispatch::<FudChunkNotFound>().await;
+
+        let proto_ver = ProtocolVersion::new(
+            channel.clone(),
+            self.p2p.settings().clone(),
+            self.p2p.hosts().clone(),
+        )
+        .await;
+
+        let handshake_task = session_out.perform_handshake_protocols(
+            proto_ver,
+            channel.clone(),
+            self.executor.clone(),
+        );
+
+        channel.clone().start(self.executor.clone());
+
+        if let Err(e) = handshake_task.await {
+            error!(target: "fud::connect()", "Handshake with {} failed: {}", url, e);
+            channel.stop().await;
+            return Err(e)
+        }
+
+        Ok(channel)
+    }
+
+    /// Look up the providers of a file in the DHT, and return an address
+    /// for each of them
+    async fn providers(&self, file_hash: &blake3::Hash) -> Vec<Url> {
+        let providers = self.dht.find_providers(file_hash).await;
+        providers.into_iter().filter_map(|p| p.addresses.first().cloned()).collect()
+    }
+
+    /// Try fetching file metadata from the providers of the file, except
+    /// the given ones. Returns the provider the metadata was fetched from,
+    /// once inserted into Geode.
+    async fn fetch_metadata(
+        &self,
+        file_hash: &blake3::Hash,
+        exclude: &HashSet<Url>,
+    ) -> Option<Url> {
+        let mut peers = self.providers(file_hash).await;
+        peers.retain(|p| !exclude.contains(p));
+        if peers.is_empty() {
+            warn!(target: "fud::fetch_metadata()", "Found no providers of {}", file_hash);
+            return None
+        }
+
+        for peer in peers.iter() {
+            info!(target: "fud::fetch_metadata()", "Fetching {} metadata from {}", file_hash, peer);
+            let channel = match self.connect(peer).await {
+                Ok(v) => v,
+                Err(e) => {
+                    error!(target: "fud::fetch_metadata()", "Failed to connect to {}: {}", peer, e);
+                    continue
+                }
+            };
+
+            let reply = self.request_metadata(&channel, file_hash).await;
+            channel.stop().await;
+
+            let chunk_hashes = match reply {
+                Ok(Some(v)) => v,
+                Ok(None) => {
+                    debug!(target: "fud::fetch_metadata()", "{} does not have {}", peer, file_hash);
+                    continue
+                }
+                Err(e) => {
+                    error!(
+                        target: "fud::fetch_metadata()",
+                        "Failed fetching {} metadata from {}: {}", file_hash, peer, e,
+                    );
+                    continue
+                }
+            };
+
+            if let Err(e) = self.geode.insert_file(file_hash, &chunk_hashes).await {
+                error!(
+                    target: "fud::fetch_metadata()",
+                    "Failed inserting file {} to Geode: {}", file_hash, e,
+                );
+                return None
+            }
+
+            return Some(peer.clone())
+        }
+
+        None
+    }
+
+    /// Request file metadata over the given channel. Returns `None` if
+    /// the peer does not have the file.
+    async fn request_metadata(
+        &self,
+        channel: &ChannelPtr,
+        file_hash: &blake3::Hash,
+    ) -> Result<Option<Vec<blake3::Hash>>> {
+        let reply_sub = channel.subscribe_msg::<FudFileReply>().await?;
+        let not_found_sub = channel.subscribe_msg::<FudFileNotFound>().await?;
+        channel.send(&FudFileRequest { file_hash: *file_hash }).await?;
+
+        let reply = async { reply_sub.receive().await.map(|r| Some(r.chunk_hashes.clone())) };
+        let not_found = async { not_found_sub.receive().await.map(|_| None) };
+        let reply = timeout(REPLY_TIMEOUT, future::or(reply, not_found)).await;
+
+        reply_sub.unsubscribe().await;
+        not_found_sub.unsubscribe().await;
+
+        match reply {
+            Ok(v) => v,
+            Err(_) => Err(Error::ConnectTimeout),
+        }
+    }
+
+    /// Run a download round over the given chunks, with one worker per
+    /// provider. Returns the number of chunks fetched.
+    async fn fetch_chunks(
+        self: &Arc<Self>,
+        download: &DownloadPtr,
+        missing: Vec<blake3::Hash>,
+    ) -> usize {
+        let peers = self.providers(&download.file_hash).await;
+        if peers.is_empty() {
+            warn!(target: "fud::fetch_chunks()", "Found no providers of {}", download.file_hash);
+            return 0
+        }
+
+        let queue = Arc::new(Mutex::new(ChunkQueue {
+            pending: missing.into_iter().collect(),
+            tried: HashMap::new(),
+        }));
+
+        let mut workers = vec![];
+        for peer in peers.into_iter().take(MAX_DOWNLOAD_PEERS) {
+            let worker = self.clone().chunk_worker(download.clone(), peer, queue.clone());
+            workers.push(self.executor.spawn(worker));
+        }
+
+        let mut fetched = 0;
+        for worker in workers {
+            fetched += worker.await;
+        }
+
+        fetched
+    }
+
+    /// Fetch chunks from a single peer until there are none left it has
+    /// not failed on. Returns the number of chunks fetched.
+    async fn chunk_worker(
+        self: Arc<Self>,
+        download: DownloadPtr,
+        peer: Url,
+        queue: Arc<Mutex<ChunkQueue>>,
+    ) -> usize {
+        let channel = match self.connect(&peer).await {
+            Ok(v) => v,
+            Err(e) => {
+                error!(target: "fud::chunk_worker()", "Failed to connect to {}: {}", peer, e);
+                return 0
+            }
+        };
+
+        let (reply_sub, not_found_sub) = match future::zip(
+            channel.subscribe_msg::<FudChunkReply>(),
+            channel.subscribe_msg::<FudChunkNotFound>(),
+        )
+        .await
+        {
+            (Ok(r), Ok(n)) => (r, n),
+            _ => {
+                channel.stop().await;
+                return 0
+            }
+        };
+
+        download.status.write().await.peers.insert(peer.clone());
+
+        let mut fetched = 0;
+        while !download.is_cancelled() {
+            let Some(chunk_hash) = queue.lock().await.next_for(&peer) else { break };
+            debug!(target: "fud::chunk_worker()", "Requesting chunk {} from {}", chunk_hash, peer);
+
+            if let Err(e) = channel.send(&FudChunkRequest { chunk_hash }).await {
+                error!(target: "fud::chunk_worker()", "Failed sending request to {}: {}", peer, e);
+                queue.lock().await.failed(chunk_hash, &peer);
+                break
+            }
+
+            let reply = async { reply_sub.receive().await.map(Some) };
+            let not_found = async { not_found_sub.receive().await.map(|_| None) };
+
+            let reply = match timeout(REPLY_TIMEOUT, future::or(reply, not_found)).await {
+                Ok(Ok(Some(v))) => v,
+                Ok(Ok(None)) => {
+                    debug!(target: "fud::chunk_worker()", "{} does not have {}", peer, chunk_hash);
+                    queue.lock().await.failed(chunk_hash, &peer);
+                    continue
+                }
+                Ok(Err(e)) => {
+                    error!(target: "fud::chunk_worker()", "Failed receiving chunk: {}", e);
+                    queue.lock().await.failed(chunk_hash, &peer);
+                    break
+                }
+                Err(_) => {
+                    // A late reply could be mistaken for the next chunk,
+                    // so we stop using this peer.
+                    warn!(target: "fud::chunk_worker()", "Timed out waiting for {}", peer);
+                    queue.lock().await.failed(chunk_hash, &peer);
+                    break
+                }
+            };
+
+            // Verify the chunk before we write anything to disk
+            if blake3::hash(&reply.chunk) != chunk_hash {
+                warn!(
+                    target: "fud::chunk_worker()",
+                    "Received chunk from {} does not match requested chunk", peer,
+                );
+                queue.lock().await.failed(chunk_hash, &peer);
+                break
+            }
+
+            if let Err(e) = self.geode.insert_chunk(&reply.chunk).await {
+                error!(
+                    target: "fud::chunk_worker()",
+                    "Failed inserting chunk {} to Geode: {}", chunk_hash, e,
+                );
+                queue.lock().await.failed(chunk_hash, &peer);
+                break
+            }
+
+            fetched += 1;
+            download.status.write().await.chunks_done += 1;
+        }
+
+        reply_sub.unsubscribe().await;
+        not_found_sub.unsubscribe().await;
+        channel.stop().await;
+        download.status.write().await.peers.remove(&peer);
+
+        fetched
+    }
+}
+
+/// Background task that receives started downloads and runs them.
+pub async fn download_task(fud: Arc<Fud>) -> Result<()> {
+    info!(target: "fud::download_task()", "Started background download task");
+    loop {
+        let download = fud.download_rx.recv().await.unwrap();
+        fud.executor.spawn(fud.clone().run_download(download)).detach();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use darkfi::{
+        dht::Dht,
+        geode::Geode,
+        net::{P2p, Settings},
+    };
+    use smol::Executor;
+
+    /// Create a fud instance storing its data in the given temporary directory
+    async fn test_fud(name: &str, ex: Arc<Executor<'static>>) -> Result<Arc<Fud>> {
+        let base_path = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&base_path).await;
+        let geode = Geode::new(&base_path).await?;
+
+        let p2p = P2p::new(Settings::default(), ex.clone()).await;
+        let dht = Dht::new(p2p.clone(), ex.clone());
+
+        let downloads_path = base_path.join(DOWNLOADS_PATH);
+        fs::create_dir_all(&downloads_path).await?;
+
+        let (download_tx, download_rx) = smol::channel::unbounded();
+        Ok(Arc::new(Fud {
+            dht,
+            p2p,
+            geode,
+            downloads: RwLock::new(HashMap::new()),
+            downloads_path,
+            executor: ex,
+            storage_quota: None,
+            download_tx,
+            download_rx,
+            rpc_connections: Mutex::new(HashSet::new()),
+        }))
+    }
+
+    #[test]
+    fn chunk_queue() {
+        let a = blake3::hash(b"a");
+        let b = blake3::hash(b"b");
+        let peer_1 = Url::parse("tcp://127.0.0.1:1").unwrap();
+        let peer_2 = Url::parse("tcp://127.0.0.1:2").unwrap();
+        let mut queue = ChunkQueue { pending: VecDeque::from([a, b]), tried: HashMap::new() };
+
+        // Chunks a peer failed on go to the other peers
+        assert_eq!(queue.next_for(&peer_1), Some(a));
+        queue.failed(a, &peer_1);
+        assert_eq!(queue.next_for(&peer_1), Some(b));
+        assert_eq!(queue.next_for(&peer_1), None);
+        assert_eq!(queue.next_for(&peer_2), Some(a));
+        assert_eq!(queue.next_for(&peer_2), None);
+    }
+
+    #[test]
+    fn download_lifecycle() -> Result<()> {
+        let ex = Arc::new(Executor::new());
+
+        smol::block_on(async {
+            let fud = test_fud("darkfi_test_fud_downloads", ex.clone()).await?;
+            let file_hash = blake3::hash(b"file");
+            let record = fud.downloads_path.join(file_hash.to_hex().as_str());
+
+            // Downloads are recorded so they are resumed after a restart
+            let sub = fud.download(file_hash).await?;
+            assert!(record.exists());
+            let download = fud.download_rx.recv().await.unwrap();
+            assert_eq!(download.file_hash, file_hash);
+            assert_eq!(download.status().await.state, DownloadState::Metadata);
+
+            // Joining a running download does not start another one
+            fud.download(file_hash).await?.unsubscribe().await;
+            assert!(fud.download_rx.is_empty());
+
+            // Cancelling notifies the waiters and forgets the record
+            assert!(fud.cancel_download(&file_hash).await?);
+            assert_eq!(sub.receive().await, DownloadState::Cancelled);
+            sub.unsubscribe().await;
+            assert!(!record.exists());
+            assert!(!fud.cancel_download(&blake3::hash(b"none")).await?);
+
+            // Recorded downloads are resumed
+            File::create(&record).await?;
+            fud.downloads.write().await.clear();
+            fud.resume_downloads().await?;
+            assert_eq!(fud.download_rx.recv().await.unwrap().file_hash, file_hash);
+            assert!(fud.downloads.read().await.contains_key(&file_hash));
+
+            fud.dht.stop().await;
+            Ok(())
+        })
+    }
+}
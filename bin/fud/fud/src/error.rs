@@ -0,0 +1,57 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use darkfi::rpc::jsonrpc::{ErrorCode::ServerError, JsonError, JsonId, JsonResult};
+
+/// Custom RPC errors available for fud.
+/// Please sort them sensefully.
+pub enum RpcError {
+    // Download-related errors
+    UnknownDownload = -32110,
+    DownloadFailed = -32111,
+    DownloadCancelled = -32112,
+
+    // Storage-related errors
+    FileNotFound = -32120,
+    StorageCorrupted = -32121,
+}
+
+fn to_tuple(e: RpcError) -> (i32, String) {
+    let msg = match e {
+        // Download-related errors
+        RpcError::UnknownDownload => "No download found for given file",
+        RpcError::DownloadFailed => "Failed downloading file",
+        RpcError::DownloadCancelled => "Download was cancelled",
+
+        // Storage-related errors
+        RpcError::FileNotFound => "File not found in storage",
+        RpcError::StorageCorrupted => "File metadata is corrupted, storage needs cleanup",
+    };
+
+    (e as i32, msg.to_string())
+}
+
+pub fn server_error(e: RpcError, id: JsonId, msg: Option<&str>) -> JsonResult {
+    let (code, default_msg) = to_tuple(e);
+
+    if let Some(message) = msg {
+        return JsonError::new(ServerError(code), Some(message.to_string()), id).into()
+    }
+
+    JsonError::new(ServerError(code), Some(default_msg), id).into()
+}
@@ -18,14 +18,15 @@
 
 use std::{
     collections::{HashMap, HashSet},
+    path::PathBuf,
     sync::Arc,
 };
 
 use async_trait::async_trait;
-use log::{debug, error, info, warn};
+use log::{error, info};
 use smol::{
     channel,
-    fs::File,
+    fs::{self, File},
     lock::{Mutex, MutexGuard, RwLock},
     stream::StreamExt,
     Executor,
@@ -36,13 +37,13 @@ use url::Url;
 
 use darkfi::{
     async_daemonize, cli_desc,
-    geode::Geode,
-    net::{
-        self, connector::Connector, protocol::ProtocolVersion, session::Session,
-        settings::SettingsOpt, P2p, P2pPtr,
-    },
+    dht::{proto::ProtocolDht, Dht, DhtPtr, DHT_CAPABILITY},
+    geode::{ChunkedFile, Geode, ManifestEntry},
+    net::{self, settings::SettingsOpt, P2p, P2pPtr},
     rpc::{
-        jsonrpc::{ErrorCode, JsonError, JsonRequest, JsonResponse, JsonResult},
+        auth::MethodAccess,
+        jsonrpc::{ErrorCode, JsonError, JsonId, JsonRequest, JsonResponse, JsonResult},
+        registry::{JsonType, RpcMethod, RpcParam},
         server::{listen_and_serve, RequestHandler},
     },
     system::{StoppableTask, StoppableTaskPtr},
@@ -52,10 +53,15 @@ use darkfi::{
 
 /// P2P protocols
 mod proto;
-use proto::{
-    FudChunkPut, FudChunkReply, FudChunkRequest, FudFilePut, FudFileReply, FudFileRequest,
-    ProtocolFud,
-};
+use proto::ProtocolFud;
+
+/// Download manager
+mod download;
+use download::{download_task, DownloadPtr, DownloadState, DOWNLOADS_PATH};
+
+/// Error codes
+mod error;
+use error::{server_error, RpcError};
 
 const CONFIG_FILE: &str = "fud_config.toml";
 const CONFIG_FILE_CONTENTS: &str = include_str!("../fud_config.toml");
@@ -84,29 +90,87 @@ struct Args {
     /// Base directory for filesystem storage
     base_dir: String,
 
+    #[structopt(long)]
+    /// Storage quota in MiB, least recently used unpinned files are evicted above it
+    storage_quota: Option<u64>,
+
     #[structopt(flatten)]
     /// Network settings
     net: SettingsOpt,
 }
 
 pub struct Fud {
-    /// Routing table for file metadata
-    metadata_router: Arc<RwLock<HashMap<blake3::Hash, HashSet<Url>>>>,
-    /// Routing table for file chunks
-    chunks_router: Arc<RwLock<HashMap<blake3::Hash, HashSet<Url>>>>,
+    /// DHT used to find the providers of files
+    dht: DhtPtr,
     /// Pointer to the P2P network instance
     p2p: P2pPtr,
     /// The Geode instance
     geode: Geode,
-
-    file_fetch_tx: channel::Sender<(blake3::Hash, Result<()>)>,
-    file_fetch_rx: channel::Receiver<(blake3::Hash, Result<()>)>,
-    chunk_fetch_tx: channel::Sender<(blake3::Hash, Result<()>)>,
-    chunk_fetch_rx: channel::Receiver<(blake3::Hash, Result<()>)>,
+    /// Downloads started since we are running
+    downloads: RwLock<HashMap<blake3::Hash, DownloadPtr>>,
+    /// Path to the filesystem directory where pending downloads are recorded
+    downloads_path: PathBuf,
+    /// Executor used to spawn downloads
+    executor: Arc<Executor<'static>>,
+    /// Maximum amount of bytes stored in Geode, if any
+    storage_quota: Option<u64>,
+    /// Files in use by running requests, kept from quota eviction
+    retained: RwLock<HashMap<blake3::Hash, usize>>,
+
+    download_tx: channel::Sender<DownloadPtr>,
+    download_rx: channel::Receiver<DownloadPtr>,
 
     rpc_connections: Mutex<HashSet<StoppableTaskPtr>>,
 }
 
+/// Shorthand for declaring the methods of the registry. Every method
+/// manages local storage, so they are all admin only.
+const fn method(
+    name: &'static str,
+    description: &'static str,
+    params: &'static [RpcParam],
+    result: JsonType,
+) -> RpcMethod {
+    RpcMethod { name, description, params, result, access: MethodAccess::Admin }
+}
+
+const FILE_HASH: RpcParam =
+    RpcParam::required("file_hash", JsonType::String, "Hex-encoded file hash");
+
+/// Methods served by the fud JSON-RPC
+const METHODS: &[RpcMethod] = &[
+    method("ping", "Reply with pong", &[], JsonType::String),
+    method(
+        "put",
+        "Put a file, or a directory tree, onto the network, replying with its hash",
+        &[RpcParam::required("path", JsonType::String, "Local filesystem path")],
+        JsonType::String,
+    ),
+    method(
+        "get",
+        "Fetch a file, or a directory tree, from the network. Replies with the paths \
+         to its local chunks, or with the destination path if one was given",
+        &[
+            FILE_HASH,
+            RpcParam::optional("dest", JsonType::String, "Local filesystem path to write to"),
+        ],
+        JsonType::Any,
+    ),
+    method("status", "Progress of a download", &[FILE_HASH], JsonType::Object),
+    method("list_downloads", "Progress of all downloads", &[], JsonType::Array),
+    method("cancel", "Cancel a download", &[FILE_HASH], JsonType::Boolean),
+    method("list", "Files stored locally", &[], JsonType::Array),
+    method("pin", "Pin a stored file so it is never evicted", &[FILE_HASH], JsonType::Boolean),
+    method("unpin", "Unpin a stored file", &[FILE_HASH], JsonType::Boolean),
+    method("remove", "Remove a stored file and stop providing it", &[FILE_HASH], JsonType::Boolean),
+    method(
+        "dnet_switch",
+        "Activate or deactivate dnet in the P2P stack",
+        &[RpcParam::required("enable", JsonType::Boolean, "Whether dnet should be active")],
+        JsonType::Boolean,
+    ),
+];
+
 #[async_trait]
 impl RequestHandler for Fud {
     async fn handle_request(&self, req: JsonRequest) -> JsonResult {
@@ -115,12 +179,24 @@ impl RequestHandler for Fud {
 
             "put" => return self.put(req.id, req.params).await,
             "get" => return self.get(req.id, req.params).await,
+            "status" => return self.status(req.id, req.params).await,
+            "list_downloads" => return self.list_downloads(req.id, req.params).await,
+            "cancel" => return self.cancel(req.id, req.params).await,
+
+            "list" => return self.list(req.id, req.params).await,
+            "pin" => return self.pin(req.id, req.params).await,
+            "unpin" => return self.unpin(req.id, req.params).await,
+            "remove" => return self.remove(req.id, req.params).await,
 
             "dnet_switch" => return self.dnet_switch(req.id, req.params).await,
             _ => return JsonError::new(ErrorCode::MethodNotFound, None, req.id).into(),
         }
     }
 
+    fn methods(&self) -> &'static [RpcMethod] {
+        METHODS
+    }
+
     async fn connections_mut(&self) -> MutexGuard<'_, HashSet<StoppableTaskPtr>> {
         self.rpc_connections.lock().await
     }
@@ -129,11 +205,14 @@ impl RequestHandler for Fud {
 impl Fud {
     // RPCAPI:
     // Put a file onto the network. Takes a local filesystem path as a parameter.
-    // Returns the file hash that serves as a pointer to the uploaded file.
+    // If the path is a directory, all of its files are put along with a manifest
+    // describing the tree.
+    // Returns the file hash that serves as a pointer to the uploaded file, or
+    // the manifest hash in case of a directory.
     //
     // --> {"jsonrpc": "2.0", "method": "put", "params": ["/foo.txt"], "id": 42}
     // <-- {"jsonrpc": "2.0", "result: "df4...3db7", "id": 42}
-    async fn put(&self, id: u16, params: JsonValue) -> JsonResult {
+    async fn put(&self, id: JsonId, params: JsonValue) -> JsonResult {
         let params = params.get::<Vec<JsonValue>>().unwrap();
         if params.len() != 1 || !params[0].is_string() {
             return JsonError::new(ErrorCode::InvalidParams, None, id).into()
@@ -145,6 +224,32 @@ impl Fud {
             Err(_) => return JsonError::new(ErrorCode::InvalidParams, None, id).into(),
         };
 
+        // A directory is put as a manifest and the files it describes
+        if path.is_dir() {
+            let (manifest_hash, manifest) = match self.geode.insert_dir(&path).await {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("Failed inserting directory {:?} to geode: {}", path, e);
+                    return JsonError::new(ErrorCode::InternalError, None, id).into()
+                }
+            };
+
+            let mut file_hashes = vec![manifest_hash];
+            for entry in manifest.files() {
+                if let ManifestEntry::File { file_hash, .. } = entry {
+                    file_hashes.push(*file_hash);
+                }
+            }
+
+            for file_hash in &file_hashes {
+                self.dht.announce(file_hash).await;
+            }
+            self.enforce_quota(&file_hashes).await;
+
+            return JsonResponse::new(JsonValue::String(manifest_hash.to_hex().to_string()), id)
+                .into()
+        }
+
         // A valid path was passed. Let's see if we can read it, and if so,
         // add it to Geode.
         let fd = match File::open(&path).await {
@@ -155,7 +260,7 @@ impl Fud {
             }
         };
 
-        let (file_hash, chunk_hashes) = match self.geode.insert(fd).await {
+        let (file_hash, _) = match self.geode.insert(fd).await {
             Ok(v) => v,
             Err(e) => {
                 error!("Failed inserting file {:?} to geode: {}", path, e);
@@ -163,8 +268,8 @@ impl Fud {
             }
         };
 
-        let fud_file = FudFilePut { file_hash, chunk_hashes };
-        self.p2p.broadcast(&fud_file).await;
+        self.dht.announce(&file_hash).await;
+        self.enforce_quota(&[file_hash]).await;
 
         JsonResponse::new(JsonValue::String(file_hash.to_hex().to_string()), id).into()
     }
@@ -172,12 +277,22 @@ impl Fud {
     // RPCAPI:
     // Fetch a file from the network. Takes a file hash as parameter.
     // Returns the paths to the local chunks of the file, if found/fetched.
+    // Missing chunks are downloaded from several peers at once, and the
+    // download can be followed using `status`.
+    // If the file is a manifest, all the files of the tree it describes are
+    // fetched as well.
+    // Optionally takes a local filesystem path as second parameter, in which
+    // case the file, or the tree described by the manifest, is written there
+    // and the path is returned instead.
     //
     // --> {"jsonrpc": "2.0", "method": "get", "params": ["1211...abfd"], "id": 42}
     // <-- {"jsonrpc": "2.0", "result: ["~/.local/share/fud/chunks/fab1...2314", ...], "id": 42}
-    async fn get(&self, id: u16, params: JsonValue) -> JsonResult {
+    //
+    // --> {"jsonrpc": "2.0", "method": "get", "params": ["1211...abfd", "/tmp/foo"], "id": 42}
+    // <-- {"jsonrpc": "2.0", "result: "/tmp/foo", "id": 42}
+    async fn get(&self, id: JsonId, params: JsonValue) -> JsonResult {
         let params = params.get::<Vec<JsonValue>>().unwrap();
-        if params.len() != 1 || !params[0].is_string() {
+        if params.is_empty() || params.len() > 2 || !params.iter().all(|p| p.is_string()) {
             return JsonError::new(ErrorCode::InvalidParams, None, id).into()
         }
 
@@ -186,97 +301,308 @@ impl Fud {
             Err(_) => return JsonError::new(ErrorCode::InvalidParams, None, id).into(),
         };
 
-        let chunked_file = match self.geode.get(&file_hash).await {
+        let dest = match params.get(1) {
+            Some(path) => match expand_path(path.get::<String>().unwrap()) {
+                Ok(v) => Some(v),
+                Err(_) => return JsonError::new(ErrorCode::InvalidParams, None, id).into(),
+            },
+            None => None,
+        };
+
+        // Fetching the files of a manifest enforces the quota, so everything
+        // we fetch is kept until we are done with it.
+        let mut retained = vec![file_hash];
+        self.retain(&retained).await;
+        let result = self.get_retained(file_hash, dest, &mut retained, id).await;
+        self.release(&retained).await;
+
+        result
+    }
+
+    /// Body of `get`, run while the given file is retained. Files of a
+    /// manifest are retained as well and added to `retained`.
+    async fn get_retained(
+        &self,
+        file_hash: blake3::Hash,
+        dest: Option<PathBuf>,
+        retained: &mut Vec<blake3::Hash>,
+        id: JsonId,
+    ) -> JsonResult {
+        let chunked_file = match self.fetch_file(file_hash, &id).await {
             Ok(v) => v,
-            Err(Error::GeodeNeedsGc) => todo!(),
-            Err(Error::GeodeFileNotFound) => {
-                info!("Requested file {} not found in Geode, triggering fetch", file_hash);
-                self.file_fetch_tx.send((file_hash, Ok(()))).await.unwrap();
-                info!("Waiting for background file fetch task...");
-                let (i_file_hash, status) = self.file_fetch_rx.recv().await.unwrap();
-                match status {
-                    Ok(()) => {
-                        let ch_file = self.geode.get(&file_hash).await.unwrap();
-
-                        let m = FudFilePut {
-                            file_hash: i_file_hash,
-                            chunk_hashes: ch_file.iter().map(|(h, _)| *h).collect(),
-                        };
-
-                        self.p2p.broadcast(&m).await;
-
-                        ch_file
-                    }
+            Err(e) => return e,
+        };
 
-                    Err(Error::GeodeFileRouteNotFound) => {
-                        // TODO: Return FileNotFound error
-                        return JsonError::new(ErrorCode::InternalError, None, id).into()
-                    }
+        // If the file is a manifest, we also need the files it describes
+        let manifest = match self.geode.get_manifest(&file_hash).await {
+            Ok(v) => Some(v),
+            Err(Error::GeodeInvalidManifest) => None,
+            Err(e) => {
+                error!("Failed reading file {}: {}", file_hash, e);
+                return JsonError::new(ErrorCode::InternalError, None, id).into()
+            }
+        };
 
-                    Err(e) => panic!("{}", e),
+        if let Some(manifest) = &manifest {
+            if let Err(e) = self.geode.insert_manifest_files(manifest).await {
+                error!("Failed inserting files of manifest {}: {}", file_hash, e);
+                return JsonError::new(ErrorCode::InternalError, None, id).into()
+            }
+
+            let file_hashes: Vec<blake3::Hash> = manifest
+                .files()
+                .filter_map(|entry| match entry {
+                    ManifestEntry::File { file_hash, .. } => Some(*file_hash),
+                    _ => None,
+                })
+                .collect();
+            self.retain(&file_hashes).await;
+            retained.extend(file_hashes.iter().copied());
+
+            for file_hash in file_hashes {
+                if let Err(e) = self.fetch_file(file_hash, &id).await {
+                    return e
                 }
             }
+        }
 
-            Err(e) => panic!("{}", e),
+        if let Some(dest) = dest {
+            let result = match &manifest {
+                Some(manifest) => self.geode.extract(manifest, &dest).await,
+                None => self.geode.extract_file(&file_hash, &dest).await,
+            };
+
+            if let Err(e) = result {
+                error!("Failed writing {} to {:?}: {}", file_hash, dest, e);
+                return JsonError::new(ErrorCode::InternalError, None, id).into()
+            }
+
+            let dest = dest.into_os_string().into_string().unwrap();
+            return JsonResponse::new(JsonValue::String(dest), id).into()
+        }
+
+        let mut chunks = vec![];
+        for (_, path) in chunked_file.iter() {
+            let Some(path) = path else {
+                error!("Chunks of {} went missing after fetching it", file_hash);
+                return JsonError::new(ErrorCode::InternalError, None, id).into()
+            };
+            chunks.push(JsonValue::String(path.to_string_lossy().to_string()));
+        }
+
+        JsonResponse::new(JsonValue::Array(chunks), id).into()
+    }
+
+    // RPCAPI:
+    // Return the progress of a download. Takes a file hash as parameter.
+    // `state` is one of `metadata`, `chunks`, `done`, `failed` or `cancelled`,
+    // and `peers` are the peers chunks are currently being fetched from.
+    //
+    // --> {"jsonrpc": "2.0", "method": "status", "params": ["1211...abfd"], "id": 42}
+    // <-- {"jsonrpc": "2.0", "result": {"file_hash": "1211...abfd", "state": "chunks", "chunks_total": 12, "chunks_done": 5, "peers": ["tcp://..."]}, "id": 42}
+    async fn status(&self, id: JsonId, params: JsonValue) -> JsonResult {
+        let params = params.get::<Vec<JsonValue>>().unwrap();
+        if params.len() != 1 || !params[0].is_string() {
+            return JsonError::new(ErrorCode::InvalidParams, None, id).into()
+        }
+
+        let file_hash = match blake3::Hash::from_hex(params[0].get::<String>().unwrap()) {
+            Ok(v) => v,
+            Err(_) => return JsonError::new(ErrorCode::InvalidParams, None, id).into(),
         };
 
-        if chunked_file.is_complete() {
-            let chunks: Vec<JsonValue> = chunked_file
-                .iter()
-                .map(|(_, path)| {
-                    JsonValue::String(
-                        path.as_ref().unwrap().clone().into_os_string().into_string().unwrap(),
-                    )
-                })
-                .collect();
+        let Some(download) = self.downloads.read().await.get(&file_hash).cloned() else {
+            return server_error(RpcError::UnknownDownload, id, None)
+        };
 
-            return JsonResponse::new(JsonValue::Array(chunks), id).into()
+        JsonResponse::new(download.to_json().await, id).into()
+    }
+
+    // RPCAPI:
+    // Return the progress of all downloads started since the daemon is
+    // running, in the same format as `status`.
+    //
+    // --> {"jsonrpc": "2.0", "method": "list_downloads", "params": [], "id": 42}
+    // <-- {"jsonrpc": "2.0", "result": [{"file_hash": "1211...abfd", "state": "done", ...}, ...], "id": 42}
+    async fn list_downloads(&self, id: JsonId, params: JsonValue) -> JsonResult {
+        let params = params.get::<Vec<JsonValue>>().unwrap();
+        if !params.is_empty() {
+            return JsonError::new(ErrorCode::InvalidParams, None, id).into()
+        }
+
+        let downloads: Vec<DownloadPtr> = self.downloads.read().await.values().cloned().collect();
+        let mut ret = vec![];
+        for download in downloads {
+            ret.push(download.to_json().await);
+        }
+
+        JsonResponse::new(JsonValue::Array(ret), id).into()
+    }
+
+    // RPCAPI:
+    // Cancel a download. Takes a file hash as parameter. Chunks fetched
+    // so far are kept. Returns `true` on success.
+    //
+    // --> {"jsonrpc": "2.0", "method": "cancel", "params": ["1211...abfd"], "id": 42}
+    // <-- {"jsonrpc": "2.0", "result": true, "id": 42}
+    async fn cancel(&self, id: JsonId, params: JsonValue) -> JsonResult {
+        let params = params.get::<Vec<JsonValue>>().unwrap();
+        if params.len() != 1 || !params[0].is_string() {
+            return JsonError::new(ErrorCode::InvalidParams, None, id).into()
         }
 
-        // Fetch any missing chunks
-        let mut missing_chunks = vec![];
-        for (chunk, path) in chunked_file.iter() {
-            if path.is_none() {
-                missing_chunks.push(*chunk);
+        let file_hash = match blake3::Hash::from_hex(params[0].get::<String>().unwrap()) {
+            Ok(v) => v,
+            Err(_) => return JsonError::new(ErrorCode::InvalidParams, None, id).into(),
+        };
+
+        match self.cancel_download(&file_hash).await {
+            Ok(true) => JsonResponse::new(JsonValue::Boolean(true), id).into(),
+            Ok(false) => server_error(RpcError::UnknownDownload, id, None),
+            Err(e) => {
+                error!("Failed cancelling download of {}: {}", file_hash, e);
+                JsonError::new(ErrorCode::InternalError, None, id).into()
             }
         }
+    }
 
-        for chunk in missing_chunks {
-            self.chunk_fetch_tx.send((chunk, Ok(()))).await.unwrap();
-            let (i_chunk_hash, status) = self.chunk_fetch_rx.recv().await.unwrap();
+    // RPCAPI:
+    // List the files stored locally. `chunks_done` is the number of chunks
+    // available locally and `size` the amount of bytes they take.
+    //
+    // --> {"jsonrpc": "2.0", "method": "list", "params": [], "id": 42}
+    // <-- {"jsonrpc": "2.0", "result": [{"file_hash": "1211...abfd", "chunks_total": 12, "chunks_done": 12, "size": 3145728, "pinned": true}, ...], "id": 42}
+    async fn list(&self, id: JsonId, params: JsonValue) -> JsonResult {
+        let params = params.get::<Vec<JsonValue>>().unwrap();
+        if !params.is_empty() {
+            return JsonError::new(ErrorCode::InvalidParams, None, id).into()
+        }
 
-            match status {
-                Ok(()) => {
-                    let m = FudChunkPut { chunk_hash: i_chunk_hash };
-                    self.p2p.broadcast(&m).await;
-                    break
+        let file_hashes = match self.geode.list_files().await {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Failed listing stored files: {}", e);
+                return JsonError::new(ErrorCode::InternalError, None, id).into()
+            }
+        };
+
+        let mut ret = vec![];
+        for file_hash in file_hashes {
+            let Ok(chunked_file) = self.geode.inspect(&file_hash).await else { continue };
+
+            let mut chunks_done = 0;
+            let mut size = 0;
+            for (_, path) in chunked_file.iter() {
+                let Some(path) = path else { continue };
+                chunks_done += 1;
+                if let Ok(metadata) = fs::metadata(path).await {
+                    size += metadata.len();
                 }
-                Err(Error::GeodeChunkRouteNotFound) => continue,
+            }
 
-                Err(e) => panic!("{}", e),
+            ret.push(JsonValue::Object(HashMap::from([
+                ("file_hash".to_string(), JsonValue::String(file_hash.to_hex().to_string())),
+                ("chunks_total".to_string(), JsonValue::Number(chunked_file.iter().len() as f64)),
+                ("chunks_done".to_string(), JsonValue::Number(chunks_done as f64)),
+                ("size".to_string(), JsonValue::Number(size as f64)),
+                ("pinned".to_string(), JsonValue::Boolean(self.geode.is_pinned(&file_hash))),
+            ])));
+        }
+
+        JsonResponse::new(JsonValue::Array(ret), id).into()
+    }
+
+    // RPCAPI:
+    // Pin a stored file, so it is never evicted to satisfy the storage quota.
+    // Takes a file hash as parameter. Returns `true` on success.
+    //
+    // --> {"jsonrpc": "2.0", "method": "pin", "params": ["1211...abfd"], "id": 42}
+    // <-- {"jsonrpc": "2.0", "result": true, "id": 42}
+    async fn pin(&self, id: JsonId, params: JsonValue) -> JsonResult {
+        let params = params.get::<Vec<JsonValue>>().unwrap();
+        if params.len() != 1 || !params[0].is_string() {
+            return JsonError::new(ErrorCode::InvalidParams, None, id).into()
+        }
+
+        let file_hash = match blake3::Hash::from_hex(params[0].get::<String>().unwrap()) {
+            Ok(v) => v,
+            Err(_) => return JsonError::new(ErrorCode::InvalidParams, None, id).into(),
+        };
+
+        match self.geode.pin(&file_hash).await {
+            Ok(()) => JsonResponse::new(JsonValue::Boolean(true), id).into(),
+            Err(Error::GeodeFileNotFound) => server_error(RpcError::FileNotFound, id, None),
+            Err(e) => {
+                error!("Failed pinning {}: {}", file_hash, e);
+                JsonError::new(ErrorCode::InternalError, None, id).into()
             }
         }
+    }
 
-        let chunked_file = match self.geode.get(&file_hash).await {
+    // RPCAPI:
+    // Unpin a stored file. Takes a file hash as parameter.
+    // Returns `true` on success.
+    //
+    // --> {"jsonrpc": "2.0", "method": "unpin", "params": ["1211...abfd"], "id": 42}
+    // <-- {"jsonrpc": "2.0", "result": true, "id": 42}
+    async fn unpin(&self, id: JsonId, params: JsonValue) -> JsonResult {
+        let params = params.get::<Vec<JsonValue>>().unwrap();
+        if params.len() != 1 || !params[0].is_string() {
+            return JsonError::new(ErrorCode::InvalidParams, None, id).into()
+        }
+
+        let file_hash = match blake3::Hash::from_hex(params[0].get::<String>().unwrap()) {
             Ok(v) => v,
-            Err(e) => panic!("{}", e),
+            Err(_) => return JsonError::new(ErrorCode::InvalidParams, None, id).into(),
         };
 
-        if !chunked_file.is_complete() {
-            todo!();
-            // Return JsonError missing chunks
+        if let Err(e) = self.geode.unpin(&file_hash).await {
+            error!("Failed unpinning {}: {}", file_hash, e);
+            return JsonError::new(ErrorCode::InternalError, None, id).into()
         }
 
-        let chunks: Vec<JsonValue> = chunked_file
-            .iter()
-            .map(|(_, path)| {
-                JsonValue::String(
-                    path.as_ref().unwrap().clone().into_os_string().into_string().unwrap(),
-                )
-            })
-            .collect();
+        // The quota might have been waiting for this
+        self.enforce_quota(&[]).await;
 
-        JsonResponse::new(JsonValue::Array(chunks), id).into()
+        JsonResponse::new(JsonValue::Boolean(true), id).into()
+    }
+
+    // RPCAPI:
+    // Remove a stored file, even if it is pinned, and stop providing it.
+    // A running download of the file is cancelled. Chunks shared with other
+    // stored files are kept. Takes a file hash as parameter.
+    // Returns `true` on success.
+    //
+    // --> {"jsonrpc": "2.0", "method": "remove", "params": ["1211...abfd"], "id": 42}
+    // <-- {"jsonrpc": "2.0", "result": true, "id": 42}
+    async fn remove(&self, id: JsonId, params: JsonValue) -> JsonResult {
+        let params = params.get::<Vec<JsonValue>>().unwrap();
+        if params.len() != 1 || !params[0].is_string() {
+            return JsonError::new(ErrorCode::InvalidParams, None, id).into()
+        }
+
+        let file_hash = match blake3::Hash::from_hex(params[0].get::<String>().unwrap()) {
+            Ok(v) => v,
+            Err(_) => return JsonError::new(ErrorCode::InvalidParams, None, id).into(),
+        };
+
+        if let Err(e) = self.cancel_download(&file_hash).await {
+            error!("Failed cancelling download of {}: {}", file_hash, e);
+            return JsonError::new(ErrorCode::InternalError, None, id).into()
+        }
+
+        match self.geode.remove(&file_hash).await {
+            Ok(_) => {}
+            Err(Error::GeodeFileNotFound) => return server_error(RpcError::FileNotFound, id, None),
+            Err(e) => {
+                error!("Failed removing {}: {}", file_hash, e);
+                return JsonError::new(ErrorCode::InternalError, None, id).into()
+            }
+        }
+
+        self.dht.withdraw(&file_hash).await;
+
+        JsonResponse::new(JsonValue::Boolean(true), id).into()
     }
 
     // RPCAPI:
@@ -286,7 +612,7 @@ impl Fud {
     //
     // --> {"jsonrpc": "2.0", "method": "dnet_switch", "params": [true], "id": 42}
     // <-- {"jsonrpc": "2.0", "result": true, "id": 42}
-    async fn dnet_switch(&self, id: u16, params: JsonValue) -> JsonResult {
+    async fn dnet_switch(&self, id: JsonId, params: JsonValue) -> JsonResult {
         let params = params.get::<Vec<JsonValue>>().unwrap();
         if params.len() != 1 || !params[0].is_bool() {
             return JsonError::new(ErrorCode::InvalidParams, None, id).into()
@@ -304,226 +630,104 @@ impl Fud {
     }
 }
 
-/// Background task that receives file fetch requests and tries to
-/// fetch objects from the network using the routing table.
-/// TODO: This can be optimised a lot for connection reuse, etc.
-async fn fetch_file_task(fud: Arc<Fud>, executor: Arc<Executor<'_>>) -> Result<()> {
-    info!("Started background file fetch task");
-    loop {
-        let (file_hash, _) = fud.file_fetch_rx.recv().await.unwrap();
-        info!("fetch_file_task: Received {}", file_hash);
-
-        let mut metadata_router = fud.metadata_router.write().await;
-        let peers = metadata_router.get_mut(&file_hash);
-
-        if peers.is_none() {
-            warn!("File {} not in routing table, cannot fetch", file_hash);
-            fud.file_fetch_tx.send((file_hash, Err(Error::GeodeFileRouteNotFound))).await.unwrap();
-            continue
-        }
-
-        let mut found = false;
-        let peers = peers.unwrap();
-        let mut invalid_file_routes = vec![];
-
-        for peer in peers.iter() {
-            let session_out = fud.p2p.session_outbound();
-            let session_weak = Arc::downgrade(&fud.p2p.session_outbound());
-
-            info!("Connecting to {} to fetch {}", peer, file_hash);
-            let connector = Connector::new(fud.p2p.settings(), session_weak);
-            match connector.connect(peer).await {
-                Ok((url, channel)) => {
-                    let proto_ver = ProtocolVersion::new(
-                        channel.clone(),
-                        fud.p2p.settings().clone(),
-                        fud.p2p.hosts().clone(),
-                    )
-                    .await;
-
-                    let handshake_task = session_out.perform_handshake_protocols(
-                        proto_ver,
-                        channel.clone(),
-                        executor.clone(),
-                    );
-
-                    channel.clone().start(executor.clone());
-
-                    if let Err(e) = handshake_task.await {
-                        error!("Handshake with {} failed: {}", url, e);
-                        // Delete peer from router
-                        invalid_file_routes.push(peer.clone());
-                        continue
+impl Fud {
+    /// Return a complete file from Geode, downloading it first if needed.
+    /// On failure, returns the JSON-RPC error to reply with.
+    async fn fetch_file(
+        &self,
+        file_hash: blake3::Hash,
+        id: &JsonId,
+    ) -> std::result::Result<ChunkedFile, JsonResult> {
+        match self.geode.get(&file_hash).await {
+            Ok(v) if v.is_complete() => Ok(v),
+            Ok(_) | Err(Error::GeodeFileNotFound) => {
+                let sub = match self.download(file_hash).await {
+                    Ok(v) => v,
+                    Err(e) => {
+                        error!("Failed starting download of {}: {}", file_hash, e);
+                        return Err(JsonError::new(ErrorCode::InternalError, None, id.clone()).into())
                     }
+                };
 
-                    let msg_subscriber = channel.subscribe_msg::<FudFileReply>().await.unwrap();
-                    let request = FudFileRequest { file_hash };
+                info!("Waiting for download of {}...", file_hash);
+                let state = sub.receive().await;
+                sub.unsubscribe().await;
 
-                    if let Err(e) = channel.send(&request).await {
-                        error!("Failed sending FudFileRequest({}) to {}: {}", file_hash, url, e);
-                        continue
+                match state {
+                    DownloadState::Done => {}
+                    DownloadState::Cancelled => {
+                        return Err(server_error(RpcError::DownloadCancelled, id.clone(), None))
                     }
+                    _ => return Err(server_error(RpcError::DownloadFailed, id.clone(), None)),
+                }
 
-                    // TODO: With timeout!
-                    let reply = match msg_subscriber.receive().await {
-                        Ok(v) => v,
-                        Err(e) => {
-                            error!("Error receiving FudFileReply from subscriber: {}", e);
-                            continue
-                        }
-                    };
-
-                    msg_subscriber.unsubscribe().await;
-                    channel.stop().await;
-
-                    if let Err(e) = fud.geode.insert_file(&file_hash, &reply.chunk_hashes).await {
-                        error!("Failed inserting file {} to Geode: {}", file_hash, e);
-                        continue
+                match self.geode.get(&file_hash).await {
+                    Ok(v) => Ok(v),
+                    Err(e) => {
+                        error!("Failed reading downloaded file {}: {}", file_hash, e);
+                        Err(JsonError::new(ErrorCode::InternalError, None, id.clone()).into())
                     }
-
-                    found = true;
-                    break
                 }
+            }
 
-                Err(e) => {
-                    error!("Failed to connect to {}: {}", peer, e);
-                    continue
-                }
+            Err(Error::GeodeNeedsGc) => {
+                error!("Metadata of {} is corrupted, Geode needs garbage collection", file_hash);
+                Err(server_error(RpcError::StorageCorrupted, id.clone(), None))
             }
-        }
 
-        for peer in invalid_file_routes {
-            debug!("Removing peer {} from {} file router", peer, file_hash);
-            peers.remove(&peer);
+            Err(e) => {
+                error!("Failed reading file {} from Geode: {}", file_hash, e);
+                Err(JsonError::new(ErrorCode::InternalError, None, id.clone()).into())
+            }
         }
+    }
 
-        if !found {
-            warn!("Did not manage to fetch {} file metadata", file_hash);
-            fud.file_fetch_tx.send((file_hash, Err(Error::GeodeFileRouteNotFound))).await.unwrap();
-            continue
+    /// Keep the given files from quota eviction until they are released
+    async fn retain(&self, file_hashes: &[blake3::Hash]) {
+        let mut retained = self.retained.write().await;
+        for file_hash in file_hashes {
+            *retained.entry(*file_hash).or_default() += 1;
         }
-
-        info!("Successfully fetched {} file metadata", file_hash);
-        fud.file_fetch_tx.send((file_hash, Ok(()))).await.unwrap();
     }
-}
 
-/// Background task that receives chunk fetch requests and tries to
-/// fetch objects from the network using the routing table.
-/// TODO: This can be optimised a lot for connection reuse, etc.
-async fn fetch_chunk_task(fud: Arc<Fud>, executor: Arc<Executor<'_>>) -> Result<()> {
-    info!("Started background chunk fetch task");
-    loop {
-        let (chunk_hash, _) = fud.chunk_fetch_rx.recv().await.unwrap();
-        info!("fetch_chunk_task: Received {}", chunk_hash);
-
-        let mut chunk_router = fud.chunks_router.write().await;
-        let peers = chunk_router.get_mut(&chunk_hash);
-
-        if peers.is_none() {
-            warn!("Chunk {} not in routing table, cannot fetch", chunk_hash);
-            fud.chunk_fetch_tx
-                .send((chunk_hash, Err(Error::GeodeChunkRouteNotFound)))
-                .await
-                .unwrap();
-            continue
+    /// Release files previously passed to [`Fud::retain`]
+    async fn release(&self, file_hashes: &[blake3::Hash]) {
+        let mut retained = self.retained.write().await;
+        for file_hash in file_hashes {
+            let Some(count) = retained.get_mut(file_hash) else { continue };
+            *count -= 1;
+            if *count == 0 {
+                retained.remove(file_hash);
+            }
         }
+    }
 
-        let mut found = false;
-        let peers = peers.unwrap();
-        let mut invalid_chunk_routes = vec![];
-
-        for peer in peers.iter() {
-            let session_out = fud.p2p.session_outbound();
-            let session_weak = Arc::downgrade(&fud.p2p.session_outbound());
-
-            info!("Connecting to {} to fetch {}", peer, chunk_hash);
-            let connector = Connector::new(fud.p2p.settings(), session_weak);
-            match connector.connect(peer).await {
-                Ok((url, channel)) => {
-                    let proto_ver = ProtocolVersion::new(
-                        channel.clone(),
-                        fud.p2p.settings().clone(),
-                        fud.p2p.hosts().clone(),
-                    )
-                    .await;
-
-                    let handshake_task = session_out.perform_handshake_protocols(
-                        proto_ver,
-                        channel.clone(),
-                        executor.clone(),
-                    );
-
-                    channel.clone().start(executor.clone());
-
-                    if let Err(e) = handshake_task.await {
-                        error!("Handshake with {} failed: {}", url, e);
-                        // Delete peer from router
-                        invalid_chunk_routes.push(peer.clone());
-                        continue
-                    }
-
-                    let msg_subscriber = channel.subscribe_msg::<FudChunkReply>().await.unwrap();
-                    let request = FudChunkRequest { chunk_hash };
-
-                    if let Err(e) = channel.send(&request).await {
-                        error!("Failed sending FudChunkRequest({}) to {}: {}", chunk_hash, url, e);
-                        continue
-                    }
-
-                    // TODO: With timeout!
-                    let reply = match msg_subscriber.receive().await {
-                        Ok(v) => v,
-                        Err(e) => {
-                            error!("Error receiving FudChunkReply from subscriber: {}", e);
-                            continue
-                        }
-                    };
-
-                    msg_subscriber.unsubscribe().await;
-                    channel.stop().await;
-
-                    match fud.geode.insert_chunk(&reply.chunk).await {
-                        Ok(inserted_hash) => {
-                            if inserted_hash != chunk_hash {
-                                warn!("Received chunk does not match requested chunk");
-                                invalid_chunk_routes.push(peer.clone());
-                                continue
-                            }
-                        }
-                        Err(e) => {
-                            error!("Failed inserting chunk {} to Geode: {}", chunk_hash, e);
-                            continue
-                        }
-                    }
-
-                    found = true;
-                    break
-                }
-
-                Err(e) => {
-                    error!("Failed to connect to {}: {}", peer, e);
-                    continue
-                }
+    /// Evict the least recently used unpinned files if the storage quota is
+    /// exceeded, and stop providing them. Files being downloaded, retained
+    /// files, as well as the given files, are kept.
+    async fn enforce_quota(&self, file_hashes: &[blake3::Hash]) {
+        let Some(quota) = self.storage_quota else { return };
+
+        let mut keep: HashSet<blake3::Hash> = file_hashes.iter().copied().collect();
+        keep.extend(self.retained.read().await.keys().copied());
+        for (hash, download) in self.downloads.read().await.iter() {
+            if download.status().await.state.is_active() {
+                keep.insert(*hash);
             }
         }
 
-        for peer in invalid_chunk_routes {
-            debug!("Removing peer {} from {} chunk router", peer, chunk_hash);
-            peers.remove(&peer);
-        }
+        let evicted = match self.geode.enforce_quota(quota, &keep).await {
+            Ok(v) => v,
+            Err(e) => {
+                error!(target: "fud", "Failed enforcing storage quota: {}", e);
+                return
+            }
+        };
 
-        if !found {
-            warn!("Did not manage to fetch {} chunk", chunk_hash);
-            fud.chunk_fetch_tx
-                .send((chunk_hash, Err(Error::GeodeChunkRouteNotFound)))
-                .await
-                .unwrap();
-            continue
+        for file_hash in evicted {
+            info!(target: "fud", "Evicted {} to satisfy the storage quota", file_hash);
+            self.dht.withdraw(&file_hash).await;
         }
-
-        info!("Successfully fetched {} chunk", chunk_hash);
-        fud.chunk_fetch_tx.send((chunk_hash, Ok(()))).await.unwrap();
     }
 }
 
@@ -532,53 +736,42 @@ async fn realmain(args: Args, ex: Arc<Executor<'static>>) -> Result<()> {
     // The working directory for this daemon and geode.
     let basedir = expand_path(&args.base_dir)?;
 
-    // Hashmaps used for routing
-    let metadata_router = Arc::new(RwLock::new(HashMap::new()));
-    let chunks_router = Arc::new(RwLock::new(HashMap::new()));
-
     info!("Instantiating Geode instance");
     let geode = Geode::new(&basedir).await?;
 
     info!("Instantiating P2P network");
     let p2p = P2p::new(args.net.into(), ex.clone()).await;
 
+    info!("Instantiating DHT");
+    let dht = Dht::new_with_datastore(p2p.clone(), &basedir, ex.clone())?;
+
+    let downloads_path = basedir.join(DOWNLOADS_PATH);
+    fs::create_dir_all(&downloads_path).await?;
+
     // Daemon instantiation
-    let (file_fetch_tx, file_fetch_rx) = smol::channel::unbounded();
-    let (chunk_fetch_tx, chunk_fetch_rx) = smol::channel::unbounded();
+    let (download_tx, download_rx) = smol::channel::unbounded();
     let fud = Arc::new(Fud {
-        metadata_router,
-        chunks_router,
+        dht: dht.clone(),
         p2p: p2p.clone(),
         geode,
-        file_fetch_tx,
-        file_fetch_rx,
-        chunk_fetch_tx,
-        chunk_fetch_rx,
+        downloads: RwLock::new(HashMap::new()),
+        downloads_path,
+        executor: ex.clone(),
+        storage_quota: args.storage_quota.map(|v| v * 1024 * 1024),
+        retained: RwLock::new(HashMap::new()),
+        download_tx,
+        download_rx,
         rpc_connections: Mutex::new(HashSet::new()),
     });
 
-    info!(target: "fud", "Starting fetch file task");
-    let file_task = StoppableTask::new();
-    file_task.clone().start(
-        fetch_file_task(fud.clone(), ex.clone()),
+    info!(target: "fud", "Starting download task");
+    let downloads_task = StoppableTask::new();
+    downloads_task.clone().start(
+        download_task(fud.clone()),
         |res| async {
             match res {
                 Ok(()) | Err(Error::DetachedTaskStopped) => { /* Do nothing */ }
-                Err(e) => error!(target: "fud", "Failed starting fetch file task: {}", e),
-            }
-        },
-        Error::DetachedTaskStopped,
-        ex.clone(),
-    );
-
-    info!(target: "fud", "Starting fetch chunk task");
-    let chunk_task = StoppableTask::new();
-    chunk_task.clone().start(
-        fetch_chunk_task(fud.clone(), ex.clone()),
-        |res| async {
-            match res {
-                Ok(()) | Err(Error::DetachedTaskStopped) => { /* Do nothing */ }
-                Err(e) => error!(target: "fud", "Failed starting fetch chunk task: {}", e),
+                Err(e) => error!(target: "fud", "Failed starting download task: {}", e),
             }
         },
         Error::DetachedTaskStopped,
@@ -604,27 +797,48 @@ async fn realmain(args: Args, ex: Arc<Executor<'static>>) -> Result<()> {
     let registry = p2p.protocol_registry();
     let fud_ = fud.clone();
     registry
-        .register(net::SESSION_ALL, move |channel, p2p| {
+        .register(net::SESSION_ALL, move |channel, _| {
             let fud_ = fud_.clone();
-            async move { ProtocolFud::init(fud_, channel, p2p).await.unwrap() }
+            async move { ProtocolFud::init(fud_, channel).await.unwrap() }
+        })
+        .await;
+    let dht_ = dht.clone();
+    registry
+        .register_with_capability(net::SESSION_ALL, DHT_CAPABILITY, move |channel, _| {
+            let dht_ = dht_.clone();
+            async move { ProtocolDht::init(dht_, channel).await.unwrap() }
         })
         .await;
     p2p.clone().start().await?;
 
+    fud.enforce_quota(&[]).await;
+
+    info!(target: "fud", "Announcing stored files");
+    for file_hash in fud.geode.list_files().await? {
+        if let Ok(chunked_file) = fud.geode.inspect(&file_hash).await {
+            if chunked_file.is_complete() {
+                dht.announce(&file_hash).await;
+            }
+        }
+    }
+
+    info!(target: "fud", "Resuming pending downloads");
+    fud.resume_downloads().await?;
+
     // Signal handling for graceful termination.
     let (signals_handler, signals_task) = SignalHandler::new(ex)?;
     signals_handler.wait_termination(signals_task).await?;
     info!("Caught termination signal, cleaning up and exiting...");
 
-    info!(target: "fud", "Stopping fetch file task...");
-    file_task.stop().await;
-
-    info!(target: "fud", "Stopping fetch chunk task...");
-    chunk_task.stop().await;
+    info!(target: "fud", "Stopping download task...");
+    downloads_task.stop().await;
 
     info!(target: "fud", "Stopping JSON-RPC server...");
     rpc_task.stop().await;
 
+    info!(target: "fud", "Stopping DHT...");
+    dht.stop().await;
+
     info!("Stopping P2P network");
     p2p.stop().await;
 
@@ -24,10 +24,12 @@ use smol::lock::{Mutex, MutexGuard};
 use tinyjson::JsonValue;
 
 use darkfi::{
-    event_graph::{proto::EventPut, Event, EventGraphPtr},
+    event_graph::{Event, EventGraphPtr},
     net,
     rpc::{
-        jsonrpc::{ErrorCode, JsonError, JsonRequest, JsonResponse, JsonResult},
+        auth::MethodAccess,
+        jsonrpc::{ErrorCode, JsonError, JsonId, JsonRequest, JsonResponse, JsonResult},
+        registry::{JsonType, RpcMethod, RpcParam},
         server::RequestHandler,
     },
     system::StoppableTaskPtr,
@@ -43,6 +45,42 @@ pub struct JsonRpcInterface {
     rpc_connections: Mutex<HashSet<StoppableTaskPtr>>,
 }
 
+/// Methods served by the genevd JSON-RPC
+const METHODS: &[RpcMethod] = &[
+    RpcMethod {
+        name: "add",
+        description: "Add a new event and broadcast it",
+        params: &[RpcParam::required(
+            "event",
+            JsonType::String,
+            "Serialized `GenEvent`, base64-encoded",
+        )],
+        result: JsonType::Boolean,
+        access: MethodAccess::Admin,
+    },
+    RpcMethod {
+        name: "list",
+        description: "Known events, serialized and base64-encoded",
+        params: &[],
+        result: JsonType::String,
+        access: MethodAccess::Admin,
+    },
+    RpcMethod {
+        name: "ping",
+        description: "Reply with pong",
+        params: &[],
+        result: JsonType::String,
+        access: MethodAccess::Admin,
+    },
+    RpcMethod {
+        name: "dnet_switch",
+        description: "Activate or deactivate dnet in the P2P stack",
+        params: &[RpcParam::required("enable", JsonType::Boolean, "Whether dnet should be active")],
+        result: JsonType::Boolean,
+        access: MethodAccess::Admin,
+    },
+];
+
 #[async_trait]
 impl RequestHandler for JsonRpcInterface {
     async fn handle_request(&self, req: JsonRequest) -> JsonResult {
@@ -56,6 +94,10 @@ impl RequestHandler for JsonRpcInterface {
         }
     }
 
+    fn methods(&self) -> &'static [RpcMethod] {
+        METHODS
+    }
+
     async fn connections_mut(&self) -> MutexGuard<'_, HashSet<StoppableTaskPtr>> {
         self.rpc_connections.lock().await
     }
@@ -73,7 +115,7 @@ impl JsonRpcInterface {
     //
     // --> {"jsonrpc": "2.0", "method": "dnet_switch", "params": [true], "id": 42}
     // <-- {"jsonrpc": "2.0", "result": true, "id": 42}
-    async fn dnet_switch(&self, id: u16, params: JsonValue) -> JsonResult {
+    async fn dnet_switch(&self, id: JsonId, params: JsonValue) -> JsonResult {
         let params = params.get::<Vec<JsonValue>>().unwrap();
         if params.len() != 1 || !params[0].is_bool() {
             return JsonError::new(ErrorCode::InvalidParams, None, id).into()
@@ -94,7 +136,7 @@ impl JsonRpcInterface {
     // Add a new event
     // --> {"jsonrpc": "2.0", "method": "add", "params": [], "id": 1}
     // <-- {"jsonrpc": "2.0", "result": [nickname, ...], "id": 1}
-    async fn add(&self, id: u16, params: JsonValue) -> JsonResult {
+    async fn add(&self, id: JsonId, params: JsonValue) -> JsonResult {
         let params = params.get::<Vec<JsonValue>>().unwrap();
         if params.len() != 1 || !params[0].is_string() {
             return JsonError::new(ErrorCode::InvalidParams, None, id).into()
@@ -111,7 +153,7 @@ impl JsonRpcInterface {
             error!("Failed inserting new event to DAG: {}", e);
         } else {
             // Otherwise, broadcast it
-            self.p2p.broadcast(&EventPut(event)).await;
+            self.event_graph.broadcast(&event, &[]).await;
         }
 
         let json = JsonValue::Boolean(true);
@@ -122,7 +164,7 @@ impl JsonRpcInterface {
     // List events
     // --> {"jsonrpc": "2.0", "method": "list", "params": [], "id": 1}
     // <-- {"jsonrpc": "2.0", "result": [task_id, ...], "id": 1}
-    async fn list(&self, id: u16, _params: JsonValue) -> JsonResult {
+    async fn list(&self, id: JsonId, _params: JsonValue) -> JsonResult {
         debug!("Fetching all events");
         let mut seen_events = vec![];
         let dag_events = self.event_graph.order_events().await;
@@ -16,7 +16,7 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use darkfi::rpc::jsonrpc::{ErrorCode, JsonError, JsonResponse, JsonResult};
+use darkfi::rpc::jsonrpc::{ErrorCode, JsonError, JsonId, JsonResponse, JsonResult};
 use tinyjson::JsonValue;
 
 #[derive(Debug, thiserror::Error)]
@@ -53,7 +53,7 @@ impl From<std::io::Error> for TaudError {
     }
 }
 
-pub fn to_json_result(res: TaudResult<JsonValue>, id: u16) -> JsonResult {
+pub fn to_json_result(res: TaudResult<JsonValue>, id: JsonId) -> JsonResult {
     match res {
         Ok(v) => JsonResponse::new(v, id).into(),
         Err(err) => match err {
@@ -32,8 +32,10 @@ use tinyjson::JsonValue;
 use darkfi::{
     net,
     rpc::{
-        jsonrpc::{ErrorCode, JsonError, JsonRequest, JsonResult, JsonSubscriber},
+        auth::MethodAccess,
+        jsonrpc::{ErrorCode, JsonError, JsonId, JsonRequest, JsonResult, JsonSubscriber},
         p2p_method::HandlerP2p,
+        registry::{JsonType, RpcMethod, RpcParam},
         server::RequestHandler,
     },
     system::StoppableTaskPtr,
@@ -59,6 +61,113 @@ pub struct JsonRpcInterface {
     rpc_connections: Mutex<HashSet<StoppableTaskPtr>>,
 }
 
+/// Shorthand for declaring the methods of the registry. taud is only
+/// meant to be used by its local owner, so all methods are admin only.
+const fn method(
+    name: &'static str,
+    description: &'static str,
+    params: &'static [RpcParam],
+    result: JsonType,
+) -> RpcMethod {
+    RpcMethod { name, description, params, result, access: MethodAccess::Admin }
+}
+
+/// Task reference ID parameter
+const REF_ID: RpcParam = RpcParam::required("ref_id", JsonType::String, "Task reference ID");
+
+/// Month parameter of the archive methods
+const MONTH: RpcParam =
+    RpcParam::required("month", JsonType::String, "Month timestamp, as a u64 string");
+
+/// Methods served by the taud JSON-RPC
+const METHODS: &[RpcMethod] = &[
+    // ============
+    // Task methods
+    // ============
+    method(
+        "add",
+        "Add a new task to the current workspace",
+        &[RpcParam::required(
+            "task",
+            JsonType::Object,
+            "Task with title, desc, assign, project, tags, due, rank and created_at",
+        )],
+        JsonType::Boolean,
+    ),
+    method("get_ref_ids", "Reference IDs of the current tasks", &[], JsonType::Array),
+    method(
+        "get_archive_ref_ids",
+        "Reference IDs of the tasks archived in the given month",
+        &[MONTH],
+        JsonType::Array,
+    ),
+    method(
+        "modify",
+        "Modify the given fields of a task",
+        &[REF_ID, RpcParam::required("fields", JsonType::Object, "Fields to modify")],
+        JsonType::Boolean,
+    ),
+    method(
+        "set_state",
+        "Set the state of a task, one of stop, start, open and pause",
+        &[REF_ID, RpcParam::required("state", JsonType::String, "New state")],
+        JsonType::Boolean,
+    ),
+    method(
+        "set_comment",
+        "Add a comment to a task",
+        &[REF_ID, RpcParam::required("comment", JsonType::String, "Comment content")],
+        JsonType::Boolean,
+    ),
+    method("get_task_by_ref_id", "Task with the given reference ID", &[REF_ID], JsonType::Object),
+    method("fetch_deactive_tasks", "Tasks archived in the given month", &[MONTH], JsonType::Array),
+    method(
+        "fetch_archive_task",
+        "Task with the given reference ID archived in the given month",
+        &[REF_ID, MONTH],
+        JsonType::Object,
+    ),
+    // =================
+    // Workspace methods
+    // =================
+    method(
+        "switch_ws",
+        "Switch to the given workspace, replying whether it is configured",
+        &[RpcParam::required("workspace", JsonType::String, "Workspace name")],
+        JsonType::Boolean,
+    ),
+    method("get_ws", "Name of the current workspace", &[], JsonType::String),
+    method(
+        "export",
+        "Export the tasks of the current workspace to the given directory",
+        &[RpcParam::required("path", JsonType::String, "Directory to export to")],
+        JsonType::Boolean,
+    ),
+    method(
+        "import",
+        "Import the tasks exported to the given directory",
+        &[RpcParam::required("path", JsonType::String, "Directory to import from")],
+        JsonType::Boolean,
+    ),
+    // =====================
+    // Miscellaneous methods
+    // =====================
+    method("ping", "Reply with pong", &[], JsonType::String),
+    method(
+        "dnet.subscribe_events",
+        "Subscribe to notifications of P2P dnet events",
+        &[],
+        JsonType::Any,
+    ),
+    method(
+        "dnet.switch",
+        "Activate or deactivate dnet in the P2P stack",
+        &[RpcParam::required("enable", JsonType::Boolean, "Whether dnet should be active")],
+        JsonType::Boolean,
+    ),
+    method("p2p.get_info", "Information about the P2P stack", &[], JsonType::Object),
+];
+
 #[async_trait]
 impl RequestHandler for JsonRpcInterface {
     async fn handle_request(&self, req: JsonRequest) -> JsonResult {
@@ -88,6 +197,10 @@ impl RequestHandler for JsonRpcInterface {
         to_json_result(rep, req.id)
     }
 
+    fn methods(&self) -> &'static [RpcMethod] {
+        METHODS
+    }
+
     async fn connections_mut(&self) -> MutexGuard<'_, HashSet<StoppableTaskPtr>> {
         self.rpc_connections.lock().await
     }
@@ -152,7 +265,7 @@ impl JsonRpcInterface {
     //
     // --> {"jsonrpc": "2.0", "method": "dnet.subscribe_events", "params": [], "id": 1}
     // <-- {"jsonrpc": "2.0", "method": "dnet.subscribe_events", "params": [`event`]}
-    pub async fn dnet_subscribe_events(&self, id: u16, params: JsonValue) -> JsonResult {
+    pub async fn dnet_subscribe_events(&self, id: JsonId, params: JsonValue) -> JsonResult {
         let params = params.get::<Vec<JsonValue>>().unwrap();
         if !params.is_empty() {
             return JsonError::new(ErrorCode::InvalidParams, None, id).into()
@@ -45,15 +45,14 @@ use tinyjson::JsonValue;
 use darkfi::{
     async_daemonize,
     event_graph::{
-        proto::{EventPut, ProtocolEventGraph},
-        Event, EventGraph, EventGraphPtr, NULL_ID,
+        proto::ProtocolEventGraph, Event, EventGraph, EventGraphPtr, DEFAULT_TOPIC, NULL_ID,
     },
-    net::{P2p, P2pPtr, SESSION_ALL},
+    net::{P2p, SESSION_ALL},
     rpc::{
         jsonrpc::JsonSubscriber,
         server::{listen_and_serve, RequestHandler},
     },
-    system::{sleep, StoppableTask},
+    system::{sleep, StoppableTask, Subscriber, Subscription},
     util::path::expand_path,
     Error, Result,
 };
@@ -93,6 +92,20 @@ fn get_workspaces(settings: &Args) -> Result<HashMap<String, ChaChaBox>> {
     Ok(workspaces)
 }
 
+/// Return the event DAG topics of the workspaces configured with one
+fn get_workspace_topics(settings: &Args) -> HashMap<String, String> {
+    let mut topics = HashMap::new();
+
+    for workspace in settings.workspaces.iter() {
+        let workspace: Vec<&str> = workspace.split(':').collect();
+        if let Some(topic) = workspace.get(2) {
+            topics.insert(workspace[0].to_string(), topic.to_string());
+        }
+    }
+
+    topics
+}
+
 #[derive(Debug, Clone, SerialEncodable, SerialDecodable)]
 pub struct EncryptedTask {
     payload: String,
@@ -149,16 +162,15 @@ fn try_decrypt_task(encrypt_task: &EncryptedTask, chacha_box: &ChaChaBox) -> Tau
 
 #[allow(clippy::too_many_arguments)]
 async fn start_sync_loop(
-    event_graph: EventGraphPtr,
+    workspace_graphs: HashMap<String, EventGraphPtr>,
+    incoming: Subscription<Event>,
     broadcast_rcv: smol::channel::Receiver<TaskInfo>,
     workspaces: Arc<HashMap<String, ChaChaBox>>,
     datastore_path: std::path::PathBuf,
     piped: bool,
-    p2p: P2pPtr,
     last_sent: RwLock<blake3::Hash>,
     seen: OnceLock<sled::Tree>,
 ) -> TaudResult<()> {
-    let incoming = event_graph.event_sub.clone().subscribe().await;
     let seen_events = seen.get().unwrap();
 
     loop {
@@ -168,6 +180,7 @@ async fn start_sync_loop(
                 let tk = task_event.map_err(Error::from)?;
                 if workspaces.contains_key(&tk.workspace) {
                     let chacha_box = workspaces.get(&tk.workspace).unwrap();
+                    let event_graph = workspace_graphs.get(&tk.workspace).unwrap();
                     let encrypted_task = encrypt_task(&tk, chacha_box, &mut OsRng)?;
                     info!(target: "taud", "Send the task: ref: {}", tk.ref_id);
                     // Build a DAG event and return it.
@@ -192,7 +205,7 @@ async fn start_sync_loop(
                         // seen.get().unwrap().insert(event_id.as_bytes(), &[]).unwrap();
 
                         // Otherwise, broadcast it
-                        p2p.broadcast(&EventPut(event)).await;
+                        event_graph.broadcast(&event, &[]).await;
                     }
                 }
             }
@@ -342,6 +355,7 @@ async fn realmain(settings: Args, executor: Arc<smol::Executor<'static>>) -> Res
     }
 
     let workspaces = Arc::new(get_workspaces(&settings)?);
+    let workspace_topics = get_workspace_topics(&settings);
 
     if workspaces.is_empty() {
         error!(target: "taud", "Please add at least one workspace to the config file.");
@@ -361,6 +375,16 @@ async fn realmain(settings: Args, executor: Arc<smol::Executor<'static>>) -> Res
     let event_graph =
         EventGraph::new(p2p.clone(), sled_db.clone(), "taud_dag", 0, executor.clone()).await?;
 
+    // Workspaces configured with a topic get their own DAG
+    let mut workspace_graphs = HashMap::new();
+    for workspace in workspaces.keys() {
+        let workspace_graph = match workspace_topics.get(workspace) {
+            Some(topic) => event_graph.add_topic(topic, 0, executor.clone()).await?,
+            None => event_graph.clone(),
+        };
+        workspace_graphs.insert(workspace.clone(), workspace_graph);
+    }
+
     info!("Registering EventGraph P2P protocol");
     let event_graph_ = Arc::clone(&event_graph);
     let registry = p2p.protocol_registry();
@@ -399,6 +423,18 @@ async fn realmain(settings: Args, executor: Arc<smol::Executor<'static>>) -> Res
                 }
             }
         }
+
+        for topic in event_graph.followed_topics().await {
+            if topic == DEFAULT_TOPIC {
+                continue
+            }
+
+            info!(target: "taud", "Syncing event DAG of topic {}", topic);
+            let topic_graph = event_graph.get_topic(&topic).await.unwrap();
+            if let Err(e) = topic_graph.dag_sync().await {
+                error!(target: "taud", "Failed syncing DAG of topic {}: {}", topic, e);
+            }
+        }
     }
 
     ////////////////////
@@ -412,37 +448,57 @@ async fn realmain(settings: Args, executor: Arc<smol::Executor<'static>>) -> Res
     ////////////////////
     // get history
     ////////////////////
-    let dag_events = event_graph.order_events().await;
     let seen_events = seen.get().unwrap();
+    let incoming = Subscriber::new();
+    let incoming_sub = incoming.clone().subscribe().await;
+
+    for topic in event_graph.followed_topics().await {
+        let topic_graph = event_graph.get_topic(&topic).await.unwrap();
+
+        // Relay the topic's new events to the sync loop
+        let event_sub = topic_graph.event_sub.clone().subscribe().await;
+        let incoming_ = incoming.clone();
+        executor
+            .spawn(async move {
+                loop {
+                    incoming_.notify(event_sub.receive().await).await;
+                }
+            })
+            .detach();
 
-    for event_id in dag_events.iter() {
-        // If it was seen, skip
-        if seen_events.contains_key(event_id.as_bytes()).unwrap() {
-            continue
-        }
+        let dag_events = topic_graph.order_events().await;
+
+        for event_id in dag_events.iter() {
+            // If it was seen, skip
+            if seen_events.contains_key(event_id.as_bytes()).unwrap() {
+                continue
+            }
 
-        // Get the event from the DAG
-        let event = event_graph.dag_get(event_id).await.unwrap().unwrap();
+            // Get the event from the DAG
+            let event = topic_graph.dag_get(event_id).await.unwrap().unwrap();
 
-        // Try to deserialize it. (Here we skip errors)
-        let Ok((enc_task, _)) = deserialize_async_partial(event.content()).await else { continue };
+            // Try to deserialize it. (Here we skip errors)
+            let Ok((enc_task, _)) = deserialize_async_partial(event.content()).await else {
+                continue
+            };
 
-        // Potentially decrypt the privmsg
-        on_receive_task(&enc_task, &datastore_path, &workspaces, false).await.unwrap();
+            // Potentially decrypt the privmsg
+            on_receive_task(&enc_task, &datastore_path, &workspaces, false).await.unwrap();
 
-        debug!(target: "taud", "Marking event {} as seen", event_id);
-        seen_events.insert(event_id.as_bytes(), &[]).unwrap();
+            debug!(target: "taud", "Marking event {} as seen", event_id);
+            seen_events.insert(event_id.as_bytes(), &[]).unwrap();
+        }
     }
 
     let sync_loop_task = StoppableTask::new();
     sync_loop_task.clone().start(
         start_sync_loop(
-            event_graph.clone(),
+            workspace_graphs,
+            incoming_sub,
             broadcast_rcv,
             workspaces.clone(),
             datastore_path.clone(),
             settings.piped,
-            p2p.clone(),
             last_sent,
             seen.clone(),
         ),
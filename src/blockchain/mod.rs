@@ -16,7 +16,10 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
 
 use log::debug;
 use sled::Transactional;
@@ -24,7 +27,11 @@ use sled::Transactional;
 use darkfi_sdk::blockchain::Slot;
 use darkfi_serial::{deserialize, serialize, Decodable};
 
-use crate::{tx::Transaction, Error, Result};
+use crate::{
+    tx::Transaction,
+    util::metrics::{registry, Gauge},
+    Error, Result,
+};
 
 /// Block related definitions and storage implementations
 pub mod block_store;
@@ -78,6 +85,8 @@ pub struct Blockchain {
     pub contracts: ContractStateStore,
     /// Wasm bincodes
     pub wasm_bincode: WasmStore,
+    /// Number of transactions in the pending tx store
+    pending_txs_gauge: Arc<Gauge>,
 }
 
 impl Blockchain {
@@ -95,6 +104,19 @@ impl Blockchain {
         let contracts = ContractStateStore::new(db)?;
         let wasm_bincode = WasmStore::new(db)?;
 
+        // Counting a sled tree is O(n), so we only do it once here, and
+        // keep the gauge up to date as pending txs are added and removed.
+        // Every instance counts its own database, and the latest one opened
+        // is the one exported.
+        let pending_txs_gauge = Arc::new(Gauge::default());
+        pending_txs_gauge.set(pending_txs.0.len() as i64);
+        registry().insert_gauge(
+            "darkfi_blockchain_pending_txs",
+            "Transactions in the pending tx store",
+            &[],
+            pending_txs_gauge.clone(),
+        );
+
         Ok(Self {
             sled_db: db.clone(),
             headers,
@@ -108,6 +130,7 @@ impl Blockchain {
             pending_txs_order,
             contracts,
             wasm_bincode,
+            pending_txs_gauge,
         })
     }
 
@@ -315,11 +338,13 @@ impl Blockchain {
     pub fn add_pending_txs(&self, txs: &[Transaction]) -> Result<Vec<blake3::Hash>> {
         let (txs_batch, txs_hashes) = self.pending_txs.insert_batch(txs)?;
         let txs_order_batch = self.pending_txs_order.insert_batch(&txs_hashes)?;
+        let added = self.count_pending_txs(&txs_hashes, false)?;
 
         // Perform an atomic transaction over the trees and apply the batches.
         let trees = [self.pending_txs.0.clone(), self.pending_txs_order.0.clone()];
         let batches = [txs_batch, txs_order_batch];
         self.atomic_write(&trees, &batches)?;
+        self.pending_txs_gauge.add(added as i64);
 
         Ok(txs_hashes)
     }
@@ -357,15 +382,29 @@ impl Blockchain {
 
         let txs_batch = self.pending_txs.remove_batch(&txs_hashes);
         let txs_order_batch = self.pending_txs_order.remove_batch(&removed_indexes);
+        let removed = self.count_pending_txs(&txs_hashes, true)?;
 
         // Perform an atomic transaction over the trees and apply the batches.
         let trees = [self.pending_txs.0.clone(), self.pending_txs_order.0.clone()];
         let batches = [txs_batch, txs_order_batch];
         self.atomic_write(&trees, &batches)?;
+        self.pending_txs_gauge.add(-(removed as i64));
 
         Ok(())
     }
 
+    /// Count the distinct transaction hashes that are, or are not, in the
+    /// pending tx store.
+    fn count_pending_txs(&self, txs_hashes: &[blake3::Hash], stored: bool) -> Result<usize> {
+        let mut count = 0;
+        for tx_hash in txs_hashes.iter().collect::<HashSet<_>>() {
+            if self.pending_txs.contains(tx_hash)? == stored {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
     /// Auxiliary function to write to multiple trees completely atomic.
     fn atomic_write(&self, trees: &[sled::Tree], batches: &[sled::Batch]) -> Result<()> {
         if trees.len() != batches.len() {
@@ -0,0 +1,720 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Kademlia-style distributed hash table over the P2P network.
+//!
+//! Every node has a 256-bit ID in the same space as the keys, which are
+//! BLAKE3 hashes. The ID is the hash of the node's ed25519 public key.
+//! Nodes introduce themselves to their P2P peers with their ID and
+//! external addresses, signed along with a random challenge sent by the
+//! peer, so nobody can claim the ID of another node. Only nodes that
+//! proved their ID are kept in the [`RoutingTable`] of k-buckets.
+//!
+//! Instead of storing values, the DHT stores provider records: a node
+//! announces that it provides a key by storing a record on the [`K`]
+//! nodes closest to the key. A node can only store records of itself.
+//! Records expire after [`PROVIDER_TTL`], so providers periodically
+//! republish the keys they announced. Looking up nodes and providers is
+//! done iteratively, querying [`ALPHA`] nodes at once and moving closer
+//! to the key with every reply.
+//!
+//! Nodes we are not connected to are reached by opening a short-lived
+//! connection to one of their addresses, over which both sides prove
+//! their IDs. Nodes reported by others are only added to the routing
+//! table once they answered us this way. Nodes without external
+//! addresses can use the DHT, but can't be found in it nor provide keys.
+//!
+//! The P2P protocol is implemented by [`ProtocolDht`], which has to be
+//! registered with the P2P network by the application. It is registered
+//! with the [`DHT_CAPABILITY`], so it only runs with peers that use the
+//! DHT as well:
+//!
+//! ```ignore
+//! let dht = Dht::new_with_datastore(p2p.clone(), &datastore, ex.clone())?;
+//! let dht_ = dht.clone();
+//! p2p.protocol_registry()
+//!     .register_with_capability(SESSION_ALL, DHT_CAPABILITY, move |channel, _| {
+//!         let dht_ = dht_.clone();
+//!         async move { ProtocolDht::init(dht_, channel).await.unwrap() }
+//!     })
+//!     .await;
+//! ```
+//!
+//! [`ProtocolDht`]: proto::ProtocolDht
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::Path,
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use darkfi_serial::{async_trait, serialize, SerialDecodable, SerialEncodable};
+use futures::stream::{FuturesUnordered, StreamExt};
+use log::{debug, error, info, warn};
+use rand::{rngs::OsRng, RngCore};
+use smol::{lock::RwLock, Executor};
+use url::Url;
+
+use crate::{
+    net::{
+        connector::Connector, protocol::ProtocolVersion, session::Session, ChannelPtr, Message,
+        P2pPtr,
+    },
+    system::{sleep, timeout::timeout, StoppableTask, StoppableTaskPtr},
+    util::file::load_file,
+    Error, Result,
+};
+
+/// Kademlia routing table
+pub mod routing;
+use routing::{distance, RoutingTable};
+
+/// P2P protocol implementation for the DHT
+pub mod proto;
+use proto::{
+    DhtChallenge, DhtFindNodeRep, DhtFindNodeReq, DhtFindValueRep, DhtFindValueReq, DhtPing,
+    DhtStore,
+};
+
+#[cfg(test)]
+mod tests;
+
+/// Capability advertised by nodes running the DHT protocol
+pub const DHT_CAPABILITY: &str = "dht";
+/// Maximum number of nodes in a k-bucket, and number of nodes a lookup returns
+pub const K: usize = 20;
+/// Number of nodes queried at once during a lookup
+pub const ALPHA: usize = 3;
+/// Seconds a provider record is kept for if it is not republished
+pub const PROVIDER_TTL: u64 = 86400;
+/// Maximum number of providers we keep for a key
+const MAX_PROVIDERS: usize = 64;
+/// Maximum number of keys we keep provider records for
+const MAX_PROVIDER_KEYS: usize = 16384;
+/// Maximum number of provider records we keep in total
+const MAX_PROVIDER_RECORDS: usize = 65536;
+/// Seconds between routing table refreshes and republishing our keys
+const REFRESH_INTERVAL: u64 = 3600;
+/// Seconds to wait for the first P2P connections before bootstrapping
+const BOOTSTRAP_DELAY: u64 = 10;
+/// Time to wait for a node to reply to a request
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Name of the file in the datastore holding our keypair
+const KEYFILE_NAME: &str = "dht_keypair";
+
+/// A node participating in the DHT
+#[derive(Clone, Debug, PartialEq, Eq, SerialEncodable, SerialDecodable)]
+pub struct DhtNode {
+    /// ID of the node, in the same space as the keys
+    pub id: blake3::Hash,
+    /// Addresses the node can be reached at
+    pub addresses: Vec<Url>,
+}
+
+/// A provider of a key, along with the time the record expires
+struct ProviderRecord {
+    node: DhtNode,
+    expires: u64,
+}
+
+/// Provider records stored on our node, bounded in keys and records
+#[derive(Default)]
+struct ProviderStore {
+    records: HashMap<blake3::Hash, Vec<ProviderRecord>>,
+    /// Total number of records
+    len: usize,
+}
+
+impl ProviderStore {
+    /// Store a provider record, or refresh it if we have it already.
+    /// Returns `false` if there is no room left for it.
+    fn insert(&mut self, key: blake3::Hash, node: DhtNode, expires: u64) -> bool {
+        if let Some(records) = self.records.get_mut(&key) {
+            if let Some(record) = records.iter_mut().find(|r| r.node.id == node.id) {
+                record.node = node;
+                record.expires = expires;
+                return true
+            }
+        }
+
+        let new_key = !self.records.contains_key(&key);
+        if self.len >= MAX_PROVIDER_RECORDS || (new_key && self.records.len() >= MAX_PROVIDER_KEYS)
+        {
+            self.prune(now());
+            let new_key = !self.records.contains_key(&key);
+            if self.len >= MAX_PROVIDER_RECORDS ||
+                (new_key && self.records.len() >= MAX_PROVIDER_KEYS)
+            {
+                return false
+            }
+        }
+
+        // Make room by dropping the record expiring first
+        let records = self.records.entry(key).or_default();
+        if records.len() >= MAX_PROVIDERS {
+            let (pos, _) = records.iter().enumerate().min_by_key(|(_, r)| r.expires).unwrap();
+            records.remove(pos);
+            self.len -= 1;
+        }
+
+        records.push(ProviderRecord { node, expires });
+        self.len += 1;
+        true
+    }
+
+    /// Return the providers of the given key not expired at `now`
+    fn get(&self, key: &blake3::Hash, now: u64) -> Vec<DhtNode> {
+        let Some(records) = self.records.get(key) else { return vec![] };
+        records.iter().filter(|r| r.expires > now).map(|r| r.node.clone()).take(K).collect()
+    }
+
+    /// Remove the records expired at `now`
+    fn prune(&mut self, now: u64) {
+        for records in self.records.values_mut() {
+            records.retain(|r| r.expires > now);
+        }
+        self.records.retain(|_, records| !records.is_empty());
+        self.len = self.records.values().map(|r| r.len()).sum();
+    }
+}
+
+/// Atomic pointer to a [`Dht`] instance
+pub type DhtPtr = Arc<Dht>;
+
+/// Kademlia-style DHT instance, see the module documentation
+pub struct Dht {
+    /// Our own node
+    node: DhtNode,
+    /// Keypair our node ID is derived from
+    keypair: ed25519_compact::KeyPair,
+    /// Pointer to the P2P network instance
+    p2p: P2pPtr,
+    /// Nodes we know of
+    routing_table: RwLock<RoutingTable>,
+    /// Channels to nodes that proved their ID to us
+    channels: RwLock<HashMap<blake3::Hash, ChannelPtr>>,
+    /// Provider records stored on our node
+    providers: RwLock<ProviderStore>,
+    /// Keys we provide, which are republished periodically
+    announced: RwLock<HashSet<blake3::Hash>>,
+    /// Routing table refresh and republishing task
+    maintenance_task: StoppableTaskPtr,
+    /// Executor used for connections to nodes
+    ex: Arc<Executor<'static>>,
+}
+
+/// Return the current UNIX timestamp
+fn now() -> u64 {
+    UNIX_EPOCH.elapsed().unwrap().as_secs()
+}
+
+impl Dht {
+    /// Create a new DHT instance with a random keypair, and the node ID
+    /// derived from it. The node can be reached at the external addresses
+    /// of the P2P settings.
+    pub fn new(p2p: P2pPtr, ex: Arc<Executor<'static>>) -> DhtPtr {
+        Self::with_keypair(p2p, ed25519_compact::KeyPair::generate(), ex)
+    }
+
+    /// Create a new DHT instance with the keypair stored in `datastore`,
+    /// so the node keeps its ID across restarts. If there is no keypair
+    /// yet, a new one is generated and stored.
+    pub fn new_with_datastore(
+        p2p: P2pPtr,
+        datastore: &Path,
+        ex: Arc<Executor<'static>>,
+    ) -> Result<DhtPtr> {
+        let keypair = Self::load_or_create_keypair(&datastore.join(KEYFILE_NAME))?;
+        Ok(Self::with_keypair(p2p, keypair, ex))
+    }
+
+    /// Load a keypair from the given path. If the file does not exist,
+    /// a new keypair is generated and written to it.
+    fn load_or_create_keypair(path: &Path) -> Result<ed25519_compact::KeyPair> {
+        if path.exists() {
+            let contents = load_file(path)?;
+            let Ok(bytes) = bs58::decode(contents.trim()).into_vec() else {
+                return Err(Error::DhtInvalidKeyfile)
+            };
+
+            // The stored public key has to be the one of the secret key
+            let Ok(keypair) = ed25519_compact::KeyPair::from_slice(&bytes) else {
+                return Err(Error::DhtInvalidKeyfile)
+            };
+            if keypair.validate().is_err() {
+                return Err(Error::DhtInvalidKeyfile)
+            }
+
+            return Ok(keypair)
+        }
+
+        let keypair = ed25519_compact::KeyPair::generate();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // The keyfile holds our secret key, so it is created private
+        // before anything gets written to it.
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let mut file = options.open(path)?;
+        io::Write::write_all(&mut file, bs58::encode(&keypair.sk[..]).into_string().as_bytes())?;
+
+        Ok(keypair)
+    }
+
+    /// Create a new DHT instance with the given keypair
+    fn with_keypair(
+        p2p: P2pPtr,
+        keypair: ed25519_compact::KeyPair,
+        ex: Arc<Executor<'static>>,
+    ) -> DhtPtr {
+        let node =
+            DhtNode { id: node_id(&keypair.pk), addresses: p2p.settings().external_addrs.clone() };
+        info!(target: "dht::new()", "[DHT] Our node ID is {}", node.id);
+
+        let dht = Arc::new(Self {
+            routing_table: RwLock::new(RoutingTable::new(node.id)),
+            node,
+            keypair,
+            p2p,
+            channels: RwLock::new(HashMap::new()),
+            providers: RwLock::new(ProviderStore::default()),
+            announced: RwLock::new(HashSet::new()),
+            maintenance_task: StoppableTask::new(),
+            ex: ex.clone(),
+        });
+
+        dht.maintenance_task.clone().start(
+            dht.clone().maintenance(),
+            |res| async {
+                match res {
+                    Ok(()) | Err(Error::DetachedTaskStopped) => { /* Do nothing */ }
+                    Err(e) => error!(target: "dht::maintenance()", "[DHT] Failed: {}", e),
+                }
+            },
+            Error::DetachedTaskStopped,
+            ex,
+        );
+
+        dht
+    }
+
+    /// Stop the DHT background task
+    pub async fn stop(&self) {
+        self.maintenance_task.stop().await;
+    }
+
+    /// Return our own node
+    pub fn node(&self) -> &DhtNode {
+        &self.node
+    }
+
+    /// Return the number of nodes in our routing table
+    pub async fn known_nodes(&self) -> usize {
+        self.routing_table.read().await.len()
+    }
+
+    /// Find the [`K`] nodes closest to the given key
+    pub async fn find_node(&self, key: &blake3::Hash) -> Vec<DhtNode> {
+        self.lookup(key, false).await.0
+    }
+
+    /// Find the providers of the given key. We are never part of them,
+    /// even if we announced the key.
+    pub async fn find_providers(&self, key: &blake3::Hash) -> Vec<DhtNode> {
+        self.lookup(key, true).await.1
+    }
+
+    /// Announce that we provide the given key. The key is republished
+    /// until [`Dht::withdraw`] is called.
+    pub async fn announce(&self, key: &blake3::Hash) {
+        self.announced.write().await.insert(*key);
+        self.publish(key).await;
+    }
+
+    /// Stop republishing the given key. The records stored on other nodes
+    /// are left to expire.
+    pub async fn withdraw(&self, key: &blake3::Hash) {
+        self.announced.write().await.remove(key);
+    }
+
+    /// Introduce our node to a peer, signing the challenge it sent us
+    fn ping(&self, challenge: &[u8; 32]) -> DhtPing {
+        let signature = self.keypair.sk.sign(ping_message(challenge, &self.node), None);
+        DhtPing(self.node.clone(), *self.keypair.pk, *signature)
+    }
+
+    /// Check that a peer owns the node ID it introduced itself with,
+    /// by signing the challenge we sent it
+    fn verify_ping(ping: &DhtPing, challenge: &[u8; 32]) -> bool {
+        let public = ed25519_compact::PublicKey::new(ping.1);
+        let signature = ed25519_compact::Signature::new(ping.2);
+        node_id(&public) == ping.0.id &&
+            public.verify(ping_message(challenge, &ping.0), &signature).is_ok()
+    }
+
+    /// Add a node that proved its ID over the given channel. A live
+    /// channel we already have to the node is kept.
+    async fn add_node(&self, node: DhtNode, channel: ChannelPtr) {
+        if node.id == self.node.id {
+            return
+        }
+
+        let mut channels = self.channels.write().await;
+        match channels.get(&node.id) {
+            Some(existing) if !existing.is_stopped() => {}
+            _ => {
+                channels.insert(node.id, channel);
+            }
+        }
+        drop(channels);
+
+        // Nodes without addresses can't be reached by others
+        if !node.addresses.is_empty() {
+            self.routing_table.write().await.insert(node);
+        }
+    }
+
+    /// Return the nodes we know closest to the given key
+    async fn closest_nodes(&self, key: &blake3::Hash, count: usize) -> Vec<DhtNode> {
+        self.routing_table.read().await.closest(key, count)
+    }
+
+    /// Store a provider record for the given key
+    async fn add_provider(&self, key: blake3::Hash, node: DhtNode) {
+        if node.id == self.node.id {
+            return
+        }
+
+        let expires = now() + PROVIDER_TTL;
+        if !self.providers.write().await.insert(key, node, expires) {
+            debug!(target: "dht::add_provider()", "[DHT] Provider store full, dropping {}", key);
+        }
+    }
+
+    /// Return the unexpired providers of the given key stored on our node
+    async fn local_providers(&self, key: &blake3::Hash) -> Vec<DhtNode> {
+        self.providers.read().await.get(key, now())
+    }
+
+    /// Remove expired provider records
+    async fn prune_providers(&self) {
+        self.providers.write().await.prune(now());
+    }
+
+    /// Iterative lookup of the given key. Returns the [`K`] closest nodes
+    /// that replied to us, and the providers found if `find_value` is set.
+    async fn lookup(&self, key: &blake3::Hash, find_value: bool) -> (Vec<DhtNode>, Vec<DhtNode>) {
+        let mut shortlist = self.closest_nodes(key, K).await;
+        let mut queried = HashSet::new();
+        let mut providers: HashMap<blake3::Hash, DhtNode> = HashMap::new();
+        if find_value {
+            for node in self.local_providers(key).await {
+                providers.insert(node.id, node);
+            }
+        }
+
+        loop {
+            let round: Vec<DhtNode> = shortlist
+                .iter()
+                .filter(|n| !queried.contains(&n.id))
+                .take(ALPHA)
+                .cloned()
+                .collect();
+            if round.is_empty() {
+                break
+            }
+
+            let mut futures = FuturesUnordered::new();
+            for node in round {
+                queried.insert(node.id);
+                futures.push(async move {
+                    let reply = self.query(&node, key, find_value).await;
+                    (node, reply)
+                });
+            }
+
+            while let Some((node, reply)) = futures.next().await {
+                let (nodes, found) = match reply {
+                    Ok(v) => v,
+                    Err(e) => {
+                        debug!(
+                            target: "dht::lookup()",
+                            "[DHT] Node {} failed to reply: {}", node.id, e,
+                        );
+                        shortlist.retain(|n| n.id != node.id);
+                        self.routing_table.write().await.remove(&node.id);
+                        continue
+                    }
+                };
+
+                for n in nodes.into_iter().take(K) {
+                    if n.id != self.node.id && !shortlist.iter().any(|s| s.id == n.id) {
+                        shortlist.push(n);
+                    }
+                }
+
+                for provider in found.into_iter().take(MAX_PROVIDERS) {
+                    if provider.id != self.node.id {
+                        providers.insert(provider.id, provider);
+                    }
+                }
+
+                // The node is alive, and proved its ID when we reached it
+                self.routing_table.write().await.insert(node);
+            }
+
+            shortlist.sort_by_key(|n| distance(key, &n.id));
+            shortlist.truncate(K);
+
+            if find_value && !providers.is_empty() {
+                break
+            }
+        }
+
+        shortlist.retain(|n| queried.contains(&n.id));
+        (shortlist, providers.into_values().collect())
+    }
+
+    /// Query a node for the given key
+    async fn query(
+        &self,
+        node: &DhtNode,
+        key: &blake3::Hash,
+        find_value: bool,
+    ) -> Result<(Vec<DhtNode>, Vec<DhtNode>)> {
+        if find_value {
+            let rep = self.request(node, &DhtFindValueReq(*key), |r: &DhtFindValueRep| r.0 == *key);
+            let rep = rep.await?;
+            return Ok((rep.2.clone(), rep.1.clone()))
+        }
+
+        let rep = self.request(node, &DhtFindNodeReq(*key), |r: &DhtFindNodeRep| r.0 == *key);
+        Ok((rep.await?.1.clone(), vec![]))
+    }
+
+    /// Store a provider record of ours for the given key on the nodes
+    /// closest to it
+    async fn publish(&self, key: &blake3::Hash) {
+        if self.node.addresses.is_empty() {
+            warn!(
+                target: "dht::publish()",
+                "[DHT] We have no external addresses, can't provide {}", key,
+            );
+            return
+        }
+
+        let nodes = self.find_node(key).await;
+        debug!(target: "dht::publish()", "[DHT] Publishing {} to {} nodes", key, nodes.len());
+
+        let msg = DhtStore(*key);
+        for node in nodes {
+            let (channel, temporary) = match self.node_channel(&node).await {
+                Ok(v) => v,
+                Err(e) => {
+                    debug!(target: "dht::publish()", "[DHT] Node {} unreachable: {}", node.id, e);
+                    continue
+                }
+            };
+
+            if let Err(e) = channel.send(&msg).await {
+                debug!(target: "dht::publish()", "[DHT] Failed storing on {}: {}", node.id, e);
+            }
+
+            if temporary {
+                channel.stop().await;
+            }
+        }
+    }
+
+    /// Send a request to a node and wait for the reply matching it
+    async fn request<Req: Message, Rep: Message>(
+        &self,
+        node: &DhtNode,
+        req: &Req,
+        matches: impl Fn(&Rep) -> bool,
+    ) -> Result<Arc<Rep>> {
+        let (channel, temporary) = self.node_channel(node).await?;
+
+        let sub = channel.subscribe_msg::<Rep>().await?;
+        let reply = async {
+            channel.send(req).await?;
+            loop {
+                let rep = sub.receive().await?;
+                if matches(&rep) {
+                    return Ok::<_, Error>(rep)
+                }
+            }
+        };
+        let reply = timeout(REPLY_TIMEOUT, reply).await;
+        sub.unsubscribe().await;
+
+        if temporary {
+            channel.stop().await;
+        }
+
+        match reply {
+            Ok(v) => v,
+            Err(_) => Err(Error::ConnectTimeout),
+        }
+    }
+
+    /// Return a channel to the given node. If we are not connected to it,
+    /// a temporary connection is opened, and `true` is returned along with
+    /// the channel. The caller is responsible for stopping it. Either way,
+    /// the node at the other end proved it owns the node ID.
+    async fn node_channel(&self, node: &DhtNode) -> Result<(ChannelPtr, bool)> {
+        if let Some(channel) = self.channels.read().await.get(&node.id) {
+            if !channel.is_stopped() {
+                return Ok((channel.clone(), false))
+            }
+        }
+
+        let mut err = Error::ConnectFailed;
+        for addr in node.addresses.iter() {
+            match self.connect(addr).await {
+                Ok((channel, remote)) if remote.id == node.id => return Ok((channel, true)),
+                Ok((channel, _)) => {
+                    channel.stop().await;
+                    err = Error::DhtNodeUnverified;
+                }
+                Err(e) => err = e,
+            }
+        }
+
+        Err(err)
+    }
+
+    /// Open a temporary connection to the given address. Both sides prove
+    /// their node ID, and the remote node is returned along with the channel.
+    async fn connect(&self, addr: &Url) -> Result<(ChannelPtr, DhtNode)> {
+        let session_out = self.p2p.session_outbound();
+        let session_weak = Arc::downgrade(&self.p2p.session_outbound());
+
+        let connector = Connector::new(self.p2p.settings(), session_weak);
+        let (_, channel) = connector.connect(addr).await?;
+
+        // Our protocols don't run on this channel, so we have to be able
+        // to receive the replies ourselves.
+        let msg_subsystem = channel.message_subsystem();
+        msg_subsystem.add_dispatch::<DhtFindNodeRep>().await;
+        msg_subsystem.add_dispatch::<DhtFindValueRep>().await;
+        msg_subsystem.add_dispatch::<DhtChallenge>().await;
+        msg_subsystem.add_dispatch::<DhtPing>().await;
+        let challenge_sub = channel.subscribe_msg::<DhtChallenge>().await?;
+        let ping_sub = channel.subscribe_msg::<DhtPing>().await?;
+
+        let proto_ver = ProtocolVersion::new(
+            channel.clone(),
+            self.p2p.settings().clone(),
+            self.p2p.hosts().clone(),
+        )
+        .await;
+
+        let handshake_task =
+            session_out.perform_handshake_protocols(proto_ver, channel.clone(), self.ex.clone());
+
+        channel.clone().start(self.ex.clone());
+
+        if let Err(e) = handshake_task.await {
+            channel.stop().await;
+            return Err(e)
+        }
+
+        if !channel.has_capability(DHT_CAPABILITY).await {
+            channel.stop().await;
+            return Err(Error::DhtNotSupported)
+        }
+
+        // Prove our ID to the node, and have it prove its own
+        let mut challenge = [0u8; 32];
+        OsRng.fill_bytes(&mut challenge);
+        let auth = async {
+            channel.send(&DhtChallenge(challenge)).await?;
+            let their_challenge = challenge_sub.receive().await?.0;
+            channel.send(&self.ping(&their_challenge)).await?;
+
+            let ping = ping_sub.receive().await?;
+            if !Self::verify_ping(&ping, &challenge) {
+                return Err(Error::DhtNodeUnverified)
+            }
+            Ok::<_, Error>(ping.0.clone())
+        };
+        let auth = timeout(REPLY_TIMEOUT, auth).await;
+        challenge_sub.unsubscribe().await;
+        ping_sub.unsubscribe().await;
+
+        match auth {
+            Ok(Ok(node)) => Ok((channel, node)),
+            Ok(Err(e)) => {
+                channel.stop().await;
+                Err(e)
+            }
+            Err(_) => {
+                channel.stop().await;
+                Err(Error::ConnectTimeout)
+            }
+        }
+    }
+
+    /// Background task refreshing the routing table and republishing
+    /// the keys we announced
+    async fn maintenance(self: Arc<Self>) -> Result<()> {
+        sleep(BOOTSTRAP_DELAY).await;
+
+        loop {
+            self.prune_providers().await;
+            self.channels.write().await.retain(|_, channel| !channel.is_stopped());
+
+            // Looking up our own ID fills the buckets around us
+            let nodes = self.find_node(&self.node.id).await;
+            debug!(
+                target: "dht::maintenance()",
+                "[DHT] Refreshed routing table, {} closest nodes", nodes.len(),
+            );
+
+            let keys: Vec<blake3::Hash> = self.announced.read().await.iter().cloned().collect();
+            for key in keys {
+                self.publish(&key).await;
+            }
+
+            sleep(REFRESH_INTERVAL).await;
+        }
+    }
+}
+
+/// Return the node ID derived from the given public key
+fn node_id(public: &ed25519_compact::PublicKey) -> blake3::Hash {
+    blake3::hash(&public[..])
+}
+
+/// Message signed by a node introducing itself in a [`DhtPing`]
+fn ping_message(challenge: &[u8; 32], node: &DhtNode) -> Vec<u8> {
+    let mut message = challenge.to_vec();
+    message.extend_from_slice(&serialize(node));
+    message
+}
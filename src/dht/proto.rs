@@ -0,0 +1,237 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::sync::Arc;
+
+use darkfi_serial::{async_trait, SerialDecodable, SerialEncodable};
+use log::{debug, trace};
+use rand::{rngs::OsRng, RngCore};
+use smol::{lock::OnceCell, Executor};
+
+use super::{Dht, DhtNode, DhtPtr, K, REPLY_TIMEOUT};
+use crate::{impl_p2p_message, net::*, system::timeout::timeout, Result};
+
+/// A P2P message asking the peer to prove its DHT node ID, by signing
+/// the given random challenge in its [`DhtPing`]
+#[derive(Clone, SerialEncodable, SerialDecodable)]
+pub struct DhtChallenge(pub [u8; 32]);
+impl_p2p_message!(DhtChallenge, "Dht::Challenge");
+
+/// A P2P message introducing our DHT node to the peer, along with the
+/// ed25519 public key the node ID is derived from, and the signature
+/// over the peer's challenge and the node
+#[derive(Clone, SerialEncodable, SerialDecodable)]
+pub struct DhtPing(pub DhtNode, pub [u8; 32], pub [u8; 64]);
+impl_p2p_message!(DhtPing, "Dht::Ping");
+
+/// A P2P message asking for the nodes closest to a key
+#[derive(Clone, SerialEncodable, SerialDecodable)]
+pub struct DhtFindNodeReq(pub blake3::Hash);
+impl_p2p_message!(DhtFindNodeReq, "Dht::FindNodeReq");
+
+/// A P2P message replying with the nodes closest to a key
+#[derive(Clone, SerialEncodable, SerialDecodable)]
+pub struct DhtFindNodeRep(pub blake3::Hash, pub Vec<DhtNode>);
+impl_p2p_message!(DhtFindNodeRep, "Dht::FindNodeRep");
+
+/// A P2P message asking for the providers of a key
+#[derive(Clone, SerialEncodable, SerialDecodable)]
+pub struct DhtFindValueReq(pub blake3::Hash);
+impl_p2p_message!(DhtFindValueReq, "Dht::FindValueReq");
+
+/// A P2P message replying with the known providers of a key,
+/// along with the nodes closest to it
+#[derive(Clone, SerialEncodable, SerialDecodable)]
+pub struct DhtFindValueRep(pub blake3::Hash, pub Vec<DhtNode>, pub Vec<DhtNode>);
+impl_p2p_message!(DhtFindValueRep, "Dht::FindValueRep");
+
+/// A P2P message storing a provider record of the sender for a key
+#[derive(Clone, SerialEncodable, SerialDecodable)]
+pub struct DhtStore(pub blake3::Hash);
+impl_p2p_message!(DhtStore, "Dht::Store");
+
+/// P2P protocol implementation for the DHT.
+pub struct ProtocolDht {
+    /// Pointer to the connected peer
+    channel: ChannelPtr,
+    /// Pointer to the DHT instance
+    dht: DhtPtr,
+    /// Challenge the peer has to sign to prove its node ID
+    challenge: [u8; 32],
+    /// The peer's node, once it proved its ID
+    peer: OnceCell<DhtNode>,
+    /// `MessageSubscriber` for `DhtChallenge`
+    challenge_sub: MessageSubscription<DhtChallenge>,
+    /// `MessageSubscriber` for `DhtPing`
+    ping_sub: MessageSubscription<DhtPing>,
+    /// `MessageSubscriber` for `DhtFindNodeReq`
+    find_node_sub: MessageSubscription<DhtFindNodeReq>,
+    /// `MessageSubscriber` for `DhtFindValueReq`
+    find_value_sub: MessageSubscription<DhtFindValueReq>,
+    /// `MessageSubscriber` for `DhtStore`
+    store_sub: MessageSubscription<DhtStore>,
+    /// P2P jobs manager pointer
+    jobsman: ProtocolJobsManagerPtr,
+}
+
+#[async_trait]
+impl ProtocolBase for ProtocolDht {
+    async fn start(self: Arc<Self>, ex: Arc<Executor<'_>>) -> Result<()> {
+        self.jobsman.clone().start(ex.clone());
+        self.jobsman.clone().spawn(self.clone().handle_challenge(), ex.clone()).await;
+        self.jobsman.clone().spawn(self.clone().handle_ping(), ex.clone()).await;
+        self.jobsman.clone().spawn(self.clone().handle_find_node(), ex.clone()).await;
+        self.jobsman.clone().spawn(self.clone().handle_find_value(), ex.clone()).await;
+        self.jobsman.clone().spawn(self.clone().handle_store(), ex.clone()).await;
+
+        // Ask the peer to introduce itself, so we can add it to our routing table
+        self.channel.send(&DhtChallenge(self.challenge)).await?;
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "ProtocolDht"
+    }
+}
+
+impl ProtocolDht {
+    pub async fn init(dht: DhtPtr, channel: ChannelPtr) -> Result<ProtocolBasePtr> {
+        let mut challenge = [0u8; 32];
+        OsRng.fill_bytes(&mut challenge);
+
+        let msg_subsystem = channel.message_subsystem();
+        msg_subsystem.add_dispatch::<DhtChallenge>().await;
+        msg_subsystem.add_dispatch::<DhtPing>().await;
+        msg_subsystem.add_dispatch::<DhtFindNodeReq>().await;
+        msg_subsystem.add_dispatch::<DhtFindNodeRep>().await;
+        msg_subsystem.add_dispatch::<DhtFindValueReq>().await;
+        msg_subsystem.add_dispatch::<DhtFindValueRep>().await;
+        msg_subsystem.add_dispatch::<DhtStore>().await;
+
+        let challenge_sub = channel.subscribe_msg::<DhtChallenge>().await?;
+        let ping_sub = channel.subscribe_msg::<DhtPing>().await?;
+        let find_node_sub = channel.subscribe_msg::<DhtFindNodeReq>().await?;
+        let find_value_sub = channel.subscribe_msg::<DhtFindValueReq>().await?;
+        let store_sub = channel.subscribe_msg::<DhtStore>().await?;
+
+        Ok(Arc::new(Self {
+            channel: channel.clone(),
+            dht,
+            challenge,
+            peer: OnceCell::new(),
+            challenge_sub,
+            ping_sub,
+            find_node_sub,
+            find_value_sub,
+            store_sub,
+            jobsman: ProtocolJobsManager::new("ProtocolDht", channel.clone()),
+        }))
+    }
+
+    async fn handle_challenge(self: Arc<Self>) -> Result<()> {
+        // We only answer the first challenge, so the peer can't make us
+        // sign at will.
+        let challenge = self.challenge_sub.receive().await?.0;
+        self.channel.send(&self.dht.ping(&challenge)).await?;
+
+        loop {
+            self.challenge_sub.receive().await?;
+        }
+    }
+
+    async fn handle_ping(self: Arc<Self>) -> Result<()> {
+        loop {
+            let ping = self.ping_sub.receive().await?;
+            trace!(
+                target: "dht::protocol::handle_ping()",
+                "Got DhtPing ({}) [{}]", ping.0.id, self.channel.address(),
+            );
+
+            if self.peer.get().is_some() {
+                continue
+            }
+
+            if !Dht::verify_ping(&ping, &self.challenge) {
+                debug!(
+                    target: "dht::protocol::handle_ping()",
+                    "Node {} failed to prove its ID [{}]", ping.0.id, self.channel.address(),
+                );
+                continue
+            }
+
+            let node = ping.0.clone();
+            let _ = self.peer.set(node.clone()).await;
+            self.dht.add_node(node, self.channel.clone()).await;
+        }
+    }
+
+    async fn handle_find_node(self: Arc<Self>) -> Result<()> {
+        loop {
+            let key = self.find_node_sub.receive().await?.0;
+            trace!(
+                target: "dht::protocol::handle_find_node()",
+                "Got DhtFindNodeReq ({}) [{}]", key, self.channel.address(),
+            );
+
+            let nodes = self.dht.closest_nodes(&key, K).await;
+            self.channel.send(&DhtFindNodeRep(key, nodes)).await?;
+        }
+    }
+
+    async fn handle_find_value(self: Arc<Self>) -> Result<()> {
+        loop {
+            let key = self.find_value_sub.receive().await?.0;
+            trace!(
+                target: "dht::protocol::handle_find_value()",
+                "Got DhtFindValueReq ({}) [{}]", key, self.channel.address(),
+            );
+
+            let providers = self.dht.local_providers(&key).await;
+            let nodes = self.dht.closest_nodes(&key, K).await;
+            self.channel.send(&DhtFindValueRep(key, providers, nodes)).await?;
+        }
+    }
+
+    async fn handle_store(self: Arc<Self>) -> Result<()> {
+        loop {
+            let key = self.store_sub.receive().await?.0;
+
+            // Records are stored for the peer itself, so it has to prove
+            // its ID first. Its ping may still be on the way.
+            let Ok(provider) = timeout(REPLY_TIMEOUT, self.peer.wait()).await else {
+                debug!(
+                    target: "dht::protocol::handle_store()",
+                    "Ignoring DhtStore ({}) from unverified node [{}]", key, self.channel.address(),
+                );
+                continue
+            };
+            let provider = provider.clone();
+            debug!(
+                target: "dht::protocol::handle_store()",
+                "Got DhtStore ({}) from {} [{}]", key, provider.id, self.channel.address(),
+            );
+
+            // Providers must be reachable for the record to be useful
+            if provider.addresses.is_empty() {
+                continue
+            }
+
+            self.dht.add_provider(key, provider).await;
+        }
+    }
+}
@@ -0,0 +1,120 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Kademlia routing table.
+//!
+//! Nodes are kept in 256 k-buckets, where bucket `i` holds the nodes
+//! whose XOR distance to our ID has `i` leading zero bits. Buckets hold
+//! at most [`K`] nodes in least-recently-seen order. As in Kademlia, full
+//! buckets prefer the nodes they already have, since long-lived nodes are
+//! likely to stay online. Nodes failing our requests are removed, which
+//! makes room for new ones.
+
+use std::collections::VecDeque;
+
+use super::{DhtNode, K};
+
+/// XOR distance between two IDs, comparable as a big-endian integer
+pub type Distance = [u8; 32];
+
+/// Return the XOR distance between two IDs
+pub fn distance(a: &blake3::Hash, b: &blake3::Hash) -> Distance {
+    let mut d = [0u8; 32];
+    for (i, (x, y)) in a.as_bytes().iter().zip(b.as_bytes().iter()).enumerate() {
+        d[i] = x ^ y;
+    }
+    d
+}
+
+/// Return the index of the bucket an ID belongs to, or `None` if it
+/// is our own ID.
+fn bucket_index(local_id: &blake3::Hash, id: &blake3::Hash) -> Option<usize> {
+    let d = distance(local_id, id);
+    let mut zeros = 0;
+    for byte in d.iter() {
+        if *byte != 0 {
+            return Some(zeros + byte.leading_zeros() as usize)
+        }
+        zeros += 8;
+    }
+
+    None
+}
+
+/// Routing table of known DHT nodes
+pub struct RoutingTable {
+    local_id: blake3::Hash,
+    buckets: Vec<VecDeque<DhtNode>>,
+}
+
+impl RoutingTable {
+    /// Create an empty routing table around the given ID
+    pub fn new(local_id: blake3::Hash) -> Self {
+        Self { local_id, buckets: vec![VecDeque::new(); 256] }
+    }
+
+    /// Insert a node we have seen alive, or mark it as most recently seen
+    /// if we already know it. Returns `false` if its bucket is full.
+    pub fn insert(&mut self, node: DhtNode) -> bool {
+        let Some(index) = bucket_index(&self.local_id, &node.id) else { return false };
+        let bucket = &mut self.buckets[index];
+
+        if let Some(pos) = bucket.iter().position(|n| n.id == node.id) {
+            bucket.remove(pos);
+            bucket.push_back(node);
+            return true
+        }
+
+        if bucket.len() >= K {
+            return false
+        }
+
+        bucket.push_back(node);
+        true
+    }
+
+    /// Remove a node from the routing table
+    pub fn remove(&mut self, id: &blake3::Hash) {
+        let Some(index) = bucket_index(&self.local_id, id) else { return };
+        self.buckets[index].retain(|n| &n.id != id);
+    }
+
+    /// Check if we know the node with the given ID
+    pub fn contains(&self, id: &blake3::Hash) -> bool {
+        let Some(index) = bucket_index(&self.local_id, id) else { return false };
+        self.buckets[index].iter().any(|n| &n.id == id)
+    }
+
+    /// Return up to `count` known nodes closest to the given key
+    pub fn closest(&self, key: &blake3::Hash, count: usize) -> Vec<DhtNode> {
+        let mut nodes: Vec<DhtNode> = self.buckets.iter().flatten().cloned().collect();
+        nodes.sort_by_key(|n| distance(key, &n.id));
+        nodes.truncate(count);
+        nodes
+    }
+
+    /// Return the number of known nodes
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(|b| b.len()).sum()
+    }
+
+    /// Check if the routing table is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,362 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::sync::Arc;
+
+use log::info;
+use smol::{channel, future, Executor};
+use url::Url;
+
+use super::{
+    proto::DhtPing, routing::distance, Dht, DhtNode, DhtPtr, ProviderStore, RoutingTable,
+    DHT_CAPABILITY, K, KEYFILE_NAME, MAX_PROVIDERS, MAX_PROVIDER_KEYS, MAX_PROVIDER_RECORDS,
+};
+use crate::{
+    dht::proto::ProtocolDht,
+    net::{ChannelPtr, P2p, P2pPtr, Settings, SESSION_ALL},
+    system::sleep,
+    Error,
+};
+
+/// Number of nodes to spawn in the network test
+const N_NODES: usize = 8;
+
+fn make_node(id: [u8; 32]) -> DhtNode {
+    DhtNode { id: id.into(), addresses: vec![Url::parse("tcp://127.0.0.1:1").unwrap()] }
+}
+
+#[test]
+fn dht_routing_table() {
+    let local = [0u8; 32];
+    let mut table = RoutingTable::new(local.into());
+
+    // We never insert ourselves
+    assert!(!table.insert(make_node(local)));
+    assert!(table.is_empty());
+
+    // Fill the bucket of nodes with the first bit set
+    for i in 0..K {
+        let mut id = [0u8; 32];
+        id[0] = 0x80;
+        id[31] = i as u8;
+        assert!(table.insert(make_node(id)));
+    }
+
+    // The bucket is full, the new node is dropped
+    let mut id = [0u8; 32];
+    id[0] = 0x80;
+    id[31] = 0xff;
+    assert!(!table.insert(make_node(id)));
+    assert!(!table.contains(&id.into()));
+
+    // Known nodes are still refreshed
+    let mut id = [0u8; 32];
+    id[0] = 0x80;
+    assert!(table.insert(make_node(id)));
+
+    // Other buckets still have room
+    let mut near = [0u8; 32];
+    near[31] = 1;
+    assert!(table.insert(make_node(near)));
+    assert_eq!(table.len(), K + 1);
+
+    // Removing makes room again
+    table.remove(&id.into());
+    assert!(!table.contains(&id.into()));
+    let mut id = [0u8; 32];
+    id[0] = 0x80;
+    id[31] = 0xff;
+    assert!(table.insert(make_node(id)));
+
+    // Closest nodes are sorted by distance to the key
+    let closest = table.closest(&local.into(), 3);
+    assert_eq!(closest.len(), 3);
+    assert_eq!(closest[0].id, near.into());
+    for pair in closest.windows(2) {
+        assert!(distance(&local.into(), &pair[0].id) < distance(&local.into(), &pair[1].id));
+    }
+}
+
+#[test]
+fn dht_keyfile() {
+    let ex = Arc::new(Executor::new());
+    let datastore = std::env::temp_dir().join("darkfi_test_dht_keyfile");
+    let _ = std::fs::remove_dir_all(&datastore);
+
+    smol::block_on(async {
+        let p2p = P2p::new(Settings::default(), ex.clone()).await;
+
+        // The node keeps its ID across restarts
+        let dht = Dht::new_with_datastore(p2p.clone(), &datastore, ex.clone()).unwrap();
+        let restarted = Dht::new_with_datastore(p2p.clone(), &datastore, ex.clone()).unwrap();
+        assert_eq!(restarted.node.id, dht.node.id);
+        dht.stop().await;
+        restarted.stop().await;
+
+        let path = datastore.join(KEYFILE_NAME);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // A public key that doesn't belong to the secret key is refused
+        let mut bytes = dht.keypair.sk[..32].to_vec();
+        bytes.extend_from_slice(&ed25519_compact::KeyPair::generate().pk[..]);
+        std::fs::write(&path, bs58::encode(bytes).into_string()).unwrap();
+        assert!(matches!(
+            Dht::new_with_datastore(p2p, &datastore, ex.clone()),
+            Err(Error::DhtInvalidKeyfile)
+        ));
+    });
+
+    std::fs::remove_dir_all(datastore).unwrap();
+}
+
+#[test]
+fn dht_provider_records() {
+    let ex = Arc::new(Executor::new());
+
+    smol::block_on(async {
+        let p2p = P2p::new(Settings::default(), ex.clone()).await;
+        let dht = Dht::new(p2p, ex.clone());
+        let key = blake3::hash(b"key");
+
+        let provider = make_node([1u8; 32]);
+        dht.add_provider(key, provider.clone()).await;
+        dht.add_provider(key, provider.clone()).await;
+        assert_eq!(dht.local_providers(&key).await, vec![provider.clone()]);
+        assert!(dht.local_providers(&blake3::hash(b"other")).await.is_empty());
+
+        // We don't store records of ourselves
+        dht.add_provider(key, dht.node().clone()).await;
+        assert_eq!(dht.local_providers(&key).await.len(), 1);
+
+        // Expired records are not returned, and pruned
+        dht.providers.write().await.records.get_mut(&key).unwrap()[0].expires = 0;
+        assert!(dht.local_providers(&key).await.is_empty());
+        dht.prune_providers().await;
+        assert!(dht.providers.read().await.records.is_empty());
+        assert_eq!(dht.providers.read().await.len, 0);
+
+        // Without known nodes, lookups only return what we have locally
+        dht.add_provider(key, provider.clone()).await;
+        assert!(dht.find_node(&key).await.is_empty());
+        assert_eq!(dht.find_providers(&key).await, vec![provider]);
+    });
+}
+
+#[test]
+fn dht_provider_store_limits() {
+    let mut store = ProviderStore::default();
+    let key = blake3::hash(b"key");
+
+    // Records per key are capped, dropping the one expiring first
+    for i in 0..MAX_PROVIDERS + 1 {
+        assert!(store.insert(key, make_node([i as u8; 32]), u64::MAX - i as u64));
+    }
+    assert_eq!(store.records[&key].len(), MAX_PROVIDERS);
+    assert_eq!(store.len, MAX_PROVIDERS);
+    let has = |store: &ProviderStore, i: usize| {
+        store.records[&key].iter().any(|r| r.node.id == [i as u8; 32].into())
+    };
+    assert!(!has(&store, MAX_PROVIDERS - 1));
+    assert!(has(&store, MAX_PROVIDERS));
+
+    // The number of keys is capped
+    let mut store = ProviderStore::default();
+    for i in 0..MAX_PROVIDER_KEYS {
+        assert!(store.insert(blake3::hash(&i.to_le_bytes()), make_node([1; 32]), u64::MAX));
+    }
+    assert!(!store.insert(key, make_node([1; 32]), u64::MAX));
+
+    // Expired records make room
+    store.records.values_mut().next().unwrap()[0].expires = 0;
+    assert!(store.insert(key, make_node([1; 32]), u64::MAX));
+    assert_eq!(store.records.len(), MAX_PROVIDER_KEYS);
+
+    // And so is the total number of records
+    let mut store = ProviderStore::default();
+    let keys = MAX_PROVIDER_RECORDS / MAX_PROVIDERS;
+    for i in 0..keys {
+        for j in 0..MAX_PROVIDERS {
+            let node = make_node([j as u8; 32]);
+            assert!(store.insert(blake3::hash(&i.to_le_bytes()), node, u64::MAX));
+        }
+    }
+    assert_eq!(store.len, MAX_PROVIDER_RECORDS);
+    assert!(!store.insert(blake3::hash(&keys.to_le_bytes()), make_node([1; 32]), u64::MAX));
+}
+
+#[test]
+fn dht_node_ids() {
+    let ex = Arc::new(Executor::new());
+
+    smol::block_on(async {
+        let p2p = P2p::new(Settings::default(), ex.clone()).await;
+        let dht = Dht::new(p2p.clone(), ex.clone());
+        let other = Dht::new(p2p, ex.clone());
+        let challenge = [7u8; 32];
+
+        let ping = dht.ping(&challenge);
+        assert!(Dht::verify_ping(&ping, &challenge));
+
+        // The signature is bound to the challenge
+        assert!(!Dht::verify_ping(&ping, &[8u8; 32]));
+
+        // Nodes can't claim the ID of others, nor other addresses
+        let DhtPing(node, public, signature) = other.ping(&challenge);
+        let impostor = DhtPing(dht.node().clone(), public, signature);
+        assert!(!Dht::verify_ping(&impostor, &challenge));
+
+        let mut moved = node.clone();
+        moved.addresses = vec![Url::parse("tcp://127.0.0.1:2").unwrap()];
+        assert!(!Dht::verify_ping(&DhtPing(moved, public, signature), &challenge));
+        assert!(Dht::verify_ping(&DhtPing(node, public, signature), &challenge));
+
+        dht.stop().await;
+        other.stop().await;
+    });
+}
+
+#[test]
+#[ignore]
+fn dht_lookup() {
+    let mut cfg = simplelog::ConfigBuilder::new();
+    cfg.add_filter_ignore("net".to_string());
+
+    simplelog::TermLogger::init(
+        simplelog::LevelFilter::Debug,
+        cfg.build(),
+        simplelog::TerminalMode::Mixed,
+        simplelog::ColorChoice::Auto,
+    )
+    .unwrap();
+
+    let ex = Arc::new(Executor::new());
+    let ex_ = ex.clone();
+    let (signal, shutdown) = channel::unbounded::<()>();
+
+    easy_parallel::Parallel::new()
+        .each(0..N_NODES, |_| future::block_on(ex.run(shutdown.recv())))
+        .finish(|| {
+            future::block_on(async {
+                dht_lookup_real(ex_).await;
+                drop(signal);
+            })
+        });
+}
+
+/// Create a DHT node listening on the given port and connecting to the
+/// given peers once its P2P network is started. Without `capability`, the
+/// node runs the protocol without advertising it, like an older node.
+async fn make_dht(
+    port: usize,
+    peers: &[usize],
+    capability: bool,
+    ex: Arc<Executor<'static>>,
+) -> DhtPtr {
+    let addr = Url::parse(&format!("memory://dht-node:{}", port)).unwrap();
+    let settings = Settings {
+        localnet: true,
+        inbound_addrs: vec![addr.clone()],
+        external_addrs: vec![addr],
+        outbound_connections: 0,
+        outbound_connect_timeout: 2,
+        inbound_connections: usize::MAX,
+        peers: peers
+            .iter()
+            .map(|port| Url::parse(&format!("memory://dht-node:{}", port)).unwrap())
+            .collect(),
+        allowed_transports: vec!["memory".to_string()],
+        ..Default::default()
+    };
+
+    let p2p = P2p::new(settings, ex.clone()).await;
+    let dht = Dht::new(p2p.clone(), ex.clone());
+    let dht_ = dht.clone();
+
+    let constructor = move |channel: ChannelPtr, _: P2pPtr| {
+        let dht_ = dht_.clone();
+        async move { ProtocolDht::init(dht_, channel).await.unwrap() }
+    };
+
+    let registry = p2p.protocol_registry();
+    if capability {
+        registry.register_with_capability(SESSION_ALL, DHT_CAPABILITY, constructor).await;
+    } else {
+        registry.register(SESSION_ALL, constructor).await;
+    }
+
+    dht
+}
+
+async fn dht_lookup_real(ex: Arc<Executor<'static>>) {
+    let mut dhts = vec![];
+
+    // Every node only connects to the previous one, so lookups have
+    // to hop through the network.
+    for i in 0..N_NODES {
+        let peers = if i > 0 { vec![13400 + i - 1] } else { vec![] };
+        dhts.push(make_dht(13400 + i, &peers, true, ex.clone()).await);
+    }
+
+    // A node that doesn't advertise the DHT, connected to the first one
+    let legacy = make_dht(13400 + N_NODES, &[13400], false, ex.clone()).await;
+
+    for dht in dhts.iter().chain([&legacy]) {
+        dht.p2p.clone().start().await.unwrap();
+    }
+
+    info!("Waiting 5s until all peers connect");
+    sleep(5).await;
+
+    // Bootstrap every node by looking up its own ID
+    for dht in dhts.iter() {
+        dht.find_node(&dht.node().id).await;
+    }
+
+    for (i, dht) in dhts.iter().enumerate() {
+        assert!(dht.known_nodes().await > 1, "Node {}", i);
+    }
+
+    // The first node provides a key, the last one looks for it
+    let key = blake3::hash(b"dht_lookup");
+    dhts[0].announce(&key).await;
+
+    let providers = dhts[N_NODES - 1].find_providers(&key).await;
+    assert_eq!(providers.len(), 1);
+    assert_eq!(providers[0].id, dhts[0].node().id);
+
+    // Nobody provides this one
+    assert!(dhts[N_NODES - 1].find_providers(&blake3::hash(b"none")).await.is_empty());
+
+    // The first node skipped the DHT protocol on its channel to the node
+    // without the capability, so neither of them learned about the other.
+    assert_eq!(legacy.known_nodes().await, 0);
+    let found = dhts[0].find_node(&legacy.node().id).await;
+    assert!(found.iter().all(|node| node.id != legacy.node().id));
+    let addr = &legacy.node().addresses[0];
+    assert!(matches!(dhts[0].connect(addr).await, Err(Error::DhtNotSupported)));
+
+    for dht in dhts.iter().chain([&legacy]) {
+        dht.stop().await;
+        dht.p2p.stop().await;
+    }
+}
@@ -168,6 +168,9 @@ pub enum Error {
     #[error("Malformed packet")]
     MalformedPacket,
 
+    #[error("Packet too large: {0} bytes")]
+    PacketTooLarge(u64),
+
     #[error("Socks proxy error: {0}")]
     SocksError(String),
 
@@ -238,6 +241,9 @@ pub enum Error {
     #[error("Invalid DNS Name {0}")]
     RustlsInvalidDns(String),
 
+    #[error("Noise error: {0}")]
+    NoiseError(String),
+
     #[error("unable to decrypt rcpt")]
     TxRcptDecryptionError,
 
@@ -566,12 +572,30 @@ pub enum Error {
     #[error("Geode chunk route not found")]
     GeodeChunkRouteNotFound,
 
+    #[error("Geode manifest is invalid")]
+    GeodeInvalidManifest,
+
+    #[error("Geode file does not match its hash")]
+    GeodeFileMismatch,
+
+    #[error("DHT node failed to prove its ID")]
+    DhtNodeUnverified,
+
+    #[error("Peer does not support the DHT")]
+    DhtNotSupported,
+
+    #[error("DHT keyfile is invalid")]
+    DhtInvalidKeyfile,
+
     // ==================
     // Event Graph errors
     // ==================
     #[error("DAG sync failed")]
     DagSyncFailed,
 
+    #[error("Invalid event graph topic: {0}")]
+    DagInvalidTopic(String),
+
     // =========
     // Catch-all
     // =========
@@ -655,6 +679,12 @@ pub enum RpcError {
 
     #[error("IO Error: {0}")]
     IoError(std::io::ErrorKind),
+
+    #[error("Malformed HTTP request: {0}")]
+    MalformedHttp(String),
+
+    #[error("Malformed WebSocket frame: {0}")]
+    MalformedWebSocket(String),
 }
 
 #[cfg(feature = "rpc")]
@@ -771,6 +801,13 @@ impl From<async_rustls::rustls::client::InvalidDnsNameError> for Error {
     }
 }
 
+#[cfg(feature = "snow")]
+impl From<snow::Error> for Error {
+    fn from(err: snow::Error) -> Self {
+        Self::NoiseError(err.to_string())
+    }
+}
+
 #[cfg(feature = "serde_json")]
 impl From<serde_json::Error> for Error {
     fn from(err: serde_json::Error) -> Self {
@@ -0,0 +1,323 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Admission control for events received from the network.
+//!
+//! An [`EventAdmission`] checks the admission stamp an event carries,
+//! which proves the author spent something to publish it. Events that
+//! fail admission are considered spam: they are not inserted into the
+//! DAG nor relayed, and count against the peer that sent them.
+
+use super::Event;
+
+/// Admission control deciding whether events received from the network
+/// may enter the DAG. See [`PowAdmission`] and [`RlnAdmission`].
+pub trait EventAdmission: Send + Sync {
+    /// Check the admission stamp of the given event
+    fn admit(&self, event: &Event) -> bool;
+}
+
+/// Hashcash-style proof of work. The stamp is a little-endian `u64`
+/// nonce such that the BLAKE3 hash of the event ID followed by the
+/// nonce has at least `difficulty` leading zero bits.
+pub struct PowAdmission {
+    difficulty: u32,
+}
+
+impl PowAdmission {
+    /// Create a new [`PowAdmission`] requiring the given number of
+    /// leading zero bits
+    pub fn new(difficulty: u32) -> Self {
+        Self { difficulty }
+    }
+
+    /// Count the leading zero bits of the PoW hash for the given nonce
+    fn work(event_id: &blake3::Hash, nonce: u64) -> u32 {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(event_id.as_bytes());
+        hasher.update(&nonce.to_le_bytes());
+
+        let mut zeros = 0;
+        for byte in hasher.finalize().as_bytes() {
+            zeros += byte.leading_zeros();
+            if *byte != 0 {
+                break
+            }
+        }
+
+        zeros
+    }
+
+    /// Find a nonce for the event with the given difficulty and set it as
+    /// the event's stamp. The stamp is bound to the event ID, which commits
+    /// to the author's public key but not to the signature. Changing the
+    /// author, timestamp, content or parents invalidates the stamp, while
+    /// re-signing with the same key does not.
+    pub fn stamp(event: &mut Event, difficulty: u32) {
+        let event_id = event.id();
+        let mut nonce = 0u64;
+        while Self::work(&event_id, nonce) < difficulty {
+            nonce += 1;
+        }
+
+        event.set_stamp(nonce.to_le_bytes().to_vec());
+    }
+}
+
+impl EventAdmission for PowAdmission {
+    fn admit(&self, event: &Event) -> bool {
+        let Ok(nonce) = event.stamp().try_into() else { return false };
+        Self::work(&event.id(), u64::from_le_bytes(nonce)) >= self.difficulty
+    }
+}
+
+#[cfg(feature = "zk")]
+pub use rln::{RlnAdmission, RlnIdentity, RlnStamp};
+
+#[cfg(feature = "zk")]
+mod rln {
+    use std::{
+        collections::{BTreeMap, HashMap, HashSet},
+        sync::{Mutex, RwLock},
+        time::UNIX_EPOCH,
+    };
+
+    use darkfi_sdk::{
+        crypto::{poseidon_hash, MerkleNode},
+        pasta::{
+            group::ff::{FromUniformBytes, PrimeField},
+            pallas,
+        },
+    };
+    use darkfi_serial::{deserialize, serialize, SerialDecodable, SerialEncodable};
+    use log::warn;
+    use rand::rngs::OsRng;
+
+    use super::{Event, EventAdmission};
+    use crate::{
+        zk::{halo2::Value, Proof, ProvingKey, VerifyingKey, Witness, ZkCircuit},
+        zkas::ZkBinary,
+        Error, Result,
+    };
+
+    /// Derivation path of the identity commitment, as used in the circuit
+    const IDENTITY_DERIVATION_PATH: u64 = 11;
+    /// Derivation path of the internal nullifier, as used in the circuit
+    const NULLIFIER_DERIVATION_PATH: u64 = 12;
+    /// Number of future epochs accepted to account for clock drift
+    const FUTURE_EPOCHS: u64 = 1;
+
+    /// Rate-limiting nullifier stamp of an event, see
+    /// <https://darkrenaissance.github.io/darkfi/crypto/rln.html>
+    #[derive(Clone, SerialEncodable, SerialDecodable)]
+    pub struct RlnStamp {
+        /// Epoch the event was published in
+        pub epoch: u64,
+        /// Root of the membership tree the author is in
+        pub root: pallas::Base,
+        /// Internal nullifier, the same for all signals of an author in an epoch
+        pub nullifier: pallas::Base,
+        /// Share of the author's secret key
+        pub y: pallas::Base,
+        /// Proof of the signal
+        pub proof: Proof,
+    }
+
+    /// Membership of an author in the RLN membership tree
+    pub struct RlnIdentity {
+        /// Secret key of the author
+        pub secret_key: pallas::Base,
+        /// Position of the identity commitment in the membership tree
+        pub leaf_pos: u32,
+        /// Authentication path of the identity commitment
+        pub path: [MerkleNode; 32],
+        /// Root of the membership tree
+        pub root: pallas::Base,
+    }
+
+    impl RlnIdentity {
+        /// Return the identity commitment to insert into the membership tree
+        pub fn commitment(&self) -> pallas::Base {
+            poseidon_hash([pallas::Base::from(IDENTITY_DERIVATION_PATH), self.secret_key])
+        }
+    }
+
+    /// Rate-limiting nullifiers. Every member of the membership tree can
+    /// publish one event per epoch. Publishing more reveals shares of the
+    /// member's secret key, and the extra events are rejected.
+    pub struct RlnAdmission {
+        /// Verifying key of the signal circuit
+        vk: VerifyingKey,
+        /// Application specific RLN identifier
+        rln_identifier: pallas::Base,
+        /// Length of an epoch in seconds
+        epoch_len: u64,
+        /// Number of past epochs signals are accepted and remembered for
+        window: u64,
+        /// Accepted membership tree roots
+        roots: RwLock<HashSet<[u8; 32]>>,
+        /// Signals seen per epoch, mapping nullifiers to message hashes
+        signals: Mutex<BTreeMap<u64, HashMap<[u8; 32], [u8; 32]>>>,
+    }
+
+    /// Map an event ID to the message hash signalled in the circuit
+    fn message_hash(event: &Event) -> pallas::Base {
+        let mut wide = [0u8; 64];
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(event.id().as_bytes());
+        hasher.finalize_xof().fill(&mut wide);
+        pallas::Base::from_uniform_bytes(&wide)
+    }
+
+    impl RlnAdmission {
+        /// Create a new [`RlnAdmission`] with the signal circuit's verifying key.
+        /// Events from more than `window` epochs ago are rejected, since we
+        /// no longer know which nullifiers were used in them. The window
+        /// should cover the DAG's lifetime so older events can still be synced.
+        pub fn new(
+            vk: VerifyingKey,
+            rln_identifier: pallas::Base,
+            epoch_len: u64,
+            window: u64,
+        ) -> Self {
+            assert!(epoch_len > 0);
+            Self {
+                vk,
+                rln_identifier,
+                epoch_len,
+                window,
+                roots: RwLock::new(HashSet::new()),
+                signals: Mutex::new(BTreeMap::new()),
+            }
+        }
+
+        /// Accept signals made against the given membership tree root
+        pub fn add_root(&self, root: pallas::Base) {
+            self.roots.write().unwrap().insert(root.to_repr());
+        }
+
+        /// Stop accepting signals made against the given membership tree root
+        pub fn remove_root(&self, root: pallas::Base) {
+            self.roots.write().unwrap().remove(&root.to_repr());
+        }
+
+        /// Create the RLN stamp of an event and set it on the event.
+        /// `zkbin` and `pk` are the signal circuit and its proving key.
+        pub fn stamp(
+            event: &mut Event,
+            identity: &RlnIdentity,
+            zkbin: &ZkBinary,
+            pk: &ProvingKey,
+            rln_identifier: pallas::Base,
+            epoch_len: u64,
+        ) -> Result<()> {
+            let epoch = event.timestamp / epoch_len;
+            let message_hash = message_hash(event);
+
+            let external_nullifier = poseidon_hash([pallas::Base::from(epoch), rln_identifier]);
+            let a_1 = poseidon_hash([identity.secret_key, external_nullifier]);
+            let nullifier = poseidon_hash([pallas::Base::from(NULLIFIER_DERIVATION_PATH), a_1]);
+            let y = a_1 * message_hash + identity.secret_key;
+
+            let witnesses = vec![
+                Witness::Base(Value::known(identity.secret_key)),
+                Witness::MerklePath(Value::known(identity.path)),
+                Witness::Uint32(Value::known(identity.leaf_pos)),
+                Witness::Base(Value::known(message_hash)),
+                Witness::Base(Value::known(pallas::Base::from(epoch))),
+                Witness::Base(Value::known(rln_identifier)),
+            ];
+            let public_inputs = vec![
+                pallas::Base::from(epoch),
+                rln_identifier,
+                message_hash,
+                identity.root,
+                nullifier,
+                y,
+            ];
+
+            let circuit = ZkCircuit::new(witnesses, zkbin);
+            let proof = Proof::create(pk, &[circuit], &public_inputs, &mut OsRng)
+                .map_err(|e| Error::Custom(format!("Failed creating RLN proof: {}", e)))?;
+
+            let stamp = RlnStamp { epoch, root: identity.root, nullifier, y, proof };
+            event.set_stamp(serialize(&stamp));
+            Ok(())
+        }
+    }
+
+    impl EventAdmission for RlnAdmission {
+        fn admit(&self, event: &Event) -> bool {
+            let Ok(stamp) = deserialize::<RlnStamp>(event.stamp()) else { return false };
+
+            // The stamp must be for the epoch the event claims to be from
+            if stamp.epoch != event.timestamp / self.epoch_len {
+                return false
+            }
+
+            // The epoch must be within the window around the current time,
+            // otherwise we can't tell whether the nullifier was reused.
+            let current = UNIX_EPOCH.elapsed().unwrap().as_secs() / self.epoch_len;
+            let oldest = current.saturating_sub(self.window);
+            if stamp.epoch < oldest || stamp.epoch > current + FUTURE_EPOCHS {
+                return false
+            }
+
+            if !self.roots.read().unwrap().contains(&stamp.root.to_repr()) {
+                return false
+            }
+
+            let message_hash = message_hash(event);
+            let public_inputs = vec![
+                pallas::Base::from(stamp.epoch),
+                self.rln_identifier,
+                message_hash,
+                stamp.root,
+                stamp.nullifier,
+                stamp.y,
+            ];
+            if stamp.proof.verify(&self.vk, &public_inputs).is_err() {
+                return false
+            }
+
+            // Allow one message per nullifier and epoch. Seeing the same
+            // message again is fine, it's just being relayed.
+            let mut signals = self.signals.lock().unwrap();
+            let epoch_signals = signals.entry(stamp.epoch).or_default();
+            match epoch_signals.get(&stamp.nullifier.to_repr()) {
+                Some(seen) if seen != &message_hash.to_repr() => {
+                    warn!(
+                        target: "event_graph::admission::rln",
+                        "[EVENTGRAPH] RLN nullifier reused in epoch {}, rejecting event {}",
+                        stamp.epoch, event.id(),
+                    );
+                    return false
+                }
+                Some(_) => {}
+                None => {
+                    epoch_signals.insert(stamp.nullifier.to_repr(), message_hash.to_repr());
+                }
+            }
+
+            // Forget about epochs that left the window
+            *signals = signals.split_off(&oldest);
+
+            true
+        }
+    }
+}
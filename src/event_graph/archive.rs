@@ -0,0 +1,228 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Archive of pruned DAG events.
+//!
+//! In archive mode, the events of a rotation period are written to an
+//! append-only file before the DAG is pruned, so they are not lost when
+//! the period ends. Every period has its own file, named after the
+//! timestamp of its genesis event. A file is a sequence of frames, each
+//! being the little-endian `u64` length of a deflate-compressed,
+//! serialized `Vec<Event>`, followed by the compressed data. Events in
+//! a frame are ordered parents first, and every event is archived once,
+//! so archiving the same period again only appends the events that are
+//! not in its file yet. A frame cut short by a crash is dropped.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use darkfi_serial::{deserialize_async, serialize_async};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use log::{debug, info, warn};
+use smol::{
+    fs::{self, OpenOptions},
+    io::AsyncWriteExt,
+};
+
+use super::{Event, EventGraph, NULL_ID};
+use crate::{Error, Result};
+
+/// File extension of archive files
+const ARCHIVE_EXT: &str = "dag";
+
+/// Return the archive directory of the given topic inside `path`
+pub(super) fn topic_archive_path(path: &Path, topic: &str) -> PathBuf {
+    let name: String = topic.bytes().map(|b| format!("{:02x}", b)).collect();
+    path.join(name)
+}
+
+/// Read all complete frames of an archive file. Returns the events along
+/// with the length of the file they span, which is shorter than the file
+/// if its last frame was cut short.
+async fn read_archive_file(path: &Path) -> Result<(Vec<Event>, u64)> {
+    let data = fs::read(path).await?;
+
+    let mut events = vec![];
+    let mut pos = 0;
+    while pos < data.len() {
+        let complete = data.len() - pos >= 8 && {
+            let len = u64::from_le_bytes(data[pos..pos + 8].try_into().unwrap());
+            (data.len() - pos - 8) as u64 >= len
+        };
+
+        if !complete {
+            warn!(
+                target: "event_graph::archive",
+                "[EVENTGRAPH] Dropping truncated frame at the end of {:?}", path,
+            );
+            break
+        }
+
+        let len = u64::from_le_bytes(data[pos..pos + 8].try_into().unwrap()) as usize;
+        pos += 8;
+
+        let mut frame = vec![];
+        DeflateDecoder::new(&data[pos..pos + len]).read_to_end(&mut frame)?;
+        let mut frame_events: Vec<Event> = deserialize_async(&frame).await?;
+        events.append(&mut frame_events);
+        pos += len;
+    }
+
+    Ok((events, pos as u64))
+}
+
+impl EventGraph {
+    /// Return the archive directory, if archive mode is enabled
+    pub fn archive_path(&self) -> Option<&Path> {
+        self.archive.as_deref()
+    }
+
+    /// Append the events of the current rotation period that are not
+    /// archived yet to its archive file. This is called right before
+    /// pruning, without the DAG locked. Returns the IDs of the events
+    /// that are safe to prune, i.e. the archived ones and the genesis.
+    pub(super) async fn archive_dag(&self, path: &Path) -> Result<HashSet<blake3::Hash>> {
+        let mut genesis = None;
+        let mut events = HashMap::new();
+        for iter_elem in self.dag.iter() {
+            let (_, event) = iter_elem?;
+            let event: Event = deserialize_async(&event).await?;
+
+            // The genesis event is the only one without parents
+            if event.parents.iter().all(|p| p == &NULL_ID) {
+                genesis = Some((event.id(), event.timestamp));
+                continue
+            }
+
+            events.insert(event.id(), event);
+        }
+
+        // Without a genesis there is no period to archive the events in
+        let Some((genesis_id, genesis_timestamp)) = genesis else {
+            return Ok(events.into_keys().collect())
+        };
+
+        // Skip what a previous run already archived, e.g. if we crashed
+        // before pruning, and drop what it left half-written.
+        let file_path = path.join(format!("{}.{}", genesis_timestamp, ARCHIVE_EXT));
+        let mut archived_len = 0;
+        let mut archived_ids = HashSet::from([genesis_id]);
+        if file_path.exists() {
+            let (archived, len) = read_archive_file(&file_path).await?;
+            archived_ids.extend(archived.iter().map(|e| e.id()));
+            events.retain(|id, _| !archived_ids.contains(id));
+            archived_len = len;
+        }
+
+        if events.is_empty() {
+            debug!(target: "event_graph::archive_dag()", "Nothing to archive");
+            return Ok(archived_ids)
+        }
+
+        archived_ids.extend(events.keys().copied());
+
+        let events = self.order_for_insertion(events)?;
+        info!(
+            target: "event_graph::archive_dag()",
+            "[EVENTGRAPH] Archiving {} events of period {}", events.len(), genesis_timestamp,
+        );
+
+        let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+        encoder.write_all(&serialize_async(&events).await)?;
+        let compressed = encoder.finish()?;
+
+        let mut frame = Vec::with_capacity(8 + compressed.len());
+        frame.extend_from_slice(&(compressed.len() as u64).to_le_bytes());
+        frame.extend_from_slice(&compressed);
+
+        fs::create_dir_all(path).await?;
+        let mut file = OpenOptions::new().create(true).append(true).open(file_path).await?;
+        file.set_len(archived_len).await?;
+        file.write_all(&frame).await?;
+        file.sync_all().await?;
+
+        Ok(archived_ids)
+    }
+
+    /// Return the archived rotation periods, identified by the timestamp
+    /// of their genesis event, oldest first.
+    pub async fn archived_periods(&self) -> Result<Vec<u64>> {
+        let Some(path) = &self.archive else { return Ok(vec![]) };
+        if !path.exists() {
+            return Ok(vec![])
+        }
+
+        let mut periods = vec![];
+        let mut entries = fs::read_dir(path).await?;
+        while let Some(entry) = smol::stream::StreamExt::next(&mut entries).await {
+            let file_path = entry?.path();
+            if file_path.extension().and_then(|e| e.to_str()) != Some(ARCHIVE_EXT) {
+                continue
+            }
+
+            let Some(period) = file_path.file_stem().and_then(|s| s.to_str()) else { continue };
+            if let Ok(period) = period.parse() {
+                periods.push(period);
+            }
+        }
+
+        periods.sort_unstable();
+        Ok(periods)
+    }
+
+    /// Return the archived events of the given rotation period, parents first.
+    pub async fn archived_events(&self, period: u64) -> Result<Vec<Event>> {
+        let Some(path) = &self.archive else { return Ok(vec![]) };
+
+        let file_path = path.join(format!("{}.{}", period, ARCHIVE_EXT));
+        if !file_path.exists() {
+            return Ok(vec![])
+        }
+
+        Ok(read_archive_file(&file_path).await?.0)
+    }
+
+    /// Search the archived events with the given predicate, returning up
+    /// to `limit` of the most recent matching events, oldest first. Periods
+    /// are read one at a time, newest first, and the search stops as soon
+    /// as enough events were found, so only as much of the archive is loaded
+    /// as needed.
+    pub async fn search_archive(
+        &self,
+        predicate: impl Fn(&Event) -> bool,
+        limit: usize,
+    ) -> Result<Vec<Event>> {
+        let mut found = vec![];
+        for period in self.archived_periods().await?.into_iter().rev() {
+            if found.len() >= limit {
+                break
+            }
+
+            let mut events = self.archived_events(period).await?;
+            events.retain(|event| predicate(event));
+            events.append(&mut found);
+            found = events;
+        }
+
+        let skip = found.len().saturating_sub(limit);
+        Ok(found.split_off(skip))
+    }
+}
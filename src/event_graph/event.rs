@@ -18,10 +18,24 @@
 
 use std::{collections::HashSet, time::UNIX_EPOCH};
 
+use darkfi_sdk::crypto::{
+    schnorr::{SchnorrPublic, SchnorrSecret, Signature},
+    PublicKey, SecretKey,
+};
 use darkfi_serial::{async_trait, Encodable, SerialDecodable, SerialEncodable};
+use rand::rngs::OsRng;
 
 use super::{EventGraphPtr, EVENT_TIME_DRIFT, NULL_ID, N_EVENT_PARENTS};
 
+/// Author of a signed [`Event`], along with their signature over the event ID
+#[derive(Debug, Clone, SerialEncodable, SerialDecodable)]
+pub struct EventAuthor {
+    /// Public key of the author
+    pub public: PublicKey,
+    /// Schnorr signature over the event ID
+    pub signature: Signature,
+}
+
 /// Representation of an event in the Event Graph
 #[derive(Debug, Clone, SerialEncodable, SerialDecodable)]
 pub struct Event {
@@ -31,6 +45,26 @@ pub struct Event {
     pub(super) content: Vec<u8>,
     /// Parent nodes in the event DAG
     pub(super) parents: [blake3::Hash; N_EVENT_PARENTS],
+    /// Author of the event, if it is signed
+    pub(super) author: Option<EventAuthor>,
+    /// Admission stamp of the event, checked by the [`EventAdmission`]
+    /// of the receiving nodes. Like the signature, it is not part of the
+    /// event ID.
+    ///
+    /// [`EventAdmission`]: super::EventAdmission
+    pub(super) stamp: Vec<u8>,
+}
+
+/// Compact header of an [`Event`], exchanged during DAG sync before
+/// fetching the event bodies
+#[derive(Debug, Clone, SerialEncodable, SerialDecodable)]
+pub struct EventHeader {
+    /// ID of the event
+    pub id: blake3::Hash,
+    /// Timestamp of the event
+    pub timestamp: u64,
+    /// Parent nodes in the event DAG
+    pub parents: [blake3::Hash; N_EVENT_PARENTS],
 }
 
 impl Event {
@@ -44,15 +78,41 @@ impl Event {
             timestamp: UNIX_EPOCH.elapsed().unwrap().as_secs(),
             content: data,
             parents: event_graph.get_unreferenced_tips().await,
+            author: None,
+            stamp: vec![],
         }
     }
 
-    /// Hash the [`Event`] to retrieve its ID
+    /// Create a new event like [`Event::new`], signed by the given secret key.
+    pub async fn new_signed(data: Vec<u8>, secret: &SecretKey, event_graph: EventGraphPtr) -> Self {
+        let mut event = Self::new(data, event_graph).await;
+        event.sign(secret);
+        event
+    }
+
+    /// Sign the event with the given secret key. The author's public key is
+    /// part of the event ID, so this changes the ID of the event.
+    pub fn sign(&mut self, secret: &SecretKey) {
+        let public = PublicKey::from_secret(*secret);
+        let signature = secret.sign(&mut OsRng, self.id_with_author(Some(&public)).as_bytes());
+        self.author = Some(EventAuthor { public, signature });
+    }
+
+    /// Hash the [`Event`] to retrieve its ID. For signed events, the ID
+    /// commits to the author's public key, but not to the signature.
     pub fn id(&self) -> blake3::Hash {
+        self.id_with_author(self.author.as_ref().map(|author| &author.public))
+    }
+
+    /// ID of the event, as authored by the given public key, if any
+    fn id_with_author(&self, author: Option<&PublicKey>) -> blake3::Hash {
         let mut hasher = blake3::Hasher::new();
         self.timestamp.encode(&mut hasher).unwrap();
         self.content.encode(&mut hasher).unwrap();
         self.parents.encode(&mut hasher).unwrap();
+        if let Some(public) = author {
+            public.encode(&mut hasher).unwrap();
+        }
         hasher.finalize()
     }
 
@@ -61,6 +121,35 @@ impl Event {
         &self.content
     }
 
+    /// Return the event's admission stamp
+    pub fn stamp(&self) -> &[u8] {
+        &self.stamp
+    }
+
+    /// Set the event's admission stamp
+    pub fn set_stamp(&mut self, stamp: Vec<u8>) {
+        self.stamp = stamp;
+    }
+
+    /// Return the event's header
+    pub fn header(&self) -> EventHeader {
+        EventHeader { id: self.id(), timestamp: self.timestamp, parents: self.parents }
+    }
+
+    /// Return the public key of the event's author, if the event is signed
+    pub fn author(&self) -> Option<PublicKey> {
+        self.author.as_ref().map(|author| author.public)
+    }
+
+    /// Verify the author's signature. Unsigned events have nothing to
+    /// verify, so this returns `true` for them.
+    pub fn verify_signature(&self) -> bool {
+        match &self.author {
+            Some(author) => author.public.verify(self.id().as_bytes(), &author.signature),
+            None => true,
+        }
+    }
+
     /*
     /// Check if an [`Event`] is considered too old.
     fn is_too_old(&self) -> bool {
@@ -71,11 +160,6 @@ impl Event {
     /// Validate a new event for the correct layout and enforce relevant age,
     /// assuming some possibility for a time drift.
     pub fn validate(&self) -> bool {
-        // Let's not bother with empty events
-        if self.content.is_empty() {
-            return false
-        }
-
         // Check if the event is too old or too new
         let now = UNIX_EPOCH.elapsed().unwrap().as_secs();
         let too_old = self.timestamp < now - EVENT_TIME_DRIFT;
@@ -85,6 +169,17 @@ impl Event {
             return false
         }
 
+        self.validate_layout()
+    }
+
+    /// Validate the layout of an event regardless of its age. Used for
+    /// events fetched during sync, which can be as old as the DAG.
+    pub fn validate_layout(&self) -> bool {
+        // Let's not bother with empty events
+        if self.content.is_empty() {
+            return false
+        }
+
         // Validate the parents. We have to check that at least one parent
         // is not NULL, that the parent does not recursively reference the
         // event, and that no two parents are the same.
@@ -126,6 +221,8 @@ mod tests {
                 blake3::hash(b"4"),
                 blake3::hash(b"5"),
             ],
+            author: None,
+            stamp: vec![],
         }
     }
     #[test]
@@ -134,6 +231,31 @@ mod tests {
         assert!(make_valid_event().validate());
     }
 
+    #[test]
+    fn signed_events() {
+        let secret = SecretKey::random(&mut OsRng);
+        let mut event = make_valid_event();
+        let unsigned_id = event.id();
+        assert!(event.verify_signature());
+        assert!(event.author().is_none());
+
+        event.sign(&secret);
+        assert_ne!(event.id(), unsigned_id);
+        assert_eq!(event.author(), Some(PublicKey::from_secret(secret)));
+        assert!(event.verify_signature());
+
+        // Tampering with the content invalidates the signature
+        let mut tampered = event.clone();
+        tampered.content = vec![2u8];
+        assert!(!tampered.verify_signature());
+
+        // Claiming someone else's signature over the same data does not work either
+        let mut impostor = event.clone();
+        impostor.author.as_mut().unwrap().public =
+            PublicKey::from_secret(SecretKey::random(&mut OsRng));
+        assert!(!impostor.verify_signature());
+    }
+
     #[test]
     fn invalid_events() {
         // TODO: Not checked:
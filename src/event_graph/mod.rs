@@ -17,34 +17,49 @@
  */
 
 use std::{
-    cmp::Ordering,
-    collections::{HashMap, HashSet, VecDeque},
+    collections::{BTreeSet, HashMap, HashSet},
+    path::PathBuf,
     sync::Arc,
 };
 
-use async_recursion::async_recursion;
 use darkfi_serial::{deserialize_async, serialize_async};
 use log::{debug, error, info};
-use num_bigint::BigUint;
 use smol::{
     lock::{OnceCell, RwLock},
     Executor,
 };
+use url::Url;
 
 use crate::{
     event_graph::util::seconds_until_next_rotation,
-    net::P2pPtr,
-    system::{sleep, timeout::timeout, StoppableTask, StoppableTaskPtr, Subscriber, SubscriberPtr},
+    net::{ChannelPtr, P2pPtr},
+    system::{sleep, StoppableTask, StoppableTaskPtr, Subscriber, SubscriberPtr},
     Error, Result,
 };
 
 /// An event graph event
 pub mod event;
-pub use event::Event;
+pub use event::{Event, EventAuthor, EventHeader};
 
 /// P2P protocol implementation for the Event Graph
 pub mod proto;
-use proto::{EventRep, EventReq, TipRep, TipReq, REPLY_TIMEOUT};
+use proto::{EventPut, TopicList};
+
+/// Admission control for events received from the network
+pub mod admission;
+pub use admission::{EventAdmission, PowAdmission};
+
+/// Archive of pruned DAG events
+mod archive;
+
+/// Total ordering of the DAG and causal queries
+mod order;
+use order::OrderIndex;
+pub use order::{EventPages, EventPosition};
+
+/// Header-first DAG sync
+mod sync;
+pub use sync::{EventRange, RangeSummary, SyncPhase, SyncProgress};
 
 /// Utility functions
 mod util;
@@ -58,6 +73,10 @@ mod tests;
 const INITIAL_GENESIS: u64 = 1694044800;
 /// Genesis event contents
 const GENESIS_CONTENTS: &[u8] = &[0x47, 0x45, 0x4e, 0x45, 0x53, 0x49, 0x53];
+/// Version of the serialized [`Event`] layout. Genesis events commit to
+/// it, so a DAG written with another layout does not contain the current
+/// genesis and gets pruned at startup, instead of failing to decode.
+const EVENT_FORMAT_VERSION: u8 = 1;
 
 /// The number of parents an event is supposed to have.
 const N_EVENT_PARENTS: usize = 5;
@@ -66,17 +85,47 @@ const EVENT_TIME_DRIFT: u64 = 60;
 /// Null event ID
 pub const NULL_ID: blake3::Hash = blake3::Hash::from_bytes([0x00; blake3::OUT_LEN]);
 
+/// Name of the topic the DAG created with [`EventGraph::new`] belongs to
+pub const DEFAULT_TOPIC: &str = "default";
+/// Maximum length of a topic name
+const MAX_TOPIC_LEN: usize = 64;
+
 /// Atomic pointer to an [`EventGraph`] instance.
 pub type EventGraphPtr = Arc<EventGraph>;
 
+/// Policy deciding which events received from the network are accepted
+/// into the DAG, e.g. by their author. Signatures are verified before the
+/// policy is consulted, so [`Event::author`] can be trusted here.
+pub trait EventPolicy: Send + Sync {
+    fn accept(&self, event: &Event) -> bool;
+}
+
+impl<F: Fn(&Event) -> bool + Send + Sync> EventPolicy for F {
+    fn accept(&self, event: &Event) -> bool {
+        self(event)
+    }
+}
+
 /// An Event Graph instance
 pub struct EventGraph {
     /// Pointer to the P2P network instance
     p2p: P2pPtr,
+    /// Sled database holding the DAG trees
+    sled_db: sled::Db,
+    /// Name of the default topic's sled tree. Other topics are
+    /// stored in trees prefixed by it.
+    dag_tree_name: String,
+    /// Topic this DAG belongs to
+    topic: String,
     /// Sled tree containing the DAG
     dag: sled::Tree,
     /// The set of unreferenced DAG tips
     unreferenced_tips: RwLock<HashSet<blake3::Hash>>,
+    /// Index of the DAG's event IDs ordered by their timestamps,
+    /// used to answer range queries during sync
+    timestamp_index: RwLock<BTreeSet<(u64, [u8; blake3::OUT_LEN])>>,
+    /// Index of the DAG's events in their total order
+    order_index: RwLock<OrderIndex>,
     /// A `HashSet` containg event IDs and their 1-level parents.
     /// These come from the events we've sent out using `EventPut`.
     /// They are used with `EventReq` to decide if we should reply
@@ -85,10 +134,26 @@ pub struct EventGraph {
     broadcasted_ids: RwLock<HashSet<blake3::Hash>>,
     /// DAG Pruning Task
     prune_task: OnceCell<StoppableTaskPtr>,
+    /// Directory the DAG's events are archived to before pruning,
+    /// if archive mode is enabled
+    archive: Option<PathBuf>,
+    /// Policy accepting or rejecting events received from the network
+    policy: RwLock<Option<Arc<dyn EventPolicy>>>,
+    /// Admission control for events received from the network
+    admission: RwLock<Option<Arc<dyn EventAdmission>>>,
     /// Event subscriber, this notifies whenever an event is
     /// inserted into the DAG
     pub event_sub: SubscriberPtr<Event>,
+    /// Progress of the current or last DAG sync
+    sync_progress: RwLock<SyncProgress>,
+    /// Sync subscriber, this notifies whenever the sync progresses
+    pub sync_sub: SubscriberPtr<SyncProgress>,
     days_rotation: u64,
+    /// DAGs of the other topics we follow, sharing our P2P network
+    /// and protocol. Only used by the default topic's instance.
+    topics: RwLock<HashMap<String, EventGraphPtr>>,
+    /// Topics followed by each connected peer, shared by all topics
+    peer_topics: Arc<RwLock<HashMap<Url, HashSet<String>>>>,
 }
 
 impl EventGraph {
@@ -101,21 +166,79 @@ impl EventGraph {
         days_rotation: u64,
         ex: Arc<Executor<'_>>,
     ) -> Result<EventGraphPtr> {
-        let dag = sled_db.open_tree(dag_tree_name)?;
+        let peer_topics = Arc::new(RwLock::new(HashMap::new()));
+        Self::open(p2p, sled_db, dag_tree_name, DEFAULT_TOPIC, days_rotation, None, peer_topics, ex)
+            .await
+    }
+
+    /// Create a new [`EventGraph`] instance in archive mode. Before the DAG
+    /// is pruned, its events are appended to an archive file in `archive_path`,
+    /// so they can still be read after the rotation. Topics added to this
+    /// instance are archived in subdirectories of `archive_path`.
+    pub async fn new_with_archive(
+        p2p: P2pPtr,
+        sled_db: sled::Db,
+        dag_tree_name: &str,
+        days_rotation: u64,
+        archive_path: PathBuf,
+        ex: Arc<Executor<'_>>,
+    ) -> Result<EventGraphPtr> {
+        let peer_topics = Arc::new(RwLock::new(HashMap::new()));
+        Self::open(
+            p2p,
+            sled_db,
+            dag_tree_name,
+            DEFAULT_TOPIC,
+            days_rotation,
+            Some(archive_path),
+            peer_topics,
+            ex,
+        )
+        .await
+    }
+
+    /// Open the DAG of the given topic and spawn its pruning task.
+    #[allow(clippy::too_many_arguments)]
+    async fn open(
+        p2p: P2pPtr,
+        sled_db: sled::Db,
+        dag_tree_name: &str,
+        topic: &str,
+        days_rotation: u64,
+        archive: Option<PathBuf>,
+        peer_topics: Arc<RwLock<HashMap<Url, HashSet<String>>>>,
+        ex: Arc<Executor<'_>>,
+    ) -> Result<EventGraphPtr> {
+        let dag = match topic {
+            DEFAULT_TOPIC => sled_db.open_tree(dag_tree_name)?,
+            _ => sled_db.open_tree(format!("{}:{}", dag_tree_name, topic))?,
+        };
         let unreferenced_tips = RwLock::new(HashSet::new());
         let broadcasted_ids = RwLock::new(HashSet::new());
         let event_sub = Subscriber::new();
 
         // Create the current genesis event based on the `days_rotation`
-        let current_genesis = Self::generate_genesis(days_rotation);
+        let current_genesis = Self::generate_genesis(topic, days_rotation);
         let self_ = Arc::new(Self {
             p2p,
+            sled_db,
+            dag_tree_name: dag_tree_name.to_string(),
+            topic: topic.to_string(),
             dag: dag.clone(),
             unreferenced_tips,
+            timestamp_index: RwLock::new(BTreeSet::new()),
+            order_index: RwLock::new(OrderIndex::default()),
             broadcasted_ids,
             prune_task: OnceCell::new(),
+            archive,
+            policy: RwLock::new(None),
+            admission: RwLock::new(None),
             event_sub,
+            sync_progress: RwLock::new(SyncProgress::default()),
+            sync_sub: Subscriber::new(),
             days_rotation,
+            topics: RwLock::new(HashMap::new()),
+            peer_topics,
         });
 
         // Check if we have it in our DAG.
@@ -131,6 +254,11 @@ impl EventGraph {
         // Find the unreferenced tips in the current DAG state.
         *self_.unreferenced_tips.write().await = self_.find_unreferenced_tips().await;
 
+        // Index the events by their timestamps and their total order
+        let (timestamp_index, order_index) = self_.build_indexes().await;
+        *self_.timestamp_index.write().await = timestamp_index;
+        *self_.order_index.write().await = order_index;
+
         // Spawn the DAG pruning task
         if days_rotation > 0 {
             let self__ = self_.clone();
@@ -140,7 +268,7 @@ impl EventGraph {
             prune_task.clone().start(
                 self_.clone().dag_prune_task(days_rotation),
                 |_| async move {
-                    self__.clone()._handle_stop(sled_db).await;
+                    self__.clone()._handle_stop().await;
                 },
                 Error::DetachedTaskStopped,
                 ex.clone(),
@@ -154,269 +282,258 @@ impl EventGraph {
         self.days_rotation
     }
 
-    async fn _handle_stop(&self, sled_db: sled::Db) {
-        info!(target: "event_graph::_handle_stop()", "[EVENTGRAPH] Prune task stopped, flushing sled");
-        sled_db.flush_async().await.unwrap();
+    /// Return the topic this DAG belongs to
+    pub fn topic(&self) -> &str {
+        &self.topic
     }
 
-    /// Generate a deterministic genesis event corresponding to the DAG's configuration.
-    fn generate_genesis(days_rotation: u64) -> Event {
-        // Days rotation is u64 except zero
-        let genesis_days_rotation = if days_rotation == 0 { 1 } else { days_rotation };
+    /// Start following the given topic. Its DAG lives next to the default
+    /// one and shares the P2P network and protocol, but has its own genesis,
+    /// `days_rotation` and tips. Connected peers are told about the topic so
+    /// they relay its events to us. Topics can only be added through the
+    /// default topic's instance.
+    pub async fn add_topic(
+        &self,
+        topic: &str,
+        days_rotation: u64,
+        ex: Arc<Executor<'_>>,
+    ) -> Result<EventGraphPtr> {
+        if self.topic != DEFAULT_TOPIC || !Self::valid_topic(topic) {
+            return Err(Error::DagInvalidTopic(topic.to_string()))
+        }
 
-        // First check how many days passed since initial genesis.
-        let days_passed = days_since(INITIAL_GENESIS);
+        let mut topics = self.topics.write().await;
+        if let Some(event_graph) = topics.get(topic) {
+            return Ok(event_graph.clone())
+        }
 
-        // Calculate the number of days_rotation intervals since INITIAL_GENESIS
-        let rotations_since_genesis = days_passed / genesis_days_rotation;
+        info!(target: "event_graph::add_topic()", "[EVENTGRAPH] Following topic {}", topic);
+        let event_graph = Self::open(
+            self.p2p.clone(),
+            self.sled_db.clone(),
+            &self.dag_tree_name,
+            topic,
+            days_rotation,
+            self.archive.as_ref().map(|path| archive::topic_archive_path(path, topic)),
+            self.peer_topics.clone(),
+            ex,
+        )
+        .await?;
+        topics.insert(topic.to_string(), event_graph.clone());
+        drop(topics);
+
+        self.announce_topics().await;
+        Ok(event_graph)
+    }
 
-        // Calculate the timestamp of the most recent event
-        let timestamp =
-            INITIAL_GENESIS + (rotations_since_genesis * genesis_days_rotation * DAY as u64);
+    /// Stop following the given topic. Its DAG is kept on disk, so it
+    /// can be picked up again with [`EventGraph::add_topic`].
+    pub async fn remove_topic(&self, topic: &str) -> Result<()> {
+        let Some(event_graph) = self.topics.write().await.remove(topic) else {
+            return Err(Error::DagInvalidTopic(topic.to_string()))
+        };
 
-        Event { timestamp, content: GENESIS_CONTENTS.to_vec(), parents: [NULL_ID; N_EVENT_PARENTS] }
+        info!(target: "event_graph::remove_topic()", "[EVENTGRAPH] Unfollowing topic {}", topic);
+        if let Some(prune_task) = event_graph.prune_task.get() {
+            prune_task.stop().await;
+        }
+
+        self.announce_topics().await;
+        Ok(())
     }
 
-    /// Sync the DAG from connected peers
-    pub async fn dag_sync(&self) -> Result<()> {
-        // We do an optimistic sync where we ask all our connected peers for
-        // the DAG tips (unreferenced events)  and then we accept the ones we
-        // see the most times.
-        // * Compare received tips with local ones, identify which we are missing.
-        // * Request these from peers
-        // * Recursively request these backward
-        //
-        // Verification:
-        // * Timestamps should go backwards
-        // * Cross-check with multiple peers, this means we should request the
-        //   same event from multiple peers and make sure it is the same.
-        // * Since we should be pruning, if we're not synced after some reasonable
-        //   amount of iterations, these could be faulty peers and we can try again
-        //   from the beginning
-
-        // Get references to all our peers.
-        let channels = self.p2p.channels().await;
-        let mut communicated_peers = channels.len();
-        info!(
-            target: "event_graph::dag_sync()",
-            "[EVENTGRAPH] Syncing DAG from {} peers...", communicated_peers,
-        );
-
-        // Here we keep track of the tips and how many time we've seen them.
-        let mut tips: HashMap<blake3::Hash, usize> = HashMap::new();
-
-        // Let's first ask all of our peers for their tips and collect them
-        // in our hashmap above.
-        for channel in channels.iter() {
-            let url = channel.address();
-
-            let tip_rep_sub = match channel.subscribe_msg::<TipRep>().await {
-                Ok(v) => v,
-                Err(e) => {
-                    error!(
-                        target: "event_graph::dag_sync()",
-                        "[EVENTGRAPH] Sync: Couldn't subscribe TipReq for peer {}, skipping ({})",
-                        url, e,
-                    );
-                    communicated_peers -= 1;
-                    continue
-                }
-            };
+    /// Return the DAG of the given topic, if we follow it
+    pub async fn get_topic(self: &Arc<Self>, topic: &str) -> Option<EventGraphPtr> {
+        if topic == self.topic {
+            return Some(self.clone())
+        }
 
-            if let Err(e) = channel.send(&TipReq {}).await {
-                error!(
-                    target: "event_graph::dag_sync()",
-                    "[EVENTGRAPH] Sync: Couldn't contact peer {}, skipping ({})", url, e,
-                );
-                communicated_peers -= 1;
-                continue
-            };
+        self.topics.read().await.get(topic).cloned()
+    }
 
-            let peer_tips = match timeout(REPLY_TIMEOUT, tip_rep_sub.receive()).await {
-                Ok(peer_tips) => peer_tips?,
-                Err(_) => {
-                    error!(
-                        target: "event_graph::dag_sync()",
-                        "[EVENTGRAPH] Sync: Peer {} didn't reply with tips in time, skipping", url,
-                    );
-                    communicated_peers -= 1;
-                    continue
-                }
-            };
-            let peer_tips = &peer_tips.0;
-
-            // Note down the seen tips
-            for tip in peer_tips {
-                if let Some(seen_tip) = tips.get_mut(tip) {
-                    *seen_tip += 1;
-                } else {
-                    tips.insert(*tip, 1);
-                }
-            }
-        }
+    /// Return the sorted list of topics we follow
+    pub async fn followed_topics(&self) -> Vec<String> {
+        let mut topics: Vec<String> = self.topics.read().await.keys().cloned().collect();
+        topics.push(self.topic.clone());
+        topics.sort_unstable();
+        topics
+    }
 
-        // After we've communicated all the peers, let's see what happened.
-        if tips.is_empty() {
-            error!(
-                target: "event_graph::dag_sync()",
-                "[EVENTGRAPH] Sync: Could not find any DAG tips",
-            );
-            return Err(Error::DagSyncFailed)
-        }
+    /// Check if a topic name is acceptable
+    fn valid_topic(topic: &str) -> bool {
+        !topic.is_empty() &&
+            topic.len() <= MAX_TOPIC_LEN &&
+            topic.chars().all(|c| c.is_ascii_graphic()) &&
+            topic != DEFAULT_TOPIC
+    }
 
-        // We know the number of peers we've communicated with.
-        // Arbitrarily, let's not consider events we only got once.
-        // TODO: This should be more sensible depending on the peer number.
-        let mut considered_tips = HashSet::new();
-        for (tip, amount) in tips.iter() {
-            if amount > &1 {
-                considered_tips.insert(*tip);
-            }
+    /// Tell all connected peers which topics we follow
+    async fn announce_topics(&self) {
+        let channels = self.p2p.channels().await;
+        if channels.is_empty() {
+            return
         }
-        drop(tips);
 
-        // Now begin fetching the events backwards.
-        let mut missing_parents = vec![];
-        for tip in considered_tips.iter() {
-            assert!(tip != &NULL_ID);
+        self.p2p.broadcast_to(&TopicList(self.followed_topics().await), &channels).await;
+    }
 
-            if !self.dag.contains_key(tip.as_bytes()).unwrap() {
-                missing_parents.push(*tip);
-            }
-        }
+    /// Note down the topics followed by the given peer
+    pub(super) async fn set_peer_topics(&self, addr: &Url, topics: HashSet<String>) {
+        self.peer_topics.write().await.insert(addr.clone(), topics);
+    }
+
+    /// Return the connected channels whose peers follow our topic.
+    /// Peers that haven't told us their topics yet (or never will, e.g.
+    /// older nodes) are assumed to follow only the default topic.
+    pub(super) async fn channels_following(&self) -> Vec<ChannelPtr> {
+        let channels = self.p2p.channels().await;
+        let mut peer_topics = self.peer_topics.write().await;
+
+        // Forget about peers that are gone
+        peer_topics.retain(|addr, _| channels.iter().any(|c| c.address() == addr));
+
+        channels
+            .into_iter()
+            .filter(|c| match peer_topics.get(c.address()) {
+                Some(topics) => topics.contains(&self.topic),
+                None => self.topic == DEFAULT_TOPIC,
+            })
+            .collect()
+    }
 
-        if missing_parents.is_empty() {
-            info!(target: "event_graph::dag_sync()", "[EVENTGRAPH] DAG synced successfully!");
-            return Ok(())
+    /// Broadcast an event to the peers following our topic, excluding
+    /// the ones provided in `exclude_list`.
+    pub async fn broadcast(&self, event: &Event, exclude_list: &[Url]) {
+        let channels: Vec<ChannelPtr> = self
+            .channels_following()
+            .await
+            .into_iter()
+            .filter(|c| !exclude_list.contains(c.address()))
+            .collect();
+
+        if channels.is_empty() {
+            debug!(
+                target: "event_graph::broadcast()",
+                "No peers following topic {}, not broadcasting {}", self.topic, event.id(),
+            );
+            return
         }
 
-        info!(target: "event_graph::dag_sync()", "[EVENTGRAPH] Fetching events");
-        let mut received_events = vec![];
-        while !missing_parents.is_empty() {
-            for parent_id in missing_parents.clone().iter() {
-                let mut found_event = false;
+        self.p2p.broadcast_to(&EventPut(event.clone(), self.topic.clone()), &channels).await;
+    }
 
-                for channel in channels.iter() {
-                    let url = channel.address();
+    /// Set the policy deciding which events received from the network
+    /// are accepted. Without one, all valid events are accepted.
+    pub async fn set_policy(&self, policy: Arc<dyn EventPolicy>) {
+        *self.policy.write().await = Some(policy);
+    }
 
-                    debug!(
-                        target: "event_graph::dag_sync()",
-                        "Requesting {} from {}...", parent_id, url,
-                    );
+    /// Check if the configured policy accepts the given event
+    pub(super) async fn policy_accepts(&self, event: &Event) -> bool {
+        match &*self.policy.read().await {
+            Some(policy) => policy.accept(event),
+            None => true,
+        }
+    }
 
-                    let ev_rep_sub = match channel.subscribe_msg::<EventRep>().await {
-                        Ok(v) => v,
-                        Err(e) => {
-                            error!(
-                                target: "event_graph::dag_sync()",
-                                "[EVENTGRAPH] Sync: Couldn't subscribe EventRep for peer {}, skipping ({})",
-                                url, e,
-                            );
-                            continue
-                        }
-                    };
-
-                    if let Err(e) = channel.send(&EventReq(*parent_id)).await {
-                        error!(
-                            target: "event_graph::dag_sync()",
-                            "[EVENTGRAPH] Sync: Failed communicating EventReq({}) to {}: {}",
-                            parent_id, url, e,
-                        );
-                        continue
-                    }
+    /// Set the admission control for events received from the network.
+    /// Without one, all valid events are admitted.
+    pub async fn set_admission(&self, admission: Arc<dyn EventAdmission>) {
+        *self.admission.write().await = Some(admission);
+    }
 
-                    let parent = match timeout(REPLY_TIMEOUT, ev_rep_sub.receive()).await {
-                        Ok(parent) => parent,
-                        Err(_) => {
-                            error!(
-                                target: "event_graph::dag_sync()",
-                                "[EVENTGRAPH] Sync: Timeout waiting for parent {} from {}",
-                                parent_id, url,
-                            );
-                            continue
-                        }
-                    };
-
-                    let parent = match parent {
-                        Ok(v) => v.0.clone(),
-                        Err(e) => {
-                            error!(
-                                target: "event_graph::dag_sync()",
-                                "[EVENTGRAPH] Sync: Failed receiving parent {}: {}",
-                                parent_id, e,
-                            );
-                            continue
-                        }
-                    };
-
-                    if &parent.id() != parent_id {
-                        error!(
-                            target: "event_graph::dag_sync()",
-                            "[EVENTGRAPH] Sync: Peer {} replied with a wrong event: {}",
-                            url, parent.id(),
-                        );
-                        continue
-                    }
+    /// Check if the configured admission control admits the given event
+    pub(super) async fn admits(&self, event: &Event) -> bool {
+        match &*self.admission.read().await {
+            Some(admission) => admission.admit(event),
+            None => true,
+        }
+    }
 
-                    debug!(
-                        target: "event_graph::dag_sync()",
-                        "Got correct parent event {}", parent_id,
-                    );
+    async fn _handle_stop(&self) {
+        info!(target: "event_graph::_handle_stop()", "[EVENTGRAPH] Prune task stopped, flushing sled");
+        self.sled_db.flush_async().await.unwrap();
+    }
 
-                    received_events.push(parent.clone());
-                    let pos = missing_parents.iter().position(|id| id == &parent.id()).unwrap();
-                    missing_parents.remove(pos);
-                    found_event = true;
-
-                    // See if we have the upper parents
-                    for upper_parent in parent.parents.iter() {
-                        if upper_parent == &NULL_ID {
-                            continue
-                        }
-
-                        if !self.dag.contains_key(upper_parent.as_bytes()).unwrap() {
-                            debug!(
-                                target: "event_graph::dag_sync()",
-                                "Found upper missing parent event{}", upper_parent,
-                            );
-                            missing_parents.push(*upper_parent);
-                        }
-                    }
+    /// Contents of the given topic's genesis events. Other topics commit
+    /// to their name, so events can't be replayed across topics.
+    fn genesis_contents(topic: &str) -> Vec<u8> {
+        let mut contents = GENESIS_CONTENTS.to_vec();
+        contents.push(EVENT_FORMAT_VERSION);
+        if topic != DEFAULT_TOPIC {
+            contents.extend_from_slice(topic.as_bytes());
+        }
+        contents
+    }
 
-                    break
-                }
+    /// Generate a deterministic genesis event corresponding to the DAG's configuration.
+    fn generate_genesis(topic: &str, days_rotation: u64) -> Event {
+        // Days rotation is u64 except zero
+        let genesis_days_rotation = if days_rotation == 0 { 1 } else { days_rotation };
 
-                if !found_event {
-                    error!(
-                        target: "event_graph::dag_sync()",
-                        "[EVENTGRAPH] Sync: Failed to get all events",
-                    );
-                    return Err(Error::DagSyncFailed)
-                }
-            }
-        } // <-- while !missing_parents.is_empty
+        // First check how many days passed since initial genesis.
+        let days_passed = days_since(INITIAL_GENESIS);
 
-        // At this point we should've got all the events.
-        // We should add them to the DAG.
-        // TODO: FIXME: Also validate these events.
-        // TODO: FIXME: This insert should also be atomic, dag_insert might need a rewrite
-        let received_events_rev: Vec<Event> = received_events.iter().rev().cloned().collect();
-        self.dag_insert(&received_events_rev).await.unwrap();
+        // Calculate the number of days_rotation intervals since INITIAL_GENESIS
+        let rotations_since_genesis = days_passed / genesis_days_rotation;
 
-        info!(target: "event_graph::dag_sync()", "[EVENTGRAPH] DAG synced successfully!");
-        Ok(())
+        // Calculate the timestamp of the most recent event
+        let timestamp =
+            INITIAL_GENESIS + (rotations_since_genesis * genesis_days_rotation * DAY as u64);
+
+        Event {
+            timestamp,
+            content: Self::genesis_contents(topic),
+            parents: [NULL_ID; N_EVENT_PARENTS],
+            author: None,
+            stamp: vec![],
+        }
     }
 
     /// Atomically prune the DAG and insert the given event as genesis.
+    /// In archive mode, the DAG is left as is if it can't be archived.
     async fn dag_prune(&self, genesis_event: Event) -> Result<()> {
         debug!(target: "event_graph::dag_prune()", "Pruning DAG...");
 
-        // Acquire exclusive locks to unreferenced_tips and broadcasted_ids while
-        // this operation is happening. We do this to ensure that during the pruning
-        // operation, no other operations are able to access the intermediate state
-        // which could lead to producing the wrong state after pruning.
-        let mut unreferenced_tips = self.unreferenced_tips.write().await;
-        let mut broadcasted_ids = self.broadcasted_ids.write().await;
+        let locks = loop {
+            // In archive mode, save the events before they are gone. This
+            // is done before locking, so the file I/O doesn't hold up
+            // inserts. Events inserted meanwhile are archived on the next
+            // pass.
+            let archived = match &self.archive {
+                Some(path) => Some(self.archive_dag(path).await?),
+                None => None,
+            };
+
+            // Acquire exclusive locks to unreferenced_tips and broadcasted_ids while
+            // this operation is happening. We do this to ensure that during the pruning
+            // operation, no other operations are able to access the intermediate state
+            // which could lead to producing the wrong state after pruning.
+            let unreferenced_tips = self.unreferenced_tips.write().await;
+            let broadcasted_ids = self.broadcasted_ids.write().await;
+            let timestamp_index = self.timestamp_index.write().await;
+            let order_index = self.order_index.write().await;
+
+            if let Some(archived) = archived {
+                let mut unarchived = false;
+                for key in self.dag.iter().keys() {
+                    let id = blake3::Hash::from_bytes((&key? as &[u8]).try_into().unwrap());
+                    if !archived.contains(&id) {
+                        unarchived = true;
+                        break
+                    }
+                }
+
+                if unarchived {
+                    debug!(target: "event_graph::dag_prune()", "DAG changed, archiving again");
+                    continue
+                }
+            }
+
+            break (unreferenced_tips, broadcasted_ids, timestamp_index, order_index)
+        };
+        let (mut unreferenced_tips, mut broadcasted_ids, mut timestamp_index, mut order_index) =
+            locks;
 
         // Atomically clear the DAG and write the new genesis event.
         let mut batch = sled::Batch::default();
@@ -433,8 +550,14 @@ impl EventGraph {
         // Clear unreferenced tips and bcast ids
         *unreferenced_tips = HashSet::from([genesis_event.id()]);
         *broadcasted_ids = HashSet::new();
+        *timestamp_index =
+            BTreeSet::from([(genesis_event.timestamp, *genesis_event.id().as_bytes())]);
+        *order_index = OrderIndex::default();
+        order_index.insert(genesis_event.id(), &genesis_event);
         drop(unreferenced_tips);
         drop(broadcasted_ids);
+        drop(timestamp_index);
+        drop(order_index);
 
         debug!(target: "event_graph::dag_prune()", "DAG pruned successfully");
         Ok(())
@@ -455,8 +578,10 @@ impl EventGraph {
             // Prepare the new genesis event
             let current_genesis = Event {
                 timestamp: next_rotation,
-                content: GENESIS_CONTENTS.to_vec(),
+                content: Self::genesis_contents(&self.topic),
                 parents: [NULL_ID; N_EVENT_PARENTS],
+                author: None,
+                stamp: vec![],
             };
 
             // Sleep until it's time to rotate.
@@ -466,8 +591,14 @@ impl EventGraph {
             sleep(s).await;
             debug!(target: "event_graph::dag_prune_task()", "Rotation period reached");
 
-            // Trigger DAG prune
-            self.dag_prune(current_genesis).await?;
+            // Trigger DAG prune. If it fails, e.g. because the DAG could
+            // not be archived, we keep the DAG and retry at next rotation.
+            if let Err(e) = self.dag_prune(current_genesis).await {
+                error!(
+                    target: "event_graph::dag_prune_task()",
+                    "[EVENTGRAPH] Failed pruning DAG: {}", e,
+                );
+            }
         }
     }
 
@@ -479,9 +610,12 @@ impl EventGraph {
     /// TODO: The `broadcasted_ids` set should periodically be pruned, when
     /// some sensible time has passed after broadcasting the event.
     pub async fn dag_insert(&self, events: &[Event]) -> Result<Vec<blake3::Hash>> {
-        // Acquire exclusive locks to `unreferenced_tips and broadcasted_ids`
+        // Acquire exclusive locks to `unreferenced_tips`, `broadcasted_ids`
+        // and the indexes
         let mut unreferenced_tips = self.unreferenced_tips.write().await;
         let mut broadcasted_ids = self.broadcasted_ids.write().await;
+        let mut timestamp_index = self.timestamp_index.write().await;
+        let mut order_index = self.order_index.write().await;
 
         // Here we keep the IDs to return
         let mut ids = Vec::with_capacity(events.len());
@@ -518,6 +652,8 @@ impl EventGraph {
                 "Adding {} to unreferenced tips", event_id,
             );
             unreferenced_tips.insert(event_id);
+            timestamp_index.insert((event.timestamp, *event_id.as_bytes()));
+            order_index.insert(event_id, event);
 
             // Add the event to the atomic batch
             batch.insert(event_id.as_bytes(), event_se);
@@ -540,6 +676,8 @@ impl EventGraph {
         // Drop the exclusive locks
         drop(unreferenced_tips);
         drop(broadcasted_ids);
+        drop(timestamp_index);
+        drop(order_index);
 
         Ok(ids)
     }
@@ -552,19 +690,34 @@ impl EventGraph {
         Ok(Some(event))
     }
 
-    /// Find the unreferenced tips in the current DAG state.
-    async fn find_unreferenced_tips(&self) -> HashSet<blake3::Hash> {
-        // First get all the event IDs
-        let mut tips = HashSet::new();
+    /// Decode every event in the current DAG state. Events that fail to
+    /// decode are skipped, so a corrupt entry can't stop us from starting.
+    async fn dag_events(&self) -> HashMap<blake3::Hash, Event> {
+        let mut events = HashMap::new();
         for iter_elem in self.dag.iter() {
-            let (id, _) = iter_elem.unwrap();
+            let (id, event) = iter_elem.unwrap();
             let id = blake3::Hash::from_bytes((&id as &[u8]).try_into().unwrap());
-            tips.insert(id);
+            match deserialize_async::<Event>(&event).await {
+                Ok(event) => {
+                    events.insert(id, event);
+                }
+                Err(e) => {
+                    error!(
+                        target: "event_graph::dag_events()",
+                        "[EVENTGRAPH] Skipping undecodable event {}: {}", id, e,
+                    );
+                }
+            }
         }
 
-        for iter_elem in self.dag.iter() {
-            let (_, event) = iter_elem.unwrap();
-            let event: Event = deserialize_async(&event).await.unwrap();
+        events
+    }
+
+    /// Find the unreferenced tips in the current DAG state.
+    async fn find_unreferenced_tips(&self) -> HashSet<blake3::Hash> {
+        let events = self.dag_events().await;
+        let mut tips: HashSet<blake3::Hash> = events.keys().copied().collect();
+        for event in events.values() {
             for parent in event.parents.iter() {
                 tips.remove(parent);
             }
@@ -573,6 +726,16 @@ impl EventGraph {
         tips
     }
 
+    /// Index the events in the current DAG state by their timestamps
+    /// and by their total order.
+    async fn build_indexes(&self) -> (BTreeSet<(u64, [u8; blake3::OUT_LEN])>, OrderIndex) {
+        let events = self.dag_events().await;
+        let timestamp_index =
+            events.iter().map(|(id, event)| (event.timestamp, *id.as_bytes())).collect();
+
+        (timestamp_index, OrderIndex::build(events))
+    }
+
     /// Get the current set of unreferenced tips in the DAG.
     async fn get_unreferenced_tips(&self) -> [blake3::Hash; N_EVENT_PARENTS] {
         // TODO: return vec of all instead of N_EVENT_PARENTS
@@ -586,109 +749,4 @@ impl EventGraph {
         assert!(tips.iter().any(|x| x != &NULL_ID));
         tips
     }
-
-    /// Internal function used for DAG sorting.
-    async fn get_unreferenced_tips_sorted(&self) -> [blake3::Hash; N_EVENT_PARENTS] {
-        let tips = self.get_unreferenced_tips().await;
-
-        // Convert the hash to BigUint for sorting
-        let mut sorted: Vec<_> =
-            tips.iter().map(|x| BigUint::from_bytes_be(x.as_bytes())).collect();
-        sorted.sort_unstable();
-
-        // Convert back to blake3
-        let mut tips_sorted = [NULL_ID; N_EVENT_PARENTS];
-        for (i, id) in sorted.iter().enumerate() {
-            let mut bytes = id.to_bytes_be();
-
-            // Ensure we have 32 bytes
-            while bytes.len() < blake3::OUT_LEN {
-                bytes.insert(0, 0);
-            }
-
-            tips_sorted[i] = blake3::Hash::from_bytes(bytes.try_into().unwrap());
-        }
-
-        tips_sorted
-    }
-
-    /// Perform a topological sort of the DAG.
-    pub async fn order_events(&self) -> Vec<blake3::Hash> {
-        let mut ordered_events = VecDeque::new();
-        let mut visited = HashSet::new();
-
-        for tip in self.get_unreferenced_tips_sorted().await {
-            if !visited.contains(&tip) && tip != NULL_ID {
-                let tip = self.dag.get(tip.as_bytes()).unwrap().unwrap();
-                let tip = deserialize_async(&tip).await.unwrap();
-                self.dfs_topological_sort(tip, &mut visited, &mut ordered_events).await;
-            }
-        }
-
-        ordered_events.make_contiguous().to_vec()
-    }
-
-    /// We do a DFS (<https://en.wikipedia.org/wiki/Depth-first_search>), and
-    /// additionally we consider the timestamps.
-    #[async_recursion]
-    async fn dfs_topological_sort(
-        &self,
-        event: Event,
-        visited: &mut HashSet<blake3::Hash>,
-        ordered_events: &mut VecDeque<blake3::Hash>,
-    ) {
-        let event_id = event.id();
-        visited.insert(event_id);
-
-        for parent_id in event.parents.iter() {
-            if !visited.contains(parent_id) && parent_id != &NULL_ID {
-                let p_event = self.dag.get(parent_id.as_bytes()).unwrap().unwrap();
-                let p_event = deserialize_async(&p_event).await.unwrap();
-                self.dfs_topological_sort(p_event, visited, ordered_events).await;
-            }
-        }
-
-        // Before inserting, check timestamps to determine the correct position.
-        let mut pos = ordered_events.len();
-        for (idx, existing_id) in ordered_events.iter().enumerate().rev() {
-            assert!(existing_id != &NULL_ID);
-            if self.share_same_parents(&event_id, existing_id).await {
-                let existing_event = self.dag.get(existing_id.as_bytes()).unwrap().unwrap();
-                let existing_event: Event = deserialize_async(&existing_event).await.unwrap();
-
-                // Sort by timestamp
-                match event.timestamp.cmp(&existing_event.timestamp) {
-                    Ordering::Less => pos = idx,
-                    Ordering::Equal => {
-                        // In case of a tie-breaker, use the event ID
-                        let a = BigUint::from_bytes_be(event_id.as_bytes());
-                        let b = BigUint::from_bytes_be(existing_id.as_bytes());
-                        if a < b {
-                            pos = idx;
-                        }
-                    }
-                    _ => {}
-                }
-            }
-        }
-
-        ordered_events.insert(pos, event_id);
-    }
-
-    /// Check if two events have the same parents
-    async fn share_same_parents(&self, event_id1: &blake3::Hash, event_id2: &blake3::Hash) -> bool {
-        let event1 = self.dag.get(event_id1.as_bytes()).unwrap().unwrap();
-        let event1: Event = deserialize_async(&event1).await.unwrap();
-        let mut parents1: Vec<_> =
-            event1.parents.iter().map(|x| BigUint::from_bytes_be(x.as_bytes())).collect();
-        parents1.sort_unstable();
-
-        let event2 = self.dag.get(event_id2.as_bytes()).unwrap().unwrap();
-        let event2: Event = deserialize_async(&event2).await.unwrap();
-        let mut parents2: Vec<_> =
-            event2.parents.iter().map(|x| BigUint::from_bytes_be(x.as_bytes())).collect();
-        parents2.sort_unstable();
-
-        parents1 == parents2
-    }
 }
@@ -0,0 +1,306 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Deterministic total ordering of the DAG and causal queries.
+//!
+//! Every event has a layer: the genesis event is in layer 0, and any
+//! other event is one layer above its highest parent. Events are ordered
+//! by their layer, ties broken by their timestamp and then by their ID,
+//! compared as big-endian bytes. The order only depends on the set of
+//! events in the DAG, so nodes holding the same events agree on it, and
+//! parents always come before their children.
+
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+
+use darkfi_serial::deserialize_async;
+
+use super::{Event, EventGraph, EventGraphPtr, NULL_ID};
+use crate::Result;
+
+/// Position of an event in the total order of the DAG
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EventPosition {
+    /// Layer of the event in the DAG
+    pub layer: u64,
+    /// Timestamp of the event
+    pub timestamp: u64,
+    /// ID of the event
+    pub id: [u8; blake3::OUT_LEN],
+}
+
+impl EventPosition {
+    /// Return the ID of the event at this position
+    pub fn event_id(&self) -> blake3::Hash {
+        blake3::Hash::from_bytes(self.id)
+    }
+}
+
+/// Index keeping the DAG's events in their total order, along with the
+/// links needed to walk the DAG in both directions.
+#[derive(Default)]
+pub(super) struct OrderIndex {
+    /// Position of each event
+    positions: HashMap<blake3::Hash, EventPosition>,
+    /// Children of each event
+    children: HashMap<blake3::Hash, Vec<blake3::Hash>>,
+    /// Events in their total order
+    order: BTreeSet<EventPosition>,
+}
+
+impl OrderIndex {
+    /// Add an event to the index. Parents we don't know about are considered
+    /// to be in layer 0 until they are inserted, at which point the layers of
+    /// their descendants are raised as needed.
+    pub(super) fn insert(&mut self, event_id: blake3::Hash, event: &Event) {
+        if self.positions.contains_key(&event_id) {
+            return
+        }
+
+        let mut layer = 0;
+        for parent_id in event.parents.iter() {
+            if parent_id == &NULL_ID {
+                continue
+            }
+
+            let parent_layer = self.positions.get(parent_id).map_or(0, |p| p.layer);
+            layer = layer.max(parent_layer + 1);
+            self.children.entry(*parent_id).or_default().push(event_id);
+        }
+
+        let position =
+            EventPosition { layer, timestamp: event.timestamp, id: *event_id.as_bytes() };
+        self.positions.insert(event_id, position);
+        self.order.insert(position);
+
+        // Children inserted before the event were placed as if it was in
+        // layer 0, so move them and their own descendants up if needed.
+        let mut queue = VecDeque::from([event_id]);
+        while let Some(parent_id) = queue.pop_front() {
+            let parent_layer = self.positions[&parent_id].layer;
+            let Some(children) = self.children.get(&parent_id) else { continue };
+
+            for child_id in children {
+                let Some(position) = self.positions.get_mut(child_id) else { continue };
+                if position.layer > parent_layer {
+                    continue
+                }
+
+                self.order.remove(&*position);
+                position.layer = parent_layer + 1;
+                self.order.insert(*position);
+                queue.push_back(*child_id);
+            }
+        }
+    }
+
+    /// Build the index from all the events of a DAG
+    pub(super) fn build(mut events: HashMap<blake3::Hash, Event>) -> Self {
+        let mut index = Self::default();
+
+        // Insert events once all their parents are in, so layers are right
+        while !events.is_empty() {
+            let ready: Vec<blake3::Hash> = events
+                .iter()
+                .filter(|(_, event)| {
+                    event.parents.iter().all(|p| p == &NULL_ID || !events.contains_key(p))
+                })
+                .map(|(id, _)| *id)
+                .collect();
+
+            // A cycle can't happen with hashes, but don't spin forever
+            if ready.is_empty() {
+                break
+            }
+
+            for id in ready {
+                let event = events.remove(&id).unwrap();
+                index.insert(id, &event);
+            }
+        }
+
+        index
+    }
+
+    /// Return the position of an event
+    pub(super) fn position(&self, event_id: &blake3::Hash) -> Option<EventPosition> {
+        self.positions.get(event_id).copied()
+    }
+}
+
+/// Paging iterator over the events of a DAG in their total order,
+/// created with [`EventGraph::pages`].
+pub struct EventPages {
+    event_graph: EventGraphPtr,
+    cursor: Option<EventPosition>,
+    page_size: usize,
+}
+
+impl EventPages {
+    /// Return the next page of events, or an empty page once all events
+    /// have been returned. Events inserted later at positions after the
+    /// last returned one will show up in following pages.
+    pub async fn next_page(&mut self) -> Result<Vec<Event>> {
+        let page = self.event_graph.events_after(self.cursor.as_ref(), self.page_size).await?;
+        if let Some((position, _)) = page.last() {
+            self.cursor = Some(*position);
+        }
+
+        Ok(page.into_iter().map(|(_, event)| event).collect())
+    }
+
+    /// Return the position of the last returned event
+    pub fn cursor(&self) -> Option<EventPosition> {
+        self.cursor
+    }
+}
+
+impl EventGraph {
+    /// Return the IDs of all events of the DAG in their total order:
+    /// by layer, then by timestamp, then by ID.
+    pub async fn order_events(&self) -> Vec<blake3::Hash> {
+        self.order_index.read().await.order.iter().map(|p| p.event_id()).collect()
+    }
+
+    /// Return the position of an event in the total order
+    pub async fn event_position(&self, event_id: &blake3::Hash) -> Option<EventPosition> {
+        self.order_index.read().await.position(event_id)
+    }
+
+    /// Fetch the events with the given IDs, sorted by their total order
+    async fn fetch_ordered(&self, ids: Vec<EventPosition>) -> Result<Vec<Event>> {
+        Ok(self.fetch_positioned(ids).await?.into_iter().map(|(_, event)| event).collect())
+    }
+
+    /// Fetch the events with the given IDs along with their positions,
+    /// sorted by their total order. Events pruned in the meantime are
+    /// left out.
+    async fn fetch_positioned(
+        &self,
+        mut ids: Vec<EventPosition>,
+    ) -> Result<Vec<(EventPosition, Event)>> {
+        ids.sort_unstable();
+
+        let mut events = Vec::with_capacity(ids.len());
+        for position in ids {
+            let Some(bytes) = self.dag.get(position.id)? else { continue };
+            events.push((position, deserialize_async(&bytes).await?));
+        }
+
+        Ok(events)
+    }
+
+    /// Return up to `limit` events following the given position in the
+    /// total order, along with their positions. With no position, start
+    /// from the beginning.
+    pub async fn events_after(
+        &self,
+        after: Option<&EventPosition>,
+        limit: usize,
+    ) -> Result<Vec<(EventPosition, Event)>> {
+        let index = self.order_index.read().await;
+        let positions: Vec<EventPosition> = match after {
+            Some(after) => index
+                .order
+                .range((std::ops::Bound::Excluded(*after), std::ops::Bound::Unbounded))
+                .take(limit)
+                .copied()
+                .collect(),
+            None => index.order.iter().take(limit).copied().collect(),
+        };
+        drop(index);
+
+        self.fetch_positioned(positions).await
+    }
+
+    /// Return a paging iterator over the events of the DAG in their total order
+    pub fn pages(self: &EventGraphPtr, page_size: usize) -> EventPages {
+        EventPages { event_graph: self.clone(), cursor: None, page_size }
+    }
+
+    /// Return the events with a timestamp equal to or later than the given
+    /// one, in their total order.
+    pub async fn events_since(&self, timestamp: u64) -> Result<Vec<Event>> {
+        let timestamp_index = self.timestamp_index.read().await;
+        let order_index = self.order_index.read().await;
+        let positions: Vec<EventPosition> = timestamp_index
+            .range((timestamp, [0u8; blake3::OUT_LEN])..)
+            .filter_map(|(_, id)| order_index.position(&blake3::Hash::from_bytes(*id)))
+            .collect();
+        drop(order_index);
+        drop(timestamp_index);
+
+        self.fetch_ordered(positions).await
+    }
+
+    /// Return the ancestors of an event up to `depth` levels of parents
+    /// away, in their total order. The event itself is not included.
+    pub async fn ancestors(&self, event_id: &blake3::Hash, depth: usize) -> Result<Vec<Event>> {
+        let index = self.order_index.read().await;
+
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::from([(*event_id, 0)]);
+        let mut positions = vec![];
+        while let Some((id, level)) = queue.pop_front() {
+            if level == depth {
+                continue
+            }
+
+            let Some(bytes) = self.dag.get(id.as_bytes())? else { continue };
+            let event: Event = deserialize_async(&bytes).await?;
+            for parent_id in event.parents.iter() {
+                if parent_id == &NULL_ID || !visited.insert(*parent_id) {
+                    continue
+                }
+
+                if let Some(position) = index.position(parent_id) {
+                    positions.push(position);
+                    queue.push_back((*parent_id, level + 1));
+                }
+            }
+        }
+        drop(index);
+
+        self.fetch_ordered(positions).await
+    }
+
+    /// Return all the descendants of an event, in their total order.
+    /// The event itself is not included.
+    pub async fn descendants(&self, event_id: &blake3::Hash) -> Result<Vec<Event>> {
+        let index = self.order_index.read().await;
+
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::from([*event_id]);
+        let mut positions = vec![];
+        while let Some(id) = queue.pop_front() {
+            let Some(children) = index.children.get(&id) else { continue };
+            for child_id in children {
+                if !visited.insert(*child_id) {
+                    continue
+                }
+
+                if let Some(position) = index.position(child_id) {
+                    positions.push(position);
+                    queue.push_back(*child_id);
+                }
+            }
+        }
+        drop(index);
+
+        self.fetch_ordered(positions).await
+    }
+}
@@ -19,6 +19,7 @@
 // TODO: FIXME: Some of the protocols should block operations until DAG is synced.
 
 use std::{
+    collections::HashSet,
     sync::{
         atomic::{AtomicUsize, Ordering::SeqCst},
         Arc,
@@ -30,12 +31,17 @@ use darkfi_serial::{async_trait, deserialize_async, SerialDecodable, SerialEncod
 use log::{debug, error, trace, warn};
 use smol::Executor;
 
-use super::{Event, EventGraph, EventGraphPtr, NULL_ID};
+use super::{
+    sync::EVENT_BATCH, Event, EventGraph, EventGraphPtr, EventHeader, EventRange, RangeSummary,
+    NULL_ID,
+};
 use crate::{impl_p2p_message, net::*, system::timeout::timeout, Error, Result};
 
 /// Malicious behaviour threshold. If the threshold is reached, we will
 /// drop the peer from our P2P connection.
 const MALICIOUS_THRESHOLD: usize = 5;
+/// Maximum number of topics we note down for a peer
+const MAX_PEER_TOPICS: usize = 256;
 /// Time to wait for a parent ID reply
 pub(super) const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
 
@@ -55,37 +61,82 @@ pub struct ProtocolEventGraph {
     tip_req_sub: MessageSubscription<TipReq>,
     /// `MessageSubscriber` for `TipRep`
     _tip_rep_sub: MessageSubscription<TipRep>,
+    /// `MessageSubscriber` for `TopicList`
+    topic_list_sub: MessageSubscription<TopicList>,
+    /// `MessageSubscriber` for `RangeReq`
+    range_req_sub: MessageSubscription<RangeReq>,
+    /// `MessageSubscriber` for `HeaderReq`
+    header_req_sub: MessageSubscription<HeaderReq>,
+    /// `MessageSubscriber` for `EventBatchReq`
+    ev_batch_req_sub: MessageSubscription<EventBatchReq>,
     /// Peer malicious message count
     malicious_count: AtomicUsize,
     /// P2P jobs manager pointer
     jobsman: ProtocolJobsManagerPtr,
 }
 
-/// A P2P message representing publishing an event on the network
+/// A P2P message representing publishing an event of a topic on the network
 #[derive(Clone, SerialEncodable, SerialDecodable)]
-pub struct EventPut(pub Event);
+pub struct EventPut(pub Event, pub String);
 impl_p2p_message!(EventPut, "EventGraph::EventPut");
 
-/// A P2P message representing an event request
+/// A P2P message representing an event request for a topic
 #[derive(Clone, SerialEncodable, SerialDecodable)]
-pub struct EventReq(pub blake3::Hash);
+pub struct EventReq(pub blake3::Hash, pub String);
 impl_p2p_message!(EventReq, "EventGraph::EventReq");
 
-/// A P2P message representing an event reply
+/// A P2P message representing an event reply for a topic
 #[derive(Clone, SerialEncodable, SerialDecodable)]
-pub struct EventRep(pub Event);
+pub struct EventRep(pub Event, pub String);
 impl_p2p_message!(EventRep, "EventGraph::EventRep");
 
-/// A P2P message representing a request for a peer's DAG tips
+/// A P2P message representing a request for a peer's DAG tips of a topic
 #[derive(Clone, SerialEncodable, SerialDecodable)]
-pub struct TipReq {}
+pub struct TipReq(pub String);
 impl_p2p_message!(TipReq, "EventGraph::TipReq");
 
-/// A P2P message representing a reply for the peer's DAG tips
+/// A P2P message representing a reply for the peer's DAG tips of a topic
 #[derive(Clone, SerialEncodable, SerialDecodable)]
-pub struct TipRep(pub Vec<blake3::Hash>);
+pub struct TipRep(pub Vec<blake3::Hash>, pub String);
 impl_p2p_message!(TipRep, "EventGraph::TipRep");
 
+/// A P2P message advertising the topics a peer follows
+#[derive(Clone, SerialEncodable, SerialDecodable)]
+pub struct TopicList(pub Vec<String>);
+impl_p2p_message!(TopicList, "EventGraph::TopicList");
+
+/// A P2P message representing a request for the summary of a topic's
+/// events in a timestamp range
+#[derive(Clone, SerialEncodable, SerialDecodable)]
+pub struct RangeReq(pub EventRange, pub String);
+impl_p2p_message!(RangeReq, "EventGraph::RangeReq");
+
+/// A P2P message representing a reply with the summary of a range
+#[derive(Clone, SerialEncodable, SerialDecodable)]
+pub struct RangeRep(pub EventRange, pub RangeSummary, pub String);
+impl_p2p_message!(RangeRep, "EventGraph::RangeRep");
+
+/// A P2P message representing a request for the event headers in a range
+#[derive(Clone, SerialEncodable, SerialDecodable)]
+pub struct HeaderReq(pub EventRange, pub String);
+impl_p2p_message!(HeaderReq, "EventGraph::HeaderReq");
+
+/// A P2P message representing a reply with the event headers in a range.
+/// The last field tells if the headers were truncated at `MAX_HEADERS`.
+#[derive(Clone, SerialEncodable, SerialDecodable)]
+pub struct HeaderRep(pub EventRange, pub Vec<EventHeader>, pub String, pub bool);
+impl_p2p_message!(HeaderRep, "EventGraph::HeaderRep");
+
+/// A P2P message representing a request for a batch of events
+#[derive(Clone, SerialEncodable, SerialDecodable)]
+pub struct EventBatchReq(pub Vec<blake3::Hash>, pub String);
+impl_p2p_message!(EventBatchReq, "EventGraph::EventBatchReq");
+
+/// A P2P message representing a reply with a batch of events
+#[derive(Clone, SerialEncodable, SerialDecodable)]
+pub struct EventBatchRep(pub Vec<Event>, pub String);
+impl_p2p_message!(EventBatchRep, "EventGraph::EventBatchRep");
+
 #[async_trait]
 impl ProtocolBase for ProtocolEventGraph {
     async fn start(self: Arc<Self>, ex: Arc<Executor<'_>>) -> Result<()> {
@@ -93,6 +144,13 @@ impl ProtocolBase for ProtocolEventGraph {
         self.jobsman.clone().spawn(self.clone().handle_event_put(), ex.clone()).await;
         self.jobsman.clone().spawn(self.clone().handle_event_req(), ex.clone()).await;
         self.jobsman.clone().spawn(self.clone().handle_tip_req(), ex.clone()).await;
+        self.jobsman.clone().spawn(self.clone().handle_topic_list(), ex.clone()).await;
+        self.jobsman.clone().spawn(self.clone().handle_range_req(), ex.clone()).await;
+        self.jobsman.clone().spawn(self.clone().handle_header_req(), ex.clone()).await;
+        self.jobsman.clone().spawn(self.clone().handle_event_batch_req(), ex.clone()).await;
+
+        // Let the peer know which topics we follow
+        self.channel.send(&TopicList(self.event_graph.followed_topics().await)).await?;
         Ok(())
     }
 
@@ -109,12 +167,23 @@ impl ProtocolEventGraph {
         msg_subsystem.add_dispatch::<EventRep>().await;
         msg_subsystem.add_dispatch::<TipReq>().await;
         msg_subsystem.add_dispatch::<TipRep>().await;
+        msg_subsystem.add_dispatch::<TopicList>().await;
+        msg_subsystem.add_dispatch::<RangeReq>().await;
+        msg_subsystem.add_dispatch::<RangeRep>().await;
+        msg_subsystem.add_dispatch::<HeaderReq>().await;
+        msg_subsystem.add_dispatch::<HeaderRep>().await;
+        msg_subsystem.add_dispatch::<EventBatchReq>().await;
+        msg_subsystem.add_dispatch::<EventBatchRep>().await;
 
         let ev_put_sub = channel.subscribe_msg::<EventPut>().await?;
         let ev_req_sub = channel.subscribe_msg::<EventReq>().await?;
         let ev_rep_sub = channel.subscribe_msg::<EventRep>().await?;
         let tip_req_sub = channel.subscribe_msg::<TipReq>().await?;
         let _tip_rep_sub = channel.subscribe_msg::<TipRep>().await?;
+        let topic_list_sub = channel.subscribe_msg::<TopicList>().await?;
+        let range_req_sub = channel.subscribe_msg::<RangeReq>().await?;
+        let header_req_sub = channel.subscribe_msg::<HeaderReq>().await?;
+        let ev_batch_req_sub = channel.subscribe_msg::<EventBatchReq>().await?;
 
         Ok(Arc::new(Self {
             channel: channel.clone(),
@@ -124,6 +193,10 @@ impl ProtocolEventGraph {
             ev_rep_sub,
             tip_req_sub,
             _tip_rep_sub,
+            topic_list_sub,
+            range_req_sub,
+            header_req_sub,
+            ev_batch_req_sub,
             malicious_count: AtomicUsize::new(0),
             jobsman: ProtocolJobsManager::new("ProtocolEventGraph", channel.clone()),
         }))
@@ -133,15 +206,24 @@ impl ProtocolEventGraph {
     /// This is triggered whenever someone broadcasts (or relays) a new
     /// event on the network.
     async fn handle_event_put(self: Arc<Self>) -> Result<()> {
-        loop {
-            let event = match self.ev_put_sub.receive().await {
-                Ok(v) => v.0.clone(),
+        'events: loop {
+            let (event, topic) = match self.ev_put_sub.receive().await {
+                Ok(v) => (v.0.clone(), v.1.clone()),
                 Err(_) => continue,
             };
             trace!(
                  target: "event_graph::protocol::handle_event_put()",
-                 "Got EventPut: {} [{}]", event.id(), self.channel.address(),
+                 "Got EventPut: {} ({}) [{}]", event.id(), topic, self.channel.address(),
             );
+
+            // Events of topics we don't follow are not our business.
+            let Some(event_graph) = self.event_graph.get_topic(&topic).await else {
+                debug!(
+                    target: "event_graph::protocol::handle_event_put()",
+                    "Event {} is of unfollowed topic {}", event.id(), topic,
+                );
+                continue
+            };
             // Check if the event is older than the genesis event. If so, we should
             // not include it in our Dag.
             // The genesis event marks the last time the Dag has been pruned of old
@@ -150,7 +232,7 @@ impl ProtocolEventGraph {
             // TODO it would be better to store/cache this instead of calculating
             // on every broadcast/relay.
             let genesis_timestamp =
-                EventGraph::generate_genesis(self.event_graph.days_rotation()).timestamp;
+                EventGraph::generate_genesis(&topic, event_graph.days_rotation()).timestamp;
             if event.timestamp < genesis_timestamp {
                 debug!(
                     target: "event_graph::protocol::handle_event_put()",
@@ -167,8 +249,9 @@ impl ProtocolEventGraph {
 
             // Validate the event first. If we do not consider it valid, we
             // will just drop it and stay quiet. If the malicious threshold
-            // is reached, we will stop the connection.
-            if !event.validate() {
+            // is reached, we will stop the connection. Signed events must
+            // carry a valid signature of their author.
+            if !event.validate() || !event.verify_signature() {
                 let malicious_count = self.malicious_count.fetch_add(1, SeqCst);
                 if malicious_count + 1 == MALICIOUS_THRESHOLD {
                     error!(
@@ -189,7 +272,7 @@ impl ProtocolEventGraph {
 
             // If we have already seen the event, we'll stay quiet.
             let event_id = event.id();
-            if self.event_graph.dag.contains_key(event_id.as_bytes()).unwrap() {
+            if event_graph.dag.contains_key(event_id.as_bytes()).unwrap() {
                 debug!(
                     target: "event_graph::protocol::handle_event_put()",
                     "Event {} is already known", event_id,
@@ -197,6 +280,38 @@ impl ProtocolEventGraph {
                 continue
             }
 
+            // Events must pass our admission control. Honest peers only relay
+            // admitted events, so anything else counts as spam from this peer.
+            if !event_graph.admits(&event).await {
+                let malicious_count = self.malicious_count.fetch_add(1, SeqCst);
+                if malicious_count + 1 == MALICIOUS_THRESHOLD {
+                    error!(
+                        target: "event_graph::protocol::handle_event_put()",
+                        "[EVENTGRAPH] Peer {} reached malicious threshold. Dropping connection.",
+                        self.channel.address(),
+                    );
+                    self.channel.stop().await;
+                    return Err(Error::ChannelStopped)
+                }
+
+                warn!(
+                    target: "event_graph::protocol::handle_event_put()",
+                    "[EVENTGRAPH] Peer {} sent us an unadmitted event {}",
+                    self.channel.address(), event_id,
+                );
+                continue
+            }
+
+            // Drop events our policy does not accept. This is not misbehaviour
+            // of the peer, it might just be relaying what it got.
+            if !event_graph.policy_accepts(&event).await {
+                debug!(
+                    target: "event_graph::protocol::handle_event_put()",
+                    "Event {} rejected by policy", event_id,
+                );
+                continue
+            }
+
             // At this point, this is a new event to us. Let's see if we
             // have all of its parents.
             debug!(
@@ -212,7 +327,7 @@ impl ProtocolEventGraph {
                     continue
                 }
 
-                if !self.event_graph.dag.contains_key(parent_id.as_bytes()).unwrap() {
+                if !event_graph.dag.contains_key(parent_id.as_bytes()).unwrap() {
                     missing_parents.push(*parent_id);
                 }
             }
@@ -241,7 +356,7 @@ impl ProtocolEventGraph {
                             "Requesting {}...", parent_id,
                         );
 
-                        self.channel.send(&EventReq(*parent_id)).await?;
+                        self.channel.send(&EventReq(*parent_id, topic.clone())).await?;
                         let parent = match timeout(REPLY_TIMEOUT, self.ev_rep_sub.receive()).await {
                             Ok(parent) => parent?,
                             Err(_) => {
@@ -254,6 +369,15 @@ impl ProtocolEventGraph {
                                 return Err(Error::ChannelStopped)
                             }
                         };
+                        if parent.1 != topic {
+                            error!(
+                                target: "event_graph::protocol::handle_event_put()",
+                                "[EVENTGRAPH] Peer {} replied with an event of topic {}",
+                                self.channel.address(), parent.1,
+                            );
+                            self.channel.stop().await;
+                            return Err(Error::ChannelStopped)
+                        }
                         let parent = parent.0.clone();
 
                         if &parent.id() != parent_id {
@@ -266,6 +390,35 @@ impl ProtocolEventGraph {
                             return Err(Error::ChannelStopped)
                         }
 
+                        if !parent.verify_signature() {
+                            error!(
+                                target: "event_graph::protocol::handle_event_put()",
+                                "[EVENTGRAPH] Peer {} replied with a badly signed event: {}",
+                                self.channel.address(), parent_id,
+                            );
+                            self.channel.stop().await;
+                            return Err(Error::ChannelStopped)
+                        }
+
+                        if !event_graph.admits(&parent).await {
+                            error!(
+                                target: "event_graph::protocol::handle_event_put()",
+                                "[EVENTGRAPH] Peer {} replied with an unadmitted event: {}",
+                                self.channel.address(), parent_id,
+                            );
+                            self.channel.stop().await;
+                            return Err(Error::ChannelStopped)
+                        }
+
+                        // Without this parent we can't insert the event either
+                        if !event_graph.policy_accepts(&parent).await {
+                            debug!(
+                                target: "event_graph::protocol::handle_event_put()",
+                                "Parent {} of event {} rejected by policy", parent_id, event_id,
+                            );
+                            continue 'events
+                        }
+
                         debug!(
                             target: "event_graph::protocol::handle_event_put()",
                             "Got correct parent event {}", parent.id(),
@@ -281,8 +434,7 @@ impl ProtocolEventGraph {
                                 continue
                             }
 
-                            if !self.event_graph.dag.contains_key(upper_parent.as_bytes()).unwrap()
-                            {
+                            if !event_graph.dag.contains_key(upper_parent.as_bytes()).unwrap() {
                                 debug!(
                                     target: "event_graph::protocol::handle_event_put()",
                                     "Found upper missing parent event{}", upper_parent,
@@ -298,7 +450,7 @@ impl ProtocolEventGraph {
                 // TODO: FIXME: Also validate these events.
                 let received_events_rev: Vec<Event> =
                     received_events.iter().rev().cloned().collect();
-                self.event_graph.dag_insert(&received_events_rev).await.unwrap();
+                event_graph.dag_insert(&received_events_rev).await.unwrap();
             } // <-- !missing_parents.is_empty()
 
             // If we're here, we have all the parents, and we can now
@@ -307,13 +459,10 @@ impl ProtocolEventGraph {
                 target: "event_graph::protocol::handle_event_put()",
                 "Got all parents necessary for insertion",
             );
-            self.event_graph.dag_insert(&[event.clone()]).await.unwrap();
+            event_graph.dag_insert(&[event.clone()]).await.unwrap();
 
-            // Relay the event to other peers.
-            self.event_graph
-                .p2p
-                .broadcast_with_exclude(&EventPut(event), &[self.channel.address().clone()])
-                .await;
+            // Relay the event to other peers following the topic.
+            event_graph.broadcast(&event, &[self.channel.address().clone()]).await;
         }
     }
 
@@ -321,13 +470,13 @@ impl ProtocolEventGraph {
     /// This is triggered whenever someone requests an event from us.
     async fn handle_event_req(self: Arc<Self>) -> Result<()> {
         loop {
-            let event_id = match self.ev_req_sub.receive().await {
-                Ok(v) => v.0,
+            let (event_id, topic) = match self.ev_req_sub.receive().await {
+                Ok(v) => (v.0, v.1.clone()),
                 Err(_) => continue,
             };
             trace!(
                 target: "event_graph::protocol::handle_event_req()",
-                "Got EventReq: {} [{}]", event_id, self.channel.address(),
+                "Got EventReq: {} ({}) [{}]", event_id, topic, self.channel.address(),
             );
 
             // We received an event request from somebody.
@@ -341,7 +490,12 @@ impl ProtocolEventGraph {
             // I dunno if this is a good idea, but it seems it will help
             // against malicious event requests where they want us to keep
             // reading our db and steal our bandwidth.
-            if !self.event_graph.broadcasted_ids.read().await.contains(&event_id) {
+            let event_graph = self.event_graph.get_topic(&topic).await;
+            let expected = match &event_graph {
+                Some(event_graph) => event_graph.broadcasted_ids.read().await.contains(&event_id),
+                None => false,
+            };
+            if !expected {
                 let malicious_count = self.malicious_count.fetch_add(1, SeqCst);
                 if malicious_count + 1 == MALICIOUS_THRESHOLD {
                     error!(
@@ -360,6 +514,7 @@ impl ProtocolEventGraph {
                 );
                 continue
             }
+            let event_graph = event_graph.unwrap();
 
             // At this point we should have it in our DAG.
             // This code panics if this is not the case.
@@ -367,7 +522,7 @@ impl ProtocolEventGraph {
                 target: "event_graph::protocol::handle_event_req()",
                 "Fetching event {} from DAG", event_id,
             );
-            let event = self.event_graph.dag.get(event_id.as_bytes()).unwrap().unwrap();
+            let event = event_graph.dag.get(event_id.as_bytes()).unwrap().unwrap();
             let event: Event = deserialize_async(&event).await.unwrap();
 
             // Check if the event is older than the genesis event. If so, something
@@ -376,7 +531,7 @@ impl ProtocolEventGraph {
             // TODO it would be better to store/cache this instead of calculating
             // on every broadcast/relay.
             let genesis_timestamp =
-                EventGraph::generate_genesis(self.event_graph.days_rotation()).timestamp;
+                EventGraph::generate_genesis(&topic, event_graph.days_rotation()).timestamp;
             if event.timestamp < genesis_timestamp {
                 error!(
                     target: "event_graph::protocol::handle_event_req()",
@@ -388,7 +543,7 @@ impl ProtocolEventGraph {
 
             // Now let's get the upper level of event IDs. When we reply, we could
             // get requests for those IDs as well.
-            let mut bcast_ids = self.event_graph.broadcasted_ids.write().await;
+            let mut bcast_ids = event_graph.broadcasted_ids.write().await;
             for parent_id in event.parents.iter() {
                 if parent_id != &NULL_ID {
                     bcast_ids.insert(*parent_id);
@@ -401,7 +556,7 @@ impl ProtocolEventGraph {
             drop(bcast_ids);
 
             // Reply with the event
-            self.channel.send(&EventRep(event)).await?;
+            self.channel.send(&EventRep(event, topic)).await?;
         }
     }
 
@@ -410,26 +565,157 @@ impl ProtocolEventGraph {
     /// tips of our DAG.
     async fn handle_tip_req(self: Arc<Self>) -> Result<()> {
         loop {
-            self.tip_req_sub.receive().await?;
+            let topic = self.tip_req_sub.receive().await?.0.clone();
             trace!(
                 target: "event_graph::protocol::handle_tip_req()",
-                "Got TipReq [{}]", self.channel.address(),
+                "Got TipReq ({}) [{}]", topic, self.channel.address(),
             );
 
             // TODO: Rate limit
 
+            // If we don't follow the topic, we have no tips to offer.
+            let Some(event_graph) = self.event_graph.get_topic(&topic).await else {
+                self.channel.send(&TipRep(vec![], topic)).await?;
+                continue
+            };
+
             // We received a tip request. Let's find them, add them to
             // our bcast ids list, and reply with them.
-            let mut tips = self.event_graph.get_unreferenced_tips().await.to_vec();
+            let mut tips = event_graph.get_unreferenced_tips().await.to_vec();
             tips.retain(|x| x != &NULL_ID);
 
-            let mut bcast_ids = self.event_graph.broadcasted_ids.write().await;
+            let mut bcast_ids = event_graph.broadcasted_ids.write().await;
             for tip in tips.iter() {
                 bcast_ids.insert(*tip);
             }
             drop(bcast_ids);
 
-            self.channel.send(&TipRep(tips)).await?;
+            self.channel.send(&TipRep(tips, topic)).await?;
+        }
+    }
+
+    /// Protocol function handling `TopicList`.
+    /// This is triggered when a peer tells us which topics it follows,
+    /// so we know which events to relay and request from it.
+    async fn handle_topic_list(self: Arc<Self>) -> Result<()> {
+        loop {
+            let topics = self.topic_list_sub.receive().await?;
+            trace!(
+                target: "event_graph::protocol::handle_topic_list()",
+                "Got TopicList: {:?} [{}]", topics.0, self.channel.address(),
+            );
+
+            let topics: HashSet<String> = topics.0.iter().take(MAX_PEER_TOPICS).cloned().collect();
+            self.event_graph.set_peer_topics(self.channel.address(), topics).await;
+        }
+    }
+
+    /// Protocol function handling `RangeReq`.
+    /// This is triggered when a syncing peer compares a range of its
+    /// DAG with ours.
+    async fn handle_range_req(self: Arc<Self>) -> Result<()> {
+        loop {
+            let req = self.range_req_sub.receive().await?;
+            let (range, topic) = (req.0, req.1.clone());
+            trace!(
+                target: "event_graph::protocol::handle_range_req()",
+                "Got RangeReq: {}..{} ({}) [{}]", range.start, range.end, topic, self.channel.address(),
+            );
+
+            // TODO: Rate limit
+
+            let summary = match self.event_graph.get_topic(&topic).await {
+                Some(event_graph) => event_graph.range_summary(&range).await,
+                None => RangeSummary { count: 0, fingerprint: [0u8; blake3::OUT_LEN] },
+            };
+
+            self.channel.send(&RangeRep(range, summary, topic)).await?;
+        }
+    }
+
+    /// Protocol function handling `HeaderReq`.
+    /// This is triggered when a syncing peer requests the event headers
+    /// of a range of our DAG.
+    async fn handle_header_req(self: Arc<Self>) -> Result<()> {
+        loop {
+            let req = self.header_req_sub.receive().await?;
+            let (range, topic) = (req.0, req.1.clone());
+            trace!(
+                target: "event_graph::protocol::handle_header_req()",
+                "Got HeaderReq: {}..{} ({}) [{}]", range.start, range.end, topic, self.channel.address(),
+            );
+
+            // TODO: Rate limit
+
+            let Some(event_graph) = self.event_graph.get_topic(&topic).await else {
+                self.channel.send(&HeaderRep(range, vec![], topic, false)).await?;
+                continue
+            };
+
+            // The peer will request the events behind these headers, so
+            // add them to our bcast ids list.
+            let (headers, truncated) = event_graph.range_headers(&range).await?;
+            let mut bcast_ids = event_graph.broadcasted_ids.write().await;
+            for header in headers.iter() {
+                bcast_ids.insert(header.id);
+            }
+            drop(bcast_ids);
+
+            self.channel.send(&HeaderRep(range, headers, topic, truncated)).await?;
+        }
+    }
+
+    /// Protocol function handling `EventBatchReq`.
+    /// This is triggered when a syncing peer requests the events whose
+    /// headers we've sent to it.
+    async fn handle_event_batch_req(self: Arc<Self>) -> Result<()> {
+        loop {
+            let req = self.ev_batch_req_sub.receive().await?;
+            let topic = req.1.clone();
+            trace!(
+                target: "event_graph::protocol::handle_event_batch_req()",
+                "Got EventBatchReq: {} events ({}) [{}]", req.0.len(), topic, self.channel.address(),
+            );
+
+            // Like with `EventReq`, we only serve events we've told the
+            // peer about, and no more than a batch at a time.
+            let event_graph = self.event_graph.get_topic(&topic).await;
+            let expected = match &event_graph {
+                Some(event_graph) if req.0.len() <= EVENT_BATCH => {
+                    let bcast_ids = event_graph.broadcasted_ids.read().await;
+                    req.0.iter().all(|id| bcast_ids.contains(id))
+                }
+                _ => false,
+            };
+
+            if !expected {
+                let malicious_count = self.malicious_count.fetch_add(1, SeqCst);
+                if malicious_count + 1 == MALICIOUS_THRESHOLD {
+                    error!(
+                        target: "event_graph::protocol::handle_event_batch_req()",
+                        "[EVENTGRAPH] Peer {} reached malicious threshold. Dropping connection.",
+                        self.channel.address(),
+                    );
+                    self.channel.stop().await;
+                    return Err(Error::ChannelStopped)
+                }
+
+                warn!(
+                    target: "event_graph::protocol::handle_event_batch_req()",
+                    "[EVENTGRAPH] Peer {} requested unexpected events", self.channel.address(),
+                );
+                continue
+            }
+            let event_graph = event_graph.unwrap();
+
+            let mut events = Vec::with_capacity(req.0.len());
+            for event_id in req.0.iter() {
+                if let Some(event) = event_graph.dag_get(event_id).await? {
+                    events.push(event);
+                }
+            }
+
+            self.channel.send(&EventBatchRep(events, topic)).await?;
         }
     }
 }
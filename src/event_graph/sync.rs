@@ -0,0 +1,518 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Header-first DAG sync.
+//!
+//! Peers first reconcile the sets of event IDs they have in timestamp
+//! ranges of the DAG. Each range is summarized by the number of events
+//! in it and the XOR of their IDs. Ranges whose summaries differ are
+//! split until they are small enough to be exchanged as a batch of
+//! [`EventHeader`]s. The bodies of the missing events are then fetched
+//! in batches from all peers that advertised them, in parallel.
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Instant, UNIX_EPOCH},
+};
+
+use darkfi_serial::{async_trait, SerialDecodable, SerialEncodable};
+use futures::stream::{FuturesUnordered, StreamExt};
+use log::{debug, error, info};
+
+use super::{
+    proto::{
+        EventBatchRep, EventBatchReq, HeaderRep, HeaderReq, RangeRep, RangeReq, REPLY_TIMEOUT,
+    },
+    Event, EventGraph, EventHeader, EVENT_TIME_DRIFT, NULL_ID,
+};
+use crate::{
+    net::{ChannelPtr, Message, MessageSubscription},
+    system::timeout::timeout,
+    Error, Result,
+};
+
+/// Number of events in a range below which we request its headers
+/// instead of splitting it further
+pub(super) const HEADER_BATCH: u64 = 256;
+/// Maximum number of headers sent in a single `HeaderRep`
+pub(super) const MAX_HEADERS: usize = 1024;
+/// Maximum number of events requested in a single `EventBatchReq`
+pub(super) const EVENT_BATCH: usize = 64;
+
+/// A range of event timestamps, `start` inclusive and `end` exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SerialEncodable, SerialDecodable)]
+pub struct EventRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl EventRange {
+    /// Split the range in two halves, if it is wider than one second
+    fn split(&self) -> Option<(Self, Self)> {
+        if self.end - self.start <= 1 {
+            return None
+        }
+
+        let mid = self.start + (self.end - self.start) / 2;
+        Some((Self { start: self.start, end: mid }, Self { start: mid, end: self.end }))
+    }
+}
+
+/// Summary of the events in an [`EventRange`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SerialEncodable, SerialDecodable)]
+pub struct RangeSummary {
+    /// Number of events in the range
+    pub count: u64,
+    /// XOR of the IDs of the events in the range
+    pub fingerprint: [u8; blake3::OUT_LEN],
+}
+
+/// Phase of a DAG sync
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPhase {
+    /// No sync has been started
+    Idle,
+    /// Reconciling ranges and downloading headers
+    Headers,
+    /// Downloading event bodies
+    Events,
+    /// The last sync finished successfully
+    Done,
+    /// The last sync failed
+    Failed,
+}
+
+/// Progress of a DAG sync, as reported by [`EventGraph::sync_progress`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncProgress {
+    /// Current phase of the sync
+    pub phase: SyncPhase,
+    /// Number of peers we are syncing from
+    pub peers: usize,
+    /// Number of missing events found from the headers
+    pub events_total: usize,
+    /// Number of missing events fetched so far
+    pub events_fetched: usize,
+}
+
+impl Default for SyncProgress {
+    fn default() -> Self {
+        Self { phase: SyncPhase::Idle, peers: 0, events_total: 0, events_fetched: 0 }
+    }
+}
+
+/// Wait for a reply matching `matches` on the given subscription,
+/// skipping replies meant for other requests.
+async fn receive_reply<M: Message>(
+    sub: &MessageSubscription<M>,
+    matches: impl Fn(&M) -> bool,
+) -> Result<std::sync::Arc<M>> {
+    let deadline = Instant::now() + REPLY_TIMEOUT;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let reply = match timeout(remaining, sub.receive()).await {
+            Ok(reply) => reply?,
+            Err(_) => return Err(Error::DagSyncFailed),
+        };
+
+        if matches(&reply) {
+            return Ok(reply)
+        }
+    }
+}
+
+impl EventGraph {
+    /// Return the current progress of the DAG sync
+    pub async fn sync_progress(&self) -> SyncProgress {
+        *self.sync_progress.read().await
+    }
+
+    /// Update the DAG sync progress and notify subscribers
+    async fn update_sync_progress(&self, update: impl FnOnce(&mut SyncProgress)) {
+        let mut progress = self.sync_progress.write().await;
+        update(&mut progress);
+        let progress = *progress;
+        self.sync_sub.notify(progress).await;
+    }
+
+    /// Summarize the events of the DAG in the given range
+    pub(super) async fn range_summary(&self, range: &EventRange) -> RangeSummary {
+        let index = self.timestamp_index.read().await;
+
+        let mut count = 0;
+        let mut fingerprint = [0u8; blake3::OUT_LEN];
+        for (_, id) in index.range((range.start, [0u8; 32])..(range.end, [0u8; 32])) {
+            count += 1;
+            for (a, b) in fingerprint.iter_mut().zip(id.iter()) {
+                *a ^= b;
+            }
+        }
+
+        RangeSummary { count, fingerprint }
+    }
+
+    /// Return up to `MAX_HEADERS` headers of the events in the given range,
+    /// along with whether there were more events in the range than that.
+    pub(super) async fn range_headers(
+        &self,
+        range: &EventRange,
+    ) -> Result<(Vec<EventHeader>, bool)> {
+        let index = self.timestamp_index.read().await;
+
+        let mut headers = vec![];
+        for (_, id) in index.range((range.start, [0u8; 32])..(range.end, [0u8; 32])) {
+            if headers.len() == MAX_HEADERS {
+                return Ok((headers, true))
+            }
+
+            // The event might have been pruned in the meantime
+            let Some(event) = self.dag_get(&blake3::Hash::from_bytes(*id)).await? else { continue };
+            headers.push(event.header());
+        }
+
+        Ok((headers, false))
+    }
+
+    /// Sync the DAG from connected peers following our topic.
+    ///
+    /// We reconcile the whole DAG range with every peer to find the headers
+    /// of the events we are missing, and then fetch those events from the
+    /// peers that have them. Events are inserted once all their parents are
+    /// known, so a sync that fails halfway keeps what it got and the next
+    /// one only has to fetch the rest.
+    pub async fn dag_sync(&self) -> Result<()> {
+        let channels = self.channels_following().await;
+        info!(
+            target: "event_graph::dag_sync()",
+            "[EVENTGRAPH] Syncing DAG of topic {} from {} peers...", self.topic, channels.len(),
+        );
+        self.update_sync_progress(|p| {
+            *p = SyncProgress {
+                phase: SyncPhase::Headers,
+                peers: channels.len(),
+                events_total: 0,
+                events_fetched: 0,
+            }
+        })
+        .await;
+
+        // Everything from the current genesis until now belongs to the DAG
+        let genesis = Self::generate_genesis(&self.topic, self.days_rotation);
+        let now = UNIX_EPOCH.elapsed().unwrap().as_secs();
+        let range = EventRange { start: genesis.timestamp, end: now + EVENT_TIME_DRIFT + 1 };
+
+        // Find the headers of the events we're missing, and who has them
+        let mut missing: HashMap<blake3::Hash, (EventHeader, Vec<ChannelPtr>)> = HashMap::new();
+        let mut reached_peers = 0;
+        for channel in channels.iter() {
+            let headers = match self.reconcile(channel, range).await {
+                Ok(v) => v,
+                Err(e) => {
+                    error!(
+                        target: "event_graph::dag_sync()",
+                        "[EVENTGRAPH] Sync: Failed reconciling with peer {}, skipping ({})",
+                        channel.address(), e,
+                    );
+                    continue
+                }
+            };
+            reached_peers += 1;
+
+            for header in headers {
+                if self.dag.contains_key(header.id.as_bytes())? {
+                    continue
+                }
+
+                missing
+                    .entry(header.id)
+                    .or_insert_with(|| (header, vec![]))
+                    .1
+                    .push(channel.clone());
+            }
+
+            let events_total = missing.len();
+            self.update_sync_progress(|p| p.events_total = events_total).await;
+        }
+
+        if reached_peers == 0 {
+            error!(target: "event_graph::dag_sync()", "[EVENTGRAPH] Sync: Could not reach any peers");
+            self.update_sync_progress(|p| p.phase = SyncPhase::Failed).await;
+            return Err(Error::DagSyncFailed)
+        }
+
+        info!(
+            target: "event_graph::dag_sync()",
+            "[EVENTGRAPH] Sync: Missing {} events, fetching...", missing.len(),
+        );
+        self.update_sync_progress(|p| p.phase = SyncPhase::Events).await;
+
+        // Fetch the events. In every round, each missing event is assigned
+        // to one of the peers that has it, and the peers are queried in
+        // parallel. Events a peer failed to give us are retried with the
+        // next peer that has them.
+        let mut received: HashMap<blake3::Hash, Event> = HashMap::new();
+        let max_rounds = missing.values().map(|(_, peers)| peers.len()).max().unwrap_or(0);
+        for round in 0..max_rounds {
+            let mut assignments: HashMap<url::Url, (ChannelPtr, Vec<EventHeader>)> = HashMap::new();
+            for (i, (id, (header, peers))) in missing.iter().enumerate() {
+                if received.contains_key(id) || round >= peers.len() {
+                    continue
+                }
+
+                let channel = &peers[(i + round) % peers.len()];
+                assignments
+                    .entry(channel.address().clone())
+                    .or_insert_with(|| (channel.clone(), vec![]))
+                    .1
+                    .push(header.clone());
+            }
+
+            if assignments.is_empty() {
+                break
+            }
+
+            let mut futures = FuturesUnordered::new();
+            for (channel, headers) in assignments.into_values() {
+                futures.push(async move {
+                    let events = self.fetch_events(&channel, &headers).await;
+                    (channel, events)
+                });
+            }
+
+            while let Some((channel, events)) = futures.next().await {
+                let events = match events {
+                    Ok(v) => v,
+                    Err(e) => {
+                        error!(
+                            target: "event_graph::dag_sync()",
+                            "[EVENTGRAPH] Sync: Failed fetching events from {}: {}",
+                            channel.address(), e,
+                        );
+                        continue
+                    }
+                };
+
+                for event in events {
+                    received.insert(event.id(), event);
+                }
+            }
+        }
+
+        let n_unfetched = missing.keys().filter(|id| !received.contains_key(id)).count();
+
+        // Drop the events our policy does not accept
+        let mut accepted = HashMap::with_capacity(received.len());
+        for (id, event) in received {
+            if !self.policy_accepts(&event).await {
+                debug!(target: "event_graph::dag_sync()", "Event {} rejected by policy", id);
+                continue
+            }
+            accepted.insert(id, event);
+        }
+
+        // Insert the events we can, parents first
+        let n_accepted = accepted.len();
+        let ordered = self.order_for_insertion(accepted)?;
+        if ordered.len() != n_accepted {
+            error!(
+                target: "event_graph::dag_sync()",
+                "[EVENTGRAPH] Sync: Dropping {} events with unknown parents",
+                n_accepted - ordered.len(),
+            );
+        }
+        self.dag_insert(&ordered).await?;
+
+        if n_unfetched > 0 {
+            error!(
+                target: "event_graph::dag_sync()",
+                "[EVENTGRAPH] Sync: Failed to get {} of {} events", n_unfetched, missing.len(),
+            );
+            self.update_sync_progress(|p| p.phase = SyncPhase::Failed).await;
+            return Err(Error::DagSyncFailed)
+        }
+
+        self.update_sync_progress(|p| p.phase = SyncPhase::Done).await;
+        info!(target: "event_graph::dag_sync()", "[EVENTGRAPH] DAG synced successfully!");
+        Ok(())
+    }
+
+    /// Reconcile the given range with a peer, returning the headers of
+    /// the events in the parts of the range where our DAGs differ.
+    async fn reconcile(&self, channel: &ChannelPtr, range: EventRange) -> Result<Vec<EventHeader>> {
+        let range_rep_sub = channel.subscribe_msg::<RangeRep>().await?;
+        let header_rep_sub = channel.subscribe_msg::<HeaderRep>().await?;
+
+        let mut headers = vec![];
+        let mut ranges = vec![range];
+        while let Some(range) = ranges.pop() {
+            channel.send(&RangeReq(range, self.topic.clone())).await?;
+            let reply =
+                receive_reply(&range_rep_sub, |r| r.0 == range && r.2 == self.topic).await?;
+            let summary = reply.1;
+
+            // Nothing to fetch if the peer has nothing here, or the same as us
+            if summary.count == 0 || summary == self.range_summary(&range).await {
+                continue
+            }
+
+            // Keep splitting until the range is small enough
+            if summary.count > HEADER_BATCH {
+                if let Some((low, high)) = range.split() {
+                    ranges.push(high);
+                    ranges.push(low);
+                    continue
+                }
+            }
+
+            debug!(
+                target: "event_graph::dag_sync()",
+                "Requesting headers of range {}..{} from {}", range.start, range.end, channel.address(),
+            );
+            channel.send(&HeaderReq(range, self.topic.clone())).await?;
+            let reply =
+                receive_reply(&header_rep_sub, |r| r.0 == range && r.2 == self.topic).await?;
+
+            // If the peer couldn't fit all the headers, ask for the halves
+            // instead. A single second can't be split, so we keep what we got.
+            if reply.3 {
+                if let Some((low, high)) = range.split() {
+                    ranges.push(high);
+                    ranges.push(low);
+                    continue
+                }
+
+                error!(
+                    target: "event_graph::dag_sync()",
+                    "[EVENTGRAPH] Sync: Peer {} truncated the headers of range {}..{}",
+                    channel.address(), range.start, range.end,
+                );
+            }
+
+            headers.extend(
+                reply
+                    .1
+                    .iter()
+                    .filter(|h| h.timestamp >= range.start && h.timestamp < range.end)
+                    .cloned(),
+            );
+        }
+
+        Ok(headers)
+    }
+
+    /// Fetch the events behind the given headers from a peer in batches.
+    /// Events that don't match what we asked for, are malformed, badly
+    /// signed or not admitted are left out.
+    async fn fetch_events(
+        &self,
+        channel: &ChannelPtr,
+        headers: &[EventHeader],
+    ) -> Result<Vec<Event>> {
+        let event_batch_rep_sub = channel.subscribe_msg::<EventBatchRep>().await?;
+
+        let mut events = vec![];
+        for batch in headers.chunks(EVENT_BATCH) {
+            debug!(
+                target: "event_graph::dag_sync()",
+                "Requesting {} events from {}", batch.len(), channel.address(),
+            );
+            let ids = batch.iter().map(|h| h.id).collect();
+            channel.send(&EventBatchReq(ids, self.topic.clone())).await?;
+            let reply = receive_reply(&event_batch_rep_sub, |r| r.1 == self.topic).await?;
+
+            let requested: HashMap<blake3::Hash, &EventHeader> =
+                batch.iter().map(|h| (h.id, h)).collect();
+            let mut fetched = 0;
+            for event in reply.0.iter() {
+                let matches_header = requested
+                    .get(&event.id())
+                    .is_some_and(|h| h.timestamp == event.timestamp && h.parents == event.parents);
+
+                if !matches_header || !event.validate_layout() || !event.verify_signature() {
+                    error!(
+                        target: "event_graph::dag_sync()",
+                        "[EVENTGRAPH] Sync: Peer {} replied with a wrong event: {}",
+                        channel.address(), event.id(),
+                    );
+                    continue
+                }
+
+                if !self.admits(event).await {
+                    error!(
+                        target: "event_graph::dag_sync()",
+                        "[EVENTGRAPH] Sync: Peer {} replied with an unadmitted event: {}",
+                        channel.address(), event.id(),
+                    );
+                    continue
+                }
+
+                events.push(event.clone());
+                fetched += 1;
+            }
+
+            self.update_sync_progress(|p| p.events_fetched += fetched).await;
+        }
+
+        Ok(events)
+    }
+
+    /// Order the given events so that parents come before their children.
+    /// Events whose parents are neither in the DAG nor among the given
+    /// events are left out.
+    pub(super) fn order_for_insertion(
+        &self,
+        mut events: HashMap<blake3::Hash, Event>,
+    ) -> Result<Vec<Event>> {
+        let mut ordered = Vec::with_capacity(events.len());
+        let mut inserted = HashSet::new();
+
+        loop {
+            let mut ready = vec![];
+            for (id, event) in events.iter() {
+                let mut have_parents = true;
+                for parent in event.parents.iter() {
+                    if parent == &NULL_ID || inserted.contains(parent) {
+                        continue
+                    }
+
+                    if events.contains_key(parent) || !self.dag.contains_key(parent.as_bytes())? {
+                        have_parents = false;
+                        break
+                    }
+                }
+
+                if have_parents {
+                    ready.push(*id);
+                }
+            }
+
+            if ready.is_empty() {
+                break
+            }
+
+            // Keep the order deterministic between runs
+            ready.sort_unstable_by_key(|id| (events[id].timestamp, *id.as_bytes()));
+            for id in ready {
+                ordered.push(events.remove(&id).unwrap());
+                inserted.insert(id);
+            }
+        }
+
+        Ok(ordered)
+    }
+}
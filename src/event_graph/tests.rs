@@ -18,7 +18,7 @@
 
 // cargo +nightly test --release --features=event-graph --lib eventgraph_propagation -- --include-ignored
 
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use log::info;
 use rand::{prelude::SliceRandom, Rng};
@@ -27,8 +27,8 @@ use url::Url;
 
 use crate::{
     event_graph::{
-        proto::{EventPut, ProtocolEventGraph},
-        Event, EventGraph, NULL_ID,
+        proto::ProtocolEventGraph, Event, EventGraph, EventGraphPtr, EventRange, PowAdmission,
+        SyncPhase, DEFAULT_TOPIC, NULL_ID,
     },
     net::{P2p, Settings, SESSION_ALL},
     system::sleep,
@@ -97,17 +97,17 @@ async fn eventgraph_propagation_real(ex: Arc<Executor<'static>>) {
             while port == 13200 + i {
                 port = 13200 + rng.gen_range(0..N_NODES);
             }
-            peers.push(Url::parse(&format!("tcp://127.0.0.1:{}", port)).unwrap());
+            peers.push(Url::parse(&format!("memory://eg-node:{}", port)).unwrap());
         }
 
         let settings = Settings {
             localnet: true,
-            inbound_addrs: vec![Url::parse(&format!("tcp://127.0.0.1:{}", 13200 + i)).unwrap()],
+            inbound_addrs: vec![Url::parse(&format!("memory://eg-node:{}", 13200 + i)).unwrap()],
             outbound_connections: 0,
             outbound_connect_timeout: 2,
             inbound_connections: usize::MAX,
             peers,
-            allowed_transports: vec!["tcp".to_string()],
+            allowed_transports: vec!["memory".to_string()],
             ..Default::default()
         };
 
@@ -166,7 +166,7 @@ async fn eventgraph_propagation_real(ex: Arc<Executor<'static>>) {
     assert!(tips.get(&event_id).is_some());
     drop(tips);
     info!("Broadcasting event {}", event_id);
-    random_node.p2p.broadcast(&EventPut(event)).await;
+    random_node.broadcast(&event, &[]).await;
     info!("Waiting 10s for event propagation");
     sleep(10).await;
 
@@ -204,7 +204,7 @@ async fn eventgraph_propagation_real(ex: Arc<Executor<'static>>) {
 
     info!("Broadcasting event {}", event2_id);
     info!("Event chain: {:#?}", event_chain);
-    random_node.p2p.broadcast(&EventPut(event2)).await;
+    random_node.broadcast(&event2, &[]).await;
     info!("Waiting 10s for event propagation");
     sleep(10).await;
 
@@ -226,15 +226,15 @@ async fn eventgraph_propagation_real(ex: Arc<Executor<'static>>) {
     let node1 = eg_instances.choose(&mut rand::thread_rng()).unwrap();
     let event0_1 = Event::new(vec![1, 2, 3, 4, 3], node1.clone()).await;
     let _ = node1.dag_insert(&[event0_1.clone()]).await.unwrap()[0];
-    node1.p2p.broadcast(&EventPut(event0_1)).await;
+    node1.broadcast(&event0_1, &[]).await;
 
     let event1_1 = Event::new(vec![1, 2, 3, 4, 4], node1.clone()).await;
     let _ = node1.dag_insert(&[event1_1.clone()]).await.unwrap()[0];
-    node1.p2p.broadcast(&EventPut(event1_1)).await;
+    node1.broadcast(&event1_1, &[]).await;
 
     let event2_1 = Event::new(vec![1, 2, 3, 4, 5], node1.clone()).await;
     let _ = node1.dag_insert(&[event2_1.clone()]).await.unwrap()[0];
-    node1.p2p.broadcast(&EventPut(event2_1)).await;
+    node1.broadcast(&event2_1, &[]).await;
 
     // =======
     // node 2
@@ -242,14 +242,14 @@ async fn eventgraph_propagation_real(ex: Arc<Executor<'static>>) {
     let node2 = eg_instances.choose(&mut rand::thread_rng()).unwrap();
     let event0_2 = Event::new(vec![1, 2, 3, 4, 6], node2.clone()).await;
     let _ = node2.dag_insert(&[event0_2.clone()]).await.unwrap()[0];
-    node2.p2p.broadcast(&EventPut(event0_2)).await;
+    node2.broadcast(&event0_2, &[]).await;
     let event1_2 = Event::new(vec![1, 2, 3, 4, 7], node2.clone()).await;
     let _ = node2.dag_insert(&[event1_2.clone()]).await.unwrap()[0];
-    node2.p2p.broadcast(&EventPut(event1_2)).await;
+    node2.broadcast(&event1_2, &[]).await;
 
     let event2_2 = Event::new(vec![1, 2, 3, 4, 8], node2.clone()).await;
     let _ = node2.dag_insert(&[event2_2.clone()]).await.unwrap()[0];
-    node2.p2p.broadcast(&EventPut(event2_2)).await;
+    node2.broadcast(&event2_2, &[]).await;
 
     // =======
     // node 3
@@ -257,15 +257,15 @@ async fn eventgraph_propagation_real(ex: Arc<Executor<'static>>) {
     let node3 = eg_instances.choose(&mut rand::thread_rng()).unwrap();
     let event0_3 = Event::new(vec![1, 2, 3, 4, 9], node3.clone()).await;
     let _ = node3.dag_insert(&[event0_3.clone()]).await.unwrap()[0];
-    node2.p2p.broadcast(&EventPut(event0_3)).await;
+    node2.broadcast(&event0_3, &[]).await;
 
     let event1_3 = Event::new(vec![1, 2, 3, 4, 10], node3.clone()).await;
     let _ = node3.dag_insert(&[event1_3.clone()]).await.unwrap()[0];
-    node2.p2p.broadcast(&EventPut(event1_3)).await;
+    node2.broadcast(&event1_3, &[]).await;
 
     let event2_3 = Event::new(vec![1, 2, 3, 4, 11], node3.clone()).await;
     let event2_3_id = node3.dag_insert(&[event2_3.clone()]).await.unwrap()[0];
-    node3.p2p.broadcast(&EventPut(event2_3)).await;
+    node3.broadcast(&event2_3, &[]).await;
 
     info!("Waiting 10s for events propagation");
     sleep(10).await;
@@ -288,19 +288,19 @@ async fn eventgraph_propagation_real(ex: Arc<Executor<'static>>) {
         let mut peers = vec![];
         for _ in 0..N_CONNS {
             let port = 13200 + rng.gen_range(0..N_NODES);
-            peers.push(Url::parse(&format!("tcp://127.0.0.1:{}", port)).unwrap());
+            peers.push(Url::parse(&format!("memory://eg-node:{}", port)).unwrap());
         }
 
         let settings = Settings {
             localnet: true,
             inbound_addrs: vec![
-                Url::parse(&format!("tcp://127.0.0.1:{}", 13200 + N_NODES + 1)).unwrap()
+                Url::parse(&format!("memory://eg-node:{}", 13200 + N_NODES + 1)).unwrap()
             ],
             outbound_connections: 0,
             outbound_connect_timeout: 2,
             inbound_connections: usize::MAX,
             peers,
-            allowed_transports: vec!["tcp".to_string()],
+            allowed_transports: vec!["memory".to_string()],
             ..Default::default()
         };
 
@@ -347,3 +347,624 @@ async fn eventgraph_propagation_real(ex: Arc<Executor<'static>>) {
         eg.p2p.clone().stop().await;
     }
 }
+
+#[test]
+fn eventgraph_topics() {
+    let ex = Arc::new(Executor::new());
+
+    smol::block_on(async {
+        let p2p = P2p::new(Settings::default(), ex.clone()).await;
+        let sled_db = sled::Config::new().temporary(true).open().unwrap();
+        let event_graph =
+            EventGraph::new(p2p.clone(), sled_db, "dag", 0, ex.clone()).await.unwrap();
+        assert_eq!(event_graph.topic(), DEFAULT_TOPIC);
+
+        // Topics have their own DAG with their own genesis
+        let dev = event_graph.add_topic("#dev", 0, ex.clone()).await.unwrap();
+        assert_eq!(dev.topic(), "#dev");
+        assert_eq!(dev.dag.len(), 1);
+        let genesis = EventGraph::generate_genesis(DEFAULT_TOPIC, 0);
+        let dev_genesis = EventGraph::generate_genesis("#dev", 0);
+        assert_ne!(genesis.id(), dev_genesis.id());
+        assert!(dev.dag.contains_key(dev_genesis.id().as_bytes()).unwrap());
+        assert!(!dev.dag.contains_key(genesis.id().as_bytes()).unwrap());
+
+        // Adding the same topic again returns the existing DAG
+        let dev_ = event_graph.add_topic("#dev", 0, ex.clone()).await.unwrap();
+        assert!(Arc::ptr_eq(&dev, &dev_));
+
+        // Events are only inserted into their topic's DAG
+        let event = Event::new(vec![1, 2, 3], dev.clone()).await;
+        assert!(event.parents.contains(&dev_genesis.id()));
+        dev.dag_insert(&[event]).await.unwrap();
+        assert_eq!(dev.dag.len(), 2);
+        assert_eq!(event_graph.dag.len(), 1);
+
+        assert_eq!(event_graph.followed_topics().await, vec!["#dev", DEFAULT_TOPIC]);
+        assert!(event_graph.get_topic(DEFAULT_TOPIC).await.is_some());
+        assert!(event_graph.get_topic("#dev").await.is_some());
+
+        // Invalid topics, and topics added through another topic's DAG
+        assert!(event_graph.add_topic("", 0, ex.clone()).await.is_err());
+        assert!(event_graph.add_topic(DEFAULT_TOPIC, 0, ex.clone()).await.is_err());
+        assert!(event_graph.add_topic("with space", 0, ex.clone()).await.is_err());
+        assert!(dev.add_topic("#random", 0, ex.clone()).await.is_err());
+
+        event_graph.remove_topic("#dev").await.unwrap();
+        assert!(event_graph.get_topic("#dev").await.is_none());
+        assert!(event_graph.remove_topic("#dev").await.is_err());
+        assert_eq!(event_graph.followed_topics().await, vec![DEFAULT_TOPIC]);
+    });
+}
+
+#[test]
+fn eventgraph_topic_relay() {
+    let ex = Arc::new(Executor::new());
+    let ex_ = ex.clone();
+    let (signal, shutdown) = channel::unbounded::<()>();
+
+    easy_parallel::Parallel::new()
+        .each(0..2, |_| future::block_on(ex.run(shutdown.recv())))
+        .finish(|| {
+            future::block_on(async {
+                eventgraph_topic_relay_real(ex_).await;
+                drop(signal);
+            })
+        });
+}
+
+/// Create a node listening on the given port and connecting to the given
+/// peers once its P2P network is started.
+async fn make_node(port: usize, peers: &[usize], ex: Arc<Executor<'static>>) -> EventGraphPtr {
+    let settings = Settings {
+        localnet: true,
+        inbound_addrs: vec![Url::parse(&format!("memory://eg-node:{}", port)).unwrap()],
+        outbound_connections: 0,
+        outbound_connect_timeout: 2,
+        inbound_connections: usize::MAX,
+        peers: peers
+            .iter()
+            .map(|port| Url::parse(&format!("memory://eg-node:{}", port)).unwrap())
+            .collect(),
+        allowed_transports: vec!["memory".to_string()],
+        ..Default::default()
+    };
+
+    let p2p = P2p::new(settings, ex.clone()).await;
+    let sled_db = sled::Config::new().temporary(true).open().unwrap();
+    let event_graph = EventGraph::new(p2p.clone(), sled_db, "dag", 1, ex.clone()).await.unwrap();
+    let event_graph_ = event_graph.clone();
+
+    let registry = p2p.protocol_registry();
+    registry
+        .register(SESSION_ALL, move |channel, _| {
+            let event_graph_ = event_graph_.clone();
+            async move { ProtocolEventGraph::init(event_graph_, channel).await.unwrap() }
+        })
+        .await;
+
+    event_graph
+}
+
+async fn eventgraph_topic_relay_real(ex: Arc<Executor<'static>>) {
+    // Node 0 listens, node 1 connects to it
+    let eg_instances =
+        vec![make_node(13300, &[], ex.clone()).await, make_node(13301, &[13300], ex.clone()).await];
+
+    // Both nodes follow #dev, only node 0 follows #ops
+    let dev0 = eg_instances[0].add_topic("#dev", 1, ex.clone()).await.unwrap();
+    let ops0 = eg_instances[0].add_topic("#ops", 1, ex.clone()).await.unwrap();
+    let dev1 = eg_instances[1].add_topic("#dev", 1, ex.clone()).await.unwrap();
+
+    for eg in eg_instances.iter() {
+        eg.p2p.clone().start().await.unwrap();
+    }
+
+    info!("Waiting 5s until the peers connect and exchange topics");
+    sleep(5).await;
+
+    assert_eq!(dev0.channels_following().await.len(), 1);
+    assert!(ops0.channels_following().await.is_empty());
+
+    // An event of #dev reaches node 1's #dev DAG and nothing else
+    let event = Event::new(vec![1, 2, 3, 4], dev0.clone()).await;
+    let event_id = dev0.dag_insert(&[event.clone()]).await.unwrap()[0];
+    dev0.broadcast(&event, &[]).await;
+
+    // An event of #ops has nobody to go to
+    let event = Event::new(vec![5, 6, 7, 8], ops0.clone()).await;
+    ops0.dag_insert(&[event.clone()]).await.unwrap();
+    ops0.broadcast(&event, &[]).await;
+
+    info!("Waiting 5s for event propagation");
+    sleep(5).await;
+
+    assert_eq!(dev1.dag.len(), 2);
+    assert!(dev1.dag.contains_key(event_id.as_bytes()).unwrap());
+    assert_eq!(eg_instances[1].dag.len(), 1);
+    assert!(eg_instances[1].get_topic("#ops").await.is_none());
+
+    // Peers that haven't advertised their topics only get the default topic
+    eg_instances[0].peer_topics.write().await.clear();
+    assert_eq!(eg_instances[0].channels_following().await.len(), 1);
+    assert!(dev0.channels_following().await.is_empty());
+
+    for eg in eg_instances.iter() {
+        eg.p2p.clone().stop().await;
+    }
+}
+
+#[test]
+fn eventgraph_sync() {
+    let ex = Arc::new(Executor::new());
+    let ex_ = ex.clone();
+    let (signal, shutdown) = channel::unbounded::<()>();
+
+    easy_parallel::Parallel::new()
+        .each(0..2, |_| future::block_on(ex.run(shutdown.recv())))
+        .finish(|| {
+            future::block_on(async {
+                eventgraph_sync_real(ex_).await;
+                drop(signal);
+            })
+        });
+}
+
+async fn eventgraph_sync_real(ex: Arc<Executor<'static>>) {
+    // Node 0 has some history, node 1 joins late
+    let node0 = make_node(13310, &[], ex.clone()).await;
+    let node1 = make_node(13311, &[13310], ex.clone()).await;
+
+    let mut event_ids = vec![];
+    for i in 0..3 {
+        let event = Event::new(vec![1, 2, 3, i], node0.clone()).await;
+        event_ids.push(node0.dag_insert(&[event]).await.unwrap()[0]);
+    }
+
+    node0.p2p.clone().start().await.unwrap();
+    node1.p2p.clone().start().await.unwrap();
+
+    info!("Waiting 5s until the peers connect");
+    sleep(5).await;
+
+    node1.dag_sync().await.unwrap();
+    assert_eq!(node1.dag.len(), 4);
+    for event_id in event_ids.iter() {
+        assert!(node1.dag.contains_key(event_id.as_bytes()).unwrap());
+    }
+    assert_eq!(node1.sync_progress().await.phase, SyncPhase::Done);
+    assert_eq!(node1.sync_progress().await.events_fetched, 3);
+
+    // Syncing again has nothing left to fetch
+    node1.dag_sync().await.unwrap();
+    assert_eq!(node1.sync_progress().await.events_total, 0);
+
+    node0.p2p.clone().stop().await;
+    node1.p2p.clone().stop().await;
+}
+
+#[test]
+fn eventgraph_upgrade() {
+    let ex = Arc::new(Executor::new());
+
+    smol::block_on(async {
+        let p2p = P2p::new(Settings::default(), ex.clone()).await;
+        let sled_db = sled::Config::new().temporary(true).open().unwrap();
+
+        // A DAG written with an older event layout, that we can't decode
+        let dag = sled_db.open_tree("dag").unwrap();
+        dag.insert(blake3::hash(b"old genesis").as_bytes(), vec![0x47, 0x45]).unwrap();
+
+        // It does not contain the current genesis, so it gets pruned
+        let event_graph =
+            EventGraph::new(p2p.clone(), sled_db.clone(), "dag", 0, ex.clone()).await.unwrap();
+        let genesis = EventGraph::generate_genesis(DEFAULT_TOPIC, 0);
+        assert_eq!(event_graph.dag.len(), 1);
+        assert!(event_graph.dag.contains_key(genesis.id().as_bytes()).unwrap());
+
+        // Undecodable events next to the current genesis are skipped
+        dag.insert(blake3::hash(b"corrupt").as_bytes(), vec![0xff]).unwrap();
+        let event_graph =
+            EventGraph::new(p2p.clone(), sled_db, "dag", 0, ex.clone()).await.unwrap();
+        assert_eq!(event_graph.get_unreferenced_tips().await[0], genesis.id());
+    });
+}
+
+#[test]
+fn eventgraph_range_summary() {
+    let ex = Arc::new(Executor::new());
+
+    smol::block_on(async {
+        let p2p = P2p::new(Settings::default(), ex.clone()).await;
+        let sled_db = sled::Config::new().temporary(true).open().unwrap();
+        let event_graph =
+            EventGraph::new(p2p.clone(), sled_db, "dag", 0, ex.clone()).await.unwrap();
+
+        let genesis = EventGraph::generate_genesis(DEFAULT_TOPIC, 0);
+        let full = EventRange { start: genesis.timestamp, end: u64::MAX };
+        let summary = event_graph.range_summary(&full).await;
+        assert_eq!(summary.count, 1);
+        assert_eq!(&summary.fingerprint, genesis.id().as_bytes());
+
+        let event0 = Event::new(vec![1, 2, 3, 0], event_graph.clone()).await;
+        event_graph.dag_insert(&[event0.clone()]).await.unwrap();
+        let event1 = Event::new(vec![1, 2, 3, 1], event_graph.clone()).await;
+        assert!(event1.parents.contains(&event0.id()));
+        event_graph.dag_insert(&[event1.clone()]).await.unwrap();
+
+        // The summaries of two halves combine into the whole range's
+        let summary = event_graph.range_summary(&full).await;
+        assert_eq!(summary.count, 3);
+        let (low, high) = (
+            EventRange { start: full.start, end: event0.timestamp },
+            EventRange { start: event0.timestamp, end: full.end },
+        );
+        let low = event_graph.range_summary(&low).await;
+        let high = event_graph.range_summary(&high).await;
+        assert_eq!(low.count + high.count, summary.count);
+        let mut fingerprint = low.fingerprint;
+        for (a, b) in fingerprint.iter_mut().zip(high.fingerprint.iter()) {
+            *a ^= b;
+        }
+        assert_eq!(fingerprint, summary.fingerprint);
+
+        // Headers carry the IDs and parents of the events in the range
+        let (headers, truncated) = event_graph.range_headers(&full).await.unwrap();
+        assert!(!truncated);
+        assert_eq!(headers.len(), 3);
+        assert_eq!(headers[0].id, genesis.id());
+        let ids: Vec<_> = headers.iter().map(|h| h.id).collect();
+        assert!(ids.contains(&event0.id()));
+        assert!(ids.contains(&event1.id()));
+
+        // Events are ordered parents first for insertion
+        let received =
+            HashMap::from([(event1.id(), event1.clone()), (event0.id(), event0.clone())]);
+        let ordered = event_graph.order_for_insertion(received).unwrap();
+        assert_eq!(ordered.len(), 2);
+        assert_eq!(ordered[0].id(), event0.id());
+        assert_eq!(ordered[1].id(), event1.id());
+    });
+}
+
+#[test]
+fn eventgraph_archive() {
+    let ex = Arc::new(Executor::new());
+    let archive_path = std::env::temp_dir()
+        .join(format!("eventgraph_archive_{}", rand::thread_rng().gen::<u64>()));
+
+    smol::block_on(async {
+        let p2p = P2p::new(Settings::default(), ex.clone()).await;
+        let sled_db = sled::Config::new().temporary(true).open().unwrap();
+        let event_graph = EventGraph::new_with_archive(
+            p2p.clone(),
+            sled_db,
+            "dag",
+            0,
+            archive_path.clone(),
+            ex.clone(),
+        )
+        .await
+        .unwrap();
+        assert!(event_graph.archived_periods().await.unwrap().is_empty());
+
+        let genesis = EventGraph::generate_genesis(DEFAULT_TOPIC, 0);
+        let event0 = Event::new(vec![1, 2, 3, 0], event_graph.clone()).await;
+        event_graph.dag_insert(&[event0.clone()]).await.unwrap();
+        let event1 = Event::new(vec![1, 2, 3, 1], event_graph.clone()).await;
+        event_graph.dag_insert(&[event1.clone()]).await.unwrap();
+
+        // Archive as if we crashed before pruning, halfway through
+        // writing the next frame
+        event_graph.archive_dag(&archive_path).await.unwrap();
+        let file_path = archive_path.join(format!("{}.dag", genesis.timestamp));
+        let mut file = std::fs::OpenOptions::new().append(true).open(&file_path).unwrap();
+        std::io::Write::write_all(&mut file, &100u64.to_le_bytes()).unwrap();
+        std::io::Write::write_all(&mut file, &[0u8; 10]).unwrap();
+        drop(file);
+        assert_eq!(event_graph.archived_events(genesis.timestamp).await.unwrap().len(), 2);
+
+        let event2 = Event::new(vec![1, 2, 3, 2], event_graph.clone()).await;
+        event_graph.dag_insert(&[event2.clone()]).await.unwrap();
+
+        // Rotate into a new period
+        let mut next_genesis = genesis.clone();
+        next_genesis.timestamp += 86400;
+        event_graph.dag_prune(next_genesis).await.unwrap();
+        assert_eq!(event_graph.dag.len(), 1);
+
+        // The events of the previous period are in its archive once,
+        // parents first
+        assert_eq!(event_graph.archived_periods().await.unwrap(), vec![genesis.timestamp]);
+        let archived = event_graph.archived_events(genesis.timestamp).await.unwrap();
+        let ids: Vec<_> = archived.iter().map(|e| e.id()).collect();
+        assert_eq!(ids, vec![event0.id(), event1.id(), event2.id()]);
+
+        let found = event_graph.search_archive(|e| e.content() == [1, 2, 3, 1], 10).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id(), event1.id());
+
+        // Past the limit, only the most recent events are returned
+        let found = event_graph.search_archive(|_| true, 2).await.unwrap();
+        let ids: Vec<_> = found.iter().map(|e| e.id()).collect();
+        assert_eq!(ids, vec![event1.id(), event2.id()]);
+
+        // Topics are archived separately
+        let dev = event_graph.add_topic("#dev", 0, ex.clone()).await.unwrap();
+        assert!(dev.archive_path().unwrap().starts_with(&archive_path));
+        assert_ne!(dev.archive_path(), event_graph.archive_path());
+    });
+
+    std::fs::remove_dir_all(archive_path).unwrap();
+}
+
+#[test]
+fn eventgraph_archive_failure() {
+    let ex = Arc::new(Executor::new());
+    // A file where the archive directory should be makes archiving fail
+    let archive_path = std::env::temp_dir()
+        .join(format!("eventgraph_archive_failure_{}", rand::thread_rng().gen::<u64>()));
+    std::fs::write(&archive_path, b"").unwrap();
+
+    smol::block_on(async {
+        let p2p = P2p::new(Settings::default(), ex.clone()).await;
+        let sled_db = sled::Config::new().temporary(true).open().unwrap();
+        let event_graph = EventGraph::new_with_archive(
+            p2p.clone(),
+            sled_db,
+            "dag",
+            0,
+            archive_path.clone(),
+            ex.clone(),
+        )
+        .await
+        .unwrap();
+
+        let genesis = EventGraph::generate_genesis(DEFAULT_TOPIC, 0);
+        let event0 = Event::new(vec![1, 2, 3, 0], event_graph.clone()).await;
+        event_graph.dag_insert(&[event0.clone()]).await.unwrap();
+
+        // The DAG is kept as is until it can be archived
+        let mut next_genesis = genesis.clone();
+        next_genesis.timestamp += 86400;
+        assert!(event_graph.dag_prune(next_genesis).await.is_err());
+        assert_eq!(event_graph.dag.len(), 2);
+        assert!(event_graph.dag_get(&event0.id()).await.unwrap().is_some());
+        assert!(event_graph.unreferenced_tips.read().await.contains(&event0.id()));
+    });
+
+    std::fs::remove_file(archive_path).unwrap();
+}
+
+#[test]
+fn eventgraph_ordering() {
+    let ex = Arc::new(Executor::new());
+
+    smol::block_on(async {
+        let p2p = P2p::new(Settings::default(), ex.clone()).await;
+        let sled_db = sled::Config::new().temporary(true).open().unwrap();
+        let event_graph =
+            EventGraph::new(p2p.clone(), sled_db, "dag", 0, ex.clone()).await.unwrap();
+        let genesis = EventGraph::generate_genesis(DEFAULT_TOPIC, 0);
+
+        // Two concurrent events on top of genesis, and one merging them
+        let event0 = Event::new(vec![1, 2, 3, 0], event_graph.clone()).await;
+        let event1 = Event::new(vec![1, 2, 3, 1], event_graph.clone()).await;
+        event_graph.dag_insert(&[event0.clone(), event1.clone()]).await.unwrap();
+        let event2 = Event::new(vec![1, 2, 3, 2], event_graph.clone()).await;
+        assert!(event2.parents.contains(&event0.id()));
+        assert!(event2.parents.contains(&event1.id()));
+        event_graph.dag_insert(&[event2.clone()]).await.unwrap();
+
+        // Same layer events are ordered by timestamp, then ID
+        let (first, second) = {
+            let p0 = event_graph.event_position(&event0.id()).await.unwrap();
+            let p1 = event_graph.event_position(&event1.id()).await.unwrap();
+            assert_eq!(p0.layer, 1);
+            assert_eq!(p1.layer, 1);
+            if p0 < p1 {
+                (event0.id(), event1.id())
+            } else {
+                (event1.id(), event0.id())
+            }
+        };
+        let order = event_graph.order_events().await;
+        assert_eq!(order, vec![genesis.id(), first, second, event2.id()]);
+        assert_eq!(event_graph.event_position(&event2.id()).await.unwrap().layer, 2);
+
+        // The order does not depend on the insertion order
+        let sled_db = sled::Config::new().temporary(true).open().unwrap();
+        let other = EventGraph::new(p2p.clone(), sled_db, "dag", 0, ex.clone()).await.unwrap();
+        other.dag_insert(&[event1.clone(), event0.clone(), event2.clone()]).await.unwrap();
+        assert_eq!(other.order_events().await, order);
+
+        // Not even when children are inserted before their parents
+        let sled_db = sled::Config::new().temporary(true).open().unwrap();
+        let other = EventGraph::new(p2p.clone(), sled_db, "dag", 0, ex.clone()).await.unwrap();
+        other.dag_insert(&[event2.clone()]).await.unwrap();
+        assert_eq!(other.event_position(&event2.id()).await.unwrap().layer, 1);
+        other.dag_insert(&[event0.clone(), event1.clone()]).await.unwrap();
+        assert_eq!(other.event_position(&event2.id()).await.unwrap().layer, 2);
+        assert_eq!(other.order_events().await, order);
+
+        // Causal queries
+        let ancestors = event_graph.ancestors(&event2.id(), 1).await.unwrap();
+        let ids: Vec<_> = ancestors.iter().map(|e| e.id()).collect();
+        assert_eq!(ids, vec![first, second]);
+        let ancestors = event_graph.ancestors(&event2.id(), 2).await.unwrap();
+        assert_eq!(ancestors.len(), 3);
+        assert_eq!(ancestors[0].id(), genesis.id());
+
+        let descendants = event_graph.descendants(&genesis.id()).await.unwrap();
+        let ids: Vec<_> = descendants.iter().map(|e| e.id()).collect();
+        assert_eq!(ids, vec![first, second, event2.id()]);
+        assert!(event_graph.descendants(&event2.id()).await.unwrap().is_empty());
+
+        let since = event_graph.events_since(event0.timestamp).await.unwrap();
+        assert_eq!(since.len(), 3);
+
+        // Paging
+        let mut pages = event_graph.pages(3);
+        let page = pages.next_page().await.unwrap();
+        assert_eq!(page.iter().map(|e| e.id()).collect::<Vec<_>>(), order[..3]);
+        let page = pages.next_page().await.unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].id(), event2.id());
+        assert!(pages.next_page().await.unwrap().is_empty());
+
+        // Events pruned from under a query are left out, and the rest
+        // keep their own positions
+        event_graph.dag.remove(first.as_bytes()).unwrap();
+        let after = event_graph.events_after(None, 4).await.unwrap();
+        assert_eq!(after.len(), 3);
+        for (position, event) in after {
+            assert_eq!(position.event_id(), event.id());
+        }
+    });
+}
+
+#[test]
+fn eventgraph_pow_admission() {
+    let ex = Arc::new(Executor::new());
+
+    smol::block_on(async {
+        let p2p = P2p::new(Settings::default(), ex.clone()).await;
+        let sled_db = sled::Config::new().temporary(true).open().unwrap();
+        let event_graph =
+            EventGraph::new(p2p.clone(), sled_db, "dag", 0, ex.clone()).await.unwrap();
+
+        // Without admission control, everything is admitted
+        let mut event = Event::new(vec![1, 2, 3, 4], event_graph.clone()).await;
+        assert!(event_graph.admits(&event).await);
+
+        event_graph.set_admission(Arc::new(PowAdmission::new(16))).await;
+        assert!(!event_graph.admits(&event).await);
+
+        PowAdmission::stamp(&mut event, 16);
+        assert!(event_graph.admits(&event).await);
+
+        // The stamp is bound to the event ID
+        let mut other = Event::new(vec![1, 2, 3, 5], event_graph.clone()).await;
+        other.set_stamp(event.stamp().to_vec());
+        assert!(!event_graph.admits(&other).await);
+
+        // Malformed stamps are rejected
+        event.set_stamp(vec![0; 4]);
+        assert!(!event_graph.admits(&event).await);
+    });
+}
+
+#[cfg(feature = "zk")]
+#[test]
+fn eventgraph_rln_admission() {
+    use std::time::UNIX_EPOCH;
+
+    use darkfi_sdk::{
+        crypto::{pasta_prelude::Field, MerkleNode, MerkleTree},
+        pasta::pallas,
+    };
+    use rand::rngs::OsRng;
+
+    use crate::{
+        event_graph::admission::{RlnAdmission, RlnIdentity},
+        zk::{empty_witnesses, ProvingKey, VerifyingKey, ZkCircuit},
+        zkas::{Analyzer, Compiler, Lexer, Parser, ZkBinary},
+    };
+
+    const EPOCH_LEN: u64 = 600;
+    const WINDOW: u64 = 2;
+
+    // Compile the signal circuit used by darkirc
+    let source = include_str!("../../bin/darkirc/proof/signal.zk");
+    let tokens = Lexer::new("signal.zk", source.chars()).lex().unwrap();
+    let parser = Parser::new("signal.zk", source.chars(), tokens);
+    let (namespace, k, constants, witnesses, statements) = parser.parse().unwrap();
+    let mut analyzer = Analyzer::new("signal.zk", source.chars(), constants, witnesses, statements);
+    analyzer.analyze_types().unwrap();
+    let compiler = Compiler::new(
+        "signal.zk",
+        source.chars(),
+        namespace,
+        k,
+        analyzer.constants,
+        analyzer.witnesses,
+        analyzer.statements,
+        analyzer.literals,
+        false,
+    );
+    let zkbin = ZkBinary::decode(&compiler.compile().unwrap()).unwrap();
+    let circuit = ZkCircuit::new(empty_witnesses(&zkbin).unwrap(), &zkbin);
+    let pk = ProvingKey::build(zkbin.k, &circuit);
+    let vk = VerifyingKey::build(zkbin.k, &circuit);
+
+    // Register a member in the membership tree
+    let rln_identifier = pallas::Base::from(42);
+    let mut identity = RlnIdentity {
+        secret_key: pallas::Base::random(&mut OsRng),
+        leaf_pos: 0,
+        path: [MerkleNode::from(pallas::Base::ZERO); 32],
+        root: pallas::Base::ZERO,
+    };
+    let mut tree = MerkleTree::new(1);
+    tree.append(MerkleNode::from(identity.commitment()));
+    let leaf_pos = tree.mark().unwrap();
+    identity.leaf_pos = u64::from(leaf_pos) as u32;
+    identity.path = tree.witness(leaf_pos, 0).unwrap().try_into().unwrap();
+    identity.root = tree.root(0).unwrap().inner();
+
+    let ex = Arc::new(Executor::new());
+
+    smol::block_on(async {
+        let p2p = P2p::new(Settings::default(), ex.clone()).await;
+        let sled_db = sled::Config::new().temporary(true).open().unwrap();
+        let event_graph =
+            EventGraph::new(p2p.clone(), sled_db, "dag", 0, ex.clone()).await.unwrap();
+
+        let admission = Arc::new(RlnAdmission::new(vk, rln_identifier, EPOCH_LEN, WINDOW));
+        event_graph.set_admission(admission.clone()).await;
+
+        let stamp = |event: &mut Event| {
+            RlnAdmission::stamp(event, &identity, &zkbin, &pk, rln_identifier, EPOCH_LEN).unwrap()
+        };
+
+        // Signals against unknown roots are rejected
+        let mut event = Event::new(vec![1, 2, 3, 4], event_graph.clone()).await;
+        stamp(&mut event);
+        assert!(!event_graph.admits(&event).await);
+
+        admission.add_root(identity.root);
+        assert!(event_graph.admits(&event).await);
+
+        // Seeing the same event again is fine, it's being relayed
+        assert!(event_graph.admits(&event).await);
+
+        // A second event in the same epoch reuses the nullifier
+        let mut spam = Event::new(vec![1, 2, 3, 5], event_graph.clone()).await;
+        spam.timestamp = event.timestamp;
+        stamp(&mut spam);
+        assert!(!event_graph.admits(&spam).await);
+
+        // The stamp is bound to the event ID
+        let mut other = Event::new(vec![1, 2, 3, 6], event_graph.clone()).await;
+        other.set_stamp(event.stamp().to_vec());
+        assert!(!event_graph.admits(&other).await);
+
+        // Epochs outside the window around the current time are rejected
+        let now = UNIX_EPOCH.elapsed().unwrap().as_secs();
+        let mut old = Event::new(vec![1, 2, 3, 7], event_graph.clone()).await;
+        old.timestamp = now - (WINDOW + 1) * EPOCH_LEN;
+        stamp(&mut old);
+        assert!(!event_graph.admits(&old).await);
+
+        let mut future = Event::new(vec![1, 2, 3, 8], event_graph.clone()).await;
+        future.timestamp = now + 2 * EPOCH_LEN;
+        stamp(&mut future);
+        assert!(!event_graph.admits(&future).await);
+
+        // Older epochs within the window are still accepted
+        let mut recent = Event::new(vec![1, 2, 3, 9], event_graph.clone()).await;
+        recent.timestamp = now - WINDOW * EPOCH_LEN;
+        stamp(&mut recent);
+        assert!(event_graph.admits(&recent).await);
+    });
+}
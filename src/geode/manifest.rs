@@ -0,0 +1,326 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Directory tree manifests.
+//!
+//! A [`Manifest`] describes a directory tree: the relative path and mode
+//! of every directory, and the relative path, mode, size, hash and chunk
+//! list of every file. Files are inserted in Geode as usual, and the
+//! encoded manifest is inserted as a file of its own, so the manifest is
+//! content-addressed by its file hash like any other file.
+//!
+//! Encoded manifests start with [`MANIFEST_MAGIC`], which tells them apart
+//! from regular files. Since the manifest carries the chunk lists, the
+//! metadata of its files can be created with `insert_file()` as soon as
+//! the manifest is retrieved, and only the chunks need to be fetched.
+//!
+//! Paths use `/` as separator and must stay inside the tree, so `.`, `..`
+//! and empty components are rejected when extracting.
+
+use std::path::{Component, Path, PathBuf};
+
+use darkfi_serial::{async_trait, deserialize, serialize, SerialDecodable, SerialEncodable};
+use log::{debug, info};
+use smol::{
+    fs::{self, File},
+    io::{AsyncWriteExt, Cursor},
+    stream::StreamExt,
+};
+
+use super::Geode;
+use crate::{Error, Result};
+
+/// Magic bytes prefixing an encoded [`Manifest`], including a format version
+pub const MANIFEST_MAGIC: &[u8; 8] = b"GEODEMF\x01";
+
+/// Default mode of files on platforms without Unix permissions
+#[cfg(not(unix))]
+const DEFAULT_FILE_MODE: u32 = 0o644;
+/// Default mode of directories on platforms without Unix permissions
+#[cfg(not(unix))]
+const DEFAULT_DIR_MODE: u32 = 0o755;
+
+/// An entry of a [`Manifest`]
+#[derive(Clone, Debug, PartialEq, Eq, SerialEncodable, SerialDecodable)]
+pub enum ManifestEntry {
+    /// A directory, listed so that empty directories are recreated
+    Directory { path: String, mode: u32 },
+    /// A regular file
+    File {
+        path: String,
+        mode: u32,
+        size: u64,
+        file_hash: blake3::Hash,
+        chunk_hashes: Vec<blake3::Hash>,
+    },
+}
+
+impl ManifestEntry {
+    /// Relative path of the entry inside the tree
+    pub fn path(&self) -> &str {
+        match self {
+            Self::Directory { path, .. } => path,
+            Self::File { path, .. } => path,
+        }
+    }
+}
+
+/// Description of a directory tree stored in Geode
+#[derive(Clone, Debug, Default, PartialEq, Eq, SerialEncodable, SerialDecodable)]
+pub struct Manifest {
+    /// Entries sorted by path, so parents come before their children
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// Encode the manifest, prefixed with [`MANIFEST_MAGIC`].
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = MANIFEST_MAGIC.to_vec();
+        bytes.extend_from_slice(&serialize(self));
+        bytes
+    }
+
+    /// Decode a manifest encoded with [`Manifest::encode`]. Returns an error
+    /// if the data is not a manifest, or if any path leaves the tree.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let Some(bytes) = bytes.strip_prefix(MANIFEST_MAGIC) else {
+            return Err(Error::GeodeInvalidManifest)
+        };
+
+        let manifest: Self = deserialize(bytes).map_err(|_| Error::GeodeInvalidManifest)?;
+        for entry in &manifest.entries {
+            relative_path(entry.path())?;
+        }
+
+        Ok(manifest)
+    }
+
+    /// Return the files of the manifest.
+    pub fn files(&self) -> impl Iterator<Item = &ManifestEntry> {
+        self.entries.iter().filter(|e| matches!(e, ManifestEntry::File { .. }))
+    }
+
+    /// Total size of the files in the manifest
+    pub fn size(&self) -> u64 {
+        self.entries
+            .iter()
+            .map(|e| match e {
+                ManifestEntry::File { size, .. } => *size,
+                ManifestEntry::Directory { .. } => 0,
+            })
+            .sum()
+    }
+}
+
+/// Turn a manifest path into a relative filesystem path, making sure it
+/// cannot point outside of the tree.
+fn relative_path(path: &str) -> Result<PathBuf> {
+    let mut ret = PathBuf::new();
+    for component in path.split('/') {
+        let mut components = Path::new(component).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(c)), None) if c == component => ret.push(c),
+            _ => return Err(Error::GeodeInvalidManifest),
+        }
+    }
+
+    Ok(ret)
+}
+
+/// Turn a filesystem path relative to the tree root into a manifest path.
+fn manifest_path(path: &Path) -> Result<String> {
+    let mut components = vec![];
+    for component in path.components() {
+        let Component::Normal(c) = component else { return Err(Error::GeodeInvalidManifest) };
+        let Some(c) = c.to_str() else { return Err(Error::GeodeInvalidManifest) };
+        components.push(c);
+    }
+
+    Ok(components.join("/"))
+}
+
+#[cfg(unix)]
+fn mode_of(metadata: &std::fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn mode_of(metadata: &std::fs::Metadata) -> u32 {
+    if metadata.is_dir() {
+        DEFAULT_DIR_MODE
+    } else {
+        DEFAULT_FILE_MODE
+    }
+}
+
+/// Set the mode of an extracted entry. Modes come from whoever made the
+/// manifest, so only the permission bits are kept, without write access
+/// for group and others.
+#[cfg(unix)]
+async fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = mode & 0o777 & !0o022;
+    fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).await?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn set_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+impl Geode {
+    /// Insert all the files found under a directory into Geode, along with
+    /// a [`Manifest`] describing the tree. Symbolic links and other special
+    /// files are skipped. Returns the hash of the manifest and the manifest.
+    pub async fn insert_dir(&self, path: &Path) -> Result<(blake3::Hash, Manifest)> {
+        info!(target: "geode::insert_dir()", "[Geode] Inserting directory {:?}", path);
+        let mut manifest = Manifest::default();
+        let mut dirs = vec![path.to_path_buf()];
+
+        while let Some(dir) = dirs.pop() {
+            let mut entries = fs::read_dir(&dir).await?;
+            while let Some(entry) = entries.try_next().await? {
+                let entry_path = entry.path();
+                let metadata = fs::symlink_metadata(&entry_path).await?;
+                let rel_path = manifest_path(entry_path.strip_prefix(path).unwrap())?;
+
+                if metadata.is_dir() {
+                    manifest.entries.push(ManifestEntry::Directory {
+                        path: rel_path,
+                        mode: mode_of(&metadata),
+                    });
+                    dirs.push(entry_path);
+                    continue
+                }
+
+                if !metadata.is_file() {
+                    debug!(target: "geode::insert_dir()", "Skipping {:?}", entry_path);
+                    continue
+                }
+
+                let fd = File::open(&entry_path).await?;
+                let (file_hash, chunk_hashes) = self.insert(fd).await?;
+                manifest.entries.push(ManifestEntry::File {
+                    path: rel_path,
+                    mode: mode_of(&metadata),
+                    size: metadata.len(),
+                    file_hash,
+                    chunk_hashes,
+                });
+            }
+        }
+
+        manifest.entries.sort_by(|a, b| a.path().cmp(b.path()));
+
+        let (manifest_hash, _) = self.insert(Cursor::new(manifest.encode())).await?;
+        Ok((manifest_hash, manifest))
+    }
+
+    /// Fetch a [`Manifest`] from Geode. Returns an error if the manifest is
+    /// not complete, or if the file is not a manifest.
+    pub async fn get_manifest(&self, manifest_hash: &blake3::Hash) -> Result<Manifest> {
+        info!(target: "geode::get_manifest()", "[Geode] Getting manifest {}", manifest_hash);
+        let chunked_file = self.get(manifest_hash).await?;
+        if !chunked_file.is_complete() {
+            return Err(Error::GeodeChunkNotFound)
+        }
+
+        let mut bytes = vec![];
+        for (_, path) in chunked_file.iter() {
+            bytes.extend_from_slice(&fs::read(path.as_ref().unwrap()).await?);
+
+            // No need to read the whole file if it is not a manifest
+            if !bytes.starts_with(MANIFEST_MAGIC) {
+                return Err(Error::GeodeInvalidManifest)
+            }
+        }
+
+        Manifest::decode(&bytes)
+    }
+
+    /// Create the metadata of the files listed in a [`Manifest`] which are
+    /// not stored yet, so their chunks can be fetched. The chunk lists can't
+    /// be verified before the chunks are here, so the files must be checked
+    /// with [`Geode::verify_file`] once complete. [`Geode::extract_file`]
+    /// does so as well.
+    pub async fn insert_manifest_files(&self, manifest: &Manifest) -> Result<()> {
+        for entry in manifest.files() {
+            let ManifestEntry::File { file_hash, chunk_hashes, .. } = entry else { continue };
+            if let Err(Error::GeodeFileNotFound) = self.get(file_hash).await {
+                self.insert_file(file_hash, chunk_hashes).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write a complete file stored in Geode to the given path. If the
+    /// chunks don't make up the file, nothing is written and the file is
+    /// removed from Geode, see [`Geode::verify_file`].
+    pub async fn extract_file(&self, file_hash: &blake3::Hash, dest: &Path) -> Result<()> {
+        info!(target: "geode::extract_file()", "[Geode] Extracting {} to {:?}", file_hash, dest);
+        let chunked_file = self.get(file_hash).await?;
+        if !chunked_file.is_complete() {
+            return Err(Error::GeodeChunkNotFound)
+        }
+
+        if !self.verify_file(file_hash, &chunked_file).await? {
+            return Err(Error::GeodeFileMismatch)
+        }
+
+        let mut fd = File::create(dest).await?;
+        for (_, path) in chunked_file.iter() {
+            fd.write_all(&fs::read(path.as_ref().unwrap()).await?).await?;
+        }
+        fd.sync_all().await?;
+
+        Ok(())
+    }
+
+    /// Recreate the tree described by a [`Manifest`] under `dest`.
+    /// All the files of the manifest must be complete in Geode.
+    pub async fn extract(&self, manifest: &Manifest, dest: &Path) -> Result<()> {
+        info!(target: "geode::extract()", "[Geode] Extracting manifest to {:?}", dest);
+        fs::create_dir_all(dest).await?;
+
+        for entry in &manifest.entries {
+            let path = dest.join(relative_path(entry.path())?);
+            match entry {
+                ManifestEntry::Directory { .. } => fs::create_dir_all(&path).await?,
+                ManifestEntry::File { file_hash, mode, .. } => {
+                    if let Some(parent) = path.parent() {
+                        fs::create_dir_all(parent).await?;
+                    }
+                    self.extract_file(file_hash, &path).await?;
+                    set_mode(&path, *mode).await?;
+                }
+            }
+        }
+
+        // Directory modes are set last, so read-only directories can be filled
+        for entry in manifest.entries.iter().rev() {
+            if let ManifestEntry::Directory { path, mode } = entry {
+                set_mode(&dest.join(relative_path(path)?), *mode).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -19,11 +19,12 @@
 //! Chunk-based file storage implementation.
 //! This is a building block for a DHT or something similar.
 //!
-//! The API supports file insertion and retrieval. There is intentionally no
-//! `remove` support. File removal should be handled externally, and then it
-//! is only required to run `garbage_collect()` to clean things up.
+//! The API supports file insertion, retrieval and removal. Files can be
+//! pinned, and a storage quota can be enforced with `enforce_quota()`, which
+//! evicts the least recently used files that are not pinned.
 //!
-//! The filesystem hierarchy stores two directories: `files` and `chunks`.
+//! The filesystem hierarchy stores three directories: `files`, `chunks`
+//! and `pins`.
 //! `chunks` store [`MAX_CHUNK_SIZE`] files, where the filename is a BLAKE3
 //! hash of the chunk's contents.
 //! `files` store metadata about a full file, which can be retrieved by
@@ -55,8 +56,21 @@
 //! This is some kind of naive deduplication, so we actually don't consider
 //! chunks to be specific to a single file and therefore when we do garbage
 //! collection, we keep chunks and files independent of each other.
-
-use std::{collections::HashSet, path::PathBuf};
+//! When a file is removed, its chunks are reference-counted against the
+//! remaining files, and only the chunks no other file uses are deleted.
+//!
+//! Directory trees are described by a [`Manifest`], which is stored in
+//! Geode as a regular file, see the `manifest` module.
+//!
+//! `pins` contains empty files named after the hashes of pinned files.
+//! The last access time of a file is the modification time of its
+//! metadata in `files`, which is updated whenever the file is retrieved.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    time::SystemTime,
+};
 
 use futures::AsyncRead;
 use log::{debug, info, warn};
@@ -69,6 +83,10 @@ use smol::{
 
 use crate::{Error, Result};
 
+/// Directory tree manifests
+mod manifest;
+pub use manifest::{Manifest, ManifestEntry, MANIFEST_MAGIC};
+
 /// Defined maximum size of a stored chunk (256 KiB)
 pub const MAX_CHUNK_SIZE: usize = 262_144;
 
@@ -76,6 +94,8 @@ pub const MAX_CHUNK_SIZE: usize = 262_144;
 const FILES_PATH: &str = "files";
 /// Path prefix where file chunks are stored
 const CHUNKS_PATH: &str = "chunks";
+/// Path prefix where pinned file markers are stored
+const PINS_PATH: &str = "pins";
 
 /// `ChunkedFile` is a representation of a file we're trying to
 /// retrieve from `Geode`. The tuple contains `blake3::Hash` of
@@ -107,6 +127,8 @@ pub struct Geode {
     files_path: PathBuf,
     /// Path to the filesystem directory where file chunks are stored
     chunks_path: PathBuf,
+    /// Path to the filesystem directory where pinned file markers are stored
+    pins_path: PathBuf,
 }
 
 impl Geode {
@@ -116,14 +138,17 @@ impl Geode {
     pub async fn new(base_path: &PathBuf) -> Result<Self> {
         let mut files_path: PathBuf = base_path.into();
         let mut chunks_path: PathBuf = base_path.into();
+        let mut pins_path: PathBuf = base_path.into();
         files_path.push(FILES_PATH);
         chunks_path.push(CHUNKS_PATH);
+        pins_path.push(PINS_PATH);
 
         // Create necessary directory structure if needed
         fs::create_dir_all(&files_path).await?;
         fs::create_dir_all(&chunks_path).await?;
+        fs::create_dir_all(&pins_path).await?;
 
-        Ok(Self { files_path, chunks_path })
+        Ok(Self { files_path, chunks_path, pins_path })
     }
 
     /// Attempt to read chunk hashes from a given file path and return
@@ -142,6 +167,110 @@ impl Geode {
         Ok(read_chunks)
     }
 
+    /// Return the hashes found as filenames in the given directory.
+    async fn read_hashes(path: &PathBuf) -> Result<Vec<blake3::Hash>> {
+        let mut hashes = vec![];
+        let mut paths = fs::read_dir(path).await?;
+        while let Some(entry) = paths.next().await {
+            let Ok(entry) = entry else { continue };
+            let path = entry.path();
+
+            // Skip if we're not a plain file
+            if !path.is_file() {
+                continue
+            }
+
+            // Make sure that the filename is a BLAKE3 hash
+            let file_name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(v) => v,
+                None => continue,
+            };
+            let hash = match blake3::Hash::from_hex(file_name) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+
+            hashes.push(hash);
+        }
+
+        Ok(hashes)
+    }
+
+    /// Mark a file as accessed now, so it is evicted after the files
+    /// which were used less recently.
+    async fn touch(&self, file_hash: &blake3::Hash) -> Result<()> {
+        let mut file_path = self.files_path.clone();
+        file_path.push(file_hash.to_hex().as_str());
+
+        smol::unblock(move || {
+            std::fs::File::options().write(true).open(file_path)?.set_modified(SystemTime::now())
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// Count, for every chunk, the number of stored files using it.
+    /// Files with corrupted metadata are not taken into account.
+    async fn chunk_refcounts(&self) -> Result<HashMap<blake3::Hash, usize>> {
+        let mut refcounts = HashMap::new();
+        for file_hash in self.list_files().await? {
+            let mut file_path = self.files_path.clone();
+            file_path.push(file_hash.to_hex().as_str());
+
+            let Ok(chunk_hashes) = Self::read_metadata(&file_path).await else { continue };
+            for chunk_hash in chunk_hashes.into_iter().collect::<HashSet<_>>() {
+                *refcounts.entry(chunk_hash).or_insert(0) += 1;
+            }
+        }
+
+        Ok(refcounts)
+    }
+
+    /// Remove a file's metadata and pin, and release its references to
+    /// its chunks. Chunks which are not referenced anymore are deleted.
+    /// Returns the deleted chunks and the amount of bytes freed.
+    async fn delete_file(
+        &self,
+        file_hash: &blake3::Hash,
+        refcounts: &mut HashMap<blake3::Hash, usize>,
+    ) -> Result<(HashSet<blake3::Hash>, u64)> {
+        let mut file_path = self.files_path.clone();
+        file_path.push(file_hash.to_hex().as_str());
+
+        let chunk_hashes = match Self::read_metadata(&file_path).await {
+            Ok(v) => v,
+            Err(Error::Io(std::io::ErrorKind::NotFound)) => return Err(Error::GeodeFileNotFound),
+            // Corrupted metadata does not hold references to any chunks
+            Err(_) => vec![],
+        };
+
+        fs::remove_file(&file_path).await?;
+        self.unpin(file_hash).await?;
+
+        let mut deleted_chunks = HashSet::new();
+        let mut freed = 0;
+        for chunk_hash in chunk_hashes.into_iter().collect::<HashSet<_>>() {
+            let Some(refcount) = refcounts.get_mut(&chunk_hash) else { continue };
+            *refcount -= 1;
+            if *refcount > 0 {
+                continue
+            }
+            refcounts.remove(&chunk_hash);
+
+            let mut chunk_path = self.chunks_path.clone();
+            chunk_path.push(chunk_hash.to_hex().as_str());
+
+            // The chunk might not be available locally
+            let Ok(metadata) = fs::metadata(&chunk_path).await else { continue };
+            fs::remove_file(&chunk_path).await?;
+            freed += metadata.len();
+            deleted_chunks.insert(chunk_hash);
+        }
+
+        Ok((deleted_chunks, freed))
+    }
+
     /// Perform garbage collection over the filesystem hierarchy.
     /// Returns sets representing deleted files and deleted chunks, respectively.
     pub async fn garbage_collect(&self) -> Result<(HashSet<blake3::Hash>, HashSet<blake3::Hash>)> {
@@ -369,8 +498,18 @@ impl Geode {
     /// Fetch file metadata from Geode. Returns [`ChunkedFile`] which gives a list
     /// of chunks and optionally file paths to the said chunks. Returns an error if
     /// the read failed in any way (could also be the file does not exist).
+    /// This counts as an access to the file.
     pub async fn get(&self, file_hash: &blake3::Hash) -> Result<ChunkedFile> {
         info!(target: "geode::get()", "[Geode] Getting file chunks for {}...", file_hash);
+        self.read_file(file_hash, true).await
+    }
+
+    /// Same as `get()`, but without updating the access time of the file.
+    pub async fn inspect(&self, file_hash: &blake3::Hash) -> Result<ChunkedFile> {
+        self.read_file(file_hash, false).await
+    }
+
+    async fn read_file(&self, file_hash: &blake3::Hash, touch: bool) -> Result<ChunkedFile> {
         let mut file_path = self.files_path.clone();
         file_path.push(file_hash.to_hex().as_str());
 
@@ -386,6 +525,15 @@ impl Geode {
             },
         };
 
+        if touch {
+            if let Err(e) = self.touch(file_hash).await {
+                warn!(
+                    target: "geode::get()",
+                    "[Geode] Failed updating access time of {}: {}", file_hash, e,
+                );
+            }
+        }
+
         let mut chunked_file = ChunkedFile::new(&chunk_hashes);
 
         // Iterate over chunks and find which chunks we have available locally.
@@ -417,6 +565,160 @@ impl Geode {
         Ok(chunked_file)
     }
 
+    /// Return the hashes of all files whose metadata is stored in Geode.
+    /// The files are not necessarily complete.
+    pub async fn list_files(&self) -> Result<Vec<blake3::Hash>> {
+        Self::read_hashes(&self.files_path).await
+    }
+
+    /// Return the hashes of all pinned files.
+    pub async fn pinned_files(&self) -> Result<HashSet<blake3::Hash>> {
+        Ok(Self::read_hashes(&self.pins_path).await?.into_iter().collect())
+    }
+
+    /// Check whether a file is pinned.
+    pub fn is_pinned(&self, file_hash: &blake3::Hash) -> bool {
+        let mut pin_path = self.pins_path.clone();
+        pin_path.push(file_hash.to_hex().as_str());
+        pin_path.is_file()
+    }
+
+    /// Pin a file, so it is never evicted by `enforce_quota()`.
+    /// Returns an error if the file metadata is not stored in Geode.
+    pub async fn pin(&self, file_hash: &blake3::Hash) -> Result<()> {
+        info!(target: "geode::pin()", "[Geode] Pinning file {}", file_hash);
+        let mut file_path = self.files_path.clone();
+        file_path.push(file_hash.to_hex().as_str());
+
+        if !file_path.is_file() {
+            return Err(Error::GeodeFileNotFound)
+        }
+
+        let mut pin_path = self.pins_path.clone();
+        pin_path.push(file_hash.to_hex().as_str());
+        File::create(&pin_path).await?;
+
+        Ok(())
+    }
+
+    /// Unpin a file. Unpinning a file which is not pinned is a no-op.
+    pub async fn unpin(&self, file_hash: &blake3::Hash) -> Result<()> {
+        info!(target: "geode::unpin()", "[Geode] Unpinning file {}", file_hash);
+        let mut pin_path = self.pins_path.clone();
+        pin_path.push(file_hash.to_hex().as_str());
+
+        match fs::remove_file(&pin_path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Remove a file from Geode, even if it is pinned. Its chunks are only
+    /// deleted if no other stored file uses them.
+    /// Returns the set of deleted chunks.
+    pub async fn remove(&self, file_hash: &blake3::Hash) -> Result<HashSet<blake3::Hash>> {
+        info!(target: "geode::remove()", "[Geode] Removing file {}", file_hash);
+        let mut refcounts = self.chunk_refcounts().await?;
+        let (deleted_chunks, _) = self.delete_file(file_hash, &mut refcounts).await?;
+        Ok(deleted_chunks)
+    }
+
+    /// Check that the chunks of a file make up the file. The chunk list of
+    /// a file we got from someone else can't be verified before all chunks
+    /// are here, so if they don't match, the file is removed from Geode
+    /// along with the chunks no other file uses. Otherwise the bad metadata
+    /// would stick around. Returns `false` if the file is incomplete.
+    pub async fn verify_file(
+        &self,
+        file_hash: &blake3::Hash,
+        chunked_file: &ChunkedFile,
+    ) -> Result<bool> {
+        let mut hasher = blake3::Hasher::new();
+        for (_, path) in chunked_file.iter() {
+            let Some(path) = path else { return Ok(false) };
+            hasher.update(&fs::read(path).await?);
+        }
+
+        if hasher.finalize() == *file_hash {
+            return Ok(true)
+        }
+
+        warn!(target: "geode::verify_file()", "[Geode] Chunks of {} do not match, removing", file_hash);
+        self.remove(file_hash).await?;
+        Ok(false)
+    }
+
+    /// Return the amount of bytes taken by the stored chunks.
+    pub async fn usage(&self) -> Result<u64> {
+        let mut usage = 0;
+        for chunk_hash in Self::read_hashes(&self.chunks_path).await? {
+            let mut chunk_path = self.chunks_path.clone();
+            chunk_path.push(chunk_hash.to_hex().as_str());
+            usage += fs::metadata(&chunk_path).await?.len();
+        }
+
+        Ok(usage)
+    }
+
+    /// Evict the least recently used files until the stored chunks take at
+    /// most `quota` bytes. Pinned files and files in `keep` are never evicted.
+    /// Returns the evicted files, in eviction order.
+    pub async fn enforce_quota(
+        &self,
+        quota: u64,
+        keep: &HashSet<blake3::Hash>,
+    ) -> Result<Vec<blake3::Hash>> {
+        let mut usage = self.usage().await?;
+        if usage <= quota {
+            return Ok(vec![])
+        }
+
+        info!(
+            target: "geode::enforce_quota()",
+            "[Geode] Storage usage {} exceeds quota {}, evicting files", usage, quota,
+        );
+
+        let pinned = self.pinned_files().await?;
+        let mut candidates = vec![];
+        for file_hash in self.list_files().await? {
+            if pinned.contains(&file_hash) || keep.contains(&file_hash) {
+                continue
+            }
+
+            let mut file_path = self.files_path.clone();
+            file_path.push(file_hash.to_hex().as_str());
+            let last_access = fs::metadata(&file_path).await?.modified()?;
+            candidates.push((last_access, file_hash));
+        }
+        candidates.sort_by_key(|(last_access, _)| *last_access);
+
+        let mut refcounts = self.chunk_refcounts().await?;
+        let mut evicted = vec![];
+        for (_, file_hash) in candidates {
+            if usage <= quota {
+                break
+            }
+
+            let (_, freed) = self.delete_file(&file_hash, &mut refcounts).await?;
+            debug!(
+                target: "geode::enforce_quota()",
+                "Evicted file {}, freeing {} bytes", file_hash, freed,
+            );
+            usage = usage.saturating_sub(freed);
+            evicted.push(file_hash);
+        }
+
+        if usage > quota {
+            warn!(
+                target: "geode::enforce_quota()",
+                "[Geode] Storage usage {} still exceeds quota {} after eviction", usage, quota,
+            );
+        }
+
+        Ok(evicted)
+    }
+
     /// Fetch a single chunk from Geode. Returns a `PathBuf` pointing to the chunk
     /// if it is found.
     pub async fn get_chunk(&self, chunk_hash: &blake3::Hash) -> Result<PathBuf> {
@@ -443,3 +745,184 @@ impl Geode {
         Ok(chunk_path)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn geode_remove_and_quota() -> Result<()> {
+        smol::block_on(async {
+            let base_path = std::env::temp_dir().join("darkfi_test_geode");
+            let _ = fs::remove_dir_all(&base_path).await;
+            let geode = Geode::new(&base_path).await?;
+
+            // Two files sharing their first chunk
+            let shared = vec![1u8; MAX_CHUNK_SIZE];
+            let a = [shared.clone(), vec![2u8; MAX_CHUNK_SIZE]].concat();
+            let b = [shared.clone(), vec![3u8; MAX_CHUNK_SIZE]].concat();
+            let (hash_a, chunks_a) = geode.insert(Cursor::new(&a)).await?;
+            let (hash_b, chunks_b) = geode.insert(Cursor::new(&b)).await?;
+            assert_eq!(geode.usage().await?, 3 * MAX_CHUNK_SIZE as u64);
+
+            // The shared chunk stays as long as a file uses it
+            let deleted = geode.remove(&hash_a).await?;
+            assert_eq!(deleted, HashSet::from([chunks_a[1]]));
+            assert!(matches!(geode.get(&hash_a).await, Err(Error::GeodeFileNotFound)));
+            assert!(geode.get(&hash_b).await?.is_complete());
+
+            let deleted = geode.remove(&hash_b).await?;
+            assert_eq!(deleted, HashSet::from([chunks_b[0], chunks_b[1]]));
+            assert_eq!(geode.usage().await?, 0);
+
+            // Pinned and kept files are never evicted, the others are evicted
+            // least recently used first.
+            let c = vec![4u8; MAX_CHUNK_SIZE];
+            let d = vec![5u8; MAX_CHUNK_SIZE];
+            let (hash_a, _) = geode.insert(Cursor::new(&a)).await?;
+            let (hash_b, _) = geode.insert(Cursor::new(&b)).await?;
+            let (hash_c, _) = geode.insert(Cursor::new(&c)).await?;
+            let (hash_d, _) = geode.insert(Cursor::new(&d)).await?;
+            geode.pin(&hash_a).await?;
+            assert!(geode.is_pinned(&hash_a));
+
+            // Make sure the access times are ordered
+            for hash in [hash_b, hash_c] {
+                smol::Timer::after(std::time::Duration::from_millis(10)).await;
+                geode.get(&hash).await?;
+            }
+
+            let evicted = geode.enforce_quota(0, &HashSet::from([hash_d])).await?;
+            assert_eq!(evicted, vec![hash_b, hash_c]);
+            assert_eq!(geode.list_files().await?.len(), 2);
+            assert_eq!(geode.usage().await?, 3 * MAX_CHUNK_SIZE as u64);
+
+            // Nothing is evicted under the quota
+            geode.unpin(&hash_a).await?;
+            assert!(geode
+                .enforce_quota(3 * MAX_CHUNK_SIZE as u64, &HashSet::new())
+                .await?
+                .is_empty());
+
+            fs::remove_dir_all(&base_path).await?;
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn geode_verify_file() -> Result<()> {
+        smol::block_on(async {
+            let base_path = std::env::temp_dir().join("darkfi_test_geode_verify");
+            let _ = fs::remove_dir_all(&base_path).await;
+            let geode = Geode::new(&base_path).await?;
+
+            let data = [vec![1u8; MAX_CHUNK_SIZE], vec![2u8; MAX_CHUNK_SIZE]].concat();
+            let (file_hash, chunk_hashes) = geode.insert(Cursor::new(&data)).await?;
+            let chunked_file = geode.get(&file_hash).await?;
+            assert!(geode.verify_file(&file_hash, &chunked_file).await?);
+
+            // Someone claiming another file is made of these chunks
+            let other_hash = blake3::hash(b"other");
+            geode.insert_file(&other_hash, &chunk_hashes).await?;
+            let chunked_file = geode.get(&other_hash).await?;
+            assert!(chunked_file.is_complete());
+            assert!(!geode.verify_file(&other_hash, &chunked_file).await?);
+
+            // The bad metadata is gone, and the chunks of the good file are kept
+            assert!(matches!(geode.get(&other_hash).await, Err(Error::GeodeFileNotFound)));
+            assert!(geode.get(&file_hash).await?.is_complete());
+
+            // Incomplete files can't be verified
+            let partial_hash = blake3::hash(b"partial");
+            geode.insert_file(&partial_hash, &[blake3::hash(b"missing")]).await?;
+            let chunked_file = geode.get(&partial_hash).await?;
+            assert!(!geode.verify_file(&partial_hash, &chunked_file).await?);
+
+            fs::remove_dir_all(&base_path).await?;
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn geode_manifest() -> Result<()> {
+        smol::block_on(async {
+            let base_path = std::env::temp_dir().join("darkfi_test_geode_manifest");
+            let _ = fs::remove_dir_all(&base_path).await;
+            let geode = Geode::new(&base_path.join("geode")).await?;
+
+            // Build a small tree with a nested and an empty directory
+            let tree = base_path.join("tree");
+            fs::create_dir_all(tree.join("a/b")).await?;
+            fs::create_dir_all(tree.join("empty")).await?;
+            fs::write(tree.join("top.txt"), b"top").await?;
+            fs::write(tree.join("a/b/big.bin"), vec![7u8; MAX_CHUNK_SIZE + 1]).await?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let mode = std::fs::Permissions::from_mode(0o4777);
+                fs::set_permissions(tree.join("top.txt"), mode).await?;
+            }
+
+            let (manifest_hash, manifest) = geode.insert_dir(&tree).await?;
+            let paths: Vec<&str> = manifest.entries.iter().map(|e| e.path()).collect();
+            assert_eq!(paths, vec!["a", "a/b", "a/b/big.bin", "empty", "top.txt"]);
+            assert_eq!(manifest.size(), MAX_CHUNK_SIZE as u64 + 4);
+
+            // The manifest is content-addressed and stored as a regular file
+            assert_eq!(geode.get_manifest(&manifest_hash).await?, manifest);
+            assert_eq!(geode.insert_dir(&tree).await?.0, manifest_hash);
+
+            // Regular files are not manifests
+            let ManifestEntry::File { file_hash, .. } = &manifest.entries[4] else { panic!() };
+            assert!(matches!(
+                geode.get_manifest(file_hash).await,
+                Err(Error::GeodeInvalidManifest)
+            ));
+
+            // Recreate the tree
+            let out = base_path.join("out");
+            geode.extract(&manifest, &out).await?;
+            assert_eq!(fs::read(out.join("top.txt")).await?, b"top");
+            assert_eq!(fs::read(out.join("a/b/big.bin")).await?, vec![7u8; MAX_CHUNK_SIZE + 1]);
+            assert!(out.join("empty").is_dir());
+
+            // Extracted files are not writable by others, nor setuid
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let metadata = fs::metadata(out.join("top.txt")).await?;
+                assert_eq!(metadata.permissions().mode() & 0o7777, 0o755);
+            }
+
+            // Files of a manifest with bogus chunk lists are not extracted
+            let fake_hash = blake3::hash(b"fake");
+            let ManifestEntry::File { chunk_hashes, .. } = &manifest.entries[2] else { panic!() };
+            let fake = Manifest {
+                entries: vec![ManifestEntry::File {
+                    path: "fake.bin".to_string(),
+                    mode: 0o644,
+                    size: 3,
+                    file_hash: fake_hash,
+                    chunk_hashes: chunk_hashes.clone(),
+                }],
+            };
+            geode.insert_manifest_files(&fake).await?;
+            let fake_out = base_path.join("fake");
+            assert!(matches!(geode.extract(&fake, &fake_out).await, Err(Error::GeodeFileMismatch)));
+            assert!(!fake_out.join("fake.bin").exists());
+            assert!(matches!(geode.get(&fake_hash).await, Err(Error::GeodeFileNotFound)));
+            assert!(geode.get(&manifest_hash).await?.is_complete());
+
+            // Paths leaving the tree are rejected
+            for path in ["../evil", "a/../../evil", "/evil", "a//b", "."] {
+                let evil = Manifest {
+                    entries: vec![ManifestEntry::Directory { path: path.to_string(), mode: 0o755 }],
+                };
+                assert!(Manifest::decode(&evil.encode()).is_err());
+            }
+
+            fs::remove_dir_all(&base_path).await?;
+            Ok(())
+        })
+    }
+}
@@ -30,6 +30,9 @@ pub mod consensus;
 #[cfg(feature = "validator")]
 pub mod validator;
 
+#[cfg(feature = "dht")]
+pub mod dht;
+
 #[cfg(feature = "geode")]
 pub mod geode;
 
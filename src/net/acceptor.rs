@@ -62,7 +62,9 @@ impl Acceptor {
 
     /// Start accepting inbound socket connections
     pub async fn start(self: Arc<Self>, endpoint: Url, ex: Arc<Executor<'_>>) -> Result<()> {
-        let listener = Listener::new(endpoint).await?.listen().await?;
+        let noise_keypair = self.session.upgrade().unwrap().p2p().noise_keypair();
+        let listener =
+            Listener::new(endpoint).await?.with_noise_keypair(noise_keypair).listen().await?;
         self.accept(listener, ex);
         Ok(())
     }
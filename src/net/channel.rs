@@ -17,20 +17,21 @@
  */
 
 use std::{
+    collections::{HashMap, HashSet},
     fmt,
     sync::{
-        atomic::{AtomicBool, Ordering::SeqCst},
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering::SeqCst},
         Arc,
     },
 };
 
 use darkfi_serial::{async_trait, serialize, SerialDecodable, SerialEncodable};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use rand::{rngs::OsRng, Rng};
 use smol::{
     io::{self, ReadHalf, WriteHalf},
     lock::Mutex,
-    Executor,
+    Executor, Timer,
 };
 use url::Url;
 
@@ -39,29 +40,47 @@ use super::{
     message,
     message::Packet,
     message_subscriber::{MessageSubscription, MessageSubsystem},
+    mux::{self, Demuxer, SendQueues},
     p2p::P2pPtr,
+    rate_limit::TokenBucket,
     session::{Session, SessionBitFlag, SessionWeakPtr},
-    transport::PtStream,
+    transport::{PtStream, NOISE_KEY_LEN},
 };
 use crate::{
-    system::{StoppableTask, StoppableTaskPtr, Subscriber, SubscriberPtr, Subscription},
-    util::time::NanoTimestamp,
+    system::{CondVar, StoppableTask, StoppableTaskPtr, Subscriber, SubscriberPtr, Subscription},
+    util::{
+        metrics::{registry, Counter},
+        time::NanoTimestamp,
+    },
     Error, Result,
 };
 
 /// Atomic pointer to async channel
 pub type ChannelPtr = Arc<Channel>;
 
+/// Ban score added every time a peer exceeds a message rate limit
+const RATE_LIMIT_PENALTY: u32 = 10;
+
+/// Ban score added when a peer sends a packet exceeding the size limits
+const OVERSIZED_PACKET_PENALTY: u32 = 50;
+
+const TRANSPORT_SENT_BYTES: &str = "darkfi_p2p_transport_sent_bytes_total";
+const TRANSPORT_RECV_BYTES: &str = "darkfi_p2p_transport_received_bytes_total";
+const COMMAND_SENT_BYTES: &str = "darkfi_p2p_command_sent_bytes_total";
+const COMMAND_RECV_BYTES: &str = "darkfi_p2p_command_received_bytes_total";
+
 /// Channel debug info
 #[derive(Clone, Debug, SerialEncodable, SerialDecodable)]
 pub struct ChannelInfo {
     pub addr: Url,
     pub id: u32,
+    /// Static key of the remote peer, authenticated by the transport
+    pub remote_static_key: Option<[u8; NOISE_KEY_LEN]>,
 }
 
 impl ChannelInfo {
-    fn new(addr: Url) -> Self {
-        Self { addr, id: OsRng.gen() }
+    fn new(addr: Url, remote_static_key: Option<[u8; NOISE_KEY_LEN]>) -> Self {
+        Self { addr, id: OsRng.gen(), remote_static_key }
     }
 }
 
@@ -77,10 +96,28 @@ pub struct Channel {
     stop_subscriber: SubscriberPtr<Error>,
     /// Task that is listening for the stop signal
     receive_task: StoppableTaskPtr,
+    /// Outbound packets waiting to be written, per substream
+    send_queues: Mutex<SendQueues>,
+    /// Wakes up the send task when packets get queued
+    send_cv: CondVar,
+    /// Task writing queued packets to the stream
+    send_task: StoppableTaskPtr,
     /// A boolean marking if this channel is stopped
     stopped: AtomicBool,
     /// Weak pointer to respective session
     session: SessionWeakPtr,
+    /// Misbehaviour score of the remote peer
+    ban_score: AtomicU32,
+    /// Number of inbound messages dropped because of rate limiting
+    dropped_msgs: AtomicU64,
+    /// Capabilities advertised by the remote peer during the handshake
+    remote_capabilities: Mutex<HashSet<String>>,
+    /// Static key the remote peer identified itself with during the handshake
+    remote_identity: Mutex<Option<[u8; NOISE_KEY_LEN]>>,
+    /// Bytes sent over the transport of this channel
+    sent_bytes: Arc<Counter>,
+    /// Bytes received over the transport of this channel
+    recv_bytes: Arc<Counter>,
     /// Channel debug info
     pub info: ChannelInfo,
 }
@@ -90,6 +127,7 @@ impl Channel {
     /// summons the message subscriber subsystem. Performs a network handshake
     /// on the subsystem dispatchers.
     pub async fn new(stream: Box<dyn PtStream>, addr: Url, session: SessionWeakPtr) -> Arc<Self> {
+        let remote_static_key = stream.remote_static_key();
         let (reader, writer) = io::split(stream);
         let reader = Mutex::new(reader);
         let writer = Mutex::new(writer);
@@ -97,7 +135,14 @@ impl Channel {
         let message_subsystem = MessageSubsystem::new();
         Self::setup_dispatchers(&message_subsystem).await;
 
-        let info = ChannelInfo::new(addr.clone());
+        let info = ChannelInfo::new(addr.clone(), remote_static_key);
+
+        // Channels come and go, so traffic is only counted per transport
+        let labels = [("transport", info.addr.scheme())];
+        let sent_bytes =
+            registry().counter(TRANSPORT_SENT_BYTES, "Bytes sent per transport", &labels);
+        let recv_bytes =
+            registry().counter(TRANSPORT_RECV_BYTES, "Bytes received per transport", &labels);
 
         Arc::new(Self {
             reader,
@@ -105,8 +150,17 @@ impl Channel {
             message_subsystem,
             stop_subscriber: Subscriber::new(),
             receive_task: StoppableTask::new(),
+            send_queues: Mutex::new(SendQueues::new()),
+            send_cv: CondVar::new(),
+            send_task: StoppableTask::new(),
             stopped: AtomicBool::new(false),
             session,
+            ban_score: AtomicU32::new(0),
+            dropped_msgs: AtomicU64::new(0),
+            remote_capabilities: Mutex::new(HashSet::new()),
+            remote_identity: Mutex::new(None),
+            sent_bytes,
+            recv_bytes,
             info,
         })
     }
@@ -119,13 +173,23 @@ impl Channel {
         subsystem.add_dispatch::<message::PongMessage>().await;
         subsystem.add_dispatch::<message::GetAddrsMessage>().await;
         subsystem.add_dispatch::<message::AddrsMessage>().await;
+        subsystem.add_dispatch::<message::ExtAddrsMessage>().await;
     }
 
     /// Starts the channel. Runs a receive loop to start receiving messages
-    /// or handles a network failure.
+    /// or handles a network failure, and a send loop writing out queued
+    /// messages.
     pub fn start(self: Arc<Self>, executor: Arc<Executor<'_>>) {
         debug!(target: "net::channel::start()", "START {:?}", self);
 
+        let self_ = self.clone();
+        self.send_task.clone().start(
+            self.clone().main_send_loop(),
+            |_| async move { self_.send_queues.lock().await.close() },
+            Error::ChannelStopped,
+            executor.clone(),
+        );
+
         let self_ = self.clone();
         self.receive_task.clone().start(
             self.clone().main_receive_loop(),
@@ -209,10 +273,56 @@ impl Channel {
             time: NanoTimestamp::current_time(),
         });
 
-        let stream = &mut *self.writer.lock().await;
-        let _ = message::send_packet(stream, packet).await?;
+        let packet_len = (packet.command.len() + packet.payload.len()) as u64;
+        let mut data = vec![];
+        let _ = message::send_packet(&mut data, packet).await?;
 
-        Ok(())
+        self.sent_bytes.add(packet_len);
+        registry()
+            .counter(COMMAND_SENT_BYTES, "Bytes sent per message command", &[("command", M::NAME)])
+            .add(packet_len);
+
+        // Queue the packet on its substream and wait for the send task
+        // to write it out.
+        let (done, written) = smol::channel::bounded(1);
+        self.send_queues.lock().await.push(M::PRIORITY, data, done)?;
+        self.send_cv.notify();
+
+        written.recv().await?
+    }
+
+    /// Run the send loop. Writes out queued packets frame by frame, always
+    /// taking the next frame from the highest priority substream.
+    async fn main_send_loop(self: Arc<Self>) -> Result<()> {
+        debug!(target: "net::channel::main_send_loop()", "[START] {:?}", self);
+
+        // Acquire writer lock
+        let writer = &mut *self.writer.lock().await;
+
+        loop {
+            self.send_cv.reset();
+
+            let next = self.send_queues.lock().await.next_frame();
+            let Some((frame, done)) = next else {
+                self.send_cv.wait().await;
+                continue
+            };
+
+            if let Err(e) = mux::write_frame(writer, &frame).await {
+                debug!(
+                    target: "net::channel::main_send_loop()",
+                    "Write error on {:?}: {}", self, e,
+                );
+                if let Some(done) = done {
+                    let _ = done.send(Err(e.clone())).await;
+                }
+                return Err(e)
+            }
+
+            if let Some(done) = done {
+                let _ = done.send(Ok(())).await;
+            }
+        }
     }
 
     /// Subscribe to a message on the message subsystem.
@@ -238,6 +348,7 @@ impl Channel {
         debug!(target: "net::channel::handle_stop()", "[START] {:?}", self);
 
         self.stopped.store(true, SeqCst);
+        self.send_task.stop().await;
 
         match result {
             Ok(()) => panic!("Channel task should never complete without error status"),
@@ -258,10 +369,27 @@ impl Channel {
         // Acquire reader lock
         let reader = &mut *self.reader.lock().await;
 
+        // Set up the configured inbound rate limits
+        let settings = self.p2p().settings();
+        let mut bandwidth = settings.channel_bandwidth_limit.map(TokenBucket::new);
+        let mut msg_rates: HashMap<String, TokenBucket> = HashMap::new();
+        let mut demuxer = Demuxer::new(settings.max_packet_size);
+
         // Run loop
         loop {
-            let packet = match message::read_packet(reader).await {
+            let packet = match self.read_packet(reader, &mut demuxer).await {
                 Ok(packet) => packet,
+                Err(Error::PacketTooLarge(len)) => {
+                    warn!(
+                        target: "net::channel::main_receive_loop()",
+                        "[P2P] Channel {} sent an oversized packet ({} bytes), disconnecting",
+                        self.address(), len,
+                    );
+
+                    // The stream can't be resynced, so we disconnect regardless
+                    self.increase_ban_score(OVERSIZED_PACKET_PENALTY).await;
+                    return Err(Error::ChannelStopped)
+                }
                 Err(err) => {
                     if Self::is_eof_error(&err) {
                         info!(
@@ -291,9 +419,54 @@ impl Channel {
                 time: NanoTimestamp::current_time(),
             });
 
+            let packet_len = (packet.command.len() + packet.payload.len()) as u64;
+            self.recv_bytes.add(packet_len);
+
+            // Throttle reading from the stream if the peer exceeds its bandwidth
+            if let Some(ref mut bucket) = bandwidth {
+                let wait = bucket.take(packet_len);
+                if !wait.is_zero() {
+                    debug!(
+                        target: "net::channel::main_receive_loop()",
+                        "Throttling channel {:?} for {:?}", self, wait,
+                    );
+                    Timer::after(wait).await;
+                }
+            }
+
+            // Drop messages exceeding their rate limit and penalise the peer
+            if let Some(limit) = settings.message_rate_limits.get(&packet.command) {
+                let bucket = msg_rates
+                    .entry(packet.command.clone())
+                    .or_insert_with(|| TokenBucket::new(*limit));
+
+                if !bucket.try_take(1) {
+                    let dropped = self.dropped_msgs.fetch_add(1, SeqCst) + 1;
+                    let banned = self.increase_ban_score(RATE_LIMIT_PENALTY).await;
+
+                    dnetev!(self, ChannelRateLimited, {
+                        chan: self.info.clone(),
+                        cmd: packet.command.clone(),
+                        dropped,
+                        ban_score: self.ban_score(),
+                    });
+
+                    if banned {
+                        return Err(Error::ChannelStopped)
+                    }
+                    continue
+                }
+            }
+
             // Send result to our subscribers
             match self.message_subsystem.notify(&packet.command, &packet.payload).await {
-                Ok(()) => {}
+                // Only known commands get their own metric
+                Ok(()) => {
+                    let labels = [("command", packet.command.as_str())];
+                    registry()
+                        .counter(COMMAND_RECV_BYTES, "Bytes received per message command", &labels)
+                        .add(packet_len);
+                }
                 // If we're getting messages without dispatchers, it's spam.
                 Err(Error::MissingDispatcher) => {
                     debug!(target: "net::channel::main_receive_loop()", "Stopping channel {:?}", self);
@@ -314,6 +487,103 @@ impl Channel {
         }
     }
 
+    /// Read frames from the stream until a packet is complete on one of the
+    /// substreams. The payload is bounded by the size limit of its message
+    /// type as well as the global packet size limit.
+    async fn read_packet(
+        &self,
+        reader: &mut ReadHalf<Box<dyn PtStream>>,
+        demuxer: &mut Demuxer,
+    ) -> Result<Packet> {
+        let data = loop {
+            let frame = mux::read_frame(reader).await?;
+            let substream = frame.substream;
+            if let Some(data) = demuxer.push(frame)? {
+                break data
+            }
+
+            // Once the command of a partial packet is known, bound the rest
+            // of it by its message type, rather than reassembling up to the
+            // global limit first.
+            let Some(partial) = demuxer.unbounded(substream) else { continue };
+            let Ok(command) = message::read_command(&mut io::Cursor::new(partial)).await else {
+                continue
+            };
+            let max_size = self.message_subsystem.max_bytes(&command).await;
+            demuxer.bound(substream, max_size.unwrap_or(usize::MAX))?;
+        };
+
+        let reader = &mut io::Cursor::new(data);
+        let command = message::read_command(reader).await?;
+        let max_size = self.p2p().settings().max_packet_size;
+        let max_size = match self.message_subsystem.max_bytes(&command).await {
+            Some(max_bytes) => max_bytes.min(max_size),
+            None => max_size,
+        };
+        let payload = message::read_payload(reader, max_size).await?;
+        Ok(Packet { command, payload })
+    }
+
+    /// Add `points` to the ban score of the remote peer. Once the score
+    /// reaches [`Settings::ban_score_limit`](super::settings::Settings), the
+    /// peer is marked as rejected and `true` is returned. The caller is then
+    /// responsible for stopping the channel.
+    pub async fn increase_ban_score(&self, points: u32) -> bool {
+        let score = self.ban_score.fetch_add(points, SeqCst).saturating_add(points);
+        debug!(
+            target: "net::channel::increase_ban_score()",
+            "Ban score of {:?} increased to {}", self, score,
+        );
+
+        if score < self.p2p().settings().ban_score_limit {
+            return false
+        }
+
+        warn!(
+            target: "net::channel::increase_ban_score()",
+            "[P2P] Peer {} reached ban score {}, rejecting", self.address(), score,
+        );
+        self.p2p().hosts().mark_rejected(self.address()).await;
+        true
+    }
+
+    /// Returns the current ban score of the remote peer
+    pub fn ban_score(&self) -> u32 {
+        self.ban_score.load(SeqCst)
+    }
+
+    /// Returns the number of inbound messages dropped because of rate limiting
+    pub fn dropped_messages(&self) -> u64 {
+        self.dropped_msgs.load(SeqCst)
+    }
+
+    /// Store the capabilities advertised by the remote peer
+    pub(crate) async fn set_remote_capabilities(&self, capabilities: HashSet<String>) {
+        *self.remote_capabilities.lock().await = capabilities;
+    }
+
+    /// Returns the capabilities advertised by the remote peer
+    pub async fn remote_capabilities(&self) -> HashSet<String> {
+        self.remote_capabilities.lock().await.clone()
+    }
+
+    /// Check if the remote peer advertised the given capability
+    pub async fn has_capability(&self, capability: &str) -> bool {
+        self.remote_capabilities.lock().await.contains(capability)
+    }
+
+    /// Store the static key the remote peer identified itself with
+    pub(crate) async fn set_remote_identity(&self, key: [u8; NOISE_KEY_LEN]) {
+        *self.remote_identity.lock().await = Some(key);
+    }
+
+    /// Returns the static key the remote peer identified itself with during
+    /// the handshake. On transports without authentication, this is only
+    /// what the peer claims to be.
+    pub async fn remote_identity(&self) -> Option<[u8; NOISE_KEY_LEN]> {
+        *self.remote_identity.lock().await
+    }
+
     /// Returns the local socket address
     pub fn address(&self) -> &Url {
         &self.info.addr
@@ -333,7 +603,7 @@ impl Channel {
         session.type_id()
     }
 
-    fn p2p(&self) -> P2pPtr {
+    pub(crate) fn p2p(&self) -> P2pPtr {
         self.session().p2p()
     }
 
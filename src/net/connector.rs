@@ -66,7 +66,16 @@ impl Connector {
             }
         }
 
-        let dialer = Dialer::new(endpoint.clone()).await?;
+        let noise_keypair = self.session.upgrade().unwrap().p2p().noise_keypair();
+        let mut dialer = Dialer::new(endpoint.clone()).await?.with_noise_keypair(noise_keypair);
+
+        // Present our own address on transports identifying the dialing side
+        if let Some(local) =
+            self.settings.inbound_addrs.iter().find(|addr| addr.scheme() == endpoint.scheme())
+        {
+            dialer = dialer.with_local_endpoint(local.clone());
+        }
+
         let timeout = Duration::from_secs(self.settings.outbound_connect_timeout);
         let ptstream = dialer.dial(Some(timeout)).await?;
 
@@ -83,6 +83,16 @@ pub struct OutboundPeerDiscovery {
     pub state: &'static str,
 }
 
+#[derive(Clone, Debug)]
+pub struct ChannelRateLimited {
+    pub chan: ChannelInfo,
+    pub cmd: String,
+    /// Total messages dropped on this channel so far
+    pub dropped: u64,
+    /// Current ban score of the channel
+    pub ban_score: u32,
+}
+
 #[derive(Clone, Debug)]
 pub enum DnetEvent {
     SendMessage(MessageInfo),
@@ -94,4 +104,5 @@ pub enum DnetEvent {
     OutboundSlotConnected(OutboundSlotConnected),
     OutboundSlotDisconnected(OutboundSlotDisconnected),
     OutboundPeerDiscovery(OutboundPeerDiscovery),
+    ChannelRateLimited(ChannelRateLimited),
 }
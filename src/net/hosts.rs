@@ -18,17 +18,22 @@
 
 use std::{
     collections::{HashMap, HashSet},
+    path::Path,
     sync::Arc,
 };
 
-use log::debug;
-use rand::{prelude::IteratorRandom, rngs::OsRng};
+use log::{debug, info, warn};
+use rand::{prelude::SliceRandom, rngs::OsRng};
 use smol::lock::RwLock;
 use url::Url;
 
-use super::settings::SettingsPtr;
+use super::{settings::SettingsPtr, transport::NOISE_KEY_LEN};
 use crate::{
     system::{Subscriber, SubscriberPtr, Subscription},
+    util::{
+        file::{load_file, save_file},
+        time::Timestamp,
+    },
     Result,
 };
 
@@ -39,10 +44,117 @@ pub type HostsPtr = Arc<Hosts>;
 // TODO: This could perhaps be more exhaustive?
 pub const LOCAL_HOST_STRS: [&str; 2] = ["localhost", "localhost.localdomain"];
 
+/// Header line of the on-disk hostlist. Bump the version when changing the format.
+const HOSTLIST_HEADER: &str = "# darkfi hostlist v3";
+
+/// Maximum number of addresses kept on the greylist
+const GREYLIST_MAX_LEN: usize = 2000;
+/// Maximum number of addresses kept on the whitelist
+const WHITELIST_MAX_LEN: usize = 5000;
+/// Maximum number of addresses kept on the anchorlist
+const ANCHORLIST_MAX_LEN: usize = 1000;
+
+/// Connection history we keep for a known address. This is what gets
+/// persisted to the hostlist and it is used to score peers when picking
+/// random addresses from the hosts set. The transport is the URL scheme.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HostInfo {
+    /// UNIX timestamp of the last successful handshake
+    pub last_seen: u64,
+    /// Number of successful handshakes
+    pub handshakes: u32,
+    /// Number of failed connection attempts
+    pub failures: u32,
+}
+
+impl HostInfo {
+    /// Score used as a weight for random peer selection. Peers we have
+    /// successfully connected to are preferred, and failures pull the
+    /// score down. Unknown peers get a neutral score of 1.
+    pub fn score(&self) -> f64 {
+        (1.0 + self.handshakes as f64) / (1.0 + self.failures as f64)
+    }
+}
+
+/// Outcome of a ping-back probe verifying an address a peer advertised
+/// as its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AddrVerification {
+    /// Identity of the node that answered at the address, or `None` if
+    /// nothing answered
+    pub identity: Option<[u8; NOISE_KEY_LEN]>,
+    /// UNIX timestamp of the probe
+    pub checked_at: u64,
+}
+
+/// Trust tier of a known address. Addresses learned through gossip start
+/// out on the greylist, get promoted to the whitelist once we've probed
+/// them successfully, and become anchors once we've held an outbound
+/// connection with them. Outbound slots are filled starting from anchors,
+/// so flooding us with addresses cannot easily push out peers we trust.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HostTier {
+    /// Unverified addresses received from other peers
+    Grey,
+    /// Addresses that answered a version handshake probe
+    White,
+    /// Addresses we had a successful outbound connection with
+    Anchor,
+}
+
+impl HostTier {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Grey => "grey",
+            Self::White => "white",
+            Self::Anchor => "anchor",
+        }
+    }
+
+    fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "grey" => Some(Self::Grey),
+            "white" => Some(Self::White),
+            "anchor" => Some(Self::Anchor),
+            _ => None,
+        }
+    }
+
+    fn max_len(&self) -> usize {
+        match self {
+            Self::Grey => GREYLIST_MAX_LEN,
+            Self::White => WHITELIST_MAX_LEN,
+            Self::Anchor => ANCHORLIST_MAX_LEN,
+        }
+    }
+}
+
+/// Make room for a new address in a full tier by evicting the address
+/// with the worst connection history, and forgetting about it.
+fn make_room(tier: HostTier, list: &mut HashSet<Url>, info: &mut HashMap<Url, HostInfo>) {
+    if list.len() < tier.max_len() {
+        return
+    }
+
+    let score = |url: &Url| info.get(url).map(|i| i.score()).unwrap_or(1.0);
+    let worst = list.iter().min_by(|a, b| score(a).total_cmp(&score(b))).cloned();
+    if let Some(url) = worst {
+        debug!(target: "net::hosts::make_room()", "Evicting {} from the {}list", url, tier.as_str());
+        list.remove(&url);
+        info.remove(&url);
+    }
+}
+
 /// Manages a store of network addresses
 pub struct Hosts {
-    /// Set of stored addresses
-    addrs: RwLock<HashSet<Url>>,
+    /// Set of unverified addresses, see [`HostTier::Grey`]
+    greylist: RwLock<HashSet<Url>>,
+
+    /// Set of verified addresses, see [`HostTier::White`]
+    whitelist: RwLock<HashSet<Url>>,
+
+    /// Set of anchor addresses, see [`HostTier::Anchor`]
+    anchorlist: RwLock<HashSet<Url>>,
 
     /// Set of stored addresses that are quarantined.
     /// We quarantine peers we've been unable to connect to, but we keep them
@@ -54,6 +166,12 @@ pub struct Hosts {
     /// Peers we reject from connecting
     rejected: RwLock<HashSet<String>>,
 
+    /// Connection history of known addresses, used for scoring
+    info: RwLock<HashMap<Url, HostInfo>>,
+
+    /// Cached outcomes of verifying addresses advertised by peers
+    verifications: RwLock<HashMap<Url, AddrVerification>>,
+
     /// Subscriber listening for store updates
     store_subscriber: SubscriberPtr<usize>,
 
@@ -65,15 +183,20 @@ impl Hosts {
     /// Create a new hosts list>
     pub fn new(settings: SettingsPtr) -> HostsPtr {
         Arc::new(Self {
-            addrs: RwLock::new(HashSet::new()),
+            greylist: RwLock::new(HashSet::new()),
+            whitelist: RwLock::new(HashSet::new()),
+            anchorlist: RwLock::new(HashSet::new()),
             quarantine: RwLock::new(HashMap::new()),
             rejected: RwLock::new(HashSet::new()),
+            info: RwLock::new(HashMap::new()),
+            verifications: RwLock::new(HashMap::new()),
             store_subscriber: Subscriber::new(),
             settings,
         })
     }
 
-    /// Append given addrs to the known set.
+    /// Append given addrs to the greylist. Addresses we already verified
+    /// keep their current tier.
     pub async fn store(&self, addrs: &[Url]) {
         debug!(target: "net::hosts::store()", "hosts::store() [START]");
 
@@ -81,10 +204,21 @@ impl Hosts {
         let filtered_addrs_len = filtered_addrs.len();
 
         if !filtered_addrs.is_empty() {
-            let mut addrs_map = self.addrs.write().await;
+            let mut greylist = self.greylist.write().await;
+            let whitelist = self.whitelist.read().await;
+            let anchorlist = self.anchorlist.read().await;
+            let mut info_map = self.info.write().await;
             for addr in filtered_addrs {
+                if greylist.contains(&addr) ||
+                    whitelist.contains(&addr) ||
+                    anchorlist.contains(&addr)
+                {
+                    continue
+                }
                 debug!(target: "net::hosts::store()", "Inserting {}", addr);
-                addrs_map.insert(addr);
+                make_room(HostTier::Grey, &mut greylist, &mut info_map);
+                info_map.entry(addr.clone()).or_default();
+                greylist.insert(addr);
             }
         }
 
@@ -92,6 +226,78 @@ impl Hosts {
         debug!(target: "net::hosts::store()", "hosts::store() [END]");
     }
 
+    /// Promote an address to the whitelist after it answered a probe.
+    /// Anchors are left untouched.
+    pub async fn whitelist_store(&self, url: &Url) {
+        if self.anchorlist.read().await.contains(url) {
+            return
+        }
+
+        debug!(target: "net::hosts::whitelist_store()", "Whitelisting {}", url);
+        self.greylist.write().await.remove(url);
+        self.quarantine.write().await.remove(url);
+
+        let mut whitelist = self.whitelist.write().await;
+        let mut info = self.info.write().await;
+        if !whitelist.contains(url) {
+            make_room(HostTier::White, &mut whitelist, &mut info);
+        }
+        whitelist.insert(url.clone());
+        info.entry(url.clone()).or_default();
+    }
+
+    /// Promote an address to the anchorlist after a successful outbound
+    /// connection.
+    pub async fn anchorlist_store(&self, url: &Url) {
+        debug!(target: "net::hosts::anchorlist_store()", "Anchoring {}", url);
+        self.greylist.write().await.remove(url);
+        self.whitelist.write().await.remove(url);
+        self.quarantine.write().await.remove(url);
+
+        let mut anchorlist = self.anchorlist.write().await;
+        let mut info = self.info.write().await;
+        if !anchorlist.contains(url) {
+            make_room(HostTier::Anchor, &mut anchorlist, &mut info);
+        }
+        anchorlist.insert(url.clone());
+        info.entry(url.clone()).or_default();
+    }
+
+    /// Return the tier the given address is currently in, if any.
+    pub async fn tier(&self, url: &Url) -> Option<HostTier> {
+        for tier in [HostTier::Anchor, HostTier::White, HostTier::Grey] {
+            if self.tier_list(tier).read().await.contains(url) {
+                return Some(tier)
+            }
+        }
+
+        None
+    }
+
+    /// Return all known hosts in the given tier.
+    pub async fn fetch_tier(&self, tier: HostTier) -> Vec<Url> {
+        self.tier_list(tier).read().await.iter().cloned().collect()
+    }
+
+    /// Return all hosts in the given tier that match the given transport schemes.
+    pub async fn fetch_tier_with_schemes(&self, tier: HostTier, schemes: &[String]) -> Vec<Url> {
+        self.tier_list(tier)
+            .read()
+            .await
+            .iter()
+            .filter(|addr| schemes.contains(&addr.scheme().to_string()))
+            .cloned()
+            .collect()
+    }
+
+    fn tier_list(&self, tier: HostTier) -> &RwLock<HashSet<Url>> {
+        match tier {
+            HostTier::Grey => &self.greylist,
+            HostTier::White => &self.whitelist,
+            HostTier::Anchor => &self.anchorlist,
+        }
+    }
+
     pub async fn subscribe_store(&self) -> Result<Subscription<usize>> {
         let sub = self.store_subscriber.clone().subscribe().await;
         Ok(sub)
@@ -133,7 +339,7 @@ impl Hosts {
     }
 
     /// Filter given addresses based on certain rulesets and validity.
-    async fn filter_addresses(&self, addrs: &[Url]) -> Vec<Url> {
+    pub(crate) async fn filter_addresses(&self, addrs: &[Url]) -> Vec<Url> {
         debug!(target: "net::hosts::filter_addresses()", "Filtering addrs: {:?}", addrs);
         let mut ret = vec![];
         let localnet = self.settings.localnet;
@@ -190,10 +396,12 @@ impl Hosts {
                 "nym" | "nym+tls" => continue, // <-- Temp skip
 
                 #[cfg(feature = "p2p-tcp")]
-                "tcp" | "tcp+tls" => {
+                "tcp" | "tcp+tls" | "tcp+noise" => {
                     debug!(target: "net::hosts::filter_addresses()", "[TCP] Valid: {}", host_str);
                 }
 
+                // This includes `memory://` addresses, which only exist
+                // inside the process that made them up.
                 _ => continue,
             }
 
@@ -205,16 +413,22 @@ impl Hosts {
 
     pub async fn remove(&self, url: &Url) {
         debug!(target: "net::hosts::remove()", "Removing peer {}", url);
-        self.addrs.write().await.remove(url);
+        self.greylist.write().await.remove(url);
+        self.whitelist.write().await.remove(url);
+        self.anchorlist.write().await.remove(url);
         self.quarantine.write().await.remove(url);
+        self.info.write().await.remove(url);
     }
 
     /// Quarantine a peer.
     /// If they've been quarantined for more than a configured limit, forget them.
     pub async fn quarantine(&self, url: &Url) {
         debug!(target: "net::hosts::remove()", "Quarantining peer {}", url);
-        // Remove from main hosts set
-        self.addrs.write().await.remove(url);
+        // Remove from all the hosts tiers
+        self.greylist.write().await.remove(url);
+        self.whitelist.write().await.remove(url);
+        self.anchorlist.write().await.remove(url);
+        self.record_failure(url).await;
 
         let mut q = self.quarantine.write().await;
         if let Some(retries) = q.get_mut(url) {
@@ -268,70 +482,259 @@ impl Hosts {
 
     /// Check if the host list is empty.
     pub async fn is_empty(&self) -> bool {
-        self.addrs.read().await.is_empty()
+        self.greylist.read().await.is_empty() &&
+            self.whitelist.read().await.is_empty() &&
+            self.anchorlist.read().await.is_empty()
     }
 
     /// Check if host is already in the set
     pub async fn contains(&self, addr: &Url) -> bool {
-        self.addrs.read().await.contains(addr)
+        self.tier(addr).await.is_some()
     }
 
     /// Return all known hosts
     pub async fn fetch_all(&self) -> Vec<Url> {
-        self.addrs.read().await.iter().cloned().collect()
+        let mut ret = self.fetch_tier(HostTier::Anchor).await;
+        ret.append(&mut self.fetch_tier(HostTier::White).await);
+        ret.append(&mut self.fetch_tier(HostTier::Grey).await);
+        ret
     }
 
     /// Get up to n random peers from the hosts set.
+    /// Verified peers and peers with a better connection history are preferred.
     pub async fn fetch_n_random(&self, n: u32) -> Vec<Url> {
-        let n = n as usize;
-        if n == 0 {
-            return vec![]
-        }
-        let addrs = self.addrs.read().await;
-        let urls = addrs.iter().choose_multiple(&mut OsRng, n.min(addrs.len()));
-        urls.iter().map(|&url| url.clone()).collect()
+        self.fetch_n_random_filtered(n, |_| true).await
     }
 
     /// Get up to n random peers that match the given transport schemes from the hosts set.
+    /// Verified peers and peers with a better connection history are preferred.
     pub async fn fetch_n_random_with_schemes(&self, schemes: &[String], n: u32) -> Vec<Url> {
+        self.fetch_n_random_filtered(n, |addr| schemes.contains(&addr.scheme().to_string())).await
+    }
+
+    /// Get up to n random peers that don't match the given transport schemes from the hosts set.
+    /// Verified peers and peers with a better connection history are preferred.
+    pub async fn fetch_n_random_excluding_schemes(&self, schemes: &[String], n: u32) -> Vec<Url> {
+        self.fetch_n_random_filtered(n, |addr| !schemes.contains(&addr.scheme().to_string())).await
+    }
+
+    /// Get up to n random peers passing the given filter. Anchors and
+    /// whitelisted peers are picked first, and only if there aren't enough
+    /// of them do we fill up the rest from the greylist.
+    async fn fetch_n_random_filtered(&self, n: u32, filter: impl Fn(&Url) -> bool) -> Vec<Url> {
         let n = n as usize;
-        if n == 0 {
-            return vec![]
+        let mut ret = vec![];
+
+        for tiers in [&[HostTier::Anchor, HostTier::White][..], &[HostTier::Grey][..]] {
+            if ret.len() >= n {
+                break
+            }
+
+            let mut hosts = vec![];
+            for tier in tiers {
+                hosts.extend(self.fetch_tier(*tier).await.into_iter().filter(|addr| filter(addr)));
+            }
+
+            ret.append(&mut self.choose_weighted(&hosts, n - ret.len()).await);
         }
 
-        // Retrieve all peers corresponding to that transport schemes
-        let hosts = self.fetch_with_schemes(schemes, None).await;
-        if hosts.is_empty() {
-            return hosts
+        ret
+    }
+
+    /// Pick up to n distinct addresses from the given slice, using their
+    /// [`HostInfo::score`] as the selection weight.
+    async fn choose_weighted(&self, hosts: &[Url], n: usize) -> Vec<Url> {
+        let info = self.info.read().await;
+        let weight = |url: &Url| info.get(url).map(|i| i.score()).unwrap_or(1.0);
+
+        match hosts.choose_multiple_weighted(&mut OsRng, n.min(hosts.len()), weight) {
+            Ok(urls) => urls.cloned().collect(),
+            // Weights are always positive, but fall back to a uniform pick anyway.
+            Err(_) => hosts.choose_multiple(&mut OsRng, n).cloned().collect(),
         }
+    }
 
-        // Grab random ones
-        let urls = hosts.iter().choose_multiple(&mut OsRng, n.min(hosts.len()));
-        urls.iter().map(|&url| url.clone()).collect()
+    /// Record a successful handshake with the given address.
+    pub async fn record_handshake(&self, url: &Url) {
+        let mut info = self.info.write().await;
+        let entry = info.entry(url.clone()).or_default();
+        entry.handshakes = entry.handshakes.saturating_add(1);
+        entry.last_seen = Timestamp::current_time().0;
     }
 
-    /// Get up to n random peers that don't match the given transport schemes from the hosts set.
-    pub async fn fetch_n_random_excluding_schemes(&self, schemes: &[String], n: u32) -> Vec<Url> {
-        let n = n as usize;
-        if n == 0 {
-            return vec![]
+    /// Record a failed connection attempt to the given address.
+    pub async fn record_failure(&self, url: &Url) {
+        let mut info = self.info.write().await;
+        let entry = info.entry(url.clone()).or_default();
+        entry.failures = entry.failures.saturating_add(1);
+    }
+
+    /// Return the connection history of the given address, if known.
+    pub async fn host_info(&self, url: &Url) -> Option<HostInfo> {
+        self.info.read().await.get(url).copied()
+    }
+
+    /// Cache the outcome of probing an advertised address. `identity` is
+    /// the static key of the node that answered, if any.
+    pub async fn record_verification(&self, url: &Url, identity: Option<[u8; NOISE_KEY_LEN]>) {
+        let verification = AddrVerification { identity, checked_at: Timestamp::current_time().0 };
+        self.verifications.write().await.insert(url.clone(), verification);
+    }
+
+    /// Return the cached outcome of probing the given address, unless it
+    /// is older than [`Settings::addr_verification_ttl`](super::settings::Settings).
+    pub async fn verification(&self, url: &Url) -> Option<AddrVerification> {
+        let mut verifications = self.verifications.write().await;
+        let verification = *verifications.get(url)?;
+
+        let age = Timestamp::current_time().0.saturating_sub(verification.checked_at);
+        if age >= self.settings.addr_verification_ttl {
+            verifications.remove(url);
+            return None
+        }
+
+        Some(verification)
+    }
+
+    /// Load a hostlist previously written with [`Hosts::save_hosts`].
+    /// Addresses go through the usual filtering before entering the
+    /// hosts set, and their tier, connection history and verification
+    /// are restored.
+    pub async fn load_hosts(&self, path: &Path) -> Result<()> {
+        let contents = load_file(path)?;
+
+        let mut lines = contents.lines();
+        if lines.next() != Some(HOSTLIST_HEADER) {
+            warn!(target: "net::hosts::load_hosts()", "Unknown hostlist format in {:?}", path);
+            return Ok(())
+        }
+
+        let mut addrs = vec![];
+        let mut loaded = HashMap::new();
+        for line in lines {
+            let data: Vec<&str> = line.split('\t').collect();
+            if data.len() != 8 {
+                warn!(target: "net::hosts::load_hosts()", "Skipping malformed line: {}", line);
+                continue
+            }
+
+            let Some(tier) = HostTier::from_name(data[0]) else {
+                warn!(target: "net::hosts::load_hosts()", "Skipping unknown tier: {}", line);
+                continue
+            };
+
+            let url = match Url::parse(data[2]) {
+                Ok(u) => u,
+                Err(e) => {
+                    warn!(target: "net::hosts::load_hosts()", "Skipping malformed url: {} ({})", data[2], e);
+                    continue
+                }
+            };
+
+            if url.scheme() != data[1] {
+                warn!(target: "net::hosts::load_hosts()", "Skipping mismatched transport: {}", line);
+                continue
+            }
+
+            let (Ok(last_seen), Ok(handshakes), Ok(failures), Ok(checked_at)) =
+                (data[3].parse(), data[4].parse(), data[5].parse(), data[7].parse())
+            else {
+                warn!(target: "net::hosts::load_hosts()", "Skipping malformed line: {}", line);
+                continue
+            };
+
+            // The identity is `-` if nothing answered the probe, and a
+            // zero timestamp means the address was never probed.
+            let verification = match (data[6], checked_at) {
+                (_, 0) => None,
+                ("-", _) => Some(AddrVerification { identity: None, checked_at }),
+                (identity, _) => {
+                    let mut key = [0u8; NOISE_KEY_LEN];
+                    match bs58::decode(identity).onto(&mut key) {
+                        Ok(NOISE_KEY_LEN) => {}
+                        _ => {
+                            warn!(target: "net::hosts::load_hosts()", "Skipping malformed identity: {}", line);
+                            continue
+                        }
+                    }
+                    Some(AddrVerification { identity: Some(key), checked_at })
+                }
+            };
+
+            let host_info = HostInfo { last_seen, handshakes, failures };
+            loaded.insert(url.clone(), (tier, host_info, verification));
+            addrs.push(url);
+        }
+
+        // Addresses that pass filtering go straight back into their saved
+        // tier, keeping their connection history.
+        let filtered_addrs = self.filter_addresses(&addrs).await;
+        let mut n_loaded = 0;
+        for url in filtered_addrs {
+            let Some((tier, host_info, verification)) = loaded.remove(&url) else { continue };
+            if self.tier(&url).await.is_some() {
+                continue
+            }
+
+            let mut list = self.tier_list(tier).write().await;
+            let mut info = self.info.write().await;
+            make_room(tier, &mut list, &mut info);
+            list.insert(url.clone());
+            info.insert(url.clone(), host_info);
+            if let Some(verification) = verification {
+                self.verifications.write().await.insert(url, verification);
+            }
+            n_loaded += 1;
+        }
+
+        self.store_subscriber.notify(n_loaded).await;
+        info!(target: "net::hosts::load_hosts()", "Loaded {} hosts from {:?}", n_loaded, path);
+        Ok(())
+    }
+
+    /// Write the hosts set along with tiers, connection history and verification
+    /// to the given path.
+    pub async fn save_hosts(&self, path: &Path) -> Result<()> {
+        let mut hosts = vec![];
+        for tier in [HostTier::Anchor, HostTier::White, HostTier::Grey] {
+            for addr in self.fetch_tier(tier).await {
+                hosts.push((tier, addr));
+            }
         }
 
-        // Retrieve all peers not corresponding to that transport schemes
-        let hosts = self.fetch_exluding_schemes(schemes, None).await;
-        if hosts.is_empty() {
-            return hosts
+        let mut tsv = format!("{}\n", HOSTLIST_HEADER);
+        let info = self.info.read().await;
+        let verifications = self.verifications.read().await;
+        for (tier, addr) in &hosts {
+            let host_info = info.get(addr).copied().unwrap_or_default();
+            let (identity, checked_at) = match verifications.get(addr) {
+                Some(v) => (v.identity.map(|k| bs58::encode(k).into_string()), v.checked_at),
+                None => (None, 0),
+            };
+            tsv.push_str(&format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                tier.as_str(),
+                addr.scheme(),
+                addr.as_str(),
+                host_info.last_seen,
+                host_info.handshakes,
+                host_info.failures,
+                identity.as_deref().unwrap_or("-"),
+                checked_at,
+            ));
         }
 
-        // Grab random ones
-        let urls = hosts.iter().choose_multiple(&mut OsRng, n.min(hosts.len()));
-        urls.iter().map(|&url| url.clone()).collect()
+        info!(target: "net::hosts::save_hosts()", "Saving {} hosts to {:?}", hosts.len(), path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        save_file(path, &tsv)
     }
 
     /// Get up to limit peers that match the given transport schemes from the hosts set.
     /// If limit was not provided, return all matching peers.
     pub async fn fetch_with_schemes(&self, schemes: &[String], limit: Option<usize>) -> Vec<Url> {
-        let addrs = self.addrs.read().await;
+        let addrs = self.fetch_all().await;
         let mut limit = match limit {
             Some(l) => l.min(addrs.len()),
             None => addrs.len(),
@@ -368,6 +771,17 @@ impl Hosts {
         ret
     }
 
+    /// Return the quarantined hosts that match the given transport schemes.
+    pub async fn fetch_quarantine_with_schemes(&self, schemes: &[String]) -> Vec<Url> {
+        self.quarantine
+            .read()
+            .await
+            .keys()
+            .filter(|addr| schemes.contains(&addr.scheme().to_string()))
+            .cloned()
+            .collect()
+    }
+
     /// Get up to limit peers that don't match the given transport schemes from the hosts set.
     /// If limit was not provided, return all matching peers.
     pub async fn fetch_exluding_schemes(
@@ -375,7 +789,7 @@ impl Hosts {
         schemes: &[String],
         limit: Option<usize>,
     ) -> Vec<Url> {
-        let addrs = self.addrs.read().await;
+        let addrs = self.fetch_all().await;
         let mut limit = match limit {
             Some(l) => l.min(addrs.len()),
             None => addrs.len(),
@@ -498,6 +912,117 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_hostlist_persistence() {
+        smol::block_on(async {
+            let settings = Settings { localnet: false, ..Default::default() };
+            let hosts = Hosts::new(Arc::new(settings.clone()));
+
+            let good = Url::parse("tcp://dark.fi:80").unwrap();
+            let bad = Url::parse("tcp+tls://http.cat:401").unwrap();
+            hosts.store(&[good.clone(), bad.clone()]).await;
+
+            hosts.record_handshake(&good).await;
+            hosts.record_handshake(&good).await;
+            hosts.anchorlist_store(&good).await;
+            hosts.quarantine(&bad).await;
+            hosts.store(&[bad.clone()]).await;
+            hosts.record_verification(&good, Some([1u8; NOISE_KEY_LEN])).await;
+            hosts.record_verification(&bad, None).await;
+
+            let good_info = hosts.host_info(&good).await.unwrap();
+            let bad_info = hosts.host_info(&bad).await.unwrap();
+            assert_eq!(good_info.handshakes, 2);
+            assert_eq!(bad_info.failures, 1);
+            assert!(good_info.score() > bad_info.score());
+
+            let path = std::env::temp_dir().join("darkfi_test_hostlist.tsv");
+            hosts.save_hosts(&path).await.unwrap();
+
+            let loaded = Hosts::new(Arc::new(settings));
+            loaded.load_hosts(&path).await.unwrap();
+            let _ = std::fs::remove_file(&path);
+
+            assert!(loaded.contains(&good).await);
+            assert!(loaded.contains(&bad).await);
+            assert_eq!(loaded.host_info(&good).await.unwrap(), good_info);
+            assert_eq!(loaded.host_info(&bad).await.unwrap(), bad_info);
+            assert_eq!(loaded.tier(&good).await, Some(HostTier::Anchor));
+            assert_eq!(loaded.tier(&bad).await, Some(HostTier::Grey));
+            assert_eq!(loaded.verification(&good).await, hosts.verification(&good).await);
+            assert_eq!(loaded.verification(&bad).await.unwrap().identity, None);
+        });
+    }
+
+    #[test]
+    fn test_host_tiers() {
+        smol::block_on(async {
+            let settings = Settings { localnet: false, ..Default::default() };
+            let hosts = Hosts::new(Arc::new(settings));
+
+            let grey = Url::parse("tcp://dark.fi:80").unwrap();
+            let white = Url::parse("tcp://http.cat:401").unwrap();
+            let anchor = Url::parse("tcp://top.kek:111").unwrap();
+            hosts.store(&[grey.clone(), white.clone(), anchor.clone()]).await;
+            assert_eq!(hosts.fetch_tier(HostTier::Grey).await.len(), 3);
+
+            hosts.whitelist_store(&white).await;
+            hosts.whitelist_store(&anchor).await;
+            hosts.anchorlist_store(&anchor).await;
+            assert_eq!(hosts.tier(&grey).await, Some(HostTier::Grey));
+            assert_eq!(hosts.tier(&white).await, Some(HostTier::White));
+            assert_eq!(hosts.tier(&anchor).await, Some(HostTier::Anchor));
+
+            // Gossip must not demote verified peers back to the greylist
+            hosts.store(&[white.clone(), anchor.clone()]).await;
+            assert_eq!(hosts.tier(&white).await, Some(HostTier::White));
+            assert_eq!(hosts.tier(&anchor).await, Some(HostTier::Anchor));
+
+            // Whitelisting an anchor does not demote it either
+            hosts.whitelist_store(&anchor).await;
+            assert_eq!(hosts.tier(&anchor).await, Some(HostTier::Anchor));
+
+            // Verified peers are picked before greylisted ones
+            let picked = hosts.fetch_n_random(2).await;
+            assert_eq!(picked.len(), 2);
+            assert!(!picked.contains(&grey));
+            assert_eq!(hosts.fetch_n_random(5).await.len(), 3);
+
+            hosts.remove(&anchor).await;
+            assert!(!hosts.contains(&anchor).await);
+        });
+    }
+
+    #[test]
+    fn test_tier_caps() {
+        smol::block_on(async {
+            let settings = Settings { localnet: false, ..Default::default() };
+            let hosts = Hosts::new(Arc::new(settings));
+
+            let addr = |i: usize| {
+                Url::parse(&format!("tcp://1.{}.{}.{}:80", i >> 16, (i >> 8) & 0xff, i & 0xff))
+                    .unwrap()
+            };
+
+            // Flooding the greylist doesn't grow it past its limit
+            let addrs: Vec<Url> = (0..GREYLIST_MAX_LEN + 10).map(addr).collect();
+            hosts.store(&addrs).await;
+            assert_eq!(hosts.fetch_tier(HostTier::Grey).await.len(), GREYLIST_MAX_LEN);
+
+            // Anchors with a good connection history are kept when the
+            // anchorlist is full
+            let good = addr(1 << 20);
+            hosts.anchorlist_store(&good).await;
+            hosts.record_handshake(&good).await;
+            for i in 0..ANCHORLIST_MAX_LEN + 10 {
+                hosts.anchorlist_store(&addr(i)).await;
+            }
+            let anchors = hosts.fetch_tier(HostTier::Anchor).await;
+            assert_eq!(anchors.len(), ANCHORLIST_MAX_LEN);
+            assert!(anchors.contains(&good));
+        });
+    }
+
     #[test]
     fn test_is_local_host() {
         smol::block_on(async {
@@ -534,4 +1059,23 @@ mod tests {
             }
         });
     }
+
+    #[test]
+    fn test_verification_cache() {
+        smol::block_on(async {
+            let url = Url::parse("tcp://dark.fi:26661").unwrap();
+            let key = [1u8; NOISE_KEY_LEN];
+
+            let hosts = Hosts::new(Arc::new(Settings::default()));
+            assert!(hosts.verification(&url).await.is_none());
+            hosts.record_verification(&url, Some(key)).await;
+            assert_eq!(hosts.verification(&url).await.unwrap().identity, Some(key));
+
+            // Expired entries are dropped
+            let settings = Settings { addr_verification_ttl: 0, ..Default::default() };
+            let hosts = Hosts::new(Arc::new(settings));
+            hosts.record_verification(&url, None).await;
+            assert!(hosts.verification(&url).await.is_none());
+        });
+    }
 }
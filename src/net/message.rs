@@ -24,13 +24,25 @@ use log::trace;
 use smol::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use url::Url;
 
+use super::{mux::Priority, transport::NOISE_KEY_LEN};
 use crate::{Error, Result};
 
 const MAGIC_BYTES: [u8; 4] = [0xd9, 0xef, 0xb6, 0x7d];
 
+/// Default maximum size of an inbound packet payload (8 MiB)
+pub const MAX_PACKET_SIZE: usize = 8 * 1024 * 1024;
+
+/// Maximum length of a packet command
+pub const MAX_COMMAND_LEN: usize = 256;
+
 /// Generic message template.
 pub trait Message: 'static + Send + Sync + Encodable + Decodable {
     const NAME: &'static str;
+    /// Maximum serialized size of the message. Packets carrying this
+    /// message are additionally bounded by the global packet size limit.
+    const MAX_BYTES: usize = usize::MAX;
+    /// Priority of the substream the message is sent over
+    const PRIORITY: Priority = Priority::Normal;
 }
 
 #[macro_export]
@@ -40,6 +52,19 @@ macro_rules! impl_p2p_message {
             const NAME: &'static str = $nm;
         }
     };
+    ($st:ty, $nm:expr, $max:expr) => {
+        impl Message for $st {
+            const NAME: &'static str = $nm;
+            const MAX_BYTES: usize = $max;
+        }
+    };
+    ($st:ty, $nm:expr, $max:expr, $prio:ident) => {
+        impl Message for $st {
+            const NAME: &'static str = $nm;
+            const MAX_BYTES: usize = $max;
+            const PRIORITY: $crate::net::mux::Priority = $crate::net::mux::Priority::$prio;
+        }
+    };
 }
 
 /// Outbound keepalive message.
@@ -47,14 +72,14 @@ macro_rules! impl_p2p_message {
 pub struct PingMessage {
     pub nonce: u16,
 }
-impl_p2p_message!(PingMessage, "ping");
+impl_p2p_message!(PingMessage, "ping", 8, High);
 
 /// Inbound keepalive message.
 #[derive(Debug, Copy, Clone, SerialEncodable, SerialDecodable)]
 pub struct PongMessage {
     pub nonce: u16,
 }
-impl_p2p_message!(PongMessage, "pong");
+impl_p2p_message!(PongMessage, "pong", 8, High);
 
 /// Requests address of outbound connecction.
 #[derive(Debug, Clone, SerialEncodable, SerialDecodable)]
@@ -67,7 +92,7 @@ pub struct GetAddrsMessage {
     /// Preferred addresses transports
     pub transports: Vec<String>,
 }
-impl_p2p_message!(GetAddrsMessage, "getaddr");
+impl_p2p_message!(GetAddrsMessage, "getaddr", 4096);
 
 /// Sends address information to inbound connection.
 /// Response to `GetAddrsMessage`.
@@ -77,13 +102,29 @@ pub struct AddrsMessage {
 }
 impl_p2p_message!(AddrsMessage, "addr");
 
+/// Advertises the external addresses of the sending node itself.
+/// These are verified with a ping-back before being stored.
+#[derive(Debug, Clone, SerialEncodable, SerialDecodable)]
+pub struct ExtAddrsMessage {
+    pub addrs: Vec<Url>,
+}
+impl_p2p_message!(ExtAddrsMessage, "extaddr", 4096);
+
 /// Requests version information of outbound connection.
+/// Named `version2` since it gained the static key and capabilities, so
+/// older nodes ignore it instead of failing to decode it, and the
+/// handshake with them times out.
 #[derive(Debug, Clone, SerialEncodable, SerialDecodable)]
 pub struct VersionMessage {
     /// Only used for debugging. Compromises privacy when set.
     pub node_id: String,
+    /// Static Noise key of the sender. Authenticated by the transport
+    /// on Noise connections, and only a claim on any other transport.
+    pub static_key: [u8; NOISE_KEY_LEN],
+    /// Capabilities of the protocols the sender supports
+    pub capabilities: Vec<String>,
 }
-impl_p2p_message!(VersionMessage, "version");
+impl_p2p_message!(VersionMessage, "version2", 4096, High);
 
 /// Sends version information to inbound connection.
 /// Response to `VersionMessage`.
@@ -92,7 +133,7 @@ pub struct VerackMessage {
     /// App version
     pub app_version: semver::Version,
 }
-impl_p2p_message!(VerackMessage, "verack");
+impl_p2p_message!(VerackMessage, "verack", 1024, High);
 
 /// Packets are the base type read from the network.
 /// Converted to messages and passed to event loop.
@@ -103,8 +144,20 @@ pub struct Packet {
 }
 
 /// Reads and decodes an inbound payload from the given async stream.
+/// Both the command and the payload are bounded by `max_size`.
 /// Returns decoded [`Packet`].
-pub async fn read_packet<R: AsyncRead + Unpin + Send + Sized>(stream: &mut R) -> Result<Packet> {
+pub async fn read_packet<R: AsyncRead + Unpin + Send + Sized>(
+    stream: &mut R,
+    max_size: usize,
+) -> Result<Packet> {
+    let command = read_command(stream).await?;
+    let payload = read_payload(stream, max_size).await?;
+    Ok(Packet { command, payload })
+}
+
+/// Reads the packet header and command from the given async stream.
+/// Must be followed by [`read_payload`] to read the rest of the packet.
+pub async fn read_command<R: AsyncRead + Unpin + Send + Sized>(stream: &mut R) -> Result<String> {
     // Packets should have a 4 byte header of magic digits.
     // This is used for network debugging.
     let mut magic = [0u8; 4];
@@ -118,19 +171,41 @@ pub async fn read_packet<R: AsyncRead + Unpin + Send + Sized>(stream: &mut R) ->
     }
 
     // The type of the message.
-    let command_len = VarInt::decode_async(stream).await?.0 as usize;
-    let mut cmd = vec![0u8; command_len];
+    let command_len = VarInt::decode_async(stream).await?.0;
+    if command_len > MAX_COMMAND_LEN as u64 {
+        trace!(target: "net::message", "Error: Command length {} too large", command_len);
+        return Err(Error::PacketTooLarge(command_len))
+    }
+
+    let mut cmd = vec![0u8; command_len as usize];
     stream.read_exact(&mut cmd).await?;
     let command = String::from_utf8(cmd)?;
     trace!(target: "net::message", "Read command: {}", command);
 
+    Ok(command)
+}
+
+/// Reads the packet payload from the given async stream. The declared
+/// length is checked against `max_size` before anything is allocated.
+pub async fn read_payload<R: AsyncRead + Unpin + Send + Sized>(
+    stream: &mut R,
+    max_size: usize,
+) -> Result<Vec<u8>> {
     // The message-dependent data (see message types)
-    let payload_len = VarInt::decode_async(stream).await?.0 as usize;
-    let mut payload = vec![0u8; payload_len];
+    let payload_len = VarInt::decode_async(stream).await?.0;
+    if payload_len > max_size as u64 {
+        trace!(
+            target: "net::message",
+            "Error: Payload length {} exceeds limit {}", payload_len, max_size,
+        );
+        return Err(Error::PacketTooLarge(payload_len))
+    }
+
+    let mut payload = vec![0u8; payload_len as usize];
     stream.read_exact(&mut payload).await?;
     trace!(target: "net::message", "Read payload {} bytes", payload_len);
 
-    Ok(Packet { command, payload })
+    Ok(payload)
 }
 
 /// Sends an outbound packet by writing data to the given async stream.
@@ -167,3 +242,39 @@ pub async fn send_packet<W: AsyncWrite + Unpin + Send + Sized>(
 
     Ok(written)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use darkfi_serial::serialize;
+    use smol::io::Cursor;
+
+    #[test]
+    fn test_packet_size_limits() {
+        smol::block_on(async {
+            let packet = Packet { command: "ping".to_string(), payload: vec![0u8; 32] };
+            let mut buf = vec![];
+            send_packet(&mut buf, packet).await.unwrap();
+
+            let packet = read_packet(&mut Cursor::new(buf.clone()), 32).await.unwrap();
+            assert_eq!(packet.command, "ping");
+            assert_eq!(packet.payload.len(), 32);
+
+            let err = read_packet(&mut Cursor::new(buf), 31).await.unwrap_err();
+            assert!(matches!(err, Error::PacketTooLarge(32)));
+
+            // A huge declared length must be rejected before allocating
+            let mut buf = MAGIC_BYTES.to_vec();
+            buf.extend_from_slice(&serialize(&VarInt(4)));
+            buf.extend_from_slice(b"ping");
+            buf.extend_from_slice(&serialize(&VarInt(u64::MAX)));
+            let err = read_packet(&mut Cursor::new(buf), MAX_PACKET_SIZE).await.unwrap_err();
+            assert!(matches!(err, Error::PacketTooLarge(u64::MAX)));
+
+            let mut buf = MAGIC_BYTES.to_vec();
+            buf.extend_from_slice(&serialize(&VarInt(MAX_COMMAND_LEN as u64 + 1)));
+            let err = read_command(&mut Cursor::new(buf)).await.unwrap_err();
+            assert!(matches!(err, Error::PacketTooLarge(_)));
+        });
+    }
+}
@@ -157,6 +157,8 @@ trait MessageDispatcherInterface: Send + Sync {
 
     async fn trigger_error(&self, err: Error);
 
+    fn max_bytes(&self) -> usize;
+
     fn as_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync>;
 }
 
@@ -189,6 +191,11 @@ impl<M: Message> MessageDispatcherInterface for MessageDispatcher<M> {
         self._trigger_all(Err(err)).await;
     }
 
+    /// Returns the maximum serialized size of the dispatched message.
+    fn max_bytes(&self) -> usize {
+        M::MAX_BYTES
+    }
+
     /// Converts to `Any` trait. Enables the dynamic modification of static types.
     fn as_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
         self
@@ -255,6 +262,12 @@ impl MessageSubsystem {
         Ok(())
     }
 
+    /// Returns the maximum payload size allowed for the given command,
+    /// or `None` if there is no dispatcher registered for it.
+    pub async fn max_bytes(&self, command: &str) -> Option<usize> {
+        self.dispatchers.lock().await.get(command).map(|d| d.max_bytes())
+    }
+
     /// Concurrently transmits an error message across dispatchers.
     pub async fn trigger_error(&self, err: Error) {
         let mut futures = FuturesUnordered::new();
@@ -282,7 +295,7 @@ mod tests {
     fn message_subscriber_test() {
         #[derive(SerialEncodable, SerialDecodable)]
         struct MyVersionMessage(pub u32);
-        crate::impl_p2p_message!(MyVersionMessage, "verver");
+        crate::impl_p2p_message!(MyVersionMessage, "verver", 4);
 
         smol::block_on(async {
             let subsystem = MessageSubsystem::new();
@@ -294,6 +307,9 @@ mod tests {
             // 3. Do sub, return sub
             let sub = subsystem.subscribe::<MyVersionMessage>().await.unwrap();
 
+            assert_eq!(subsystem.max_bytes("verver").await, Some(4));
+            assert_eq!(subsystem.max_bytes("unknown").await, None);
+
             // Receive message and publish:
             // 1. Based on string, lookup relevant dispatcher interface
             // 2. Publish data there
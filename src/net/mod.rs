@@ -62,6 +62,10 @@ pub mod hosts;
 pub mod channel;
 pub use channel::ChannelPtr;
 
+/// Multiplexing of messages into prioritised logical substreams over a
+/// single channel, so that bulk transfers don't hold back control traffic.
+pub mod mux;
+
 /// P2P provides all core functionality to interact with the P2P network.
 ///
 /// Used to create a network, to start and run it, to broadcast messages
@@ -112,6 +116,10 @@ pub mod acceptor;
 /// Used to establish an outbound connection.
 pub mod connector;
 
+/// Token bucket rate limiting used by channels to protect against peers
+/// flooding us with messages or bytes.
+pub mod rate_limit;
+
 /// Network configuration settings. This holds the configured P2P instance
 /// behaviour and is controlled by clients of this API.
 pub mod settings;
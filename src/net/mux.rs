@@ -0,0 +1,352 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::collections::VecDeque;
+
+use darkfi_serial::VarInt;
+use smol::{
+    channel::Sender,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+};
+
+use super::message::MAX_COMMAND_LEN;
+use crate::{Error, Result};
+
+/// Maximum amount of packet data carried by a single frame
+pub const MAX_FRAME_LEN: usize = 16384;
+
+/// Upper bound of the packet header: magic, command and two `VarInt`s
+const MAX_PACKET_HEADER_LEN: usize = 4 + 9 + MAX_COMMAND_LEN + 9;
+
+/// Frame flag marking the last frame of a packet
+const FLAG_END: u8 = 0x01;
+
+/// Priority of a logical substream. Frames of higher priority substreams
+/// are always written first, so small control messages don't wait behind
+/// bulk transfers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Handshakes and keepalives
+    High = 0,
+    /// Regular protocol traffic
+    Normal = 1,
+    /// Large transfers, e.g. sync responses and file chunks
+    Bulk = 2,
+}
+
+impl Priority {
+    /// All priorities, from highest to lowest
+    pub const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Bulk];
+
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::High),
+            1 => Some(Self::Normal),
+            2 => Some(Self::Bulk),
+            _ => None,
+        }
+    }
+}
+
+/// Part of a packet sent over one of the substreams
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub substream: Priority,
+    pub end: bool,
+    pub data: Vec<u8>,
+}
+
+/// Writes a frame to the given async stream. Fails if the frame carries
+/// more than [`MAX_FRAME_LEN`] bytes.
+pub async fn write_frame<W: AsyncWrite + Unpin + Send + Sized>(
+    stream: &mut W,
+    frame: &Frame,
+) -> Result<()> {
+    if frame.data.len() > MAX_FRAME_LEN {
+        return Err(Error::PacketTooLarge(frame.data.len() as u64))
+    }
+
+    let flags = if frame.end { FLAG_END } else { 0 };
+    stream.write_all(&[frame.substream as u8, flags]).await?;
+    stream.write_all(&(frame.data.len() as u16).to_be_bytes()).await?;
+    stream.write_all(&frame.data).await?;
+    stream.flush().await?;
+
+    Ok(())
+}
+
+/// Reads a frame from the given async stream
+pub async fn read_frame<R: AsyncRead + Unpin + Send + Sized>(stream: &mut R) -> Result<Frame> {
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header).await?;
+
+    let Some(substream) = Priority::from_u8(header[0]) else { return Err(Error::MalformedPacket) };
+    let end = header[1] & FLAG_END != 0;
+
+    let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(Error::MalformedPacket)
+    }
+
+    let mut data = vec![0u8; len];
+    stream.read_exact(&mut data).await?;
+
+    Ok(Frame { substream, end, data })
+}
+
+/// A serialized packet waiting to be written
+struct PendingPacket {
+    data: Vec<u8>,
+    /// Amount of `data` already put into frames
+    pos: usize,
+    /// Notified once the last frame has been written
+    done: Sender<Result<()>>,
+}
+
+/// Outbound queues of serialized packets, one per substream.
+/// Packets in the same substream are written in order, one after another.
+#[derive(Default)]
+pub struct SendQueues {
+    queues: [VecDeque<PendingPacket>; 3],
+    closed: bool,
+}
+
+impl SendQueues {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a serialized packet on the given substream. `done` is notified
+    /// once it has been written out. Fails if the queues were closed.
+    pub fn push(
+        &mut self,
+        priority: Priority,
+        data: Vec<u8>,
+        done: Sender<Result<()>>,
+    ) -> Result<()> {
+        if self.closed {
+            return Err(Error::ChannelStopped)
+        }
+
+        self.queues[priority as usize].push_back(PendingPacket { data, pos: 0, done });
+        Ok(())
+    }
+
+    /// Take the next frame to write from the highest priority substream
+    /// with pending data. Along with the last frame of a packet, the
+    /// packet's completion notifier is returned.
+    pub fn next_frame(&mut self) -> Option<(Frame, Option<Sender<Result<()>>>)> {
+        for priority in Priority::ALL {
+            let queue = &mut self.queues[priority as usize];
+            let Some(packet) = queue.front_mut() else { continue };
+
+            let end = (packet.pos + MAX_FRAME_LEN).min(packet.data.len());
+            let data = packet.data[packet.pos..end].to_vec();
+            packet.pos = end;
+
+            if end < packet.data.len() {
+                return Some((Frame { substream: priority, end: false, data }, None))
+            }
+
+            let packet = queue.pop_front().unwrap();
+            return Some((Frame { substream: priority, end: true, data }, Some(packet.done)))
+        }
+
+        None
+    }
+
+    /// Drop all queued packets and refuse new ones.
+    /// Waiting senders get notified by their channel getting closed.
+    pub fn close(&mut self) {
+        self.closed = true;
+        for queue in self.queues.iter_mut() {
+            queue.clear();
+        }
+    }
+}
+
+/// Reassembles inbound frames into serialized packets, separately
+/// for each substream.
+pub struct Demuxer {
+    bufs: [Vec<u8>; 3],
+    /// Size limits of the packets being reassembled, once known
+    limits: [Option<usize>; 3],
+    max_len: usize,
+}
+
+impl Demuxer {
+    /// Create a new demuxer accepting packet payloads of up to `max_size` bytes
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            bufs: Default::default(),
+            limits: Default::default(),
+            max_len: max_size.saturating_add(MAX_PACKET_HEADER_LEN),
+        }
+    }
+
+    /// Add a frame to its substream. Returns the serialized packet once
+    /// its last frame has arrived.
+    pub fn push(&mut self, frame: Frame) -> Result<Option<Vec<u8>>> {
+        let substream = frame.substream as usize;
+        let buf = &mut self.bufs[substream];
+
+        let len = buf.len() + frame.data.len();
+        if len > self.limits[substream].unwrap_or(self.max_len) {
+            return Err(Error::PacketTooLarge(len as u64))
+        }
+
+        buf.extend_from_slice(&frame.data);
+
+        if !frame.end {
+            return Ok(None)
+        }
+
+        self.limits[substream] = None;
+        Ok(Some(std::mem::take(buf)))
+    }
+
+    /// Returns the partial packet reassembled so far on the given substream,
+    /// unless it was already bounded with [`Demuxer::bound`].
+    pub fn unbounded(&self, substream: Priority) -> Option<&[u8]> {
+        match self.limits[substream as usize] {
+            Some(_) => None,
+            None => Some(&self.bufs[substream as usize]),
+        }
+    }
+
+    /// Bound the payload of the packet being reassembled on the given
+    /// substream to `max_size` bytes, e.g. the size limit of its message
+    /// type. Fails if the packet already exceeds it.
+    pub fn bound(&mut self, substream: Priority, max_size: usize) -> Result<()> {
+        let limit = max_size.saturating_add(MAX_PACKET_HEADER_LEN).min(self.max_len);
+        self.limits[substream as usize] = Some(limit);
+
+        let len = self.bufs[substream as usize].len();
+        if len > limit {
+            return Err(Error::PacketTooLarge(len as u64))
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_send_queue_priorities() {
+        let mut queues = SendQueues::new();
+        let (done, _recv) = smol::channel::unbounded();
+
+        // A bulk packet spanning three frames
+        queues.push(Priority::Bulk, vec![2u8; MAX_FRAME_LEN * 2 + 1], done.clone()).unwrap();
+
+        let (frame, notify) = queues.next_frame().unwrap();
+        assert_eq!(frame.substream, Priority::Bulk);
+        assert!(!frame.end && notify.is_none());
+
+        // A high priority packet queued mid-transfer goes out next
+        queues.push(Priority::High, vec![0u8; 8], done.clone()).unwrap();
+        let (frame, notify) = queues.next_frame().unwrap();
+        assert_eq!(frame.substream, Priority::High);
+        assert!(frame.end && notify.is_some());
+
+        let (frame, _) = queues.next_frame().unwrap();
+        assert_eq!(frame.substream, Priority::Bulk);
+        assert!(!frame.end);
+
+        let (frame, notify) = queues.next_frame().unwrap();
+        assert_eq!(frame.substream, Priority::Bulk);
+        assert_eq!(frame.data.len(), 1);
+        assert!(frame.end && notify.is_some());
+
+        assert!(queues.next_frame().is_none());
+
+        // Closing drops everything queued and refuses new packets
+        queues.push(Priority::Normal, vec![1u8; 8], done).unwrap();
+        queues.close();
+        assert!(queues.next_frame().is_none());
+        let (done, _) = smol::channel::unbounded();
+        assert!(queues.push(Priority::Normal, vec![1u8; 8], done).is_err());
+    }
+
+    #[test]
+    fn test_demuxer() {
+        smol::block_on(async {
+            let mut queues = SendQueues::new();
+            let (done, _recv) = smol::channel::unbounded();
+
+            let bulk: Vec<u8> = (0..MAX_FRAME_LEN * 3).map(|i| i as u8).collect();
+            queues.push(Priority::Bulk, bulk.clone(), done.clone()).unwrap();
+            queues.push(Priority::High, vec![7u8; 16], done).unwrap();
+
+            // Interleave the substreams on the wire
+            let mut wire = vec![];
+            let (frame, _) = queues.next_frame().unwrap();
+            write_frame(&mut wire, &frame).await.unwrap();
+            while let Some((frame, _)) = queues.next_frame() {
+                write_frame(&mut wire, &frame).await.unwrap();
+            }
+
+            let mut demuxer = Demuxer::new(MAX_FRAME_LEN * 3);
+            let mut cursor = smol::io::Cursor::new(wire);
+            let mut packets = vec![];
+            while packets.len() < 2 {
+                let frame = read_frame(&mut cursor).await.unwrap();
+                if let Some(packet) = demuxer.push(frame).unwrap() {
+                    packets.push(packet);
+                }
+            }
+
+            assert_eq!(packets[0], vec![7u8; 16]);
+            assert_eq!(packets[1], bulk);
+
+            // Packets exceeding the limit are refused while reassembling
+            let mut demuxer = Demuxer::new(0);
+            let frame = Frame { substream: Priority::Normal, end: false, data: vec![0u8; 4096] };
+            assert!(matches!(demuxer.push(frame), Err(Error::PacketTooLarge(_))));
+
+            // Bounded packets are refused as soon as they exceed the bound
+            let mut demuxer = Demuxer::new(MAX_FRAME_LEN * 3);
+            let frame = Frame { substream: Priority::Bulk, end: false, data: vec![0u8; 256] };
+            demuxer.push(frame.clone()).unwrap();
+            assert_eq!(demuxer.unbounded(Priority::Bulk).unwrap().len(), 256);
+            demuxer.bound(Priority::Bulk, 0).unwrap();
+            assert!(demuxer.unbounded(Priority::Bulk).is_none());
+            assert!(matches!(demuxer.push(frame), Err(Error::PacketTooLarge(_))));
+
+            // The bound only applies to the current packet of its substream
+            let frame = Frame { substream: Priority::Normal, end: true, data: vec![0u8; 16] };
+            demuxer.bound(Priority::Normal, 0).unwrap();
+            assert!(demuxer.push(frame).unwrap().is_some());
+            assert!(demuxer.unbounded(Priority::Normal).is_some());
+
+            // Oversized frames are refused on write
+            let frame = Frame {
+                substream: Priority::Normal,
+                end: true,
+                data: vec![0u8; MAX_FRAME_LEN + 1],
+            };
+            assert!(matches!(
+                write_frame(&mut vec![], &frame).await,
+                Err(Error::PacketTooLarge(_))
+            ));
+        });
+    }
+}
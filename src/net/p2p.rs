@@ -38,9 +38,11 @@ use super::{
         OutboundSessionPtr, SeedSyncSession,
     },
     settings::{Settings, SettingsPtr},
+    transport::NoiseKeypair,
 };
 use crate::{
     system::{ExecutorPtr, Subscriber, SubscriberPtr, Subscription},
+    util::path::expand_path,
     Result,
 };
 
@@ -67,6 +69,8 @@ pub struct P2p {
     protocol_registry: ProtocolRegistry,
     /// P2P network settings
     settings: SettingsPtr,
+    /// Static keypair identifying this node on Noise transports
+    noise_keypair: Arc<NoiseKeypair>,
     /// Boolean lock marking if peer discovery is active
     pub peer_discovery_running: Mutex<bool>,
 
@@ -94,6 +98,7 @@ impl P2p {
     /// p2p parent class.
     pub async fn new(settings: Settings, executor: ExecutorPtr) -> P2pPtr {
         let settings = Arc::new(settings);
+        let noise_keypair = Arc::new(Self::load_noise_keypair(&settings));
 
         let self_ = Arc::new(Self {
             executor,
@@ -103,6 +108,7 @@ impl P2p {
             hosts: Hosts::new(settings.clone()),
             protocol_registry: ProtocolRegistry::new(),
             settings,
+            noise_keypair,
             peer_discovery_running: Mutex::new(false),
 
             session_manual: ManualSession::new(),
@@ -127,6 +133,14 @@ impl P2p {
         debug!(target: "net::p2p::start()", "P2P::start() [BEGIN]");
         info!(target: "net::p2p::start()", "[P2P] Starting P2P subsystem");
 
+        // Restore known hosts from a previous run
+        if let Some(ref hostlist) = self.settings.hostlist {
+            let path = expand_path(hostlist)?;
+            if let Err(e) = self.hosts.load_hosts(&path).await {
+                warn!(target: "net::p2p::start()", "[P2P] Failed loading hostlist {:?}: {}", path, e);
+            }
+        }
+
         // First attempt any set manual connections
         for peer in &self.settings.peers {
             self.session_manual().connect(peer.clone()).await;
@@ -166,6 +180,21 @@ impl P2p {
         self.session_manual().stop().await;
         self.session_inbound().stop().await;
         self.session_outbound().stop().await;
+
+        // Persist known hosts for the next run
+        if let Some(ref hostlist) = self.settings.hostlist {
+            let path = match expand_path(hostlist) {
+                Ok(p) => p,
+                Err(e) => {
+                    error!(target: "net::p2p::stop()", "[P2P] Invalid hostlist path: {}", e);
+                    return
+                }
+            };
+
+            if let Err(e) = self.hosts.save_hosts(&path).await {
+                error!(target: "net::p2p::stop()", "[P2P] Failed saving hostlist {:?}: {}", path, e);
+            }
+        }
     }
 
     /// Broadcasts a message concurrently across all active channels.
@@ -264,6 +293,28 @@ impl P2p {
         self.hosts.clone()
     }
 
+    /// Return the static keypair identifying this node on Noise transports
+    pub fn noise_keypair(&self) -> Arc<NoiseKeypair> {
+        self.noise_keypair.clone()
+    }
+
+    /// Load the configured Noise keypair, falling back to an ephemeral one
+    fn load_noise_keypair(settings: &Settings) -> NoiseKeypair {
+        let Some(ref keyfile) = settings.noise_keyfile else { return NoiseKeypair::generate() };
+
+        match expand_path(keyfile).and_then(|path| NoiseKeypair::load_or_create(&path)) {
+            Ok(keypair) => keypair,
+            Err(e) => {
+                error!(
+                    target: "net::p2p::load_noise_keypair()",
+                    "[P2P] Failed loading Noise keyfile {}, using an ephemeral key: {}",
+                    keyfile, e,
+                );
+                NoiseKeypair::generate()
+            }
+        }
+    }
+
     /// Reference the global executor
     pub fn executor(&self) -> ExecutorPtr {
         self.executor.clone()
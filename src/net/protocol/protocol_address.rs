@@ -16,21 +16,24 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Instant};
 
 use async_trait::async_trait;
+use futures::future::join_all;
 use log::debug;
 use smol::Executor;
+use url::Url;
 
 use super::{
     super::{
         channel::ChannelPtr,
         hosts::HostsPtr,
-        message::{AddrsMessage, GetAddrsMessage},
+        message::{AddrsMessage, ExtAddrsMessage, GetAddrsMessage},
         message_subscriber::MessageSubscription,
         p2p::P2pPtr,
         session::SESSION_OUTBOUND,
         settings::SettingsPtr,
+        transport::NOISE_KEY_LEN,
     },
     protocol_base::{ProtocolBase, ProtocolBasePtr},
     protocol_jobs_manager::{ProtocolJobsManager, ProtocolJobsManagerPtr},
@@ -41,6 +44,7 @@ use crate::{system::sleep, Result};
 pub struct ProtocolAddress {
     channel: ChannelPtr,
     addrs_sub: MessageSubscription<AddrsMessage>,
+    ext_addrs_sub: MessageSubscription<ExtAddrsMessage>,
     get_addrs_sub: MessageSubscription<GetAddrsMessage>,
     hosts: HostsPtr,
     settings: SettingsPtr,
@@ -49,6 +53,13 @@ pub struct ProtocolAddress {
 
 const PROTO_NAME: &str = "ProtocolAddress";
 
+/// Maximum number of self-advertised addresses we verify per message
+const MAX_EXT_ADDRS: usize = 8;
+
+/// Minimum time between external address messages we handle from a
+/// channel (in seconds). Peers send theirs every 15 minutes.
+const EXT_ADDRS_INTERVAL: u64 = 300;
+
 impl ProtocolAddress {
     /// Creates a new address protocol. Makes an address, an external address
     /// and a get-address subscription and adds them to the address protocol
@@ -61,6 +72,10 @@ impl ProtocolAddress {
         let addrs_sub =
             channel.subscribe_msg::<AddrsMessage>().await.expect("Missing addrs dispatcher!");
 
+        // Creates a subscription to external address message
+        let ext_addrs_sub =
+            channel.subscribe_msg::<ExtAddrsMessage>().await.expect("Missing extaddrs dispatcher!");
+
         // Creates a subscription to get-address message
         let get_addrs_sub =
             channel.subscribe_msg::<GetAddrsMessage>().await.expect("Missing getaddrs dispatcher!");
@@ -68,6 +83,7 @@ impl ProtocolAddress {
         Arc::new(Self {
             channel: channel.clone(),
             addrs_sub,
+            ext_addrs_sub,
             get_addrs_sub,
             hosts,
             jobsman: ProtocolJobsManager::new(PROTO_NAME, channel),
@@ -97,6 +113,82 @@ impl ProtocolAddress {
         }
     }
 
+    /// Handles receiving the external addresses the peer advertises as its
+    /// own. Each address is verified with a ping-back probe: the node
+    /// answering there must have the same identity as the advertising peer.
+    /// Noise transports authenticate that identity, on other transports it
+    /// is only claimed, but the probe still shows the address reaches a
+    /// node presenting it.
+    async fn handle_receive_ext_addrs(self: Arc<Self>) -> Result<()> {
+        debug!(
+            target: "net::protocol_address::handle_receive_ext_addrs()",
+            "[START] address={}", self.channel.address(),
+        );
+
+        let mut last_received: Option<Instant> = None;
+
+        loop {
+            let ext_addrs_msg = self.ext_addrs_sub.receive().await?;
+            debug!(
+                target: "net::protocol_address::handle_receive_ext_addrs()",
+                "Received {} external addrs from {}",
+                ext_addrs_msg.addrs.len(), self.channel.address(),
+            );
+
+            // Every message can make us dial out, so don't let a peer
+            // send them faster than it's supposed to.
+            if let Some(last) = last_received {
+                if last.elapsed().as_secs() < EXT_ADDRS_INTERVAL {
+                    debug!(
+                        target: "net::protocol_address::handle_receive_ext_addrs()",
+                        "[P2P] Ignoring external addrs from {}, sent too soon",
+                        self.channel.address(),
+                    );
+                    continue
+                }
+            }
+            last_received = Some(Instant::now());
+
+            // Without an identity from the version handshake, there is
+            // nothing to check the probes against.
+            let Some(identity) = self.channel.remote_identity().await else {
+                self.hosts.store(&ext_addrs_msg.addrs).await;
+                continue
+            };
+
+            // Don't probe addresses we wouldn't store anyway
+            let addrs = self.hosts.filter_addresses(&ext_addrs_msg.addrs).await;
+            let addrs: Vec<Url> = addrs.into_iter().take(MAX_EXT_ADDRS).collect();
+            let verified =
+                join_all(addrs.iter().map(|addr| self.verify_addr(addr, identity))).await;
+
+            for (addr, verified) in addrs.iter().zip(verified) {
+                // It answered a handshake probe, so it can skip the greylist
+                if verified {
+                    self.hosts.whitelist_store(addr).await;
+                } else {
+                    debug!(
+                        target: "net::protocol_address::handle_receive_ext_addrs()",
+                        "[P2P] Address {} advertised by {} failed verification",
+                        addr, self.channel.address(),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Check that the node answering at `addr` has the given identity.
+    /// Probe results are cached in the hosts store.
+    async fn verify_addr(&self, addr: &Url, identity: [u8; NOISE_KEY_LEN]) -> bool {
+        if let Some(verification) = self.hosts.verification(addr).await {
+            return verification.identity == Some(identity)
+        }
+
+        let answer = self.channel.p2p().session_outbound().probe(addr).await;
+        self.hosts.record_verification(addr, answer).await;
+        answer == Some(identity)
+    }
+
     /// Handles receiving the get-address message. Continually receives get-address
     /// messages on the get-address subscription. Then replies with an address message.
     async fn handle_receive_get_addrs(self: Arc<Self>) -> Result<()> {
@@ -158,7 +250,7 @@ impl ProtocolAddress {
 
         // FIXME: Revisit this. Why do we keep sending it?
         loop {
-            let ext_addr_msg = AddrsMessage { addrs: self.settings.external_addrs.clone() };
+            let ext_addr_msg = ExtAddrsMessage { addrs: self.settings.external_addrs.clone() };
             self.channel.send(&ext_addr_msg).await?;
             sleep(900).await;
         }
@@ -182,6 +274,7 @@ impl ProtocolBase for ProtocolAddress {
         }
 
         self.jobsman.clone().spawn(self.clone().handle_receive_addrs(), ex.clone()).await;
+        self.jobsman.clone().spawn(self.clone().handle_receive_ext_addrs(), ex.clone()).await;
         self.jobsman.spawn(self.clone().handle_receive_get_addrs(), ex).await;
 
         // Send get_address message.
@@ -29,9 +29,13 @@ use super::{
 
 type Constructor = Box<dyn Fn(ChannelPtr, P2pPtr) -> Boxed<ProtocolBasePtr> + Send + Sync>;
 
+/// A protocol attached to a channel, along with the capability the
+/// remote peer has to advertise for the protocol to be started.
+pub type AttachedProtocol = (Option<String>, ProtocolBasePtr);
+
 #[derive(Default)]
 pub struct ProtocolRegistry {
-    constructors: Mutex<Vec<(SessionBitFlag, Constructor)>>,
+    constructors: Mutex<Vec<(SessionBitFlag, Option<String>, Constructor)>>,
 }
 
 impl ProtocolRegistry {
@@ -45,22 +49,69 @@ impl ProtocolRegistry {
     where
         C: 'static + Fn(ChannelPtr, P2pPtr) -> F + Send + Sync,
         F: 'static + Future<Output = ProtocolBasePtr> + Send,
+    {
+        self.add_constructor(session_flags, None, constructor).await
+    }
+
+    /// Register a protocol that is only started on channels where the remote
+    /// peer advertised `capability` during the version handshake. The
+    /// capability is advertised to our peers as well.
+    pub async fn register_with_capability<C, F>(
+        &self,
+        session_flags: SessionBitFlag,
+        capability: &str,
+        constructor: C,
+    ) where
+        C: 'static + Fn(ChannelPtr, P2pPtr) -> F + Send + Sync,
+        F: 'static + Future<Output = ProtocolBasePtr> + Send,
+    {
+        self.add_constructor(session_flags, Some(capability.to_string()), constructor).await
+    }
+
+    async fn add_constructor<C, F>(
+        &self,
+        session_flags: SessionBitFlag,
+        capability: Option<String>,
+        constructor: C,
+    ) where
+        C: 'static + Fn(ChannelPtr, P2pPtr) -> F + Send + Sync,
+        F: 'static + Future<Output = ProtocolBasePtr> + Send,
     {
         let constructor =
             move |channel, p2p| Box::pin(constructor(channel, p2p)) as Boxed<ProtocolBasePtr>;
 
-        self.constructors.lock().await.push((session_flags, Box::new(constructor)));
+        self.constructors.lock().await.push((session_flags, capability, Box::new(constructor)));
     }
 
+    /// Returns the capabilities of all registered protocols, which we
+    /// advertise to our peers.
+    pub async fn capabilities(&self) -> Vec<String> {
+        let mut capabilities: Vec<String> = self
+            .constructors
+            .lock()
+            .await
+            .iter()
+            .filter_map(|(_, capability, _)| capability.clone())
+            .collect();
+
+        capabilities.sort();
+        capabilities.dedup();
+        capabilities
+    }
+
+    /// Construct the protocols registered for the given session. They are
+    /// created before the handshake so they can buffer incoming messages,
+    /// but should only be started if the remote peer supports them, see
+    /// [`ProtocolRegistry::filter_supported()`].
     pub async fn attach(
         &self,
         selector_id: SessionBitFlag,
         channel: ChannelPtr,
         p2p: P2pPtr,
-    ) -> Vec<ProtocolBasePtr> {
+    ) -> Vec<AttachedProtocol> {
         let mut protocols = vec![];
 
-        for (session_flags, construct) in self.constructors.lock().await.iter() {
+        for (session_flags, capability, construct) in self.constructors.lock().await.iter() {
             // Skip protocols that are not registered for this session
             if selector_id & session_flags == 0 {
                 debug!(target: "net::protocol_registry", "Skipping {selector_id:#b}, {session_flags:#b}");
@@ -69,9 +120,36 @@ impl ProtocolRegistry {
 
             let protocol = construct(channel.clone(), p2p.clone()).await;
             debug!(target: "net::protocol_registry", "Attached {}", protocol.name());
-            protocols.push(protocol);
+            protocols.push((capability.clone(), protocol));
         }
 
         protocols
     }
+
+    /// Keep only the attached protocols supported by both sides of the channel.
+    /// Must be called after the version handshake has completed.
+    pub async fn filter_supported(
+        channel: &ChannelPtr,
+        protocols: Vec<AttachedProtocol>,
+    ) -> Vec<ProtocolBasePtr> {
+        let remote_capabilities = channel.remote_capabilities().await;
+        let mut supported = vec![];
+
+        for (capability, protocol) in protocols {
+            if let Some(capability) = capability {
+                if !remote_capabilities.contains(&capability) {
+                    debug!(
+                        target: "net::protocol_registry",
+                        "Skipping {}, capability \"{}\" not supported by {}",
+                        protocol.name(), capability, channel.address(),
+                    );
+                    continue
+                }
+            }
+
+            supported.push(protocol);
+        }
+
+        supported
+    }
 }
@@ -26,7 +26,7 @@ use super::{
     super::{
         channel::ChannelPtr,
         hosts::HostsPtr,
-        message::{AddrsMessage, GetAddrsMessage},
+        message::{AddrsMessage, ExtAddrsMessage, GetAddrsMessage},
         message_subscriber::MessageSubscription,
         p2p::P2pPtr,
         settings::SettingsPtr,
@@ -74,7 +74,7 @@ impl ProtocolSeed {
             "ext_addrs={:?}, dest={}", addrs, self.channel.address(),
         );
 
-        let ext_addr_msg = AddrsMessage { addrs };
+        let ext_addr_msg = ExtAddrsMessage { addrs };
         self.channel.send(&ext_addr_msg).await?;
         debug!(target: "net::protocol_seed::send_self_address()", "[END]");
         Ok(())
@@ -123,14 +123,19 @@ impl ProtocolVersion {
     }
 
     /// Send version info and wait for version acknowledgement.
-    /// Ensures that the app version is the same.
+    /// Ensures that the app version is compatible with ours.
     async fn send_version(self: Arc<Self>) -> Result<()> {
         debug!(
             target: "net::protocol_version::send_version()",
             "START => address={}", self.channel.address(),
         );
 
-        let version = VersionMessage { node_id: self.settings.node_id.clone() };
+        let p2p = self.channel.p2p();
+        let version = VersionMessage {
+            node_id: self.settings.node_id.clone(),
+            static_key: p2p.noise_keypair().public_key(),
+            capabilities: p2p.protocol_registry().capabilities().await,
+        };
         self.channel.send(&version).await?;
 
         // Wait for verack
@@ -143,9 +148,11 @@ impl ProtocolVersion {
             self.settings.app_version, verack_msg.app_version,
         );
 
-        // MAJOR and MINOR should be the same.
-        if self.settings.app_version.major != verack_msg.app_version.major ||
-            self.settings.app_version.minor != verack_msg.app_version.minor
+        // Check compatibility according to the configured policy
+        if !self
+            .settings
+            .version_policy
+            .is_compatible(&self.settings.app_version, &verack_msg.app_version)
         {
             error!(
                 target: "net::protocol_version::send_version()",
@@ -175,9 +182,31 @@ impl ProtocolVersion {
         );
 
         // Receive version message
-        let _version = self.version_sub.receive().await?;
+        let version = self.version_sub.receive().await?;
         // TODO: self.channel.set_remote_node_id(version.node_id.clone()).await;
 
+        // On authenticated transports, the advertised static key must be
+        // the one the peer proved ownership of during the handshake.
+        if let Some(remote_static_key) = self.channel.info.remote_static_key {
+            if version.static_key != remote_static_key {
+                error!(
+                    target: "net::protocol_version::recv_version()",
+                    "[P2P] Static key mismatch from {}. Disconnecting...",
+                    self.channel.address(),
+                );
+
+                self.hosts.remove(self.channel.address()).await;
+                self.channel.stop().await;
+                return Err(Error::ChannelStopped)
+            }
+        }
+
+        // Remember what the peer supports, so only the protocols
+        // supported by both sides get started.
+        let capabilities = version.capabilities.iter().cloned().collect();
+        self.channel.set_remote_capabilities(capabilities).await;
+        self.channel.set_remote_identity(version.static_key).await;
+
         // Send verack
         let verack = VerackMessage { app_version: self.settings.app_version.clone() };
         self.channel.send(&verack).await?;
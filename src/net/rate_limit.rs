@@ -0,0 +1,99 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::time::{Duration, Instant};
+
+/// Token bucket parameters. `rate` tokens are added to the bucket every
+/// second, and the bucket holds at most `burst` tokens.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+pub struct RateLimit {
+    /// Tokens refilled per second
+    pub rate: u64,
+    /// Maximum number of tokens the bucket can hold
+    pub burst: u64,
+}
+
+/// Token bucket used to limit the rate of inbound messages and bytes
+/// on a channel.
+#[derive(Debug)]
+pub struct TokenBucket {
+    /// Configured limits
+    limit: RateLimit,
+    /// Currently available tokens. Can go negative when used as debt.
+    tokens: f64,
+    /// Last time the bucket was refilled
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Create a new full token bucket with the given limits.
+    pub fn new(limit: RateLimit) -> Self {
+        Self { limit, tokens: limit.burst as f64, last_refill: Instant::now() }
+    }
+
+    /// Add the tokens accumulated since the last refill.
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.rate as f64).min(self.limit.burst as f64);
+        self.last_refill = now;
+    }
+
+    /// Try to take `n` tokens from the bucket. Returns `false` and leaves
+    /// the bucket untouched if there aren't enough tokens.
+    pub fn try_take(&mut self, n: u64) -> bool {
+        self.refill();
+        if self.tokens < n as f64 {
+            return false
+        }
+
+        self.tokens -= n as f64;
+        true
+    }
+
+    /// Take `n` tokens from the bucket, going into debt if needed.
+    /// Returns how long the caller should wait until the debt is repaid.
+    pub fn take(&mut self, n: u64) -> Duration {
+        self.refill();
+        self.tokens -= n as f64;
+        // A zero rate would never repay the debt, so don't block forever.
+        if self.tokens >= 0.0 || self.limit.rate == 0 {
+            return Duration::ZERO
+        }
+
+        Duration::from_secs_f64(-self.tokens / self.limit.rate as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let mut bucket = TokenBucket::new(RateLimit { rate: 1, burst: 3 });
+        assert!(bucket.try_take(1));
+        assert!(bucket.try_take(2));
+        assert!(!bucket.try_take(1));
+
+        let mut bucket = TokenBucket::new(RateLimit { rate: 100, burst: 100 });
+        assert_eq!(bucket.take(50), Duration::ZERO);
+        let wait = bucket.take(100);
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+    }
+}
@@ -22,8 +22,12 @@ use async_trait::async_trait;
 use log::debug;
 use smol::Executor;
 
-use super::{channel::ChannelPtr, p2p::P2pPtr, protocol::ProtocolVersion};
-use crate::Result;
+use super::{
+    channel::ChannelPtr,
+    p2p::P2pPtr,
+    protocol::{protocol_registry::ProtocolRegistry, ProtocolVersion},
+};
+use crate::{util::metrics::registry, Result};
 
 pub mod inbound_session;
 pub use inbound_session::{InboundSession, InboundSessionPtr};
@@ -105,14 +109,21 @@ pub trait Session: Sync {
         channel.start(executor.clone());
 
         // Wait for handshake to finish.
-        handshake_task.await?;
+        if let Err(e) = handshake_task.await {
+            registry()
+                .counter("darkfi_p2p_handshake_failures_total", "Failed p2p handshakes", &[])
+                .inc();
+            return Err(e)
+        }
 
         // Now the channel is ready
         debug!(target: "net::session::register_channel()", "Session handshake complete");
         debug!(target: "net::session::register_channel()", "Activating remaining protocols");
 
-        // Now start all the protocols. They are responsible for managing their own
-        // lifetimes and correctly selfdestructing when the channel ends.
+        // Now start all the protocols supported by both sides. They are responsible
+        // for managing their own lifetimes and correctly selfdestructing when the
+        // channel ends.
+        let protocols = ProtocolRegistry::filter_supported(&channel, protocols).await;
         for protocol in protocols {
             protocol.start(executor.clone()).await?;
         }
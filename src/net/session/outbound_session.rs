@@ -28,7 +28,7 @@
 
 use std::{
     sync::{
-        atomic::{AtomicU32, Ordering},
+        atomic::{AtomicU32, AtomicUsize, Ordering},
         Arc, Weak,
     },
     time::{Duration, Instant},
@@ -37,7 +37,7 @@ use std::{
 use async_trait::async_trait;
 use log::{debug, error, info, trace, warn};
 use rand::{prelude::SliceRandom, rngs::OsRng};
-use smol::lock::Mutex;
+use smol::lock::{Mutex, Semaphore};
 use url::Url;
 
 use super::{
@@ -45,8 +45,11 @@ use super::{
         channel::ChannelPtr,
         connector::Connector,
         dnet::{self, dnetev, DnetEvent},
+        hosts::HostTier,
         message::GetAddrsMessage,
         p2p::{P2p, P2pPtr},
+        protocol::ProtocolVersion,
+        transport::NOISE_KEY_LEN,
     },
     Session, SessionBitFlag, SESSION_OUTBOUND,
 };
@@ -55,11 +58,15 @@ use crate::{
         sleep, timeout::timeout, CondVar, LazyWeak, StoppableTask, StoppableTaskPtr, Subscriber,
         SubscriberPtr,
     },
+    util::metrics::registry,
     Error, Result,
 };
 
 pub type OutboundSessionPtr = Arc<OutboundSession>;
 
+/// Maximum number of address probes running at once
+const MAX_CONCURRENT_PROBES: usize = 4;
+
 /// Defines outbound connections session.
 pub struct OutboundSession {
     /// Weak pointer to parent p2p object
@@ -71,6 +78,10 @@ pub struct OutboundSession {
     slots: Mutex<Vec<Arc<Slot>>>,
     /// Peer discovery task
     peer_discovery: Arc<PeerDiscovery>,
+    /// Greylist probing task
+    greylist_refinery: Arc<GreylistRefinery>,
+    /// Limits the number of probes running at once
+    probe_slots: Semaphore,
 }
 
 impl OutboundSession {
@@ -81,8 +92,11 @@ impl OutboundSession {
             channel_subscriber: Subscriber::new(),
             slots: Mutex::new(Vec::new()),
             peer_discovery: PeerDiscovery::new(),
+            greylist_refinery: GreylistRefinery::new(),
+            probe_slots: Semaphore::new(MAX_CONCURRENT_PROBES),
         });
         self_.peer_discovery.session.init(self_.clone());
+        self_.greylist_refinery.session.init(self_.clone());
         self_
     }
 
@@ -102,6 +116,7 @@ impl OutboundSession {
         }
 
         self.peer_discovery.clone().start().await;
+        self.greylist_refinery.clone().start().await;
     }
 
     /// Stops the outbound session.
@@ -113,6 +128,7 @@ impl OutboundSession {
         }
 
         self.peer_discovery.clone().stop().await;
+        self.greylist_refinery.clone().stop().await;
     }
 
     pub async fn slot_info(&self) -> Vec<u32> {
@@ -124,6 +140,33 @@ impl OutboundSession {
         info
     }
 
+    /// Connect to the given address and perform a version handshake, then
+    /// disconnect. Returns the identity of the node that answered if the
+    /// handshake succeeded. At most [`MAX_CONCURRENT_PROBES`] run at once,
+    /// so peers advertising addresses can't make us dial out in bulk.
+    pub(crate) async fn probe(self: Arc<Self>, addr: &Url) -> Option<[u8; NOISE_KEY_LEN]> {
+        let _permit = self.probe_slots.acquire().await;
+
+        let p2p = self.p2p();
+        let ex = p2p.executor();
+        let parent = Arc::downgrade(&self);
+        let connector = Connector::new(p2p.settings(), parent);
+
+        let channel = match connector.connect(addr).await {
+            Ok((_, channel)) => channel,
+            Err(_) => return None,
+        };
+
+        let protocol_version =
+            ProtocolVersion::new(channel.clone(), p2p.settings(), p2p.hosts()).await;
+        channel.clone().start(ex.clone());
+        let result = protocol_version.run(ex).await;
+        let identity = channel.remote_identity().await;
+        channel.stop().await;
+
+        result.ok().and(identity)
+    }
+
     fn wakeup_peer_discovery(&self) {
         self.peer_discovery.notify()
     }
@@ -146,6 +189,13 @@ impl Session for OutboundSession {
     }
 }
 
+/// States of an outbound slot, exported as metrics
+const SLOT_STATES: [&str; 4] = ["idle", "sleeping", "connecting", "connected"];
+const SLOT_IDLE: usize = 0;
+const SLOT_SLEEPING: usize = 1;
+const SLOT_CONNECTING: usize = 2;
+const SLOT_CONNECTED: usize = 3;
+
 pub struct Slot {
     slot: u32,
     process: StoppableTaskPtr,
@@ -153,6 +203,8 @@ pub struct Slot {
     session: Weak<OutboundSession>,
     // For debugging
     channel_id: AtomicU32,
+    /// Current state, as an index into `SLOT_STATES`
+    state: AtomicUsize,
 }
 
 impl Slot {
@@ -163,9 +215,34 @@ impl Slot {
             wakeup_self: CondVar::new(),
             session,
             channel_id: AtomicU32::new(0),
+            state: AtomicUsize::new(usize::MAX),
         })
     }
 
+    /// Move the slot to a new state and update the slot state gauges
+    fn set_state(&self, state: Option<usize>) {
+        let state = state.unwrap_or(usize::MAX);
+        let prev = self.state.swap(state, Ordering::Relaxed);
+        if prev == state {
+            return
+        }
+
+        let gauge = |state: usize| {
+            registry().gauge(
+                "darkfi_p2p_outbound_slots",
+                "Number of outbound slots per state",
+                &[("state", SLOT_STATES[state])],
+            )
+        };
+
+        if prev != usize::MAX {
+            gauge(prev).dec();
+        }
+        if state != usize::MAX {
+            gauge(state).inc();
+        }
+    }
+
     async fn start(self: Arc<Self>) {
         // TODO: way too many clones, look into making this implicit. See implicit-clone crate
         let ex = self.p2p().executor();
@@ -182,7 +259,8 @@ impl Slot {
         );
     }
     async fn stop(self: Arc<Self>) {
-        self.process.stop().await
+        self.process.stop().await;
+        self.set_state(None);
     }
 
     async fn run(self: Arc<Self>) {
@@ -198,6 +276,8 @@ impl Slot {
         // signal and then exit. Once it exits, we'll run `try_connect` again
         // and attempt to fill the slot with another peer.
         loop {
+            self.set_state(Some(SLOT_IDLE));
+
             // Activate the slot
             debug!(
                 target: "net::outbound_session::try_connect()",
@@ -215,6 +295,7 @@ impl Slot {
                 dnetev!(self, OutboundSlotSleeping, {
                     slot: self.slot,
                 });
+                self.set_state(Some(SLOT_SLEEPING));
 
                 self.wakeup_self.reset();
                 // Peer discovery
@@ -234,6 +315,7 @@ impl Slot {
                 slot: self.slot,
                 addr: addr.clone(),
             });
+            self.set_state(Some(SLOT_CONNECTING));
 
             let (addr_final, channel) = match self.try_connect(addr.clone()).await {
                 Ok(connect_info) => connect_info,
@@ -268,7 +350,7 @@ impl Slot {
 
             let stop_sub = channel.subscribe_stop().await.expect("Channel should not be stopped");
             // Setup new channel
-            if let Err(err) = self.setup_channel(addr, channel.clone()).await {
+            if let Err(err) = self.setup_channel(addr.clone(), channel.clone()).await {
                 info!(
                     target: "net::outbound_session",
                     "[P2P] Outbound slot #{} disconnected: {}",
@@ -284,7 +366,12 @@ impl Slot {
                 continue
             }
 
+            // Handshake was successful, so this peer gets a better score
+            // and becomes an anchor we'll prefer on the next connections.
+            self.p2p().hosts().record_handshake(&addr).await;
+            self.p2p().hosts().anchorlist_store(&addr).await;
             self.channel_id.store(channel.info.id, Ordering::Relaxed);
+            self.set_state(Some(SLOT_CONNECTED));
 
             // Wait for channel to close
             stop_sub.receive().await;
@@ -342,12 +429,29 @@ impl Slot {
     }
 
     /// Loops through host addresses to find an outbound address that we can
-    /// connect to. Check whether the address is valid by making sure it isn't
+    /// connect to. Hosts are tried tier by tier: anchors first, then the
+    /// whitelist. Greylisted hosts are never dialed here, they have to be
+    /// promoted by the [`GreylistRefinery`] first. If none of them yields
+    /// an address, quarantined hosts are tried as a last resort.
+    /// Check whether the address is valid by making sure it isn't
     /// our own inbound address, then checks whether it is already connected
     /// (exists) or connecting (pending).
     /// Lastly adds matching address to the pending list.
     /// TODO: this method should go in hosts
     async fn fetch_address_with_lock(&self, transports: &[String]) -> Option<Url> {
+        for tier in [Some(HostTier::Anchor), Some(HostTier::White), None] {
+            let hosts = self.fetch_candidates(tier, transports).await;
+            if let Some(addr) = self.lock_unused_address(&hosts).await {
+                return Some(addr)
+            }
+        }
+
+        None
+    }
+
+    /// Collect the hosts of the given tier that we can dial with the given
+    /// transports. `None` means the quarantined hosts.
+    async fn fetch_candidates(&self, tier: Option<HostTier>, transports: &[String]) -> Vec<Url> {
         let p2p = self.p2p();
 
         // Collect hosts
@@ -360,7 +464,7 @@ impl Slot {
         macro_rules! mix_transport {
             ($a:expr, $b:expr) => {
                 if transports.contains(&$a.to_string()) && transport_mixing {
-                    let mut a_to_b = p2p.hosts().fetch_with_schemes(&[$b.to_string()], None).await;
+                    let mut a_to_b = self.fetch_with_tier(tier, &[$b.to_string()]).await;
                     for addr in a_to_b.iter_mut() {
                         addr.set_scheme($a).unwrap();
                         hosts.push(addr.clone());
@@ -374,13 +478,29 @@ impl Slot {
         mix_transport!("nym+tls", "tcp+tls");
 
         // And now the actual requested transports
-        for addr in p2p.hosts().fetch_with_schemes(transports, None).await {
+        for addr in self.fetch_with_tier(tier, transports).await {
             hosts.push(addr);
         }
 
         // Randomize hosts list. Do not try to connect in a deterministic order.
         // This is healthier for multiple slots to not compete for the same addrs.
         hosts.shuffle(&mut OsRng);
+        hosts
+    }
+
+    /// Fetch hosts matching the given schemes from a tier, or from all
+    /// tiers and the quarantine if no tier is given.
+    async fn fetch_with_tier(&self, tier: Option<HostTier>, schemes: &[String]) -> Vec<Url> {
+        let hosts = self.p2p().hosts();
+        match tier {
+            Some(tier) => hosts.fetch_tier_with_schemes(tier, schemes).await,
+            None => hosts.fetch_quarantine_with_schemes(schemes).await,
+        }
+    }
+
+    /// Find an unused host in the given list and obtain a pending lock on it.
+    async fn lock_unused_address(&self, hosts: &[Url]) -> Option<Url> {
+        let p2p = self.p2p();
 
         // Try to find an unused host in the set.
         for host in hosts.iter() {
@@ -618,3 +738,88 @@ impl PeerDiscovery {
         self.session().p2p()
     }
 }
+
+/// Periodically probes a random greylisted address with a version handshake.
+/// Addresses that answer get promoted to the whitelist, and the ones that
+/// don't are forgotten. This way gossiped addresses never reach our outbound
+/// slots before we've seen them behave like actual peers.
+struct GreylistRefinery {
+    process: StoppableTaskPtr,
+    session: LazyWeak<OutboundSession>,
+}
+
+impl GreylistRefinery {
+    fn new() -> Arc<Self> {
+        Arc::new(Self { process: StoppableTask::new(), session: LazyWeak::new() })
+    }
+
+    async fn start(self: Arc<Self>) {
+        let ex = self.p2p().executor();
+        self.process.clone().start(
+            async move {
+                self.run().await;
+                unreachable!();
+            },
+            // Ignore stop handler
+            |_| async {},
+            Error::NetworkServiceStopped,
+            ex,
+        );
+    }
+    async fn stop(self: Arc<Self>) {
+        self.process.stop().await
+    }
+
+    async fn run(self: Arc<Self>) {
+        loop {
+            let p2p = self.p2p();
+
+            // Outbound slots only dial verified hosts, so don't hold back
+            // while we don't know any yet (e.g. on first start).
+            let hosts = p2p.hosts();
+            if !hosts.fetch_tier(HostTier::Anchor).await.is_empty() ||
+                !hosts.fetch_tier(HostTier::White).await.is_empty() ||
+                hosts.fetch_tier(HostTier::Grey).await.is_empty()
+            {
+                sleep(p2p.settings().greylist_refinery_interval).await;
+            }
+
+            let greylist = p2p.hosts().fetch_tier(HostTier::Grey).await;
+            let Some(addr) = greylist.choose(&mut OsRng).cloned() else {
+                trace!(target: "net::outbound_session::greylist_refinery()", "Greylist is empty");
+                continue
+            };
+
+            // Don't probe hosts we're already connected or connecting to
+            if p2p.exists(&addr).await || !p2p.add_pending(&addr).await {
+                continue
+            }
+
+            let verified = self.session().probe(&addr).await.is_some();
+            p2p.remove_pending(&addr).await;
+
+            if verified {
+                debug!(
+                    target: "net::outbound_session::greylist_refinery()",
+                    "[P2P] Greylisted host {} verified", addr,
+                );
+                p2p.hosts().record_handshake(&addr).await;
+                p2p.hosts().whitelist_store(&addr).await;
+                self.session().wakeup_slots().await;
+            } else {
+                debug!(
+                    target: "net::outbound_session::greylist_refinery()",
+                    "[P2P] Greylisted host {} unreachable, removing", addr,
+                );
+                p2p.hosts().remove(&addr).await;
+            }
+        }
+    }
+
+    fn session(&self) -> OutboundSessionPtr {
+        self.session.upgrade()
+    }
+    fn p2p(&self) -> P2pPtr {
+        self.session().p2p()
+    }
+}
@@ -16,14 +16,48 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use structopt::StructOpt;
 use url::Url;
 
+use super::{message::MAX_PACKET_SIZE, rate_limit::RateLimit};
+
 /// Atomic pointer to network settings
 pub type SettingsPtr = Arc<Settings>;
 
+/// Policy deciding which remote app versions are compatible with ours
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VersionPolicy {
+    /// Versions must be identical
+    Exact,
+    /// MAJOR and MINOR must be the same
+    Minor,
+    /// MAJOR must be the same
+    Major,
+    /// Semver compatibility: MAJOR must be the same, and while MAJOR is 0,
+    /// MINOR must be the same as well
+    Semver,
+    /// Any version is accepted
+    Any,
+}
+
+impl VersionPolicy {
+    /// Check if the remote app version is compatible with ours
+    pub fn is_compatible(&self, ours: &semver::Version, theirs: &semver::Version) -> bool {
+        match self {
+            Self::Exact => ours == theirs,
+            Self::Minor => ours.major == theirs.major && ours.minor == theirs.minor,
+            Self::Major => ours.major == theirs.major,
+            Self::Semver => {
+                ours.major == theirs.major && (ours.major != 0 || ours.minor == theirs.minor)
+            }
+            Self::Any => true,
+        }
+    }
+}
+
 /// P2P network settings. The scope of this is a P2P network instance
 /// configured by the library user.
 #[derive(Debug, Clone)]
@@ -68,6 +102,25 @@ pub struct Settings {
     pub outbound_peer_discovery_cooloff_time: u64,
     /// Time between peer discovery attempts
     pub outbound_peer_discovery_attempt_time: u64,
+    /// Time between greylist probes (in seconds)
+    pub greylist_refinery_interval: u64,
+    /// Inbound bandwidth limit per channel (in bytes)
+    pub channel_bandwidth_limit: Option<RateLimit>,
+    /// Inbound message rate limits per channel, keyed by message command
+    pub message_rate_limits: HashMap<String, RateLimit>,
+    /// Ban score at which a misbehaving peer gets disconnected and rejected
+    pub ban_score_limit: u32,
+    /// Maximum size of an inbound packet (in bytes)
+    pub max_packet_size: usize,
+    /// Path to the hostlist file used to persist known hosts across restarts
+    pub hostlist: Option<String>,
+    /// Path to the file holding the node's static Noise keypair.
+    /// It is created on first start. If unset, an ephemeral key is used.
+    pub noise_keyfile: Option<String>,
+    /// Policy deciding which remote app versions we accept
+    pub version_policy: VersionPolicy,
+    /// How long the outcome of verifying an advertised address is cached (in seconds)
+    pub addr_verification_ttl: u64,
 }
 
 impl Default for Settings {
@@ -94,6 +147,15 @@ impl Default for Settings {
             hosts_quarantine_limit: 50,
             outbound_peer_discovery_cooloff_time: 30,
             outbound_peer_discovery_attempt_time: 5,
+            greylist_refinery_interval: 15,
+            channel_bandwidth_limit: None,
+            message_rate_limits: HashMap::new(),
+            ban_score_limit: 100,
+            max_packet_size: MAX_PACKET_SIZE,
+            hostlist: None,
+            noise_keyfile: None,
+            version_policy: VersionPolicy::Minor,
+            addr_verification_ttl: 3600,
         }
     }
 }
@@ -182,6 +244,45 @@ pub struct SettingsOpt {
     /// Time between peer discovery attempts
     #[structopt(skip)]
     pub outbound_peer_discovery_attempt_time: Option<u64>,
+
+    /// Time between greylist probes in seconds
+    #[structopt(skip)]
+    pub greylist_refinery_interval: Option<u64>,
+
+    /// Inbound bandwidth limit per channel in bytes
+    #[serde(default)]
+    #[structopt(skip)]
+    pub channel_bandwidth_limit: Option<RateLimit>,
+
+    /// Inbound message rate limits per channel, keyed by message command
+    #[serde(default)]
+    #[structopt(skip)]
+    pub message_rate_limits: HashMap<String, RateLimit>,
+
+    /// Ban score at which a misbehaving peer gets disconnected and rejected
+    #[structopt(skip)]
+    pub ban_score_limit: Option<u32>,
+
+    /// Maximum size of an inbound packet in bytes
+    #[structopt(skip)]
+    pub max_packet_size: Option<usize>,
+
+    /// Hostlist file used to persist known hosts across restarts
+    #[structopt(long)]
+    pub hostlist: Option<String>,
+
+    /// File holding the static Noise keypair identifying this node
+    #[structopt(long)]
+    pub noise_keyfile: Option<String>,
+
+    /// Policy deciding which remote app versions we accept
+    #[serde(default)]
+    #[structopt(skip)]
+    pub version_policy: Option<VersionPolicy>,
+
+    /// How long the outcome of verifying an advertised address is cached in seconds
+    #[structopt(skip)]
+    pub addr_verification_ttl: Option<u64>,
 }
 
 impl From<SettingsOpt> for Settings {
@@ -212,6 +313,42 @@ impl From<SettingsOpt> for Settings {
             outbound_peer_discovery_attempt_time: opt
                 .outbound_peer_discovery_attempt_time
                 .unwrap_or(5),
+            greylist_refinery_interval: opt.greylist_refinery_interval.unwrap_or(15),
+            channel_bandwidth_limit: opt.channel_bandwidth_limit,
+            message_rate_limits: opt.message_rate_limits,
+            ban_score_limit: opt.ban_score_limit.unwrap_or(100),
+            max_packet_size: opt.max_packet_size.unwrap_or(MAX_PACKET_SIZE),
+            hostlist: opt.hostlist,
+            noise_keyfile: opt.noise_keyfile,
+            version_policy: opt.version_policy.unwrap_or(VersionPolicy::Minor),
+            addr_verification_ttl: opt.addr_verification_ttl.unwrap_or(3600),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_policy() {
+        let v = |s| semver::Version::parse(s).unwrap();
+        let ours = v("0.4.1");
+
+        assert!(VersionPolicy::Exact.is_compatible(&ours, &v("0.4.1")));
+        assert!(!VersionPolicy::Exact.is_compatible(&ours, &v("0.4.2")));
+
+        assert!(VersionPolicy::Minor.is_compatible(&ours, &v("0.4.7")));
+        assert!(!VersionPolicy::Minor.is_compatible(&ours, &v("0.5.0")));
+
+        assert!(VersionPolicy::Major.is_compatible(&ours, &v("0.5.0")));
+        assert!(!VersionPolicy::Major.is_compatible(&ours, &v("1.4.1")));
+
+        assert!(VersionPolicy::Semver.is_compatible(&ours, &v("0.4.0")));
+        assert!(!VersionPolicy::Semver.is_compatible(&ours, &v("0.5.0")));
+        assert!(VersionPolicy::Semver.is_compatible(&v("1.2.0"), &v("1.5.3")));
+        assert!(!VersionPolicy::Semver.is_compatible(&v("1.2.0"), &v("2.0.0")));
+
+        assert!(VersionPolicy::Any.is_compatible(&ours, &v("3.0.0")));
+    }
+}
@@ -0,0 +1,520 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! In-process transport for simulating whole networks inside a single
+//! test process. Nodes are addressed as `memory://name:port`, and the
+//! links between them can be given latency, packet loss, and partitions
+//! through [`MemoryNetwork`].
+//!
+//! Links without latency deliver data right away, without going through
+//! timers, so simulations on them don't depend on the wall clock. Latency
+//! is simulated with timers, so the timing of such runs varies. Lossy
+//! links decide which chunks get lost with a seeded RNG per link. As a
+//! byte stream can't skip what was lost, losing a chunk resets the
+//! connection it was written on. The network is shared by the whole process, so simulations running at the
+//! same time must use distinct node names, and should clean up after
+//! themselves with [`MemoryNetwork::forget`].
+
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    io::{self, ErrorKind},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard, OnceLock,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use futures::{ready, Stream};
+use log::debug;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use smol::{
+    channel::{Receiver, Sender},
+    io::{AsyncRead, AsyncWrite},
+    Timer,
+};
+use url::Url;
+
+use super::{PtListener, PtStream};
+use crate::{Error, Result};
+
+/// Conditions of the link between two nodes
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LinkConfig {
+    /// Delay of every written chunk
+    pub latency: Duration,
+    /// Probability of a chunk getting lost, in `[0, 1]`. A lost chunk
+    /// resets the connection it was written on.
+    pub loss: f64,
+    /// Seed of the link's random number generator
+    pub seed: u64,
+}
+
+struct Link {
+    config: LinkConfig,
+    rng: StdRng,
+}
+
+impl Link {
+    fn new(config: LinkConfig) -> Self {
+        Self { config, rng: StdRng::seed_from_u64(config.seed) }
+    }
+
+    /// Returns whether the next chunk written over this link gets lost
+    fn loses_next(&mut self) -> bool {
+        // Lossless links don't draw, so enabling loss on one link doesn't
+        // change what happens on the others
+        if self.config.loss <= 0.0 {
+            return false
+        }
+
+        self.rng.gen_bool(self.config.loss.min(1.0))
+    }
+}
+
+/// Live connection, kept so it can be cut by a partition
+struct Connection {
+    link: (String, String),
+    pipes: [Sender<Chunk>; 2],
+}
+
+#[derive(Default)]
+struct NetworkState {
+    listeners: HashMap<String, Sender<(MemoryStream, Url)>>,
+    links: HashMap<(String, String), Link>,
+    partitions: HashSet<(String, String)>,
+    connections: Vec<Connection>,
+}
+
+impl NetworkState {
+    fn link(&mut self, key: &(String, String)) -> &mut Link {
+        self.links.entry(key.clone()).or_insert_with(|| Link::new(LinkConfig::default()))
+    }
+}
+
+fn state() -> MutexGuard<'static, NetworkState> {
+    static STATE: OnceLock<Mutex<NetworkState>> = OnceLock::new();
+    STATE.get_or_init(Default::default).lock().unwrap()
+}
+
+/// Returns the `host:port` identifying a node on the memory network
+fn node_id(url: &Url) -> Result<String> {
+    match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => Ok(format!("{}:{}", host, port)),
+        _ => Err(Error::UrlParse(format!("Memory address {} lacks a host or port", url))),
+    }
+}
+
+/// Returns a fresh identity for a dialer without an address of its own
+fn anon_id() -> String {
+    static NEXT_ANON: AtomicU64 = AtomicU64::new(0);
+    format!("anon-{}:0", NEXT_ANON.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Links are undirected, so their key is ordered
+fn link_key(a: &str, b: &str) -> (String, String) {
+    if a <= b {
+        (a.to_string(), b.to_string())
+    } else {
+        (b.to_string(), a.to_string())
+    }
+}
+
+/// Controls the conditions of the in-process network used by the
+/// `memory://` transport. Nodes are identified by their listen URLs.
+pub struct MemoryNetwork;
+
+impl MemoryNetwork {
+    /// Set the conditions of the link between `a` and `b`.
+    /// Applies to connections established afterwards as well as
+    /// chunks written on existing ones.
+    pub fn set_link(a: &Url, b: &Url, config: LinkConfig) -> Result<()> {
+        let key = link_key(&node_id(a)?, &node_id(b)?);
+        state().links.insert(key, Link::new(config));
+        Ok(())
+    }
+
+    /// Partition the network between the two groups of nodes. Existing
+    /// connections across the partition are cut and new ones are refused.
+    pub fn partition(a: &[Url], b: &[Url]) -> Result<()> {
+        let mut state = state();
+        for x in a {
+            for y in b {
+                let key = link_key(&node_id(x)?, &node_id(y)?);
+                debug!(target: "net::transport::memory", "Partitioning {} <-> {}", key.0, key.1);
+                for conn in state.connections.iter().filter(|c| c.link == key) {
+                    conn.pipes[0].close();
+                    conn.pipes[1].close();
+                }
+                state.partitions.insert(key);
+            }
+        }
+        state.connections.retain(|c| !c.pipes[0].is_closed());
+        Ok(())
+    }
+
+    /// Heal a partition previously made between the two groups of nodes
+    pub fn heal(a: &[Url], b: &[Url]) -> Result<()> {
+        let mut state = state();
+        for x in a {
+            for y in b {
+                state.partitions.remove(&link_key(&node_id(x)?, &node_id(y)?));
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget the link conditions and partitions involving the given
+    /// nodes, so they don't leak into later simulations.
+    pub fn forget(nodes: &[Url]) -> Result<()> {
+        let ids: HashSet<String> = nodes.iter().map(node_id).collect::<Result<_>>()?;
+        let mut state = state();
+        state.links.retain(|(a, b), _| !ids.contains(a) && !ids.contains(b));
+        state.partitions.retain(|(a, b)| !ids.contains(a) && !ids.contains(b));
+        Ok(())
+    }
+}
+
+/// Data written to a pipe, delivered to the reader at `deliver_at`,
+/// or right away if it's `None`
+struct Chunk {
+    deliver_at: Option<Instant>,
+    data: Vec<u8>,
+}
+
+/// One end of an in-process connection
+pub struct MemoryStream {
+    link: (String, String),
+    send: Sender<Chunk>,
+    recv: Receiver<Chunk>,
+    /// Delivery time of the last written chunk, to keep chunks in order
+    last_deliver_at: Instant,
+    /// Chunk received but not yet delivered, along with its timer
+    pending: Option<(Timer, Vec<u8>)>,
+    /// Delivered data not yet returned to the reader
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl MemoryStream {
+    fn new(link: (String, String), send: Sender<Chunk>, recv: Receiver<Chunk>) -> Self {
+        Self {
+            link,
+            send,
+            recv,
+            last_deliver_at: Instant::now(),
+            pending: None,
+            buf: vec![],
+            pos: 0,
+        }
+    }
+}
+
+impl Drop for MemoryStream {
+    fn drop(&mut self) {
+        self.send.close();
+        self.recv.close();
+    }
+}
+
+impl AsyncRead for MemoryStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        loop {
+            if this.pos < this.buf.len() {
+                let n = buf.len().min(this.buf.len() - this.pos);
+                buf[..n].copy_from_slice(&this.buf[this.pos..this.pos + n]);
+                this.pos += n;
+                return Poll::Ready(Ok(n))
+            }
+
+            if let Some((timer, _)) = this.pending.as_mut() {
+                ready!(Pin::new(timer).poll(cx));
+                let (_, data) = this.pending.take().unwrap();
+                this.buf = data;
+                this.pos = 0;
+                continue
+            }
+
+            match ready!(Pin::new(&mut this.recv).poll_next(cx)) {
+                Some(Chunk { deliver_at: None, data }) => {
+                    this.buf = data;
+                    this.pos = 0;
+                }
+                Some(Chunk { deliver_at: Some(deliver_at), data }) => {
+                    this.pending = Some((Timer::at(deliver_at), data))
+                }
+                // The other end went away, or the link got cut
+                None => return Poll::Ready(Ok(0)),
+            }
+        }
+    }
+}
+
+impl AsyncWrite for MemoryStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        let (lost, latency) = {
+            let mut state = state();
+            let link = state.link(&this.link);
+            (link.loses_next(), link.config.latency)
+        };
+
+        if lost {
+            debug!(
+                target: "net::transport::memory",
+                "Chunk lost on {} <-> {}, resetting connection", this.link.0, this.link.1,
+            );
+            this.send.close();
+            this.recv.close();
+            return Poll::Ready(Err(ErrorKind::ConnectionReset.into()))
+        }
+
+        let deliver_at = (!latency.is_zero()).then(|| {
+            let deliver_at = (Instant::now() + latency).max(this.last_deliver_at);
+            this.last_deliver_at = deliver_at;
+            deliver_at
+        });
+
+        let chunk = Chunk { deliver_at, data: buf.to_vec() };
+        if this.send.try_send(chunk).is_err() {
+            return Poll::Ready(Err(ErrorKind::BrokenPipe.into()))
+        }
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.send.close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Memory Dialer implementation
+#[derive(Debug, Clone)]
+pub struct MemoryDialer;
+
+impl MemoryDialer {
+    /// Instantiate a new [`MemoryDialer`] object
+    pub(crate) async fn new() -> Result<Self> {
+        Ok(Self {})
+    }
+
+    /// Internal dial function. `local` is the address identifying us on
+    /// the memory network, if we have one.
+    pub(crate) async fn do_dial(&self, remote: &Url, local: Option<&Url>) -> Result<MemoryStream> {
+        debug!(target: "net::memory::do_dial", "Dialing {}...", remote);
+        let remote_id = node_id(remote)?;
+        let local_id = match local {
+            Some(url) => node_id(url)?,
+            None => anon_id(),
+        };
+
+        let link = link_key(&local_id, &remote_id);
+        let (local_send, remote_recv) = smol::channel::unbounded();
+        let (remote_send, local_recv) = smol::channel::unbounded();
+
+        let incoming = {
+            let mut state = state();
+            if state.partitions.contains(&link) {
+                return Err(Error::ConnectFailed)
+            }
+
+            let Some(incoming) = state.listeners.get(&remote_id).cloned() else {
+                return Err(Error::ConnectFailed)
+            };
+
+            state.connections.retain(|c| !c.pipes[0].is_closed());
+            state.connections.push(Connection {
+                link: link.clone(),
+                pipes: [local_send.clone(), remote_send.clone()],
+            });
+
+            incoming
+        };
+
+        let stream = MemoryStream::new(link.clone(), local_send, local_recv);
+        let remote_stream = MemoryStream::new(link, remote_send, remote_recv);
+
+        let url = Url::parse(&format!("memory://{}", local_id)).unwrap();
+        if incoming.send((remote_stream, url)).await.is_err() {
+            return Err(Error::ConnectFailed)
+        }
+
+        Ok(stream)
+    }
+}
+
+/// Memory Listener implementation
+#[derive(Debug, Clone)]
+pub struct MemoryListener;
+
+impl MemoryListener {
+    /// Instantiate a new [`MemoryListener`] object
+    pub(crate) async fn new() -> Result<Self> {
+        Ok(Self {})
+    }
+
+    /// Internal listen function
+    pub(crate) async fn do_listen(&self, endpoint: &Url) -> Result<MemoryIncoming> {
+        let id = node_id(endpoint)?;
+
+        let mut state = state();
+        if state.listeners.get(&id).is_some_and(|s| !s.is_closed()) {
+            return Err(Error::BindFailed(endpoint.to_string()))
+        }
+
+        let (send, recv) = smol::channel::unbounded();
+        state.listeners.insert(id.clone(), send);
+
+        Ok(MemoryIncoming { id, recv })
+    }
+}
+
+/// Queue of inbound connections to a listening node
+pub struct MemoryIncoming {
+    id: String,
+    recv: Receiver<(MemoryStream, Url)>,
+}
+
+impl Drop for MemoryIncoming {
+    fn drop(&mut self) {
+        self.recv.close();
+        let mut state = state();
+        if state.listeners.get(&self.id).is_some_and(|s| s.is_closed()) {
+            state.listeners.remove(&self.id);
+        }
+    }
+}
+
+#[async_trait]
+impl PtListener for MemoryIncoming {
+    async fn next(&self) -> std::io::Result<(Box<dyn PtStream>, Url)> {
+        match self.recv.recv().await {
+            Ok((stream, url)) => Ok((Box::new(stream), url)),
+            Err(_) => Err(ErrorKind::ConnectionAborted.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smol::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_memory_transport() {
+        smol::block_on(async {
+            let alice = Url::parse("memory://test-alice:1").unwrap();
+            let bob = Url::parse("memory://test-bob:1").unwrap();
+
+            let latency = Duration::from_millis(50);
+            MemoryNetwork::set_link(&alice, &bob, LinkConfig { latency, ..Default::default() })
+                .unwrap();
+
+            let listener = MemoryListener::new().await.unwrap().do_listen(&bob).await.unwrap();
+            let dialer = MemoryDialer::new().await.unwrap();
+
+            let mut stream = dialer.do_dial(&bob, Some(&alice)).await.unwrap();
+            let (mut remote, url) = listener.next().await.unwrap();
+            assert_eq!(url, alice);
+
+            let start = Instant::now();
+            stream.write_all(b"hello").await.unwrap();
+            let mut buf = [0u8; 5];
+            remote.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hello");
+            assert!(start.elapsed() >= latency);
+
+            // Partitions cut the link and refuse new connections
+            MemoryNetwork::partition(&[alice.clone()], &[bob.clone()]).unwrap();
+            assert_eq!(remote.read(&mut buf).await.unwrap(), 0);
+            assert!(dialer.do_dial(&bob, Some(&alice)).await.is_err());
+
+            MemoryNetwork::heal(&[alice.clone()], &[bob.clone()]).unwrap();
+            let mut stream = dialer.do_dial(&bob, Some(&alice)).await.unwrap();
+            let (mut remote, _) = listener.next().await.unwrap();
+
+            // Perfect links deliver without any delay
+            MemoryNetwork::forget(&[alice.clone()]).unwrap();
+            stream.write_all(b"hello").await.unwrap();
+            let start = Instant::now();
+            remote.read_exact(&mut buf).await.unwrap();
+            assert!(start.elapsed() < latency);
+
+            // Addresses without a host and port are not memory nodes
+            let invalid = Url::parse("memory:alice").unwrap();
+            assert!(matches!(dialer.do_dial(&invalid, None).await, Err(Error::UrlParse(_))));
+        });
+    }
+
+    #[test]
+    fn test_memory_loss() {
+        let alice = Url::parse("memory://test-lossy-alice:1").unwrap();
+        let bob = Url::parse("memory://test-lossy-bob:1").unwrap();
+
+        // Returns how many chunks got through before one was lost
+        let run = |config: LinkConfig| {
+            smol::block_on(async {
+                MemoryNetwork::set_link(&alice, &bob, config).unwrap();
+                let listener = MemoryListener::new().await.unwrap().do_listen(&bob).await.unwrap();
+                let dialer = MemoryDialer::new().await.unwrap();
+                let mut stream = dialer.do_dial(&bob, Some(&alice)).await.unwrap();
+                let (mut remote, _) = listener.next().await.unwrap();
+
+                let mut delivered = 0;
+                while stream.write_all(b"hello").await.is_ok() {
+                    delivered += 1;
+                }
+
+                // The remote end gets what was written before the loss,
+                // and then sees the connection go away
+                let mut buf = vec![];
+                remote.read_to_end(&mut buf).await.unwrap();
+                assert_eq!(buf.len(), delivered * 5);
+                assert!(remote.write_all(b"hello").await.is_err());
+                delivered
+            })
+        };
+
+        // Which chunk gets lost is determined by the seed
+        let config = LinkConfig { loss: 0.1, seed: 42, ..Default::default() };
+        assert_eq!(run(config), run(config));
+        assert_eq!(run(LinkConfig { loss: 1.0, ..Default::default() }), 0);
+
+        MemoryNetwork::forget(&[alice, bob]).unwrap();
+    }
+}
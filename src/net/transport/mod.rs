@@ -17,7 +17,7 @@
  */
 
 use log::warn;
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 use tor_error::ErrorReport;
 
 use async_trait::async_trait;
@@ -29,6 +29,10 @@ use crate::{Error, Result};
 /// TLS upgrade mechanism
 pub(crate) mod tls;
 
+/// Noise upgrade mechanism
+pub(crate) mod noise;
+pub use noise::{NoiseKeypair, NOISE_KEY_LEN};
+
 #[cfg(feature = "p2p-tcp")]
 /// TCP transport
 pub(crate) mod tcp;
@@ -45,6 +49,12 @@ pub(crate) mod nym;
 /// Unix socket transport
 pub(crate) mod unix;
 
+#[cfg(any(test, feature = "p2p-memory"))]
+/// In-process simulated transport
+pub(crate) mod memory;
+#[cfg(any(test, feature = "p2p-memory"))]
+pub use memory::{LinkConfig, MemoryNetwork};
+
 /// Dialer variants
 #[derive(Debug, Clone)]
 pub enum DialerVariant {
@@ -56,6 +66,10 @@ pub enum DialerVariant {
     /// TCP with TLS
     TcpTls(tcp::TcpDialer),
 
+    #[cfg(feature = "p2p-tcp")]
+    /// TCP with Noise
+    TcpNoise(tcp::TcpDialer),
+
     #[cfg(feature = "p2p-tor")]
     /// Tor
     Tor(tor::TorDialer),
@@ -75,6 +89,10 @@ pub enum DialerVariant {
     #[cfg(feature = "p2p-unix")]
     /// Unix socket
     Unix(unix::UnixDialer),
+
+    #[cfg(any(test, feature = "p2p-memory"))]
+    /// In-process memory pipe
+    Memory(memory::MemoryDialer),
 }
 
 /// Listener variants
@@ -88,9 +106,17 @@ pub enum ListenerVariant {
     /// TCP with TLS
     TcpTls(tcp::TcpListener),
 
+    #[cfg(feature = "p2p-tcp")]
+    /// TCP with Noise
+    TcpNoise(tcp::TcpListener),
+
     #[cfg(feature = "p2p-unix")]
     /// Unix socket
     Unix(unix::UnixListener),
+
+    #[cfg(any(test, feature = "p2p-memory"))]
+    /// In-process memory pipe
+    Memory(memory::MemoryListener),
 }
 
 /// A dialer that is able to transparently operate over arbitrary transports.
@@ -99,6 +125,10 @@ pub struct Dialer {
     endpoint: Url,
     /// The dialer variant (transport protocol)
     variant: DialerVariant,
+    /// Static keypair used by Noise transports
+    noise_keypair: Option<Arc<NoiseKeypair>>,
+    /// Our own address, used by transports that identify the dialing side
+    local_endpoint: Option<Url>,
 }
 
 macro_rules! enforce_hostport {
@@ -131,7 +161,7 @@ impl Dialer {
                 enforce_hostport!(endpoint);
                 let variant = tcp::TcpDialer::new(None).await?;
                 let variant = DialerVariant::Tcp(variant);
-                Ok(Self { endpoint, variant })
+                Ok(Self { endpoint, variant, noise_keypair: None, local_endpoint: None })
             }
 
             #[cfg(feature = "p2p-tcp")]
@@ -140,7 +170,16 @@ impl Dialer {
                 enforce_hostport!(endpoint);
                 let variant = tcp::TcpDialer::new(None).await?;
                 let variant = DialerVariant::TcpTls(variant);
-                Ok(Self { endpoint, variant })
+                Ok(Self { endpoint, variant, noise_keypair: None, local_endpoint: None })
+            }
+
+            #[cfg(feature = "p2p-tcp")]
+            "tcp+noise" => {
+                // Build a TCP dialer wrapped with Noise
+                enforce_hostport!(endpoint);
+                let variant = tcp::TcpDialer::new(None).await?;
+                let variant = DialerVariant::TcpNoise(variant);
+                Ok(Self { endpoint, variant, noise_keypair: None, local_endpoint: None })
             }
 
             #[cfg(feature = "p2p-tor")]
@@ -149,7 +188,7 @@ impl Dialer {
                 enforce_hostport!(endpoint);
                 let variant = tor::TorDialer::new().await?;
                 let variant = DialerVariant::Tor(variant);
-                Ok(Self { endpoint, variant })
+                Ok(Self { endpoint, variant, noise_keypair: None, local_endpoint: None })
             }
 
             #[cfg(feature = "p2p-tor")]
@@ -158,7 +197,7 @@ impl Dialer {
                 enforce_hostport!(endpoint);
                 let variant = tor::TorDialer::new().await?;
                 let variant = DialerVariant::TorTls(variant);
-                Ok(Self { endpoint, variant })
+                Ok(Self { endpoint, variant, noise_keypair: None, local_endpoint: None })
             }
 
             #[cfg(feature = "p2p-nym")]
@@ -167,7 +206,7 @@ impl Dialer {
                 enforce_hostport!(endpoint);
                 let variant = nym::NymDialer::new().await?;
                 let variant = DialerVariant::Nym(variant);
-                Ok(Self { endpoint, variant })
+                Ok(Self { endpoint, variant, noise_keypair: None, local_endpoint: None })
             }
 
             #[cfg(feature = "p2p-nym")]
@@ -176,7 +215,7 @@ impl Dialer {
                 enforce_hostport!(endpoint);
                 let variant = nym::NymDialer::new().await?;
                 let variant = DialerVariant::NymTls(variant);
-                Ok(Self { endpoint, variant })
+                Ok(Self { endpoint, variant, noise_keypair: None, local_endpoint: None })
             }
 
             #[cfg(feature = "p2p-unix")]
@@ -185,7 +224,16 @@ impl Dialer {
                 // Build a Unix socket dialer
                 let variant = unix::UnixDialer::new().await?;
                 let variant = DialerVariant::Unix(variant);
-                Ok(Self { endpoint, variant })
+                Ok(Self { endpoint, variant, noise_keypair: None, local_endpoint: None })
+            }
+
+            #[cfg(any(test, feature = "p2p-memory"))]
+            "memory" => {
+                // Build an in-process memory dialer
+                enforce_hostport!(endpoint);
+                let variant = memory::MemoryDialer::new().await?;
+                let variant = DialerVariant::Memory(variant);
+                Ok(Self { endpoint, variant, noise_keypair: None, local_endpoint: None })
             }
 
             x => Err(Error::UnsupportedTransport(x.to_string())),
@@ -217,6 +265,15 @@ impl Dialer {
                 Ok(Box::new(stream))
             }
 
+            #[cfg(feature = "p2p-tcp")]
+            DialerVariant::TcpNoise(dialer) => {
+                let sockaddr = self.endpoint.socket_addrs(|| None)?;
+                let stream = dialer.do_dial(sockaddr[0], timeout).await?;
+                let noiseupgrade = noise::NoiseUpgrade::new(self.noise_keypair());
+                let stream = noiseupgrade.upgrade_dialer(stream).await?;
+                Ok(Box::new(stream))
+            }
+
             #[cfg(feature = "p2p-tor")]
             DialerVariant::Tor(dialer) => {
                 let host = self.endpoint.host_str().unwrap();
@@ -274,16 +331,45 @@ impl Dialer {
                 Ok(Box::new(stream))
             }
 
+            #[cfg(any(test, feature = "p2p-memory"))]
+            DialerVariant::Memory(dialer) => {
+                let stream = dialer.do_dial(&self.endpoint, self.local_endpoint.as_ref()).await?;
+                Ok(Box::new(stream))
+            }
+
             #[cfg(not(any(
+                test,
                 feature = "p2p-tcp",
                 feature = "p2p-tor",
                 feature = "p2p-nym",
-                feature = "p2p-unix"
+                feature = "p2p-unix",
+                feature = "p2p-memory"
             )))]
             _ => panic!("No compiled p2p transports!"),
         }
     }
 
+    /// Set the static keypair used by Noise transports. Without one, an
+    /// ephemeral keypair is generated for every connection.
+    pub fn with_noise_keypair(mut self, keypair: Arc<NoiseKeypair>) -> Self {
+        self.noise_keypair = Some(keypair);
+        self
+    }
+
+    /// Set our own address. Transports that identify the dialing side
+    /// to the remote, such as `memory://`, present this address.
+    pub fn with_local_endpoint(mut self, endpoint: Url) -> Self {
+        self.local_endpoint = Some(endpoint);
+        self
+    }
+
+    fn noise_keypair(&self) -> Arc<NoiseKeypair> {
+        match self.noise_keypair {
+            Some(ref keypair) => keypair.clone(),
+            None => Arc::new(NoiseKeypair::generate()),
+        }
+    }
+
     /// Return a reference to the `Dialer` endpoint
     pub fn endpoint(&self) -> &Url {
         &self.endpoint
@@ -296,6 +382,8 @@ pub struct Listener {
     endpoint: Url,
     /// The listener variant (transport protocol)
     variant: ListenerVariant,
+    /// Static keypair used by Noise transports
+    noise_keypair: Option<Arc<NoiseKeypair>>,
 }
 
 impl Listener {
@@ -309,7 +397,7 @@ impl Listener {
                 enforce_hostport!(endpoint);
                 let variant = tcp::TcpListener::new(1024).await?;
                 let variant = ListenerVariant::Tcp(variant);
-                Ok(Self { endpoint, variant })
+                Ok(Self { endpoint, variant, noise_keypair: None })
             }
 
             #[cfg(feature = "p2p-tcp")]
@@ -318,7 +406,16 @@ impl Listener {
                 enforce_hostport!(endpoint);
                 let variant = tcp::TcpListener::new(1024).await?;
                 let variant = ListenerVariant::TcpTls(variant);
-                Ok(Self { endpoint, variant })
+                Ok(Self { endpoint, variant, noise_keypair: None })
+            }
+
+            #[cfg(feature = "p2p-tcp")]
+            "tcp+noise" => {
+                // Build a TCP listener wrapped with Noise
+                enforce_hostport!(endpoint);
+                let variant = tcp::TcpListener::new(1024).await?;
+                let variant = ListenerVariant::TcpNoise(variant);
+                Ok(Self { endpoint, variant, noise_keypair: None })
             }
 
             #[cfg(feature = "p2p-unix")]
@@ -326,7 +423,15 @@ impl Listener {
                 enforce_abspath!(endpoint);
                 let variant = unix::UnixListener::new().await?;
                 let variant = ListenerVariant::Unix(variant);
-                Ok(Self { endpoint, variant })
+                Ok(Self { endpoint, variant, noise_keypair: None })
+            }
+
+            #[cfg(any(test, feature = "p2p-memory"))]
+            "memory" => {
+                enforce_hostport!(endpoint);
+                let variant = memory::MemoryListener::new().await?;
+                let variant = ListenerVariant::Memory(variant);
+                Ok(Self { endpoint, variant, noise_keypair: None })
             }
 
             x => Err(Error::UnsupportedTransport(x.to_string())),
@@ -353,6 +458,14 @@ impl Listener {
                 Ok(Box::new(l))
             }
 
+            #[cfg(feature = "p2p-tcp")]
+            ListenerVariant::TcpNoise(listener) => {
+                let sockaddr = self.endpoint.socket_addrs(|| None)?;
+                let l = listener.do_listen(sockaddr[0]).await?;
+                let noiseupgrade = noise::NoiseUpgrade::new(self.noise_keypair());
+                Ok(Box::new(tcp::NoiseListener::new(noiseupgrade, l)))
+            }
+
             #[cfg(feature = "p2p-unix")]
             ListenerVariant::Unix(listener) => {
                 let path = self.endpoint.to_file_path()?;
@@ -360,18 +473,49 @@ impl Listener {
                 Ok(Box::new(l))
             }
 
-            #[cfg(not(any(feature = "p2p-tcp", feature = "p2p-unix")))]
+            #[cfg(any(test, feature = "p2p-memory"))]
+            ListenerVariant::Memory(listener) => {
+                let l = listener.do_listen(&self.endpoint).await?;
+                Ok(Box::new(l))
+            }
+
+            #[cfg(not(any(
+                test,
+                feature = "p2p-tcp",
+                feature = "p2p-unix",
+                feature = "p2p-memory"
+            )))]
             _ => panic!("No compiled p2p transports!"),
         }
     }
 
+    /// Set the static keypair used by Noise transports. Without one, an
+    /// ephemeral keypair is generated when listening.
+    pub fn with_noise_keypair(mut self, keypair: Arc<NoiseKeypair>) -> Self {
+        self.noise_keypair = Some(keypair);
+        self
+    }
+
+    fn noise_keypair(&self) -> Arc<NoiseKeypair> {
+        match self.noise_keypair {
+            Some(ref keypair) => keypair.clone(),
+            None => Arc::new(NoiseKeypair::generate()),
+        }
+    }
+
     pub fn endpoint(&self) -> &Url {
         &self.endpoint
     }
 }
 
 /// Wrapper trait for async streams
-pub trait PtStream: AsyncRead + AsyncWrite + Unpin + Send {}
+pub trait PtStream: AsyncRead + AsyncWrite + Unpin + Send {
+    /// Returns the authenticated static key of the remote peer,
+    /// if the transport provides one.
+    fn remote_static_key(&self) -> Option<[u8; NOISE_KEY_LEN]> {
+        None
+    }
+}
 
 #[cfg(feature = "p2p-tcp")]
 impl PtStream for smol::net::TcpStream {}
@@ -379,6 +523,13 @@ impl PtStream for smol::net::TcpStream {}
 #[cfg(feature = "p2p-tcp")]
 impl PtStream for async_rustls::TlsStream<smol::net::TcpStream> {}
 
+#[cfg(feature = "p2p-tcp")]
+impl PtStream for noise::NoiseStream<smol::net::TcpStream> {
+    fn remote_static_key(&self) -> Option<[u8; NOISE_KEY_LEN]> {
+        Some(noise::NoiseStream::remote_static_key(self))
+    }
+}
+
 #[cfg(feature = "p2p-tor")]
 impl PtStream for arti_client::DataStream {}
 
@@ -388,6 +539,9 @@ impl PtStream for async_rustls::TlsStream<arti_client::DataStream> {}
 #[cfg(feature = "p2p-unix")]
 impl PtStream for smol::net::unix::UnixStream {}
 
+#[cfg(any(test, feature = "p2p-memory"))]
+impl PtStream for memory::MemoryStream {}
+
 /// Wrapper trait for async listeners
 #[async_trait]
 pub trait PtListener: Send + Sync + Unpin {
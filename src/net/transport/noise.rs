@@ -0,0 +1,487 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{
+    fmt, fs,
+    io::{self, ErrorKind},
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::ready;
+use log::debug;
+use smol::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use snow::{
+    params::NoiseParams,
+    resolvers::{CryptoResolver, DefaultResolver},
+    Builder, HandshakeState, TransportState,
+};
+
+use crate::{system::timeout::timeout, util::file::load_file, Error, Result};
+
+/// Noise handshake pattern and primitives used by the transport
+const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+
+/// Maximum size of a single Noise message
+const MAX_FRAME_LEN: usize = 65535;
+
+/// Length of the AEAD authentication tag appended to each frame
+const TAG_LEN: usize = 16;
+
+/// Maximum plaintext carried by a single frame
+const MAX_PAYLOAD_LEN: usize = MAX_FRAME_LEN - TAG_LEN;
+
+/// Time allowed for completing the Noise handshake (in seconds)
+const HANDSHAKE_TIMEOUT: u64 = 10;
+
+/// Length of a Noise static key
+pub const NOISE_KEY_LEN: usize = 32;
+
+/// Static Curve25519 keypair identifying a node on Noise transports
+#[derive(Clone)]
+pub struct NoiseKeypair {
+    secret: [u8; NOISE_KEY_LEN],
+    public: [u8; NOISE_KEY_LEN],
+}
+
+impl NoiseKeypair {
+    /// Generate a new random keypair
+    pub fn generate() -> Self {
+        let keypair = Builder::new(NOISE_PARAMS.parse().unwrap()).generate_keypair().unwrap();
+
+        let mut secret = [0u8; NOISE_KEY_LEN];
+        let mut public = [0u8; NOISE_KEY_LEN];
+        secret.copy_from_slice(&keypair.private);
+        public.copy_from_slice(&keypair.public);
+
+        Self { secret, public }
+    }
+
+    /// Load a keypair from the given path. If the file does not exist,
+    /// a new keypair is generated and written to it.
+    pub fn load_or_create(path: &Path) -> Result<Self> {
+        if path.exists() {
+            let contents = load_file(path)?;
+            let Ok(bytes) = bs58::decode(contents.trim()).into_vec() else {
+                return Err(Error::NoiseError("Invalid keyfile encoding".to_string()))
+            };
+
+            if bytes.len() != NOISE_KEY_LEN * 2 {
+                return Err(Error::NoiseError("Invalid keyfile length".to_string()))
+            }
+
+            let mut secret = [0u8; NOISE_KEY_LEN];
+            secret.copy_from_slice(&bytes[..NOISE_KEY_LEN]);
+
+            // Only the secret key is trusted, the stored public key has
+            // to be the one derived from it.
+            let public = Self::derive_public(&secret);
+            if public[..] != bytes[NOISE_KEY_LEN..] {
+                return Err(Error::NoiseError("Keyfile public key does not match".to_string()))
+            }
+
+            return Ok(Self { secret, public })
+        }
+
+        let keypair = Self::generate();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut bytes = keypair.secret.to_vec();
+        bytes.extend_from_slice(&keypair.public);
+
+        // The keyfile holds our secret key, so it is created private
+        // before anything gets written to it.
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let mut file = options.open(path)?;
+        io::Write::write_all(&mut file, bs58::encode(bytes).into_string().as_bytes())?;
+
+        Ok(keypair)
+    }
+
+    /// Returns the public static key
+    pub fn public_key(&self) -> [u8; NOISE_KEY_LEN] {
+        self.public
+    }
+
+    /// Derive the public key belonging to the given secret key
+    fn derive_public(secret: &[u8; NOISE_KEY_LEN]) -> [u8; NOISE_KEY_LEN] {
+        let params: NoiseParams = NOISE_PARAMS.parse().unwrap();
+        let mut dh = DefaultResolver.resolve_dh(&params.dh).unwrap();
+        dh.set(secret);
+
+        let mut public = [0u8; NOISE_KEY_LEN];
+        public.copy_from_slice(dh.pubkey());
+        public
+    }
+}
+
+impl fmt::Debug for NoiseKeypair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NoiseKeypair({})", bs58::encode(self.public).into_string())
+    }
+}
+
+/// Upgrades raw streams to Noise-encrypted streams authenticated by
+/// the node's static key, using the XX handshake pattern.
+#[derive(Clone)]
+pub struct NoiseUpgrade {
+    keypair: Arc<NoiseKeypair>,
+}
+
+impl NoiseUpgrade {
+    pub fn new(keypair: Arc<NoiseKeypair>) -> Self {
+        Self { keypair }
+    }
+
+    /// Perform the handshake as the initiator of the connection
+    pub async fn upgrade_dialer<S>(&self, stream: S) -> Result<NoiseStream<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        timeout(Duration::from_secs(HANDSHAKE_TIMEOUT), self.handshake(stream, true)).await?
+    }
+
+    /// Perform the handshake as the responder of the connection
+    pub async fn upgrade_listener<S>(&self, stream: S) -> Result<NoiseStream<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        timeout(Duration::from_secs(HANDSHAKE_TIMEOUT), self.handshake(stream, false)).await?
+    }
+
+    async fn handshake<S>(&self, mut stream: S, initiator: bool) -> Result<NoiseStream<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let builder =
+            Builder::new(NOISE_PARAMS.parse().unwrap()).local_private_key(&self.keypair.secret);
+
+        let mut buf = vec![0u8; MAX_FRAME_LEN];
+
+        // -> e
+        // <- e, ee, s, es
+        // -> s, se
+        let mut hs = if initiator {
+            let mut hs = builder.build_initiator()?;
+            write_handshake(&mut stream, &mut hs, &mut buf).await?;
+            read_handshake(&mut stream, &mut hs, &mut buf).await?;
+            write_handshake(&mut stream, &mut hs, &mut buf).await?;
+            hs
+        } else {
+            let mut hs = builder.build_responder()?;
+            read_handshake(&mut stream, &mut hs, &mut buf).await?;
+            write_handshake(&mut stream, &mut hs, &mut buf).await?;
+            read_handshake(&mut stream, &mut hs, &mut buf).await?;
+            hs
+        };
+
+        let Some(remote) = hs.get_remote_static() else {
+            return Err(Error::NoiseError("Missing remote static key".to_string()))
+        };
+
+        let mut remote_static = [0u8; NOISE_KEY_LEN];
+        remote_static.copy_from_slice(remote);
+
+        debug!(
+            target: "net::transport::noise::handshake()",
+            "Noise handshake complete, remote static key: {}",
+            bs58::encode(remote_static).into_string(),
+        );
+
+        let state = hs.into_transport_mode()?;
+        Ok(NoiseStream::new(stream, state, remote_static))
+    }
+}
+
+/// Write a length-prefixed frame to the stream
+async fn send_frame<S: AsyncWrite + Unpin>(stream: &mut S, frame: &[u8]) -> Result<()> {
+    stream.write_all(&(frame.len() as u16).to_be_bytes()).await?;
+    stream.write_all(frame).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Read a length-prefixed frame from the stream
+async fn recv_frame<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Vec<u8>> {
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len).await?;
+    let mut frame = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut frame).await?;
+    Ok(frame)
+}
+
+async fn write_handshake<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    hs: &mut HandshakeState,
+    buf: &mut [u8],
+) -> Result<()> {
+    let len = hs.write_message(&[], buf)?;
+    send_frame(stream, &buf[..len]).await
+}
+
+async fn read_handshake<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    hs: &mut HandshakeState,
+    buf: &mut [u8],
+) -> Result<()> {
+    let frame = recv_frame(stream).await?;
+    hs.read_message(&frame, buf)?;
+    Ok(())
+}
+
+/// Stream encrypted with the transport keys of a completed Noise handshake.
+/// Data is carried in frames of at most [`MAX_FRAME_LEN`] bytes, each
+/// prefixed with its big-endian `u16` length.
+pub struct NoiseStream<S> {
+    inner: S,
+    state: TransportState,
+    remote_static: [u8; NOISE_KEY_LEN],
+    /// Incoming ciphertext frame, including the length prefix
+    read_buf: Vec<u8>,
+    /// Number of bytes of `read_buf` filled so far
+    read_pos: usize,
+    /// Decrypted data not yet returned to the reader
+    plaintext: Vec<u8>,
+    /// Number of bytes of `plaintext` already returned
+    plaintext_pos: usize,
+    /// Outgoing ciphertext frame, including the length prefix
+    write_buf: Vec<u8>,
+    /// Number of bytes of `write_buf` already written
+    write_pos: usize,
+}
+
+impl<S> NoiseStream<S> {
+    fn new(inner: S, state: TransportState, remote_static: [u8; NOISE_KEY_LEN]) -> Self {
+        Self {
+            inner,
+            state,
+            remote_static,
+            read_buf: vec![0u8; 2 + MAX_FRAME_LEN],
+            read_pos: 0,
+            plaintext: vec![],
+            plaintext_pos: 0,
+            write_buf: vec![],
+            write_pos: 0,
+        }
+    }
+
+    /// Returns the authenticated static key of the remote peer
+    pub fn remote_static_key(&self) -> [u8; NOISE_KEY_LEN] {
+        self.remote_static
+    }
+}
+
+impl<S: AsyncWrite + Unpin> NoiseStream<S> {
+    /// Write out any pending ciphertext
+    fn poll_write_buf(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.write_pos < self.write_buf.len() {
+            let n = ready!(
+                Pin::new(&mut self.inner).poll_write(cx, &self.write_buf[self.write_pos..])
+            )?;
+            if n == 0 {
+                return Poll::Ready(Err(ErrorKind::WriteZero.into()))
+            }
+            self.write_pos += n;
+        }
+
+        self.write_buf.clear();
+        self.write_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for NoiseStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        loop {
+            // Return any leftover decrypted data first
+            if this.plaintext_pos < this.plaintext.len() {
+                let n = buf.len().min(this.plaintext.len() - this.plaintext_pos);
+                buf[..n]
+                    .copy_from_slice(&this.plaintext[this.plaintext_pos..this.plaintext_pos + n]);
+                this.plaintext_pos += n;
+                return Poll::Ready(Ok(n))
+            }
+
+            // Read the length prefix, then the whole frame
+            let frame_end = if this.read_pos < 2 {
+                2
+            } else {
+                2 + u16::from_be_bytes([this.read_buf[0], this.read_buf[1]]) as usize
+            };
+
+            if this.read_pos < frame_end {
+                let n = ready!(Pin::new(&mut this.inner)
+                    .poll_read(cx, &mut this.read_buf[this.read_pos..frame_end]))?;
+                if n == 0 {
+                    // Clean EOF is only possible between frames
+                    if this.read_pos == 0 {
+                        return Poll::Ready(Ok(0))
+                    }
+                    return Poll::Ready(Err(ErrorKind::UnexpectedEof.into()))
+                }
+                this.read_pos += n;
+                continue
+            }
+
+            // An empty frame carries no data, keep reading
+            if frame_end == 2 {
+                this.read_pos = 0;
+                continue
+            }
+
+            this.plaintext.resize(frame_end - 2, 0);
+            let n = match this.state.read_message(&this.read_buf[2..frame_end], &mut this.plaintext)
+            {
+                Ok(n) => n,
+                Err(e) => return Poll::Ready(Err(io::Error::new(ErrorKind::InvalidData, e))),
+            };
+            this.plaintext.truncate(n);
+            this.plaintext_pos = 0;
+            this.read_pos = 0;
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for NoiseStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        // Finish writing the previous frame before encrypting another one
+        ready!(this.poll_write_buf(cx))?;
+
+        let len = buf.len().min(MAX_PAYLOAD_LEN);
+        this.write_buf.resize(2 + len + TAG_LEN, 0);
+        let n = match this.state.write_message(&buf[..len], &mut this.write_buf[2..]) {
+            Ok(n) => n,
+            Err(e) => return Poll::Ready(Err(io::Error::new(ErrorKind::InvalidInput, e))),
+        };
+        this.write_buf[..2].copy_from_slice(&(n as u16).to_be_bytes());
+        this.write_buf.truncate(2 + n);
+
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_buf(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_buf(cx))?;
+        Pin::new(&mut this.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::file::save_file;
+
+    #[test]
+    fn test_noise_handshake() {
+        smol::block_on(async {
+            let listener = smol::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+
+            let dialer_key = Arc::new(NoiseKeypair::generate());
+            let listener_key = Arc::new(NoiseKeypair::generate());
+
+            let accept = {
+                let listener_key = listener_key.clone();
+                smol::spawn(async move {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    NoiseUpgrade::new(listener_key).upgrade_listener(stream).await.unwrap()
+                })
+            };
+
+            let stream = smol::net::TcpStream::connect(addr).await.unwrap();
+            let mut dialer =
+                NoiseUpgrade::new(dialer_key.clone()).upgrade_dialer(stream).await.unwrap();
+            let mut listener = accept.await;
+
+            assert_eq!(dialer.remote_static_key(), listener_key.public_key());
+            assert_eq!(listener.remote_static_key(), dialer_key.public_key());
+
+            // Larger than a single frame
+            let payload: Vec<u8> = (0..200_000).map(|i| i as u8).collect();
+            let send = {
+                let payload = payload.clone();
+                smol::spawn(async move {
+                    dialer.write_all(&payload).await.unwrap();
+                    dialer.flush().await.unwrap();
+                })
+            };
+
+            let mut received = vec![0u8; payload.len()];
+            listener.read_exact(&mut received).await.unwrap();
+            send.await;
+            assert_eq!(received, payload);
+        });
+    }
+
+    #[test]
+    fn test_noise_keyfile() {
+        let path = std::env::temp_dir().join("darkfi_test_noise_keyfile");
+        let _ = fs::remove_file(&path);
+
+        // A created keyfile loads back to the same keypair
+        let keypair = NoiseKeypair::load_or_create(&path).unwrap();
+        let loaded = NoiseKeypair::load_or_create(&path).unwrap();
+        assert_eq!(loaded.public_key(), keypair.public_key());
+        assert_eq!(NoiseKeypair::derive_public(&keypair.secret), keypair.public_key());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // A public key that doesn't belong to the secret key is refused
+        let mut bytes = keypair.secret.to_vec();
+        bytes.extend_from_slice(&NoiseKeypair::generate().public_key());
+        save_file(&path, &bs58::encode(bytes).into_string()).unwrap();
+        assert!(NoiseKeypair::load_or_create(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -21,11 +21,18 @@ use std::{io, time::Duration};
 use async_rustls::{TlsAcceptor, TlsStream};
 use async_trait::async_trait;
 use log::debug;
-use smol::net::{SocketAddr, TcpListener as SmolTcpListener, TcpStream};
+use smol::{
+    channel::{Receiver, Sender},
+    net::{SocketAddr, TcpListener as SmolTcpListener, TcpStream},
+    Task,
+};
 use socket2::{Domain, Socket, TcpKeepalive, Type};
 use url::Url;
 
-use super::{PtListener, PtStream};
+use super::{
+    noise::{NoiseStream, NoiseUpgrade},
+    PtListener, PtStream,
+};
 use crate::Result;
 
 /// TCP Dialer implementation
@@ -169,3 +176,69 @@ impl PtListener for (TlsAcceptor, SmolTcpListener) {
         Ok((Box::new(TlsStream::Server(stream)), url))
     }
 }
+
+/// TCP listener upgrading incoming connections with Noise. Handshakes run
+/// in their own tasks so a slow or stalled peer can't hold up accepting
+/// other connections; finished streams are handed over through a channel.
+pub struct NoiseListener {
+    recv: Receiver<io::Result<(NoiseStream<TcpStream>, Url)>>,
+    _accept_task: Task<()>,
+}
+
+impl NoiseListener {
+    pub(crate) fn new(upgrade: NoiseUpgrade, listener: SmolTcpListener) -> Self {
+        let (send, recv) = smol::channel::unbounded();
+        let accept_task = smol::spawn(Self::accept_loop(upgrade, listener, send));
+        Self { recv, _accept_task: accept_task }
+    }
+
+    async fn accept_loop(
+        upgrade: NoiseUpgrade,
+        listener: SmolTcpListener,
+        send: Sender<io::Result<(NoiseStream<TcpStream>, Url)>>,
+    ) {
+        loop {
+            let (stream, peer_addr) = match listener.accept().await {
+                Ok((s, a)) => (s, a),
+                Err(e) => {
+                    // The caller decides whether the error is fatal
+                    if send.send(Err(e)).await.is_err() {
+                        return
+                    }
+                    continue
+                }
+            };
+
+            let upgrade = upgrade.clone();
+            let send = send.clone();
+            smol::spawn(async move {
+                let stream = match upgrade.upgrade_listener(stream).await {
+                    Ok(v) => v,
+                    Err(e) => {
+                        debug!(
+                            target: "net::tcp::noise_listener",
+                            "Noise handshake with {} failed: {}", peer_addr, e,
+                        );
+                        return
+                    }
+                };
+
+                let url = Url::parse(&format!("tcp+noise://{}", peer_addr)).unwrap();
+                let _ = send.send(Ok((stream, url))).await;
+            })
+            .detach();
+        }
+    }
+}
+
+#[async_trait]
+impl PtListener for NoiseListener {
+    async fn next(&self) -> std::io::Result<(Box<dyn PtStream>, Url)> {
+        let (stream, url) = match self.recv.recv().await {
+            Ok(v) => v?,
+            Err(e) => return Err(io::Error::new(io::ErrorKind::BrokenPipe, e.to_string())),
+        };
+
+        Ok((Box::new(stream), url))
+    }
+}
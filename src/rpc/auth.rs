@@ -0,0 +1,269 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Authentication and access control for JSON-RPC servers.
+//!
+//! Every method of a [`RequestHandler`](super::server::RequestHandler) has
+//! a [`MethodAccess`] level. A listener is configured with the level that
+//! can be called without authenticating, and with tokens granting higher
+//! levels. Clients authenticate by calling `rpc.auth` with a token as the
+//! only parameter, after which the level of the token applies for the rest
+//! of the connection. Over HTTP, the token can also be given in an
+//! `Authorization: Bearer` header. A connection only gets
+//! [`MAX_AUTH_FAILURES`] attempts at guessing a token, and a peer address
+//! that used them up is refused for [`AUTH_BACKOFF`], even when it
+//! reconnects.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use url::Url;
+
+use crate::{Error, Result};
+
+/// Name of the method clients authenticate with
+pub const AUTH_METHOD: &str = "rpc.auth";
+
+/// Failed authentication attempts allowed per connection, after which
+/// every token is refused
+pub const MAX_AUTH_FAILURES: usize = 3;
+
+/// Time a peer address is refused authentication after it failed
+/// [`MAX_AUTH_FAILURES`] times, over any number of connections
+pub const AUTH_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Access level of a JSON-RPC method, from least to most privileged.
+/// A connection allowed some level can call the methods of any lower level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MethodAccess {
+    /// Safe to expose to anyone, e.g. blockchain queries
+    Public,
+    /// Reads the node state without changing it
+    ReadOnly,
+    /// Changes the node state or touches the wallet
+    Admin,
+}
+
+impl FromStr for MethodAccess {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "public" => Ok(Self::Public),
+            "readonly" => Ok(Self::ReadOnly),
+            "admin" => Ok(Self::Admin),
+            _ => Err(Error::ParseFailed("Invalid RPC access level")),
+        }
+    }
+}
+
+impl fmt::Display for MethodAccess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Public => write!(f, "public"),
+            Self::ReadOnly => write!(f, "readonly"),
+            Self::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// Access control settings of a JSON-RPC listener
+#[derive(Clone, Debug)]
+pub struct AccessSettings {
+    /// Most privileged level callable without authenticating
+    pub unauthenticated: MethodAccess,
+    /// Tokens accepted by `rpc.auth`, with the level they grant
+    pub tokens: Vec<(String, MethodAccess)>,
+    /// Failed attempts by peer address, shared by the listener's connections
+    pub(super) failures: Arc<PeerFailures>,
+}
+
+impl Default for AccessSettings {
+    /// Everything is callable, as with no access control at all
+    fn default() -> Self {
+        Self { unauthenticated: MethodAccess::Admin, tokens: vec![], failures: Arc::default() }
+    }
+}
+
+impl AccessSettings {
+    /// Create access settings from configuration values. Tokens are given
+    /// as `level:token` strings, e.g. `admin:hunter2`.
+    pub fn new(unauthenticated: &str, tokens: &[String]) -> Result<Self> {
+        let unauthenticated = unauthenticated.parse()?;
+
+        let mut parsed = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            let Some((level, token)) = token.split_once(':') else {
+                return Err(Error::ParseFailed("RPC tokens must be given as level:token"))
+            };
+            if token.is_empty() {
+                return Err(Error::ParseFailed("Empty RPC token"))
+            }
+            parsed.push((token.to_string(), level.parse()?));
+        }
+
+        Ok(Self { unauthenticated, tokens: parsed, failures: Arc::default() })
+    }
+
+    /// Return the level granted by the given token, if it is valid.
+    /// Every token is compared in constant time, so the response time
+    /// doesn't tell how close a guess was.
+    pub fn authenticate(&self, token: &str) -> Option<MethodAccess> {
+        let mut granted = None;
+        for (t, access) in &self.tokens {
+            if constant_time_eq(t.as_bytes(), token.as_bytes()) {
+                granted = granted.max(Some(*access));
+            }
+        }
+        granted
+    }
+}
+
+/// Failed authentication attempts of peer addresses, kept across their
+/// connections
+#[derive(Debug, Default)]
+pub struct PeerFailures(Mutex<HashMap<String, (usize, Instant)>>);
+
+impl PeerFailures {
+    /// Whether the peer used up its attempts less than [`AUTH_BACKOFF`] ago
+    fn is_blocked(&self, peer: &str) -> bool {
+        let failures = self.0.lock().unwrap();
+        match failures.get(peer) {
+            Some((n, last)) => *n >= MAX_AUTH_FAILURES && last.elapsed() < AUTH_BACKOFF,
+            None => false,
+        }
+    }
+
+    /// Count a failed attempt of the peer. Attempts older than
+    /// [`AUTH_BACKOFF`] are forgotten.
+    fn record(&self, peer: &str) {
+        let mut failures = self.0.lock().unwrap();
+        failures.retain(|_, (_, last)| last.elapsed() < AUTH_BACKOFF);
+
+        let entry = failures.entry(peer.to_string()).or_insert((0, Instant::now()));
+        entry.0 += 1;
+        entry.1 = Instant::now();
+    }
+}
+
+/// Access of a single connection, raised by authenticating
+#[derive(Clone, Debug)]
+pub struct ConnectionAccess {
+    /// Most privileged level the connection may call
+    pub level: MethodAccess,
+    /// Number of failed authentication attempts
+    failures: usize,
+    /// Host of the peer, if the transport has one
+    peer: Option<String>,
+}
+
+impl ConnectionAccess {
+    pub fn new(level: MethodAccess, peer: &Url) -> Self {
+        Self { level, failures: 0, peer: peer.host_str().map(|h| h.to_string()) }
+    }
+
+    /// Drop the level of the connection back to the given one. Failed
+    /// attempts are still counted.
+    pub fn reset(&mut self, level: MethodAccess) {
+        self.level = level;
+    }
+
+    /// Raise the level of the connection to the one granted by the given
+    /// token, and return the resulting level. Returns `None` if the token
+    /// is invalid, or if the connection or its peer address used up its
+    /// attempts.
+    pub fn authenticate(&mut self, settings: &AccessSettings, token: &str) -> Option<MethodAccess> {
+        if self.failures >= MAX_AUTH_FAILURES {
+            return None
+        }
+
+        if let Some(peer) = &self.peer {
+            if settings.failures.is_blocked(peer) {
+                return None
+            }
+        }
+
+        let Some(granted) = settings.authenticate(token) else {
+            self.failures += 1;
+            if let Some(peer) = &self.peer {
+                settings.failures.record(peer);
+            }
+            return None
+        };
+
+        self.level = self.level.max(granted);
+        Some(self.level)
+    }
+}
+
+/// Compare the hashes of both values, so that neither the position of
+/// the first difference nor the length of the values leak.
+/// [`blake3::Hash`] equality is constant time.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    blake3::hash(a) == blake3::hash(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn access_settings() {
+        let tokens = vec!["readonly:foo".to_string(), "admin:bar:baz".to_string()];
+        let settings = AccessSettings::new("public", &tokens).unwrap();
+        assert_eq!(settings.unauthenticated, MethodAccess::Public);
+        assert_eq!(settings.authenticate("foo"), Some(MethodAccess::ReadOnly));
+        assert_eq!(settings.authenticate("bar:baz"), Some(MethodAccess::Admin));
+        assert_eq!(settings.authenticate("bar"), None);
+        assert_eq!(settings.authenticate(""), None);
+
+        assert!(MethodAccess::Public < MethodAccess::ReadOnly);
+        assert!(MethodAccess::ReadOnly < MethodAccess::Admin);
+
+        // Attempts are limited per connection
+        let peer = Url::parse("unix:///tmp/rpc.sock").unwrap();
+        let mut access = ConnectionAccess::new(MethodAccess::Public, &peer);
+        assert_eq!(access.authenticate(&settings, "foo"), Some(MethodAccess::ReadOnly));
+        for _ in 0..MAX_AUTH_FAILURES {
+            assert_eq!(access.authenticate(&settings, "guess"), None);
+        }
+        assert_eq!(access.authenticate(&settings, "bar:baz"), None);
+        assert_eq!(access.level, MethodAccess::ReadOnly);
+
+        // and per peer address, across connections
+        let peer = Url::parse("tcp://127.0.0.1:1337").unwrap();
+        for _ in 0..MAX_AUTH_FAILURES {
+            let mut access = ConnectionAccess::new(MethodAccess::Public, &peer);
+            assert_eq!(access.authenticate(&settings, "guess"), None);
+        }
+        let mut access = ConnectionAccess::new(MethodAccess::Public, &peer);
+        assert_eq!(access.authenticate(&settings, "bar:baz"), None);
+        let other = Url::parse("tcp://127.0.0.2:1337").unwrap();
+        let mut access = ConnectionAccess::new(MethodAccess::Public, &other);
+        assert_eq!(access.authenticate(&settings, "bar:baz"), Some(MethodAccess::Admin));
+
+        assert!(AccessSettings::new("root", &[]).is_err());
+        assert!(AccessSettings::new("admin", &["foo".to_string()]).is_err());
+        assert!(AccessSettings::new("admin", &["admin:".to_string()]).is_err());
+    }
+}
@@ -16,30 +16,81 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::sync::Arc;
+//! JSON-RPC client implementation.
+//!
+//! A single connection is shared by any number of concurrent callers.
+//! Requests are written to the stream as soon as they are made, and a
+//! background task reads the incoming objects and routes them: replies
+//! and errors go to the caller waiting on the request ID, while
+//! notifications go to the subscriptions made for their method.
+//! Several requests can also be sent at once as a JSON-RPC batch.
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use log::{debug, error};
-use smol::{channel, io::BufReader, Executor};
+use smol::{
+    channel, future,
+    io::{BufReader, ReadHalf, WriteHalf},
+    lock::Mutex,
+    Executor,
+};
 use tinyjson::JsonValue;
 use url::Url;
 
 use super::{
-    common::{read_from_stream, write_to_stream, INIT_BUF_SIZE},
+    common::{
+        read_from_stream, write_batch_to_stream, write_to_stream, INIT_BUF_SIZE, MAX_BATCH_LEN,
+        MAX_REPLY_SIZE, READ_TIMEOUT,
+    },
     jsonrpc::*,
 };
 use crate::{
     net::transport::{Dialer, PtStream},
-    system::{StoppableTask, StoppableTaskPtr, SubscriberPtr},
+    system::{timeout::timeout, StoppableTask, StoppableTaskPtr, SubscriberPtr},
     Error, Result,
 };
 
+/// An active subscription made with [`RpcClient::subscribe()`]
+struct Subscription {
+    /// Method of the notifications sent to the subscriber
+    method: String,
+    /// Subscriber notified with incoming notifications
+    sub: SubscriberPtr<JsonResult>,
+    /// Channel receiving the replies to the subscription request
+    rep_send: channel::Sender<JsonResult>,
+}
+
+/// Requests waiting for a reply, and active subscriptions, by request ID.
+/// Pending batches are also kept by the ID of their first request.
+#[derive(Default)]
+struct Routes {
+    pending: HashMap<JsonId, channel::Sender<JsonResult>>,
+    subscriptions: HashMap<JsonId, Subscription>,
+    batches: HashMap<JsonId, channel::Sender<JsonResult>>,
+}
+
+impl Routes {
+    /// Pick a request ID that is not in use on the connection
+    fn free_id(&self, mut id: JsonId) -> JsonId {
+        while self.pending.contains_key(&id) || self.subscriptions.contains_key(&id) {
+            id = JsonId::random();
+        }
+        id
+    }
+}
+
+/// Objects written to the connection
+enum Outgoing {
+    Request(JsonRequest),
+    Batch(Vec<JsonRequest>),
+}
+
 /// JSON-RPC client implementation using asynchronous channels.
 pub struct RpcClient {
-    /// The channel used to send JSON-RPC request objects.
-    /// The `bool` marks if we should have a reply read timeout.
-    req_send: channel::Sender<(JsonRequest, bool)>,
-    /// The channel used to read the JSON-RPC response object.
-    rep_recv: channel::Receiver<JsonResult>,
+    /// The channel used to send JSON-RPC request objects to the connection
+    req_send: channel::Sender<Outgoing>,
+    /// Where incoming replies and notifications should go
+    routes: Arc<Mutex<Routes>>,
     /// The stoppable task pointer, used on [`RpcClient::stop()`]
     task: StoppableTaskPtr,
 }
@@ -51,20 +102,29 @@ impl RpcClient {
     pub async fn new(endpoint: Url, ex: Arc<Executor<'_>>) -> Result<Self> {
         // Instantiate communication channels
         let (req_send, req_recv) = channel::unbounded();
-        let (rep_send, rep_recv) = channel::unbounded();
+        let routes = Arc::new(Mutex::new(Routes::default()));
 
         // Instantiate Dialer and dial the server
         // TODO: Could add a timeout here
         let dialer = Dialer::new(endpoint).await?;
         let stream = dialer.dial(None).await?;
+        let (reader, writer) = smol::io::split(stream);
 
-        // Create the StoppableTask running the request-reply loop.
+        // Create the StoppableTask running the read and write loops.
         // This represents the actual connection, which can be stopped
         // using `RpcClient::stop()`.
         let task = StoppableTask::new();
+        let routes_ = routes.clone();
         task.clone().start(
-            Self::reqrep_loop(stream, rep_send, req_recv),
+            future::or(
+                Self::read_loop(BufReader::new(reader), routes.clone()),
+                Self::write_loop(writer, req_recv),
+            ),
             |res| async move {
+                // Dropping the routes wakes up everyone still waiting
+                // with an error.
+                *routes_.lock().await = Routes::default();
+
                 match res {
                     Ok(()) | Err(Error::RpcClientStopped) => {}
                     Err(e) => error!(target: "rpc::client", "[RPC] Client error: {}", e),
@@ -74,68 +134,265 @@ impl RpcClient {
             ex.clone(),
         );
 
-        Ok(Self { req_send, rep_recv, task })
+        Ok(Self { req_send, routes, task })
     }
 
     /// Stop the JSON-RPC client. This will trigger `stop()` on the inner
-    /// `StoppableTaskPtr` resulting in stopping the internal reqrep loop
-    /// and therefore closing the connection.
+    /// `StoppableTaskPtr` resulting in stopping the internal loops and
+    /// therefore closing the connection.
     pub async fn stop(&self) {
         self.task.stop().await;
     }
 
-    /// Internal function that loops on a given stream and multiplexes the data
-    async fn reqrep_loop(
-        stream: Box<dyn PtStream>,
-        rep_send: channel::Sender<JsonResult>,
-        req_recv: channel::Receiver<(JsonRequest, bool)>,
+    /// Internal function that writes outgoing requests to the stream
+    async fn write_loop(
+        mut writer: WriteHalf<Box<dyn PtStream>>,
+        req_recv: channel::Receiver<Outgoing>,
     ) -> Result<()> {
-        debug!(target: "rpc::client::reqrep_loop()", "Starting reqrep loop");
+        loop {
+            match req_recv.recv().await? {
+                Outgoing::Request(req) => {
+                    write_to_stream(&mut writer, &JsonResult::Request(req)).await?
+                }
+                Outgoing::Batch(reqs) => {
+                    let reqs: Vec<_> = reqs.into_iter().map(JsonResult::Request).collect();
+                    write_batch_to_stream(&mut writer, &reqs).await?
+                }
+            }
+        }
+    }
 
-        let (reader, mut writer) = smol::io::split(stream);
-        let mut reader = BufReader::new(reader);
+    /// Internal function that reads incoming objects from the stream and
+    /// routes them to their recipient
+    async fn read_loop(
+        mut reader: BufReader<ReadHalf<Box<dyn PtStream>>>,
+        routes: Arc<Mutex<Routes>>,
+    ) -> Result<()> {
+        debug!(target: "rpc::client::read_loop()", "Starting read loop");
 
         loop {
             let mut buf = Vec::with_capacity(INIT_BUF_SIZE);
+            let _ = read_from_stream(&mut reader, &mut buf, MAX_REPLY_SIZE, false).await?;
+            let val: JsonValue = String::from_utf8(buf)?.parse()?;
+
+            // The replies to a batch arrive as an array, and are routed
+            // one by one. A bad reply in a batch doesn't spoil the others.
+            match val {
+                JsonValue::Array(batch) => {
+                    for val in &batch {
+                        let rep = match JsonResult::try_from_value(val) {
+                            Ok(v) => v,
+                            Err(e) => {
+                                error!(
+                                    target: "rpc::client::read_loop()",
+                                    "[RPC] Skipping invalid reply in batch: {}", e,
+                                );
+                                continue
+                            }
+                        };
+                        Self::route(&routes, rep).await?;
+                    }
+                }
+                _ => Self::route(&routes, JsonResult::try_from_value(&val)?).await?,
+            }
+        }
+    }
 
-            let (request, with_timeout) = req_recv.recv().await?;
+    /// Internal function that passes an incoming object to its recipient
+    async fn route(routes: &Mutex<Routes>, rep: JsonResult) -> Result<()> {
+        let id = match &rep {
+            JsonResult::Response(r) => r.id.clone(),
+            // An error without ID is the server refusing a whole message,
+            // and we can't tell which one, so every pending batch fails.
+            JsonResult::Error(e) if e.id == JsonId::Null => {
+                debug!(target: "rpc::client", "<-- {}", e.stringify()?);
+                for rep_send in routes.lock().await.batches.values() {
+                    let _ = rep_send.try_send(rep.clone());
+                }
+                return Ok(())
+            }
+            JsonResult::Error(e) => e.id.clone(),
+            JsonResult::Notification(n) => {
+                debug!(target: "rpc::client", "<-- {}", n.stringify()?);
+                let subs: Vec<_> = routes
+                    .lock()
+                    .await
+                    .subscriptions
+                    .values()
+                    .filter(|s| s.method == n.method)
+                    .map(|s| s.sub.clone())
+                    .collect();
+
+                if subs.is_empty() {
+                    debug!(
+                        target: "rpc::client::route()",
+                        "Dropping notification without subscription: {}", n.method,
+                    );
+                }
 
-            let request = JsonResult::Request(request);
-            write_to_stream(&mut writer, &request).await?;
+                for sub in subs {
+                    sub.notify(rep.clone()).await;
+                }
+                return Ok(())
+            }
+            // These are not parsed from incoming objects
+            _ => unreachable!(),
+        };
 
-            let _ = read_from_stream(&mut reader, &mut buf, with_timeout).await?;
-            let val: JsonValue = String::from_utf8(buf)?.parse()?;
-            let rep = JsonResult::try_from_value(&val)?;
-            rep_send.send(rep).await?;
-        }
+        let mut routes = routes.lock().await;
+        let rep_send = match routes.pending.remove(&id) {
+            Some(v) => v,
+            None => match routes.subscriptions.get(&id) {
+                Some(s) => s.rep_send.clone(),
+                None => {
+                    debug!(
+                        target: "rpc::client::route()",
+                        "Dropping reply to unknown request {}", id,
+                    );
+                    return Ok(())
+                }
+            },
+        };
+        drop(routes);
+
+        // The caller might have given up on the reply already
+        let _ = rep_send.send(rep).await;
+        Ok(())
     }
 
     /// Send a given JSON-RPC request over the instantiated client and
     /// return a possible result. If the response is an error, returns
-    /// a `JsonRpcError`.
+    /// a `JsonRpcError`. Times out if no reply arrives within 30 seconds.
     pub async fn request(&self, req: JsonRequest) -> Result<JsonValue> {
-        let req_id = req.id;
+        self.request_with_timeout(req, READ_TIMEOUT).await
+    }
+
+    /// Send a given JSON-RPC request over the instantiated client and
+    /// return a possible result, or an error if no reply arrives within
+    /// the given duration. If the response is an error, returns a
+    /// `JsonRpcError`.
+    pub async fn request_with_timeout(
+        &self,
+        mut req: JsonRequest,
+        dur: Duration,
+    ) -> Result<JsonValue> {
+        let (rep_send, rep_recv) = channel::bounded(1);
+
+        // Concurrent requests must not share an ID, otherwise we could
+        // not tell their replies apart.
+        let mut routes = self.routes.lock().await;
+        req.id = routes.free_id(req.id);
+        routes.pending.insert(req.id.clone(), rep_send);
+        drop(routes);
+
+        let req_id = req.id.clone();
         debug!(target: "rpc::client", "--> {}", req.stringify()?);
 
         // If the connection is closed, the sender will get an error
-        // for sending to a closed channel.
-        self.req_send.send((req, true)).await?;
+        // for sending to a closed channel, and the receiver will get
+        // an error for waiting on a closed channel.
+        let reply = match self.req_send.send(Outgoing::Request(req)).await {
+            Ok(()) => timeout(dur, rep_recv.recv()).await,
+            Err(e) => {
+                self.routes.lock().await.pending.remove(&req_id);
+                return Err(e.into())
+            }
+        };
 
-        // If the connection is closed, the receiver will get an error
-        // for waiting on a closed channel.
-        let reply = self.rep_recv.recv().await?;
+        let reply = match reply {
+            Ok(v) => v?,
+            Err(e) => {
+                self.routes.lock().await.pending.remove(&req_id);
+                return Err(e.into())
+            }
+        };
 
-        // Handle the response
-        match reply {
-            JsonResult::Response(rep) | JsonResult::SubscriberWithReply(_, rep) => {
-                debug!(target: "rpc::client", "<-- {}", rep.stringify()?);
+        Self::reply_result(reply, req_id)
+    }
 
-                // Check if the IDs match
-                if req_id != rep.id {
-                    let e = JsonError::new(ErrorCode::IdMismatch, None, rep.id);
-                    return Err(Error::JsonRpcError((e.error.code, e.error.message)))
+    /// Send the given JSON-RPC requests over the instantiated client as
+    /// batches of up to 256 requests, and return their results in the same
+    /// order. A request failing does not fail the others, while the server
+    /// refusing a batch fails the whole call. Times out if the replies to a
+    /// batch don't arrive within 30 seconds.
+    pub async fn batch(&self, reqs: Vec<JsonRequest>) -> Result<Vec<Result<JsonValue>>> {
+        let mut results = Vec::with_capacity(reqs.len());
+        for chunk in reqs.chunks(MAX_BATCH_LEN) {
+            results.extend(self.send_batch(chunk.to_vec()).await?);
+        }
+
+        Ok(results)
+    }
+
+    /// Internal function that sends the given requests as a single batch
+    async fn send_batch(&self, mut reqs: Vec<JsonRequest>) -> Result<Vec<Result<JsonValue>>> {
+        let (rep_send, rep_recv) = channel::bounded(reqs.len() + 1);
+
+        let mut routes = self.routes.lock().await;
+        for req in reqs.iter_mut() {
+            req.id = routes.free_id(req.id.clone());
+            routes.pending.insert(req.id.clone(), rep_send.clone());
+        }
+        routes.batches.insert(reqs[0].id.clone(), rep_send);
+        drop(routes);
+
+        let req_ids: Vec<_> = reqs.iter().map(|req| req.id.clone()).collect();
+        for req in &reqs {
+            debug!(target: "rpc::client", "--> {}", req.stringify()?);
+        }
+
+        // Replies may come in any order, so we collect them by ID
+        let replies = async {
+            let mut replies = HashMap::new();
+            for _ in 0..req_ids.len() {
+                let reply = rep_recv.recv().await?;
+                let id = match &reply {
+                    JsonResult::Response(r) => r.id.clone(),
+                    JsonResult::Error(e) if e.id == JsonId::Null => {
+                        return Err(Error::JsonRpcError((e.error.code, e.error.message.clone())))
+                    }
+                    JsonResult::Error(e) => e.id.clone(),
+                    _ => unreachable!(),
+                };
+                replies.insert(id, reply);
+            }
+            Ok::<_, Error>(replies)
+        };
+
+        let replies = match self.req_send.send(Outgoing::Batch(reqs)).await {
+            Ok(()) => timeout(READ_TIMEOUT, replies).await.map_err(Error::from),
+            Err(e) => Err(e.into()),
+        };
+
+        // Whatever happened, the batch is not waiting anymore
+        let mut routes = self.routes.lock().await;
+        for id in &req_ids {
+            routes.pending.remove(id);
+        }
+        routes.batches.remove(&req_ids[0]);
+        drop(routes);
+
+        let mut replies = replies??;
+        let results = req_ids
+            .into_iter()
+            .map(|id| match replies.remove(&id) {
+                Some(reply) => Self::reply_result(reply, id),
+                None => {
+                    let e = JsonError::new(ErrorCode::InvalidReply, None, id);
+                    Err(Error::JsonRpcError((e.error.code, e.error.message)))
                 }
+            })
+            .collect();
+
+        Ok(results)
+    }
 
+    /// Internal function that turns the reply to a request into its result.
+    /// If the reply is an error, returns a `JsonRpcError`.
+    fn reply_result(reply: JsonResult, req_id: JsonId) -> Result<JsonValue> {
+        match reply {
+            JsonResult::Response(rep) => {
+                debug!(target: "rpc::client", "<-- {}", rep.stringify()?);
                 Ok(rep.result)
             }
 
@@ -144,20 +401,7 @@ impl RpcClient {
                 Err(Error::JsonRpcError((e.error.code, e.error.message)))
             }
 
-            JsonResult::Notification(n) => {
-                debug!(target: "rpc::client", "<-- {}", n.stringify()?);
-                let e = JsonError::new(ErrorCode::InvalidReply, None, req_id);
-                Err(Error::JsonRpcError((e.error.code, e.error.message)))
-            }
-
-            JsonResult::Request(r) => {
-                debug!(target: "rpc::client", "<-- {}", r.stringify()?);
-                let e = JsonError::new(ErrorCode::InvalidReply, None, req_id);
-                Err(Error::JsonRpcError((e.error.code, e.error.message)))
-            }
-
-            JsonResult::Subscriber(_) => {
-                // When?
+            _ => {
                 let e = JsonError::new(ErrorCode::InvalidReply, None, req_id);
                 Err(Error::JsonRpcError((e.error.code, e.error.message)))
             }
@@ -180,27 +424,46 @@ impl RpcClient {
     }
 
     /// Listen instantiated client for notifications.
+    /// Notifications carrying the method of the request are passed to the
+    /// given subscriber until the connection closes, or until the server
+    /// replies with an error. The client can be used for other requests
+    /// and subscriptions in the meantime.
     /// NOTE: Subscriber listeners must perform response handling.
-    pub async fn subscribe(&self, req: JsonRequest, sub: SubscriberPtr<JsonResult>) -> Result<()> {
+    pub async fn subscribe(
+        &self,
+        mut req: JsonRequest,
+        sub: SubscriberPtr<JsonResult>,
+    ) -> Result<()> {
+        let (rep_send, rep_recv) = channel::unbounded();
+
+        let mut routes = self.routes.lock().await;
+        req.id = routes.free_id(req.id);
+        let subscription = Subscription { method: req.method.clone(), sub, rep_send };
+        routes.subscriptions.insert(req.id.clone(), subscription);
+        drop(routes);
+
         // Perform initial request
+        let req_id = req.id.clone();
         debug!(target: "rpc::client", "--> {}", req.stringify()?);
-        let req_id = req.id;
 
-        // If the connection is closed, the sender will get an error for
-        // sending to a closed channel.
-        self.req_send.send((req, false)).await?;
+        let result = match self.req_send.send(Outgoing::Request(req)).await {
+            Ok(()) => Self::subscription_loop(rep_recv).await,
+            Err(e) => Err(e.into()),
+        };
+
+        self.routes.lock().await.subscriptions.remove(&req_id);
+        result
+    }
 
-        // Now loop and listen to notifications
+    /// Wait on the replies to a subscription request, which are only
+    /// expected to be errors or an initial response.
+    async fn subscription_loop(rep_recv: channel::Receiver<JsonResult>) -> Result<()> {
         loop {
             // If the connection is closed, the receiver will get an error
             // for waiting on a closed channel.
-            let notification = self.rep_recv.recv().await?;
-
-            // Handle the response
-            match notification {
-                JsonResult::Notification(ref n) => {
-                    debug!(target: "rpc::client", "<-- {}", n.stringify()?);
-                    sub.notify(notification.clone()).await;
+            match rep_recv.recv().await? {
+                JsonResult::Response(r) => {
+                    debug!(target: "rpc::client", "<-- {}", r.stringify()?);
                     continue
                 }
 
@@ -209,23 +472,7 @@ impl RpcClient {
                     return Err(Error::JsonRpcError((e.error.code, e.error.message)))
                 }
 
-                JsonResult::Response(r) | JsonResult::SubscriberWithReply(_, r) => {
-                    debug!(target: "rpc::client", "<-- {}", r.stringify()?);
-                    let e = JsonError::new(ErrorCode::InvalidReply, None, req_id);
-                    return Err(Error::JsonRpcError((e.error.code, e.error.message)))
-                }
-
-                JsonResult::Request(r) => {
-                    debug!(target: "rpc::client", "<-- {}", r.stringify()?);
-                    let e = JsonError::new(ErrorCode::InvalidReply, None, req_id);
-                    return Err(Error::JsonRpcError((e.error.code, e.error.message)))
-                }
-
-                JsonResult::Subscriber(_) => {
-                    // When?
-                    let e = JsonError::new(ErrorCode::InvalidReply, None, req_id);
-                    return Err(Error::JsonRpcError((e.error.code, e.error.message)))
-                }
+                _ => unreachable!(),
             }
         }
     }
@@ -19,19 +19,38 @@
 use std::time::Duration;
 
 use smol::io::{AsyncReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf};
+use tinyjson::JsonValue;
 
-use super::jsonrpc::*;
+use super::{
+    jsonrpc::*,
+    websocket::{self, Opcode},
+};
 use crate::{error::RpcError, net::transport::PtStream, system::io_timeout, Result};
 
 pub(super) const INIT_BUF_SIZE: usize = 4096; // 4K
 pub(super) const MAX_BUF_SIZE: usize = 1024 * 8192; // 8M
+/// Maximum size of an object read by the client, large enough for the
+/// reply to a full batch
+pub(super) const MAX_REPLY_SIZE: usize = 1024 * 1024 * 128; // 128M
+/// Maximum number of requests in a batch
+pub(super) const MAX_BATCH_LEN: usize = 256;
 pub(super) const READ_TIMEOUT: Duration = Duration::from_secs(30);
 
+/// How JSON-RPC objects are delimited on a stream
+#[derive(Clone, Copy, Debug)]
+pub(super) enum Framing {
+    /// One object per line, terminated by CRLF
+    Line,
+    /// One object per WebSocket text message
+    WebSocket,
+}
+
 /// Internal read function that reads from the active stream into a buffer.
-/// Reading stops upon reaching CRLF or LF, or when `MAX_BUF_SIZE` is reached.
+/// Reading stops upon reaching CRLF or LF, or when `max_size` is reached.
 pub(super) async fn read_from_stream(
     reader: &mut BufReader<ReadHalf<Box<dyn PtStream>>>,
     buf: &mut Vec<u8>,
+    max_size: usize,
     with_timeout: bool,
 ) -> Result<usize> {
     let mut total_read = 0;
@@ -39,7 +58,7 @@ pub(super) async fn read_from_stream(
     // Intermediate buffer we use to read byte-by-byte.
     let mut tmpbuf = [0_u8];
 
-    while total_read < MAX_BUF_SIZE {
+    while total_read < max_size {
         buf.resize(total_read + INIT_BUF_SIZE, 0);
 
         // Lame we have to duplicate this code, but it is what it is.
@@ -101,23 +120,56 @@ pub(super) async fn read_from_stream(
     Ok(total_read)
 }
 
+/// Convert a JSON-RPC object into the JSON value written to the stream.
+pub(super) fn to_json_value(object: &JsonResult) -> JsonValue {
+    match object {
+        JsonResult::Notification(v) => v.into(),
+        JsonResult::Response(v) => v.into(),
+        JsonResult::Error(v) => v.into(),
+        JsonResult::Request(v) => v.into(),
+        _ => unreachable!(),
+    }
+}
+
 /// Internal write function that writes a JSON-RPC object to the active stream.
 pub(super) async fn write_to_stream(
     writer: &mut WriteHalf<Box<dyn PtStream>>,
     object: &JsonResult,
 ) -> Result<()> {
-    let object_str = match object {
-        JsonResult::Notification(v) => v.stringify()?,
-        JsonResult::Response(v) => v.stringify()?,
-        JsonResult::Error(v) => v.stringify()?,
-        JsonResult::Request(v) => v.stringify()?,
-        _ => unreachable!(),
-    };
+    write_value_to_stream(writer, &to_json_value(object), Framing::Line).await
+}
+
+/// Internal write function that writes a batch of JSON-RPC objects to the
+/// active stream, as a single JSON array.
+pub(super) async fn write_batch_to_stream(
+    writer: &mut WriteHalf<Box<dyn PtStream>>,
+    objects: &[JsonResult],
+) -> Result<()> {
+    let batch = JsonValue::Array(objects.iter().map(to_json_value).collect());
+    write_value_to_stream(writer, &batch, Framing::Line).await
+}
+
+/// Internal write function that writes a JSON value to the active stream,
+/// delimited with the given framing.
+pub(super) async fn write_value_to_stream(
+    writer: &mut WriteHalf<Box<dyn PtStream>>,
+    value: &JsonValue,
+    framing: Framing,
+) -> Result<()> {
+    let object_str = value.stringify()?;
+
+    match framing {
+        Framing::Line => {
+            // As we're a line-based protocol, we append CRLF to the end of the JSON string.
+            for i in [object_str.as_bytes(), &[b'\r', b'\n']] {
+                if let Err(e) = writer.write_all(i).await {
+                    return Err(e.into())
+                }
+            }
+        }
 
-    // As we're a line-based protocol, we append CRLF to the end of the JSON string.
-    for i in [object_str.as_bytes(), &[b'\r', b'\n']] {
-        if let Err(e) = writer.write_all(i).await {
-            return Err(e.into())
+        Framing::WebSocket => {
+            websocket::write_frame(writer, Opcode::Text, object_str.as_bytes()).await?
         }
     }
 
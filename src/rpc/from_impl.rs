@@ -22,7 +22,16 @@ use crate::net;
 #[cfg(feature = "net")]
 impl From<net::channel::ChannelInfo> for JsonValue {
     fn from(info: net::channel::ChannelInfo) -> JsonValue {
-        json_map([("addr", JsonStr(info.addr.to_string())), ("id", JsonNum(info.id.into()))])
+        let remote_static_key = match info.remote_static_key {
+            Some(key) => JsonStr(bs58::encode(key).into_string()),
+            None => JsonNull,
+        };
+
+        json_map([
+            ("addr", JsonStr(info.addr.to_string())),
+            ("id", JsonNum(info.id.into())),
+            ("remote_static_key", remote_static_key),
+        ])
     }
 }
 
@@ -89,6 +98,18 @@ impl From<net::dnet::OutboundPeerDiscovery> for JsonValue {
     }
 }
 
+#[cfg(feature = "net")]
+impl From<net::dnet::ChannelRateLimited> for JsonValue {
+    fn from(info: net::dnet::ChannelRateLimited) -> JsonValue {
+        json_map([
+            ("chan", info.chan.into()),
+            ("cmd", JsonStr(info.cmd)),
+            ("dropped", JsonNum(info.dropped as f64)),
+            ("ban_score", JsonNum(info.ban_score.into())),
+        ])
+    }
+}
+
 #[cfg(feature = "net")]
 impl From<net::dnet::DnetEvent> for JsonValue {
     fn from(event: net::dnet::DnetEvent) -> JsonValue {
@@ -120,6 +141,9 @@ impl From<net::dnet::DnetEvent> for JsonValue {
             net::dnet::DnetEvent::OutboundPeerDiscovery(info) => {
                 json_map([("event", json_str("outbound_peer_discovery")), ("info", info.into())])
             }
+            net::dnet::DnetEvent::ChannelRateLimited(info) => {
+                json_map([("event", json_str("channel_rate_limited")), ("info", info.into())])
+            }
         }
     }
 }
@@ -0,0 +1,844 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! HTTP and WebSocket front-end for the JSON-RPC server.
+//!
+//! Serves a [`RequestHandler`] to browsers and standard JSON-RPC tooling,
+//! next to the newline-delimited transport of [`super::server`]:
+//!
+//! * `POST` requests carry a JSON-RPC request or batch in their body, and
+//!   get the reply in the response body. Notifications can't be pushed
+//!   this way, so subscription methods only get their initial reply.
+//! * `GET` requests upgrading to WebSocket open a connection on which each
+//!   text message is a request or batch. Replies and the notifications of
+//!   subscriptions are sent back as text messages.
+//!
+//! Access control is the same as on the other transport, see
+//! [`super::auth`]. Tokens can be given with `rpc.auth`, or in the
+//! `Authorization: Bearer` header of each HTTP request and of the
+//! WebSocket upgrade. Unless configured otherwise, only public methods
+//! can be called without a token.
+//!
+//! Connections are kept alive between requests. Browsers can only use the
+//! endpoint from the origins listed in [`HttpSettings::allowed_origins`],
+//! requests carrying any other `Origin` are refused. `POST` bodies must be
+//! sent as `application/json`, so that cross-origin requests always need
+//! a CORS preflight.
+
+use std::{collections::HashMap, io::ErrorKind, sync::Arc};
+
+use log::{debug, error, info, warn};
+use smol::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf},
+    lock::Mutex,
+};
+use tinyjson::JsonValue;
+use url::Url;
+
+use super::{
+    auth::{AccessSettings, ConnectionAccess, MethodAccess},
+    common::{write_value_to_stream, Framing, MAX_BUF_SIZE, READ_TIMEOUT},
+    jsonrpc::*,
+    server::{handle_value, start_subscription, RequestHandler},
+    websocket,
+};
+use crate::{
+    error::RpcError,
+    net::transport::{Listener, PtStream},
+    system::{io_timeout, msleep, StoppableTask},
+    Error, Result,
+};
+
+/// Maximum size of the HTTP request head we accept
+const MAX_HEAD_LEN: usize = 8192;
+
+/// Headers sent along with `Access-Control-Allow-Origin` to allowed origins
+const CORS_HEADERS: &str = "Access-Control-Allow-Methods: GET, POST, OPTIONS\r\n\
+                            Access-Control-Allow-Headers: Content-Type, Authorization\r\n\
+                            Vary: Origin\r\n";
+
+/// Settings of an HTTP and WebSocket JSON-RPC listener
+#[derive(Clone, Debug)]
+pub struct HttpSettings {
+    /// Access control, see [`super::auth`]
+    pub access: AccessSettings,
+    /// Origins allowed to use the endpoint from a browser, e.g.
+    /// `https://example.com`. `*` allows any origin.
+    pub allowed_origins: Vec<String>,
+}
+
+impl Default for HttpSettings {
+    /// Public methods are callable without a token, and no other origin
+    /// is allowed
+    fn default() -> Self {
+        Self {
+            access: AccessSettings {
+                unauthenticated: MethodAccess::Public,
+                ..AccessSettings::default()
+            },
+            allowed_origins: vec![],
+        }
+    }
+}
+
+/// The request line and headers of an HTTP request
+pub(super) struct RequestHead {
+    pub(super) method: String,
+    pub(super) path: String,
+    version: String,
+    /// Header values by lowercase header name
+    headers: HashMap<String, String>,
+}
+
+impl RequestHead {
+    pub(super) fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(|v| v.as_str())
+    }
+
+    /// Whether the comma-separated header value holds the given token,
+    /// ignoring case.
+    fn has_token(&self, name: &str, token: &str) -> bool {
+        self.header(name)
+            .map(|v| v.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+            .unwrap_or(false)
+    }
+
+    /// Raise the access level of the connection with a valid token in the
+    /// `Authorization` header, if there is one. Returns `false` if the
+    /// token is invalid.
+    fn authenticate(&self, settings: &AccessSettings, access: &mut ConnectionAccess) -> bool {
+        let Some(authorization) = self.header("authorization") else { return true };
+        let Some(token) = authorization.strip_prefix("Bearer ") else { return false };
+        access.authenticate(settings, token.trim()).is_some()
+    }
+
+    /// Whether the connection should be closed after the response
+    fn wants_close(&self) -> bool {
+        if self.version == "HTTP/1.0" {
+            return !self.has_token("connection", "keep-alive")
+        }
+        self.has_token("connection", "close")
+    }
+
+    /// Check the `Origin` header against the allowed origins. Returns
+    /// `Err` if the request comes from a page of another origin, and the
+    /// origin to allow in the response, if any.
+    fn origin(&self, allowed_origins: &[String]) -> std::result::Result<Option<&str>, ()> {
+        // Requests not made by browsers don't have an origin
+        let Some(origin) = self.header("origin") else { return Ok(None) };
+
+        if allowed_origins.iter().any(|o| o == "*" || o == origin) {
+            return Ok(Some(origin))
+        }
+
+        Err(())
+    }
+
+    /// Whether the body is declared as JSON
+    fn is_json(&self) -> bool {
+        self.header("content-type")
+            .and_then(|v| v.split(';').next())
+            .map(|v| v.trim().eq_ignore_ascii_case("application/json"))
+            .unwrap_or(false)
+    }
+}
+
+/// Start a JSON-RPC server speaking HTTP and WebSocket, bound to the given
+/// accept URL, and use the given [`RequestHandler`] to handle incoming
+/// requests. Uses the default [`HttpSettings`].
+pub async fn listen_and_serve(
+    accept_url: Url,
+    rh: Arc<impl RequestHandler + 'static>,
+    conn_limit: Option<usize>,
+    ex: Arc<smol::Executor<'_>>,
+) -> Result<()> {
+    listen_and_serve_with_settings(accept_url, rh, conn_limit, HttpSettings::default(), ex).await
+}
+
+/// Start a JSON-RPC server speaking HTTP and WebSocket, bound to the given
+/// accept URL, and use the given [`RequestHandler`] to handle incoming
+/// requests, restricting which methods can be called and by which origins
+/// with the given [`HttpSettings`].
+pub async fn listen_and_serve_with_settings(
+    accept_url: Url,
+    rh: Arc<impl RequestHandler + 'static>,
+    conn_limit: Option<usize>,
+    settings: HttpSettings,
+    ex: Arc<smol::Executor<'_>>,
+) -> Result<()> {
+    let settings = Arc::new(settings);
+    let listener = Listener::new(accept_url.clone()).await?.listen().await?;
+    info!(target: "rpc::http", "[RPC] Serving HTTP and WebSocket on {}", accept_url);
+
+    loop {
+        match listener.next().await {
+            Ok((stream, url)) => {
+                if let Some(conn_limit) = conn_limit {
+                    if rh.active_connections().await >= conn_limit {
+                        debug!(target: "rpc::http", "Connection limit reached, refusing {}", url);
+                        continue
+                    }
+                }
+
+                info!(target: "rpc::http", "[RPC] HTTP server accepted conn from {}", url);
+
+                let task = StoppableTask::new();
+                let task_ = task.clone();
+                let rh_ = rh.clone();
+                task.clone().start(
+                    serve_connection(stream, url, rh.clone(), settings.clone(), ex.clone()),
+                    |res| async move {
+                        match res {
+                            Ok(()) | Err(Error::ChannelStopped) => {}
+                            Err(e) => debug!(target: "rpc::http", "HTTP connection failed: {}", e),
+                        }
+                        rh_.unmark_connection(task_).await;
+                    },
+                    Error::ChannelStopped,
+                    ex.clone(),
+                );
+
+                rh.mark_connection(task).await;
+            }
+
+            // As per accept(2) recommendation:
+            Err(e) if e.raw_os_error().is_some() => match e.raw_os_error().unwrap() {
+                libc::EAGAIN | libc::ECONNABORTED | libc::EPROTO | libc::EINTR => continue,
+                libc::EMFILE | libc::ENFILE => {
+                    // Give connections some time to close before retrying
+                    warn!(target: "rpc::http", "[RPC] HTTP listener out of file descriptors");
+                    msleep(100).await;
+                    continue
+                }
+                _ => {
+                    error!(target: "rpc::http", "[RPC] HTTP listener failed: {}", e);
+                    return Err(e.into())
+                }
+            },
+
+            // In case a TLS handshake fails, we'll get this:
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => continue,
+
+            // Errors we didn't handle above:
+            Err(e) => {
+                error!(target: "rpc::http", "[RPC] Unhandled HTTP listener error: {}", e);
+                continue
+            }
+        }
+    }
+}
+
+/// Serve the HTTP requests made on a connection, until either side closes
+/// it or it is upgraded to WebSocket.
+async fn serve_connection(
+    stream: Box<dyn PtStream>,
+    addr: Url,
+    rh: Arc<impl RequestHandler + 'static>,
+    settings: Arc<HttpSettings>,
+    ex: Arc<smol::Executor<'_>>,
+) -> Result<()> {
+    let (reader, writer) = smol::io::split(stream);
+    let mut reader = BufReader::new(reader);
+    let writer = Arc::new(Mutex::new(writer));
+    let mut access = ConnectionAccess::new(settings.access.unauthenticated, &addr);
+
+    loop {
+        let Some(head) = read_head(&mut reader).await? else { return Ok(()) };
+        let close = head.wants_close();
+
+        // Pages of other origins don't get to talk to us at all
+        let Ok(origin) = head.origin(&settings.allowed_origins) else {
+            debug!(target: "rpc::http", "Refusing request from {}, origin not allowed", addr);
+            write_response(&mut *writer.lock().await, "403 Forbidden", None, true, None).await?;
+            return Ok(())
+        };
+
+        match head.method.as_str() {
+            "POST" => {
+                if !head.is_json() {
+                    let status = "415 Unsupported Media Type";
+                    write_response(&mut *writer.lock().await, status, None, true, origin).await?;
+                    return Ok(())
+                }
+
+                let body = match read_body(&mut reader, &head).await {
+                    Ok(v) => v,
+                    Err(status) => {
+                        write_response(&mut *writer.lock().await, status, None, true, origin)
+                            .await?;
+                        return Ok(())
+                    }
+                };
+
+                // Authentication only lasts for the request, while failed
+                // attempts count for the whole connection
+                access.reset(settings.access.unauthenticated);
+                if !head.authenticate(&settings.access, &mut access) {
+                    let status = "401 Unauthorized";
+                    write_response(&mut *writer.lock().await, status, None, close, origin).await?;
+                    if close {
+                        return Ok(())
+                    }
+                    continue
+                }
+
+                let (reply, _) =
+                    handle_message(&rh, &addr, &body, &settings.access, &mut access).await?;
+                let (status, body) = match reply {
+                    Some(reply) => ("200 OK", Some(reply.stringify()?)),
+                    None => ("204 No Content", None),
+                };
+                write_response(&mut *writer.lock().await, status, body.as_deref(), close, origin)
+                    .await?;
+            }
+
+            "GET" if head.has_token("upgrade", "websocket") => {
+                access.reset(settings.access.unauthenticated);
+                return serve_websocket(
+                    reader,
+                    writer,
+                    &head,
+                    addr,
+                    rh,
+                    &settings.access,
+                    access,
+                    ex,
+                )
+                .await
+            }
+
+            "OPTIONS" => {
+                let status = "204 No Content";
+                write_response(&mut *writer.lock().await, status, None, close, origin).await?
+            }
+
+            _ => {
+                let status = "405 Method Not Allowed";
+                write_response(&mut *writer.lock().await, status, None, close, origin).await?
+            }
+        }
+
+        if close {
+            return Ok(())
+        }
+    }
+}
+
+/// Complete the WebSocket opening handshake and serve the JSON-RPC
+/// messages sent on the connection until it is closed.
+#[allow(clippy::too_many_arguments)]
+async fn serve_websocket(
+    mut reader: BufReader<ReadHalf<Box<dyn PtStream>>>,
+    writer: Arc<Mutex<WriteHalf<Box<dyn PtStream>>>>,
+    head: &RequestHead,
+    addr: Url,
+    rh: Arc<impl RequestHandler + 'static>,
+    settings: &AccessSettings,
+    mut access: ConnectionAccess,
+    ex: Arc<smol::Executor<'_>>,
+) -> Result<()> {
+    if !head.authenticate(settings, &mut access) {
+        write_response(&mut *writer.lock().await, "401 Unauthorized", None, true, None).await?;
+        return Ok(())
+    }
+
+    let key = match (head.header("sec-websocket-key"), head.header("sec-websocket-version")) {
+        (Some(key), Some("13")) => key,
+        _ => {
+            write_response(&mut *writer.lock().await, "400 Bad Request", None, true, None).await?;
+            return Ok(())
+        }
+    };
+
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        websocket::accept_key(key),
+    );
+    writer.lock().await.write_all(response.as_bytes()).await?;
+    debug!(target: "rpc::http", "Upgraded {} to WebSocket", addr);
+
+    // We'll hold our background tasks here
+    let tasks = Arc::new(Mutex::new(Default::default()));
+
+    while let Some(message) = websocket::read_message(&mut reader, &writer).await? {
+        let (reply, subscribers) =
+            handle_message(&rh, &addr, &message, settings, &mut access).await?;
+
+        // Write the reply before starting any subscriptions, so that
+        // notifications can't overtake it.
+        if let Some(reply) = reply {
+            let mut writer_lock = writer.lock().await;
+            write_value_to_stream(&mut writer_lock, &reply, Framing::WebSocket).await?;
+            drop(writer_lock);
+        }
+
+        for subscriber in subscribers {
+            start_subscription(
+                subscriber,
+                writer.clone(),
+                Framing::WebSocket,
+                addr.clone(),
+                tasks.clone(),
+                ex.clone(),
+            )
+            .await;
+        }
+    }
+
+    debug!(target: "rpc::http", "WebSocket connection from {} closed", addr);
+    Ok(())
+}
+
+/// Handle the JSON-RPC request or batch carried by a message. Unlike on
+/// the newline-delimited transport, invalid messages get an error reply
+/// instead of closing the connection. Returns the reply to send, if any,
+/// and the subscribers to push notifications from.
+async fn handle_message(
+    rh: &Arc<impl RequestHandler + 'static>,
+    addr: &Url,
+    message: &[u8],
+    settings: &AccessSettings,
+    access: &mut ConnectionAccess,
+) -> Result<(Option<JsonValue>, Vec<JsonSubscriber>)> {
+    let val: JsonValue = match std::str::from_utf8(message).map(|s| s.trim().parse()) {
+        Ok(Ok(v)) => v,
+        _ => {
+            debug!(target: "rpc::http", "Failed parsing JSON from {}", addr);
+            let e = JsonError::new(ErrorCode::ParseError, None, JsonId::Null);
+            return Ok((Some((&e).into()), vec![]))
+        }
+    };
+
+    debug!(target: "rpc::http", "{} --> {}", addr, val.stringify()?);
+
+    let (reply, subscribers) = match handle_value(rh, &val, settings, access).await {
+        Ok(v) => v,
+        Err(e) => {
+            debug!(target: "rpc::http", "Invalid request from {}: {}", addr, e);
+            let e = JsonError::new(ErrorCode::InvalidRequest, None, JsonId::Null);
+            (Some((&e).into()), vec![])
+        }
+    };
+
+    if let Some(reply) = &reply {
+        debug!(target: "rpc::http", "{} <-- {}", addr, reply.stringify()?);
+    }
+
+    Ok((reply, subscribers))
+}
+
+/// Read the head of the next request on the connection. Returns `None`
+/// if the connection was closed before a new request started.
+pub(super) async fn read_head(
+    reader: &mut BufReader<ReadHalf<Box<dyn PtStream>>>,
+) -> Result<Option<RequestHead>> {
+    let mut lines = vec![];
+    let mut total = 0;
+
+    loop {
+        // Bound the read so a line without a newline can't grow past
+        // the head limit.
+        let mut line = String::new();
+        let mut limited = (&mut *reader).take((MAX_HEAD_LEN - total) as u64);
+        let n = io_timeout(READ_TIMEOUT, limited.read_line(&mut line)).await?;
+        if n == 0 && total == 0 {
+            return Ok(None)
+        }
+
+        // A line cut short by the limit or by EOF has no newline
+        total += n;
+        if !line.ends_with('\n') {
+            return Err(RpcError::MalformedHttp("Incomplete request head".to_string()).into())
+        }
+
+        // Empty lines preceding the request line are ignored
+        let line = line.trim_end();
+        if line.is_empty() {
+            if lines.is_empty() {
+                continue
+            }
+            break
+        }
+        lines.push(line.to_string());
+    }
+
+    let mut request_line = lines[0].split_whitespace();
+    let (Some(method), Some(path), Some(version)) =
+        (request_line.next(), request_line.next(), request_line.next())
+    else {
+        return Err(RpcError::MalformedHttp("Invalid request line".to_string()).into())
+    };
+
+    let mut headers = HashMap::new();
+    for line in &lines[1..] {
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(RpcError::MalformedHttp("Invalid header".to_string()).into())
+        };
+        headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+    }
+
+    Ok(Some(RequestHead {
+        method: method.to_string(),
+        path: path.to_string(),
+        version: version.to_string(),
+        headers,
+    }))
+}
+
+/// Read the body of a request. On failure, returns the status to reply with.
+async fn read_body(
+    reader: &mut BufReader<ReadHalf<Box<dyn PtStream>>>,
+    head: &RequestHead,
+) -> std::result::Result<Vec<u8>, &'static str> {
+    // Chunked bodies are not supported, so we require the length
+    if head.header("transfer-encoding").is_some() {
+        return Err("411 Length Required")
+    }
+
+    let len = match head.header("content-length").map(|v| v.parse::<usize>()) {
+        Some(Ok(v)) => v,
+        Some(Err(_)) => return Err("400 Bad Request"),
+        None => return Err("411 Length Required"),
+    };
+
+    if len > MAX_BUF_SIZE {
+        return Err("413 Payload Too Large")
+    }
+
+    let mut body = vec![0_u8; len];
+    match io_timeout(READ_TIMEOUT, reader.read_exact(&mut body)).await {
+        Ok(()) => Ok(body),
+        Err(_) => Err("400 Bad Request"),
+    }
+}
+
+/// Write an HTTP response, with a JSON body if one is given. `origin` is
+/// the allowed origin of the request, if it came from a browser.
+async fn write_response(
+    writer: &mut WriteHalf<Box<dyn PtStream>>,
+    status: &str,
+    body: Option<&str>,
+    close: bool,
+    origin: Option<&str>,
+) -> Result<()> {
+    let mut response = format!("HTTP/1.1 {}\r\n", status);
+    if let Some(origin) = origin {
+        response.push_str(&format!("Access-Control-Allow-Origin: {}\r\n{}", origin, CORS_HEADERS));
+    }
+    if body.is_some() {
+        response.push_str("Content-Type: application/json\r\n");
+    }
+    response.push_str(&format!("Content-Length: {}\r\n", body.map_or(0, |b| b.len())));
+    if close {
+        response.push_str("Connection: close\r\n");
+    }
+    response.push_str("\r\n");
+    response.push_str(body.unwrap_or_default());
+
+    writer.write_all(response.as_bytes()).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::{msleep, StoppableTaskPtr};
+    use async_trait::async_trait;
+    use smol::{
+        lock::MutexGuard,
+        net::{TcpListener, TcpStream},
+        Executor,
+    };
+    use std::{collections::HashSet, net::SocketAddr};
+
+    struct RpcServer {
+        rpc_connections: Mutex<HashSet<StoppableTaskPtr>>,
+        subscriber: JsonSubscriber,
+    }
+
+    #[async_trait]
+    impl RequestHandler for RpcServer {
+        async fn handle_request(&self, req: JsonRequest) -> JsonResult {
+            match req.method.as_str() {
+                "echo" => JsonResponse::new(req.params, req.id).into(),
+                "subscribe" => {
+                    let rep = JsonResponse::new(JsonValue::Boolean(true), req.id);
+                    (self.subscriber.clone(), rep).into()
+                }
+                _ => JsonError::new(ErrorCode::MethodNotFound, None, req.id).into(),
+            }
+        }
+
+        fn method_access(&self, _method: &str) -> MethodAccess {
+            MethodAccess::Public
+        }
+
+        async fn connections_mut(&self) -> MutexGuard<'_, HashSet<StoppableTaskPtr>> {
+            self.rpc_connections.lock().await
+        }
+    }
+
+    /// Read an HTTP response, returning its status line and body
+    async fn read_response(reader: &mut BufReader<TcpStream>) -> Result<(String, String)> {
+        let (status, _, body) = read_response_with_headers(reader).await?;
+        Ok((status, body))
+    }
+
+    /// Read an HTTP response, returning its status line, lowercase header
+    /// lines and body
+    async fn read_response_with_headers(
+        reader: &mut BufReader<TcpStream>,
+    ) -> Result<(String, Vec<String>, String)> {
+        let mut status = String::new();
+        reader.read_line(&mut status).await?;
+
+        let mut len = 0;
+        let mut headers = vec![];
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).await?;
+            if line.trim().is_empty() {
+                break
+            }
+            let line = line.trim().to_lowercase();
+            if let Some(v) = line.strip_prefix("content-length:") {
+                len = v.trim().parse().unwrap();
+            }
+            headers.push(line);
+        }
+
+        let mut body = vec![0_u8; len];
+        reader.read_exact(&mut body).await?;
+        Ok((status.trim().to_string(), headers, String::from_utf8(body)?))
+    }
+
+    /// Write a masked client frame
+    async fn write_client_frame(stream: &mut TcpStream, opcode: u8, payload: &[u8]) -> Result<()> {
+        assert!(payload.len() < 126);
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        stream.write_all(&frame).await?;
+        Ok(())
+    }
+
+    /// Read an unmasked server frame, returning its opcode and payload
+    async fn read_server_frame(reader: &mut BufReader<TcpStream>) -> Result<(u8, Vec<u8>)> {
+        let mut head = [0_u8; 2];
+        reader.read_exact(&mut head).await?;
+        let len = match head[1] {
+            126 => {
+                let mut len = [0_u8; 2];
+                reader.read_exact(&mut len).await?;
+                u16::from_be_bytes(len) as usize
+            }
+            n => n as usize,
+        };
+        let mut payload = vec![0_u8; len];
+        reader.read_exact(&mut payload).await?;
+        Ok((head[0] & 0x0f, payload))
+    }
+
+    /// Serve a new [`RpcServer`] over HTTP on a free local port with the
+    /// given settings. Returns the server, its address and the task serving it.
+    async fn spawn_test_server(
+        settings: HttpSettings,
+        executor: Arc<Executor<'static>>,
+    ) -> Result<(Arc<RpcServer>, SocketAddr, StoppableTaskPtr)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let sockaddr = listener.local_addr()?;
+        let endpoint = Url::parse(&format!("tcp://127.0.0.1:{}", sockaddr.port()))?;
+        drop(listener);
+
+        let rpc_server = Arc::new(RpcServer {
+            rpc_connections: Mutex::new(HashSet::new()),
+            subscriber: JsonSubscriber::new("subscribe"),
+        });
+
+        let server_task = StoppableTask::new();
+        server_task.clone().start(
+            listen_and_serve_with_settings(
+                endpoint,
+                rpc_server.clone(),
+                None,
+                settings,
+                executor.clone(),
+            ),
+            |_| async {},
+            Error::RpcServerStopped,
+            executor,
+        );
+
+        // Let the server spawn
+        msleep(500).await;
+        Ok((rpc_server, sockaddr, server_task))
+    }
+
+    #[test]
+    fn websocket_accept_key() {
+        // Example from RFC 6455
+        assert_eq!(
+            websocket::accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn http_and_websocket() -> Result<()> {
+        let executor = Arc::new(Executor::new());
+
+        smol::block_on(executor.run(async {
+            let settings = HttpSettings {
+                allowed_origins: vec!["https://dark.fi".to_string()],
+                ..Default::default()
+            };
+            let (rpc_server, sockaddr, server_task) =
+                spawn_test_server(settings, executor.clone()).await?;
+
+            // Several POST requests on a kept-alive connection
+            let mut stream = TcpStream::connect(sockaddr).await?;
+            let mut reader = BufReader::new(stream.clone());
+
+            let body = r#"{"jsonrpc":"2.0","id":1,"method":"echo","params":["hi"]}"#;
+            let request = format!(
+                "POST / HTTP/1.1\r\n\
+                 Content-Type: application/json\r\n\
+                 Content-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(request.as_bytes()).await?;
+            let (status, body) = read_response(&mut reader).await?;
+            assert_eq!(status, "HTTP/1.1 200 OK");
+            let rep: JsonValue = body.parse()?;
+            assert_eq!(rep["result"], JsonValue::Array(vec![JsonValue::String("hi".to_string())]));
+            assert_eq!(rep["id"], JsonValue::Number(1.0));
+
+            let body = "{not json";
+            let request = format!(
+                "POST / HTTP/1.1\r\n\
+                 Content-Type: application/json; charset=utf-8\r\n\
+                 Content-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(request.as_bytes()).await?;
+            let (status, body) = read_response(&mut reader).await?;
+            assert_eq!(status, "HTTP/1.1 200 OK");
+            let rep: JsonValue = body.parse()?;
+            assert_eq!(rep["error"]["code"], JsonValue::Number(-32700.0));
+
+            // Allowed origins get CORS headers
+            let request = "OPTIONS / HTTP/1.1\r\nOrigin: https://dark.fi\r\n\r\n";
+            stream.write_all(request.as_bytes()).await?;
+            let (status, headers, _) = read_response_with_headers(&mut reader).await?;
+            assert_eq!(status, "HTTP/1.1 204 No Content");
+            assert!(headers.contains(&"access-control-allow-origin: https://dark.fi".to_string()));
+            assert!(headers
+                .iter()
+                .any(|h| h.starts_with("access-control-allow-headers:") &&
+                    h.contains("authorization")));
+
+            stream.write_all(b"DELETE / HTTP/1.1\r\nConnection: close\r\n\r\n").await?;
+            let (status, headers, _) = read_response_with_headers(&mut reader).await?;
+            assert_eq!(status, "HTTP/1.1 405 Method Not Allowed");
+            assert!(!headers.iter().any(|h| h.starts_with("access-control-allow-origin:")));
+
+            // Bodies must be declared as JSON
+            let mut stream = TcpStream::connect(sockaddr).await?;
+            let mut reader = BufReader::new(stream.clone());
+            let body = r#"{"jsonrpc":"2.0","id":1,"method":"echo","params":[]}"#;
+            let request = format!(
+                "POST / HTTP/1.1\r\n\
+                 Content-Type: text/plain\r\n\
+                 Content-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(request.as_bytes()).await?;
+            let (status, _) = read_response(&mut reader).await?;
+            assert_eq!(status, "HTTP/1.1 415 Unsupported Media Type");
+
+            // Other origins are refused, also for WebSocket
+            let mut stream = TcpStream::connect(sockaddr).await?;
+            let mut reader = BufReader::new(stream.clone());
+            let request = "GET / HTTP/1.1\r\n\
+                           Origin: https://evil.example\r\n\
+                           Upgrade: websocket\r\n\
+                           Connection: Upgrade\r\n\
+                           Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                           Sec-WebSocket-Version: 13\r\n\r\n";
+            stream.write_all(request.as_bytes()).await?;
+            let (status, _) = read_response(&mut reader).await?;
+            assert_eq!(status, "HTTP/1.1 403 Forbidden");
+
+            // WebSocket carries replies and notifications
+            let mut stream = TcpStream::connect(sockaddr).await?;
+            let mut reader = BufReader::new(stream.clone());
+
+            let request = "GET / HTTP/1.1\r\n\
+                           Upgrade: websocket\r\n\
+                           Connection: Upgrade\r\n\
+                           Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                           Sec-WebSocket-Version: 13\r\n\r\n";
+            stream.write_all(request.as_bytes()).await?;
+            let (status, _) = read_response(&mut reader).await?;
+            assert_eq!(status, "HTTP/1.1 101 Switching Protocols");
+
+            let req = r#"{"jsonrpc":"2.0","id":"sub","method":"subscribe","params":[]}"#;
+            write_client_frame(&mut stream, 0x1, req.as_bytes()).await?;
+            let (opcode, payload) = read_server_frame(&mut reader).await?;
+            assert_eq!(opcode, 0x1);
+            let rep: JsonValue = String::from_utf8(payload)?.parse()?;
+            assert_eq!(rep["result"], JsonValue::Boolean(true));
+            assert_eq!(rep["id"], JsonValue::String("sub".to_string()));
+            msleep(100).await;
+
+            rpc_server.subscriber.notify(JsonValue::Array(vec![JsonValue::Number(42.0)])).await;
+            let (opcode, payload) = read_server_frame(&mut reader).await?;
+            assert_eq!(opcode, 0x1);
+            let notif: JsonValue = String::from_utf8(payload)?.parse()?;
+            assert_eq!(notif["method"], JsonValue::String("subscribe".to_string()));
+            assert_eq!(notif["params"], JsonValue::Array(vec![JsonValue::Number(42.0)]));
+
+            // Pings are answered, and closing is acknowledged
+            write_client_frame(&mut stream, 0x9, b"ping").await?;
+            assert_eq!(read_server_frame(&mut reader).await?, (0xa, b"ping".to_vec()));
+            write_client_frame(&mut stream, 0x8, &1000_u16.to_be_bytes()).await?;
+            assert_eq!(
+                read_server_frame(&mut reader).await?,
+                (0x8, 1000_u16.to_be_bytes().to_vec())
+            );
+
+            // A head line running past the limit closes the connection
+            let mut stream = TcpStream::connect(sockaddr).await?;
+            stream.write_all(&vec![b'a'; MAX_HEAD_LEN]).await?;
+            let mut buf = vec![];
+            stream.read_to_end(&mut buf).await?;
+            assert!(buf.is_empty());
+
+            server_task.stop().await;
+            Ok(())
+        }))
+    }
+}
@@ -17,7 +17,7 @@
  */
 
 //! JSON-RPC 2.0 object definitions
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
 
 use rand::{rngs::OsRng, Rng};
 use tinyjson::JsonValue;
@@ -47,6 +47,8 @@ pub enum ErrorCode {
     IdMismatch,
     /// Invalid/Unexpected reply
     InvalidReply,
+    /// The method requires more access than the connection has.
+    Unauthorized,
     /// Reserved for implementation-defined server-errors.
     ServerError(i32),
 }
@@ -61,6 +63,7 @@ impl ErrorCode {
             Self::InternalError => -32603,
             Self::IdMismatch => -32360,
             Self::InvalidReply => -32361,
+            Self::Unauthorized => -32362,
             Self::ServerError(c) => c,
         }
     }
@@ -74,6 +77,7 @@ impl ErrorCode {
             Self::InternalError => "internal error".to_string(),
             Self::IdMismatch => "id mismatch".to_string(),
             Self::InvalidReply => "invalid reply".to_string(),
+            Self::Unauthorized => "unauthorized".to_string(),
             Self::ServerError(_) => "server error".to_string(),
         }
     }
@@ -83,6 +87,80 @@ impl ErrorCode {
     }
 }
 
+/// Largest numeric request ID we use. JSON numbers are parsed as `f64`,
+/// so larger IDs are not guaranteed to be represented exactly.
+pub const MAX_SAFE_ID: u64 = (1 << 53) - 1;
+
+/// A JSON-RPC request ID. As per the specification, it is either a number
+/// or a string, and `null` is used in error replies when the ID of the
+/// request could not be determined.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum JsonId {
+    Number(u64),
+    String(String),
+    Null,
+}
+
+impl JsonId {
+    /// Create a random numeric ID, up to [`MAX_SAFE_ID`]
+    pub fn random() -> Self {
+        Self::Number(OsRng.gen_range(0..=MAX_SAFE_ID))
+    }
+}
+
+impl fmt::Display for JsonId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Number(n) => write!(f, "{}", n),
+            Self::String(s) => write!(f, "{:?}", s),
+            Self::Null => write!(f, "null"),
+        }
+    }
+}
+
+impl From<u64> for JsonId {
+    fn from(n: u64) -> Self {
+        Self::Number(n)
+    }
+}
+
+impl From<&str> for JsonId {
+    fn from(s: &str) -> Self {
+        Self::String(s.to_string())
+    }
+}
+
+impl From<String> for JsonId {
+    fn from(s: String) -> Self {
+        Self::String(s)
+    }
+}
+
+impl From<&JsonId> for JsonValue {
+    fn from(id: &JsonId) -> JsonValue {
+        match id {
+            JsonId::Number(n) => JsonValue::Number(*n as f64),
+            JsonId::String(s) => JsonValue::String(s.clone()),
+            JsonId::Null => JsonValue::Null,
+        }
+    }
+}
+
+impl TryFrom<&JsonValue> for JsonId {
+    type Error = RpcError;
+
+    fn try_from(value: &JsonValue) -> std::result::Result<Self, Self::Error> {
+        match value {
+            JsonValue::Number(n) if *n >= 0.0 && n.fract() == 0.0 && *n <= MAX_SAFE_ID as f64 => {
+                Ok(Self::Number(*n as u64))
+            }
+            JsonValue::String(s) => Ok(Self::String(s.clone())),
+            JsonValue::Null => Ok(Self::Null),
+            _ => Err(RpcError::InvalidJson("Invalid \"id\" field".to_string())),
+        }
+    }
+}
+
 // ANCHOR: jsonresult
 /// Wrapping enum around the available JSON-RPC object types
 #[derive(Clone, Debug)]
@@ -151,7 +229,7 @@ pub struct JsonRequest {
     /// JSON-RPC version
     pub jsonrpc: &'static str,
     /// Request ID
-    pub id: u16,
+    pub id: JsonId,
     /// Request method
     pub method: String,
     /// Request parameters
@@ -164,7 +242,7 @@ impl JsonRequest {
     /// The request ID is chosen randomly.
     pub fn new(method: &str, params: JsonValue) -> Self {
         assert!(params.is_object() || params.is_array());
-        Self { jsonrpc: "2.0", id: OsRng::gen(&mut OsRng), method: method.to_string(), params }
+        Self { jsonrpc: "2.0", id: JsonId::random(), method: method.to_string(), params }
     }
 
     /// Convert the object into a JSON string
@@ -178,7 +256,7 @@ impl From<&JsonRequest> for JsonValue {
     fn from(req: &JsonRequest) -> JsonValue {
         JsonValue::Object(HashMap::from([
             ("jsonrpc".to_string(), JsonValue::String(req.jsonrpc.to_string())),
-            ("id".to_string(), JsonValue::Number(req.id.into())),
+            ("id".to_string(), (&req.id).into()),
             ("method".to_string(), JsonValue::String(req.method.clone())),
             ("params".to_string(), req.params.clone()),
         ]))
@@ -204,11 +282,11 @@ impl TryFrom<&JsonValue> for JsonRequest {
             ))
         }
 
-        if !map.contains_key("id") || !map["id"].is_number() {
+        let Some(Ok(id)) = map.get("id").map(JsonId::try_from) else {
             return Err(RpcError::InvalidJson(
                 "Request does not contain valid \"id\" field".to_string(),
             ))
-        }
+        };
 
         if !map.contains_key("method") || !map["method"].is_string() {
             return Err(RpcError::InvalidJson(
@@ -230,7 +308,7 @@ impl TryFrom<&JsonValue> for JsonRequest {
 
         Ok(Self {
             jsonrpc: "2.0",
-            id: *map["id"].get::<f64>().unwrap() as u16,
+            id,
             method: map["method"].get::<String>().unwrap().clone(),
             params: map["params"].clone(),
         })
@@ -323,7 +401,7 @@ pub struct JsonResponse {
     /// JSON-RPC version
     pub jsonrpc: &'static str,
     /// Request ID
-    pub id: u16,
+    pub id: JsonId,
     /// Response result
     pub result: JsonValue,
 }
@@ -331,7 +409,7 @@ pub struct JsonResponse {
 impl JsonResponse {
     /// Create a new [`JsonResponse`] object with the given ID and result value.
     /// Creating a `JsonResponse` implies that the method call was successful.
-    pub fn new(result: JsonValue, id: u16) -> Self {
+    pub fn new(result: JsonValue, id: JsonId) -> Self {
         Self { jsonrpc: "2.0", id, result }
     }
 
@@ -346,7 +424,7 @@ impl From<&JsonResponse> for JsonValue {
     fn from(rep: &JsonResponse) -> JsonValue {
         JsonValue::Object(HashMap::from([
             ("jsonrpc".to_string(), JsonValue::String(rep.jsonrpc.to_string())),
-            ("id".to_string(), JsonValue::Number(rep.id.into())),
+            ("id".to_string(), (&rep.id).into()),
             ("result".to_string(), rep.result.clone()),
         ]))
     }
@@ -371,17 +449,19 @@ impl TryFrom<&JsonValue> for JsonResponse {
             ))
         }
 
-        if !map.contains_key("id") || !map["id"].is_number() {
+        let Some(Ok(id)) = map.get("id").map(JsonId::try_from) else {
             return Err(RpcError::InvalidJson(
                 "Response does not contain valid \"id\" field".to_string(),
             ))
+        };
+
+        if !map.contains_key("result") {
+            return Err(RpcError::InvalidJson(
+                "Response does not contain valid \"result\" field".to_string(),
+            ))
         }
 
-        Ok(Self {
-            jsonrpc: "2.0",
-            id: *map["id"].get::<f64>().unwrap() as u16,
-            result: map["result"].clone(),
-        })
+        Ok(Self { jsonrpc: "2.0", id, result: map["result"].clone() })
     }
 }
 
@@ -391,7 +471,7 @@ pub struct JsonError {
     /// JSON-RPC version
     pub jsonrpc: &'static str,
     /// Request ID
-    pub id: u16,
+    pub id: JsonId,
     /// JSON-RPC error (code and message)
     pub error: JsonErrorVal,
 }
@@ -409,7 +489,7 @@ impl JsonError {
     /// Create a new [`JsonError`] object with the given error code, optional
     /// message, and a response ID.
     /// Creating a `JsonError` implies that the method call was unsuccessful.
-    pub fn new(c: ErrorCode, message: Option<String>, id: u16) -> Self {
+    pub fn new(c: ErrorCode, message: Option<String>, id: JsonId) -> Self {
         let error = JsonErrorVal { code: c.code(), message: message.unwrap_or(c.message()) };
         Self { jsonrpc: "2.0", id, error }
     }
@@ -430,7 +510,7 @@ impl From<&JsonError> for JsonValue {
 
         JsonValue::Object(HashMap::from([
             ("jsonrpc".to_string(), JsonValue::String(err.jsonrpc.to_string())),
-            ("id".to_string(), JsonValue::Number(err.id.into())),
+            ("id".to_string(), (&err.id).into()),
             ("error".to_string(), errmap),
         ]))
     }
@@ -455,11 +535,11 @@ impl TryFrom<&JsonValue> for JsonError {
             ))
         }
 
-        if !map.contains_key("id") || !map["id"].is_number() {
+        let Some(Ok(id)) = map.get("id").map(JsonId::try_from) else {
             return Err(RpcError::InvalidJson(
                 "Error does not contain valid \"id\" field".to_string(),
             ))
-        }
+        };
 
         if !map.contains_key("error") || !map["error"].is_object() {
             return Err(RpcError::InvalidJson(
@@ -481,7 +561,7 @@ impl TryFrom<&JsonValue> for JsonError {
 
         Ok(Self {
             jsonrpc: "2.0",
-            id: *map["id"].get::<f64>().unwrap() as u16,
+            id,
             error: JsonErrorVal {
                 code: *map["error"]["code"].get::<f64>().unwrap() as i32,
                 message: map["error"]["message"].get::<String>().unwrap().to_string(),
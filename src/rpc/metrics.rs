@@ -0,0 +1,82 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{io::ErrorKind, sync::Arc};
+
+use log::{debug, error, info};
+use smol::io::{AsyncWriteExt, BufReader};
+use url::Url;
+
+use super::http::read_head;
+use crate::{
+    net::transport::{Listener, PtStream},
+    util::metrics::registry,
+    Result,
+};
+
+/// Serve the global metrics registry over HTTP at `GET /metrics`, in the
+/// Prometheus text exposition format.
+pub async fn serve_metrics(accept_url: Url, ex: Arc<smol::Executor<'_>>) -> Result<()> {
+    let listener = Listener::new(accept_url.clone()).await?.listen().await?;
+    info!(target: "rpc::metrics", "[RPC] Serving metrics on {}", accept_url);
+
+    loop {
+        match listener.next().await {
+            Ok((stream, url)) => {
+                ex.spawn(async move {
+                    if let Err(e) = handle_request(stream).await {
+                        debug!(target: "rpc::metrics", "Metrics request from {} failed: {}", url, e);
+                    }
+                })
+                .detach();
+            }
+
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => continue,
+
+            Err(e) => {
+                error!(target: "rpc::metrics", "[RPC] Metrics listener failed: {}", e);
+                return Err(e.into())
+            }
+        }
+    }
+}
+
+async fn handle_request(stream: Box<dyn PtStream>) -> Result<()> {
+    let (reader, mut writer) = smol::io::split(stream);
+    let mut reader = BufReader::new(reader);
+
+    let Some(head) = read_head(&mut reader).await? else { return Ok(()) };
+
+    let (status, content_type, body) = match (head.method.as_str(), head.path.as_str()) {
+        ("GET", "/metrics") => {
+            ("200 OK", "text/plain; version=0.0.4; charset=utf-8", registry().render())
+        }
+        _ => ("404 Not Found", "text/plain; charset=utf-8", "Not Found\n".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    writer.write_all(response.as_bytes()).await?;
+    writer.flush().await?;
+    Ok(())
+}
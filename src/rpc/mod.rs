@@ -28,6 +28,18 @@ pub mod client;
 /// Server-side JSON-RPC implementation
 pub mod server;
 
+/// HTTP and WebSocket front-end for the JSON-RPC server
+pub mod http;
+
+/// Authentication and per-method access control
+pub mod auth;
+
+/// Declarative method registry and `rpc.discover`
+pub mod registry;
+
+/// WebSocket framing used by the HTTP front-end
+mod websocket;
+
 /// Clock sync utility module
 pub mod clock_sync;
 
@@ -37,5 +49,8 @@ pub mod from_impl;
 /// Provides optional `p2p.get_info()` method
 pub mod p2p_method;
 
+/// HTTP endpoint exporting the metrics registry
+pub mod metrics;
+
 /// Json helper methods and types
 pub mod util;
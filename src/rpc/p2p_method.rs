@@ -19,14 +19,14 @@
 use async_trait::async_trait;
 
 use super::{
-    jsonrpc::{JsonResponse, JsonResult},
+    jsonrpc::{JsonId, JsonResponse, JsonResult},
     util::*,
 };
 use crate::net;
 
 #[async_trait]
 pub trait HandlerP2p: Sync + Send {
-    async fn p2p_get_info(&self, id: u16, _params: JsonValue) -> JsonResult {
+    async fn p2p_get_info(&self, id: JsonId, _params: JsonValue) -> JsonResult {
         let mut channels = Vec::new();
         for channel in self.p2p().channels().await {
             let session = match channel.session_type_id() {
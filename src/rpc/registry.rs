@@ -0,0 +1,281 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Declarative registry of the methods served by a JSON-RPC server.
+//!
+//! A [`RequestHandler`](super::server::RequestHandler) lists its methods
+//! in [`RequestHandler::methods()`](super::server::RequestHandler::methods),
+//! each one declaring its parameters, result and access level. The server
+//! then rejects calls with malformed parameters before they reach the
+//! handler, and describes the methods to clients calling `rpc.discover`,
+//! as an [OpenRPC](https://spec.open-rpc.org) document.
+//!
+//! Parameters may be given by position or by name. Handlers always get
+//! them by position, so they don't need to care about the difference.
+
+use std::collections::HashMap;
+
+use tinyjson::JsonValue;
+
+use super::auth::MethodAccess;
+
+/// Name of the method describing the methods of the server
+pub const DISCOVER_METHOD: &str = "rpc.discover";
+
+/// Version of the OpenRPC specification our documents follow
+const OPENRPC_VERSION: &str = "1.2.6";
+
+/// Type of a JSON value
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JsonType {
+    Null,
+    Boolean,
+    Number,
+    String,
+    Array,
+    Object,
+    /// Any value at all
+    Any,
+}
+
+impl JsonType {
+    /// Check whether the given value is of this type
+    pub fn matches(&self, value: &JsonValue) -> bool {
+        match self {
+            Self::Null => value.is_null(),
+            Self::Boolean => value.is_bool(),
+            Self::Number => value.is_number(),
+            Self::String => value.is_string(),
+            Self::Array => value.is_array(),
+            Self::Object => value.is_object(),
+            Self::Any => true,
+        }
+    }
+
+    /// Name of the type, as used in JSON Schema
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Null => "null",
+            Self::Boolean => "boolean",
+            Self::Number => "number",
+            Self::String => "string",
+            Self::Array => "array",
+            Self::Object => "object",
+            Self::Any => "any",
+        }
+    }
+
+    /// JSON Schema of values of this type
+    fn schema(&self) -> JsonValue {
+        let mut schema = HashMap::new();
+        if *self != Self::Any {
+            schema.insert("type".to_string(), JsonValue::String(self.name().to_string()));
+        }
+        JsonValue::Object(schema)
+    }
+}
+
+/// A parameter of a JSON-RPC method
+#[derive(Clone, Copy, Debug)]
+pub struct RpcParam {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub ty: JsonType,
+    /// Optional parameters can only be followed by other optional ones
+    pub required: bool,
+}
+
+impl RpcParam {
+    pub const fn required(name: &'static str, ty: JsonType, description: &'static str) -> Self {
+        Self { name, description, ty, required: true }
+    }
+
+    pub const fn optional(name: &'static str, ty: JsonType, description: &'static str) -> Self {
+        Self { name, description, ty, required: false }
+    }
+
+    /// OpenRPC content descriptor of the parameter
+    fn describe(&self) -> JsonValue {
+        JsonValue::Object(HashMap::from([
+            ("name".to_string(), JsonValue::String(self.name.to_string())),
+            ("description".to_string(), JsonValue::String(self.description.to_string())),
+            ("required".to_string(), JsonValue::Boolean(self.required)),
+            ("schema".to_string(), self.ty.schema()),
+        ]))
+    }
+}
+
+/// A JSON-RPC method, as declared in the registry of a handler
+#[derive(Clone, Copy, Debug)]
+pub struct RpcMethod {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub params: &'static [RpcParam],
+    /// Type of the result. Subscriptions use the type of their initial
+    /// reply, if any.
+    pub result: JsonType,
+    /// Access needed to call the method
+    pub access: MethodAccess,
+}
+
+impl RpcMethod {
+    /// Check the parameters of a call against the declared ones, and
+    /// return them by position. On failure, returns what is wrong with
+    /// them, for the `InvalidParams` error.
+    pub fn check_params(&self, params: &JsonValue) -> Result<JsonValue, String> {
+        let values: Vec<Option<&JsonValue>> = match params {
+            JsonValue::Array(values) => {
+                if values.len() > self.params.len() {
+                    return Err(format!(
+                        "Expected at most {} params, got {}",
+                        self.params.len(),
+                        values.len()
+                    ))
+                }
+                (0..self.params.len()).map(|i| values.get(i)).collect()
+            }
+
+            JsonValue::Object(values) => {
+                if let Some(name) =
+                    values.keys().find(|k| !self.params.iter().any(|p| p.name == k.as_str()))
+                {
+                    return Err(format!("Unknown param \"{}\"", name))
+                }
+                self.params.iter().map(|p| values.get(p.name)).collect()
+            }
+
+            _ => return Err("Params must be an array or an object".to_string()),
+        };
+
+        let mut positional = vec![];
+        for (param, value) in self.params.iter().zip(values) {
+            match value {
+                Some(value) if param.ty.matches(value) => positional.push(value.clone()),
+                Some(_) => {
+                    return Err(format!("Param \"{}\" must be {}", param.name, param.ty.name()))
+                }
+                None if param.required => return Err(format!("Missing param \"{}\"", param.name)),
+                None => break,
+            }
+        }
+
+        // By name, optional params can't be skipped since handlers
+        // take them by position.
+        if let JsonValue::Object(values) = params {
+            if values.len() != positional.len() {
+                return Err("Optional params can't be skipped".to_string())
+            }
+        }
+
+        Ok(JsonValue::Array(positional))
+    }
+
+    /// OpenRPC method object describing the method
+    fn describe(&self) -> JsonValue {
+        let params = self.params.iter().map(|p| p.describe()).collect();
+        let result = HashMap::from([
+            ("name".to_string(), JsonValue::String("result".to_string())),
+            ("schema".to_string(), self.result.schema()),
+        ]);
+
+        JsonValue::Object(HashMap::from([
+            ("name".to_string(), JsonValue::String(self.name.to_string())),
+            ("description".to_string(), JsonValue::String(self.description.to_string())),
+            ("params".to_string(), JsonValue::Array(params)),
+            ("paramStructure".to_string(), JsonValue::String("either".to_string())),
+            ("result".to_string(), JsonValue::Object(result)),
+            ("x-access".to_string(), JsonValue::String(self.access.to_string())),
+        ]))
+    }
+}
+
+/// Build the OpenRPC document describing the given methods
+pub fn discover<'a>(methods: impl IntoIterator<Item = &'a RpcMethod>) -> JsonValue {
+    let info = HashMap::from([
+        ("title".to_string(), JsonValue::String("DarkFi JSON-RPC".to_string())),
+        ("version".to_string(), JsonValue::String(env!("CARGO_PKG_VERSION").to_string())),
+    ]);
+
+    JsonValue::Object(HashMap::from([
+        ("openrpc".to_string(), JsonValue::String(OPENRPC_VERSION.to_string())),
+        ("info".to_string(), JsonValue::Object(info)),
+        (
+            "methods".to_string(),
+            JsonValue::Array(methods.into_iter().map(|m| m.describe()).collect()),
+        ),
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METHOD: RpcMethod = RpcMethod {
+        name: "foo",
+        description: "Foo the bar",
+        params: &[
+            RpcParam::required("bar", JsonType::String, "The bar"),
+            RpcParam::optional("times", JsonType::Number, "How many times"),
+        ],
+        result: JsonType::Boolean,
+        access: MethodAccess::Public,
+    };
+
+    #[test]
+    fn check_params() {
+        let bar = JsonValue::String("bar".to_string());
+        let times = JsonValue::Number(2.0);
+
+        // By position
+        let params = JsonValue::Array(vec![bar.clone()]);
+        assert_eq!(METHOD.check_params(&params), Ok(params));
+        let params = JsonValue::Array(vec![bar.clone(), times.clone()]);
+        assert_eq!(METHOD.check_params(&params), Ok(params));
+        assert!(METHOD.check_params(&JsonValue::Array(vec![])).is_err());
+        assert!(METHOD.check_params(&JsonValue::Array(vec![times.clone()])).is_err());
+        let params = JsonValue::Array(vec![bar.clone(), times.clone(), times.clone()]);
+        assert!(METHOD.check_params(&params).is_err());
+
+        // By name
+        let params = JsonValue::Object(HashMap::from([
+            ("times".to_string(), times.clone()),
+            ("bar".to_string(), bar.clone()),
+        ]));
+        assert_eq!(METHOD.check_params(&params), Ok(JsonValue::Array(vec![bar.clone(), times])));
+        let params = JsonValue::Object(HashMap::from([("baz".to_string(), bar.clone())]));
+        assert!(METHOD.check_params(&params).is_err());
+
+        assert!(METHOD.check_params(&bar).is_err());
+    }
+
+    #[test]
+    fn discover_document() {
+        let doc = discover(&[METHOD]);
+        assert_eq!(doc["openrpc"], JsonValue::String(OPENRPC_VERSION.to_string()));
+        let JsonValue::Array(methods) = &doc["methods"] else { panic!() };
+        assert_eq!(methods.len(), 1);
+        assert_eq!(methods[0]["name"], JsonValue::String("foo".to_string()));
+        assert_eq!(
+            methods[0]["result"]["schema"]["type"],
+            JsonValue::String("boolean".to_string())
+        );
+        let JsonValue::Array(params) = &methods[0]["params"] else { panic!() };
+        assert_eq!(params[1]["required"], JsonValue::Boolean(false));
+        assert_eq!(params[1]["schema"]["type"], JsonValue::String("number".to_string()));
+    }
+}
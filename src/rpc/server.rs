@@ -16,7 +16,7 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::{collections::HashSet, io::ErrorKind, sync::Arc};
+use std::{collections::HashSet, io::ErrorKind, sync::Arc, time::Instant};
 
 use async_trait::async_trait;
 use log::{debug, error, info};
@@ -28,12 +28,18 @@ use tinyjson::JsonValue;
 use url::Url;
 
 use super::{
-    common::{read_from_stream, write_to_stream, INIT_BUF_SIZE},
+    auth::{AccessSettings, ConnectionAccess, MethodAccess, AUTH_METHOD},
+    common::{
+        read_from_stream, to_json_value, write_value_to_stream, Framing, INIT_BUF_SIZE,
+        MAX_BATCH_LEN, MAX_BUF_SIZE,
+    },
     jsonrpc::*,
+    registry::{discover, RpcMethod, DISCOVER_METHOD},
 };
 use crate::{
     net::transport::{Listener, PtListener, PtStream},
     system::{StoppableTask, StoppableTaskPtr},
+    util::metrics::registry,
     Error, Result,
 };
 
@@ -42,7 +48,25 @@ use crate::{
 pub trait RequestHandler: Sync + Send {
     async fn handle_request(&self, req: JsonRequest) -> JsonResult;
 
-    async fn pong(&self, id: u16, _params: JsonValue) -> JsonResult {
+    /// Registry of the methods served by the handler. Calls to registered
+    /// methods have their params checked, and passed by position, before
+    /// reaching [`RequestHandler::handle_request`]. Registered methods are
+    /// also described to clients calling `rpc.discover`.
+    fn methods(&self) -> &'static [RpcMethod] {
+        &[]
+    }
+
+    /// Access level required to call the given method. Methods are admin
+    /// only unless tagged otherwise, so that listeners restricted to lower
+    /// levels only expose the methods that were reviewed for it.
+    fn method_access(&self, method: &str) -> MethodAccess {
+        match self.methods().iter().find(|m| m.name == method) {
+            Some(m) => m.access,
+            None => MethodAccess::Admin,
+        }
+    }
+
+    async fn pong(&self, id: JsonId, _params: JsonValue) -> JsonResult {
         JsonResponse::new(JsonValue::String("pong".to_string()), id).into()
     }
 
@@ -82,6 +106,7 @@ pub async fn accept(
     addr: Url,
     rh: Arc<impl RequestHandler + 'static>,
     conn_limit: Option<usize>,
+    settings: Arc<AccessSettings>,
     ex: Arc<smol::Executor<'_>>,
 ) -> Result<()> {
     // If there's a connection limit set, we will refuse connections
@@ -99,11 +124,14 @@ pub async fn accept(
     // We'll hold our background tasks here
     let tasks = Arc::new(Mutex::new(HashSet::new()));
 
+    // Access level of the connection, raised by authenticating
+    let mut access = ConnectionAccess::new(settings.unauthenticated, &addr);
+
     loop {
         let mut buf = Vec::with_capacity(INIT_BUF_SIZE);
 
         let mut reader_lock = reader.lock().await;
-        let _ = read_from_stream(&mut reader_lock, &mut buf, false).await?;
+        let _ = read_from_stream(&mut reader_lock, &mut buf, MAX_BUF_SIZE, false).await?;
         drop(reader_lock);
 
         let line = match String::from_utf8(buf) {
@@ -129,138 +157,292 @@ pub async fn accept(
             }
         };
 
-        // Cast to JsonRequest
-        let req = match JsonRequest::try_from(&val) {
+        debug!(target: "rpc::server", "{} --> {}", addr, val.stringify()?);
+
+        let (reply, subscribers) = match handle_value(&rh, &val, &settings, &mut access).await {
             Ok(v) => v,
             Err(e) => {
                 error!(
                     target: "rpc::server::accept()",
                     "[RPC SERVER] Failed casting JSON to a JsonRequest: {}", e,
                 );
-                return Err(e.into())
+                return Err(e)
             }
         };
 
-        debug!(target: "rpc::server", "{} --> {}", addr, val.stringify()?);
-
-        let rep = rh.handle_request(req).await;
+        // Write the reply before starting any subscriptions, so that
+        // notifications can't overtake it.
+        if let Some(reply) = reply {
+            debug!(target: "rpc::server", "{} <-- {}", addr, reply.stringify()?);
+            let mut writer_lock = writer.lock().await;
+            write_value_to_stream(&mut writer_lock, &reply, Framing::Line).await?;
+            drop(writer_lock);
+        }
 
-        match rep {
-            JsonResult::Subscriber(subscriber) => {
-                let task = StoppableTask::new();
+        for subscriber in subscribers {
+            start_subscription(
+                subscriber,
+                writer.clone(),
+                Framing::Line,
+                addr.clone(),
+                tasks.clone(),
+                ex.clone(),
+            )
+            .await;
+        }
+    }
+}
 
-                // Clone what needs to go in the background
-                let task_ = task.clone();
-                let addr_ = addr.clone();
-                let tasks_ = tasks.clone();
-                let writer_ = writer.clone();
+/// Handle a JSON-RPC request, or a batch of requests. A batch is replied
+/// to with an array holding the replies, and an empty batch is invalid.
+/// Notifications are handled but not replied to, so a batch made only of
+/// notifications gets no reply at all. Returns the reply to write, if any,
+/// and the subscribers to push notifications from. Fails if the value is
+/// neither a request nor a batch.
+pub(super) async fn handle_value(
+    rh: &Arc<impl RequestHandler + 'static>,
+    val: &JsonValue,
+    settings: &AccessSettings,
+    access: &mut ConnectionAccess,
+) -> Result<(Option<JsonValue>, Vec<JsonSubscriber>)> {
+    match val {
+        JsonValue::Array(batch) if batch.is_empty() => {
+            let e = JsonError::new(ErrorCode::InvalidRequest, None, JsonId::Null);
+            Ok((Some((&e).into()), vec![]))
+        }
 
-                // Detach the subscriber so we can multiplex further requests
-                task.clone().start(
-                    async move {
-                        // Subscribe to the inner method subscriber
-                        let subscription = subscriber.sub.subscribe().await;
-                        loop {
-                            // Listen for notifications
-                            let notification = subscription.receive().await;
-
-                            // Push notification
-                            debug!(target: "rpc::server", "{} <-- {}", addr_, notification.stringify()?);
-                            let notification = JsonResult::Notification(notification);
-
-                            let mut writer_lock = writer_.lock().await;
-                            if let Err(e) = write_to_stream(&mut writer_lock, &notification).await {
-                                subscription.unsubscribe().await;
-                                return Err(e)
-                            }
-                            drop(writer_lock);
-                        }
-                    },
-                    move |_| async move {
-                        debug!(
-                            target: "rpc::server",
-                            "Removing background task {} from map", task_.task_id,
-                        );
-                        tasks_.lock().await.remove(&task_);
-                    },
-                    Error::DetachedTaskStopped,
-                    ex.clone(),
-                );
+        JsonValue::Array(batch) if batch.len() > MAX_BATCH_LEN => {
+            let msg = format!("Batch exceeds {} requests", MAX_BATCH_LEN);
+            let e = JsonError::new(ErrorCode::InvalidRequest, Some(msg), JsonId::Null);
+            Ok((Some((&e).into()), vec![]))
+        }
 
-                debug!(target: "rpc::server", "Adding background task {} to map", task.task_id);
-                tasks.lock().await.insert(task.clone());
+        JsonValue::Array(batch) => {
+            let (replies, subscribers) = handle_batch(rh, batch, settings, access).await;
+            if replies.is_empty() {
+                return Ok((None, subscribers))
             }
+            let replies = replies.iter().map(to_json_value).collect();
+            Ok((Some(JsonValue::Array(replies)), subscribers))
+        }
 
-            JsonResult::SubscriberWithReply(subscriber, reply) => {
-                // Write the response
-                debug!(target: "rpc::server", "{} <-- {}", addr, reply.stringify()?);
-                let mut writer_lock = writer.lock().await;
-                write_to_stream(&mut writer_lock, &reply.into()).await?;
-                drop(writer_lock);
-
-                let task = StoppableTask::new();
-                // Clone what needs to go in the background
-                let task_ = task.clone();
-                let addr_ = addr.clone();
-                let tasks_ = tasks.clone();
-                let writer_ = writer.clone();
+        _ => {
+            let (req, wants_reply) = parse_request(val)?;
+            let (reply, subscriber) = handle_request(rh, req, settings, access).await;
+            let reply = reply.filter(|_| wants_reply);
+            Ok((reply.as_ref().map(to_json_value), subscriber.into_iter().collect()))
+        }
+    }
+}
 
-                // Detach the subscriber so we can multiplex further requests
-                task.clone().start(
-                    async move {
-                        // Start the subscriber loop
-                        let subscription = subscriber.sub.subscribe().await;
-                        loop {
-                            // Listen for notifications
-                            let notification = subscription.receive().await;
-
-                            // Push notification
-                            debug!(target: "rpc::server", "{} <-- {}", addr_, notification.stringify()?);
-                            let notification = JsonResult::Notification(notification);
-
-                            let mut writer_lock = writer_.lock().await;
-                            if let Err(e) = write_to_stream(&mut writer_lock, &notification).await {
-                                subscription.unsubscribe().await;
-                                drop(writer_lock);
-                                return Err(e)
-                            }
-                            drop(writer_lock);
-                        }
-                    },
-                    move |_| async move {
-                        debug!(
-                            target: "rpc::server",
-                            "Removing background task {} from map", task_.task_id,
-                        );
-                        tasks_.lock().await.remove(&task_);
-                    },
-                    Error::DetachedTaskStopped,
-                    ex.clone(),
-                );
+/// Parse a request, or a notification, which is a request without an `id`.
+/// Returns the request along with whether the caller expects a reply.
+fn parse_request(val: &JsonValue) -> Result<(JsonRequest, bool)> {
+    let err = match JsonRequest::try_from(val) {
+        Ok(req) => return Ok((req, true)),
+        Err(e) => e,
+    };
+
+    // An invalid `id` does not make it a notification
+    if matches!(val, JsonValue::Object(map) if map.contains_key("id")) {
+        return Err(err.into())
+    }
 
-                debug!(target: "rpc::server", "Adding background task {} to map", task.task_id);
-                tasks.lock().await.insert(task.clone());
-            }
+    let notif = JsonNotification::try_from(val)?;
+    let req = JsonRequest {
+        jsonrpc: "2.0",
+        id: JsonId::Null,
+        method: notif.method,
+        params: notif.params,
+    };
+    Ok((req, false))
+}
 
-            JsonResult::Request(_) | JsonResult::Notification(_) => {
-                unreachable!("Should never happen")
-            }
+/// Start a background task pushing the notifications of a subscriber to
+/// the connection, until writing to it fails.
+#[allow(clippy::type_complexity)]
+pub(super) async fn start_subscription(
+    subscriber: JsonSubscriber,
+    writer: Arc<Mutex<WriteHalf<Box<dyn PtStream>>>>,
+    framing: Framing,
+    addr: Url,
+    tasks: Arc<Mutex<HashSet<StoppableTaskPtr>>>,
+    ex: Arc<smol::Executor<'_>>,
+) {
+    let task = StoppableTask::new();
+
+    // Clone what needs to go in the background
+    let task_ = task.clone();
+    let tasks_ = tasks.clone();
+
+    // Detach the subscriber so we can multiplex further requests
+    task.clone().start(
+        async move {
+            // Subscribe to the inner method subscriber
+            let subscription = subscriber.sub.subscribe().await;
+            loop {
+                // Listen for notifications
+                let notification = subscription.receive().await;
+
+                // Push notification
+                debug!(target: "rpc::server", "{} <-- {}", addr, notification.stringify()?);
+                let notification: JsonValue = (&notification).into();
 
-            JsonResult::Response(ref v) => {
-                debug!(target: "rpc::server", "{} <-- {}", addr, v.stringify()?);
                 let mut writer_lock = writer.lock().await;
-                write_to_stream(&mut writer_lock, &rep).await?;
+                if let Err(e) =
+                    write_value_to_stream(&mut writer_lock, &notification, framing).await
+                {
+                    subscription.unsubscribe().await;
+                    return Err(e)
+                }
                 drop(writer_lock);
             }
+        },
+        move |_| async move {
+            debug!(
+                target: "rpc::server",
+                "Removing background task {} from map", task_.task_id,
+            );
+            tasks_.lock().await.remove(&task_);
+        },
+        Error::DetachedTaskStopped,
+        ex,
+    );
+
+    debug!(target: "rpc::server", "Adding background task {} to map", task.task_id);
+    tasks.lock().await.insert(task);
+}
 
-            JsonResult::Error(ref v) => {
-                debug!(target: "rpc::server", "{} <-- {}", addr, v.stringify()?);
-                let mut writer_lock = writer.lock().await;
-                write_to_stream(&mut writer_lock, &rep).await?;
-                drop(writer_lock);
+/// Pass a request to the [`RequestHandler`] if the connection has enough
+/// access to call the method. Returns the reply to write, if any, and the
+/// subscriber to push notifications from, if any.
+async fn handle_request(
+    rh: &Arc<impl RequestHandler + 'static>,
+    mut req: JsonRequest,
+    settings: &AccessSettings,
+    access: &mut ConnectionAccess,
+) -> (Option<JsonResult>, Option<JsonSubscriber>) {
+    // Authentication is the same for every handler, so we do it here
+    if req.method == AUTH_METHOD {
+        return (Some(authenticate(req, settings, access)), None)
+    }
+
+    // Discovery only lists the methods the connection may call
+    if req.method == DISCOVER_METHOD {
+        let methods = rh.methods().iter().filter(|m| m.access <= access.level);
+        return (Some(JsonResponse::new(discover(methods), req.id).into()), None)
+    }
+
+    if rh.method_access(&req.method) > access.level {
+        debug!(target: "rpc::server", "Refusing unauthorized call to {}", req.method);
+        return (Some(JsonError::new(ErrorCode::Unauthorized, None, req.id).into()), None)
+    }
+
+    if let Some(method) = rh.methods().iter().find(|m| m.name == req.method) {
+        match method.check_params(&req.params) {
+            Ok(params) => req.params = params,
+            Err(e) => {
+                debug!(target: "rpc::server", "Invalid params for {}: {}", req.method, e);
+                return (
+                    Some(JsonError::new(ErrorCode::InvalidParams, Some(e), req.id).into()),
+                    None,
+                )
             }
         }
     }
+
+    let method = req.method.clone();
+    let start = Instant::now();
+    let rep = rh.handle_request(req).await;
+    record_request(&method, &rep, start);
+
+    match rep {
+        JsonResult::Response(_) | JsonResult::Error(_) => (Some(rep), None),
+        JsonResult::Subscriber(subscriber) => (None, Some(subscriber)),
+        JsonResult::SubscriberWithReply(subscriber, reply) => {
+            (Some(reply.into()), Some(subscriber))
+        }
+        JsonResult::Request(_) | JsonResult::Notification(_) => {
+            unreachable!("Should never happen")
+        }
+    }
+}
+
+/// Handle an `rpc.auth` request, raising the access level of the connection
+/// to the one granted by the given token. Replies with the resulting level.
+fn authenticate(
+    req: JsonRequest,
+    settings: &AccessSettings,
+    access: &mut ConnectionAccess,
+) -> JsonResult {
+    let token = match &req.params {
+        JsonValue::Array(params) if params.len() == 1 => params[0].get::<String>(),
+        _ => None,
+    };
+
+    let Some(token) = token else {
+        return JsonError::new(ErrorCode::InvalidParams, None, req.id).into()
+    };
+
+    match access.authenticate(settings, token) {
+        Some(level) => JsonResponse::new(JsonValue::String(level.to_string()), req.id).into(),
+        None => {
+            debug!(target: "rpc::server", "Refusing authentication token");
+            JsonError::new(ErrorCode::Unauthorized, None, req.id).into()
+        }
+    }
+}
+
+/// Pass each request of a batch to the [`RequestHandler`], in order.
+/// Elements that are not valid requests are replied to with an error,
+/// and notifications are not replied to.
+async fn handle_batch(
+    rh: &Arc<impl RequestHandler + 'static>,
+    batch: &[JsonValue],
+    settings: &AccessSettings,
+    access: &mut ConnectionAccess,
+) -> (Vec<JsonResult>, Vec<JsonSubscriber>) {
+    let mut replies = vec![];
+    let mut subscribers = vec![];
+
+    for val in batch {
+        let (req, wants_reply) = match parse_request(val) {
+            Ok(v) => v,
+            Err(e) => {
+                debug!(
+                    target: "rpc::server::handle_batch()",
+                    "[RPC SERVER] Invalid request in batch: {}", e,
+                );
+                replies.push(JsonError::new(ErrorCode::InvalidRequest, None, JsonId::Null).into());
+                continue
+            }
+        };
+
+        let (reply, subscriber) = handle_request(rh, req, settings, access).await;
+        replies.extend(reply.filter(|_| wants_reply));
+        subscribers.extend(subscriber);
+    }
+
+    (replies, subscribers)
+}
+
+/// Record the count and latency of a handled request. Unknown methods are
+/// grouped together so clients can't blow up the number of metrics.
+fn record_request(method: &str, rep: &JsonResult, start: Instant) {
+    let method = match rep {
+        JsonResult::Error(e) if e.error.code == ErrorCode::MethodNotFound.code() => "unknown",
+        _ => method,
+    };
+
+    let labels = [("method", method)];
+    registry().counter("darkfi_rpc_requests_total", "JSON-RPC requests handled", &labels).inc();
+    registry()
+        .histogram(
+            "darkfi_rpc_request_duration_seconds",
+            "Time spent handling JSON-RPC requests",
+            &labels,
+        )
+        .observe_duration(start.elapsed());
 }
 
 /// Wrapper function around [`accept()`] to take the incoming connection and
@@ -269,6 +451,7 @@ async fn run_accept_loop(
     listener: Box<dyn PtListener>,
     rh: Arc<impl RequestHandler + 'static>,
     conn_limit: Option<usize>,
+    settings: Arc<AccessSettings>,
     ex: Arc<smol::Executor<'_>>,
 ) -> Result<()> {
     loop {
@@ -285,7 +468,15 @@ async fn run_accept_loop(
                 let task_ = task.clone();
                 let ex_ = ex.clone();
                 task.clone().start(
-                    accept(reader, writer, url.clone(), rh.clone(), conn_limit, ex_),
+                    accept(
+                        reader,
+                        writer,
+                        url.clone(),
+                        rh.clone(),
+                        conn_limit,
+                        settings.clone(),
+                        ex_,
+                    ),
                     |_| async move {
                         rh_.clone().unmark_connection(task_.clone()).await;
                     },
@@ -338,19 +529,62 @@ pub async fn listen_and_serve(
     rh: Arc<impl RequestHandler + 'static>,
     conn_limit: Option<usize>,
     ex: Arc<smol::Executor<'_>>,
+) -> Result<()> {
+    listen_and_serve_with_access(accept_url, rh, conn_limit, AccessSettings::default(), ex).await
+}
+
+/// Start a JSON-RPC server bound to the given accept URL and use the
+/// given [`RequestHandler`] to handle incoming requests, restricting
+/// which methods can be called with the given [`AccessSettings`].
+pub async fn listen_and_serve_with_access(
+    accept_url: Url,
+    rh: Arc<impl RequestHandler + 'static>,
+    conn_limit: Option<usize>,
+    settings: AccessSettings,
+    ex: Arc<smol::Executor<'_>>,
 ) -> Result<()> {
     let listener = Listener::new(accept_url).await?.listen().await?;
-    run_accept_loop(listener, rh, conn_limit, ex.clone()).await
+    run_accept_loop(listener, rh, conn_limit, Arc::new(settings), ex.clone()).await
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{rpc::client::RpcClient, system::msleep};
-    use smol::{lock::Mutex, net::TcpListener, Executor};
+    use crate::{
+        rpc::{
+            client::RpcClient,
+            registry::{JsonType, RpcParam},
+        },
+        system::{msleep, Subscriber},
+    };
+    use smol::{
+        io::{AsyncBufReadExt, AsyncWriteExt},
+        lock::Mutex,
+        net::{TcpListener, TcpStream},
+        Executor,
+    };
+    use std::{collections::HashMap, time::Duration};
+
+    const METHODS: &[RpcMethod] = &[
+        RpcMethod {
+            name: "ping",
+            description: "Reply with pong",
+            params: &[],
+            result: JsonType::String,
+            access: MethodAccess::Admin,
+        },
+        RpcMethod {
+            name: "echo",
+            description: "Reply with the given params",
+            params: &[RpcParam::optional("value", JsonType::Any, "Value to echo")],
+            result: JsonType::Array,
+            access: MethodAccess::Public,
+        },
+    ];
 
     struct RpcServer {
         rpc_connections: Mutex<HashSet<StoppableTaskPtr>>,
+        subscriber: JsonSubscriber,
     }
 
     #[async_trait]
@@ -358,15 +592,63 @@ mod tests {
         async fn handle_request(&self, req: JsonRequest) -> JsonResult {
             match req.method.as_str() {
                 "ping" => return self.pong(req.id, req.params).await,
-                _ => panic!(),
+                "echo" => return JsonResponse::new(req.params, req.id).into(),
+                "slow" => {
+                    msleep(500).await;
+                    return JsonResponse::new(req.params, req.id).into()
+                }
+                "subscribe" => {
+                    let rep = JsonResponse::new(JsonValue::Boolean(true), req.id);
+                    return (self.subscriber.clone(), rep).into()
+                }
+                _ => return JsonError::new(ErrorCode::MethodNotFound, None, req.id).into(),
             }
         }
 
+        fn methods(&self) -> &'static [RpcMethod] {
+            METHODS
+        }
+
         async fn connections_mut(&self) -> MutexGuard<'_, HashSet<StoppableTaskPtr>> {
             self.rpc_connections.lock().await
         }
     }
 
+    /// Serve a new [`RpcServer`] on a free local port with the given access
+    /// settings. Returns the server, its URL and the task serving it.
+    async fn spawn_test_server(
+        settings: AccessSettings,
+        executor: Arc<Executor<'static>>,
+    ) -> Result<(Arc<RpcServer>, Url, StoppableTaskPtr)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let sockaddr = listener.local_addr()?;
+        let endpoint = Url::parse(&format!("tcp://127.0.0.1:{}", sockaddr.port()))?;
+        drop(listener);
+
+        let rpc_server = Arc::new(RpcServer {
+            rpc_connections: Mutex::new(HashSet::new()),
+            subscriber: JsonSubscriber::new("subscribe"),
+        });
+
+        let server_task = StoppableTask::new();
+        server_task.clone().start(
+            listen_and_serve_with_access(
+                endpoint.clone(),
+                rpc_server.clone(),
+                None,
+                settings,
+                executor.clone(),
+            ),
+            |_| async {},
+            Error::RpcServerStopped,
+            executor,
+        );
+
+        // Let the server spawn
+        msleep(500).await;
+        Ok((rpc_server, endpoint, server_task))
+    }
+
     #[test]
     fn conn_manager() -> Result<()> {
         let executor = Arc::new(Executor::new());
@@ -383,7 +665,10 @@ mod tests {
             let endpoint = Url::parse(&format!("tcp://127.0.0.1:{}", sockaddr.port()))?;
             drop(listener);
 
-            let rpc_server = Arc::new(RpcServer { rpc_connections: Mutex::new(HashSet::new()) });
+            let rpc_server = Arc::new(RpcServer {
+                rpc_connections: Mutex::new(HashSet::new()),
+                subscriber: JsonSubscriber::new("subscribe"),
+            });
             let rpc_server_ = rpc_server.clone();
 
             let server_task = StoppableTask::new();
@@ -439,4 +724,246 @@ mod tests {
             Ok(())
         }))
     }
+
+    #[test]
+    fn client_multiplexing() -> Result<()> {
+        let executor = Arc::new(Executor::new());
+
+        smol::block_on(executor.run(async {
+            let (rpc_server, endpoint, server_task) =
+                spawn_test_server(AccessSettings::default(), executor.clone()).await?;
+
+            let rpc_client = Arc::new(RpcClient::new(endpoint, executor.clone()).await?);
+
+            // Concurrent requests on a single connection get their own replies
+            let echo = |n: f64| {
+                let rpc_client = rpc_client.clone();
+                async move {
+                    let params = JsonValue::Array(vec![JsonValue::Number(n)]);
+                    rpc_client.request(JsonRequest::new("echo", params)).await
+                }
+            };
+            let (a, (b, c)) =
+                smol::future::zip(echo(1.0), smol::future::zip(echo(2.0), echo(3.0))).await;
+            assert_eq!(a?, JsonValue::Array(vec![JsonValue::Number(1.0)]));
+            assert_eq!(b?, JsonValue::Array(vec![JsonValue::Number(2.0)]));
+            assert_eq!(c?, JsonValue::Array(vec![JsonValue::Number(3.0)]));
+
+            // Requests time out on their own, and late replies are dropped
+            let req = JsonRequest::new("slow", JsonValue::Array(vec![]));
+            let rep = rpc_client.request_with_timeout(req, Duration::from_millis(100)).await;
+            assert!(matches!(rep, Err(Error::TimeoutError(_))));
+            let req = JsonRequest::new("ping", JsonValue::Array(vec![]));
+            assert_eq!(rpc_client.request(req).await?, JsonValue::String("pong".to_string()));
+
+            // Notifications reach the subscriber while the connection is
+            // still used for requests.
+            let subscriber = Subscriber::new();
+            let subscription = subscriber.clone().subscribe().await;
+            let rpc_client_ = rpc_client.clone();
+            let sub_task = executor.spawn(async move {
+                let req = JsonRequest::new("subscribe", JsonValue::Array(vec![]));
+                rpc_client_.subscribe(req, subscriber).await
+            });
+            msleep(500).await;
+
+            rpc_server.subscriber.notify(JsonValue::Array(vec![JsonValue::Number(42.0)])).await;
+            let JsonResult::Notification(n) = subscription.receive().await else { panic!() };
+            assert_eq!(n.method, "subscribe");
+            assert_eq!(n.params, JsonValue::Array(vec![JsonValue::Number(42.0)]));
+
+            let req = JsonRequest::new("ping", JsonValue::Array(vec![]));
+            assert_eq!(rpc_client.request(req).await?, JsonValue::String("pong".to_string()));
+
+            // Stopping the client ends the subscription
+            rpc_client.stop().await;
+            assert!(sub_task.await.is_err());
+            subscription.unsubscribe().await;
+
+            server_task.stop().await;
+            Ok(())
+        }))
+    }
+
+    #[test]
+    fn batch_requests() -> Result<()> {
+        let executor = Arc::new(Executor::new());
+
+        smol::block_on(executor.run(async {
+            let (_, endpoint, server_task) =
+                spawn_test_server(AccessSettings::default(), executor.clone()).await?;
+
+            // Results come back in the order of the requests, and errors
+            // only fail their own request. String and large numeric IDs
+            // are echoed back as they were sent.
+            let rpc_client = RpcClient::new(endpoint.clone(), executor.clone()).await?;
+            let mut reqs = vec![];
+            for (id, n) in [(JsonId::from("foo"), 1.0), (JsonId::from(MAX_SAFE_ID), 2.0)] {
+                let mut req =
+                    JsonRequest::new("echo", JsonValue::Array(vec![JsonValue::Number(n)]));
+                req.id = id;
+                reqs.push(req);
+            }
+            reqs.push(JsonRequest::new("unknown", JsonValue::Array(vec![])));
+
+            let results = rpc_client.batch(reqs).await?;
+            assert_eq!(results.len(), 3);
+            assert_eq!(
+                results[0].as_ref().unwrap(),
+                &JsonValue::Array(vec![JsonValue::Number(1.0)])
+            );
+            assert_eq!(
+                results[1].as_ref().unwrap(),
+                &JsonValue::Array(vec![JsonValue::Number(2.0)])
+            );
+            assert!(matches!(results[2], Err(Error::JsonRpcError((-32601, _)))));
+            assert!(rpc_client.batch(vec![]).await?.is_empty());
+
+            // Large batches are split by the client
+            let params = |n: usize| JsonValue::Array(vec![JsonValue::Number(n as f64)]);
+            let reqs: Vec<_> =
+                (0..MAX_BATCH_LEN + 1).map(|n| JsonRequest::new("echo", params(n))).collect();
+            let results = rpc_client.batch(reqs).await?;
+            assert_eq!(results.len(), MAX_BATCH_LEN + 1);
+            assert_eq!(
+                results[MAX_BATCH_LEN].as_ref().unwrap(),
+                &params(MAX_BATCH_LEN)
+            );
+            rpc_client.stop().await;
+
+            // Invalid batches and elements are replied to with errors
+            let stream = TcpStream::connect(endpoint.socket_addrs(|| None)?[0]).await?;
+            let mut reader = smol::io::BufReader::new(stream.clone());
+            let mut writer = stream;
+
+            writer.write_all(b"[]\r\n").await?;
+            let mut line = String::new();
+            reader.read_line(&mut line).await?;
+            let rep: JsonValue = line.trim().parse()?;
+            assert_eq!(rep["error"]["code"], JsonValue::Number(-32600.0));
+            assert_eq!(rep["id"], JsonValue::Null);
+
+            let req = r#"{"jsonrpc":"2.0","id":"a","method":"echo","params":[]}"#;
+            let batch = vec![req; MAX_BATCH_LEN + 1].join(",");
+            writer.write_all(format!("[{}]\r\n", batch).as_bytes()).await?;
+            let mut line = String::new();
+            reader.read_line(&mut line).await?;
+            let rep: JsonValue = line.trim().parse()?;
+            assert_eq!(rep["error"]["code"], JsonValue::Number(-32600.0));
+            assert_eq!(rep["id"], JsonValue::Null);
+
+            let batch = r#"[{"jsonrpc":"2.0","id":"a","method":"echo","params":[]},42]"#;
+            writer.write_all(format!("{}\r\n", batch).as_bytes()).await?;
+            let mut line = String::new();
+            reader.read_line(&mut line).await?;
+            let rep: JsonValue = line.trim().parse()?;
+            let JsonValue::Array(replies) = rep else { panic!() };
+            assert_eq!(replies.len(), 2);
+            assert_eq!(replies[0]["id"], JsonValue::String("a".to_string()));
+            assert_eq!(replies[0]["result"], JsonValue::Array(vec![]));
+            assert_eq!(replies[1]["error"]["code"], JsonValue::Number(-32600.0));
+            assert_eq!(replies[1]["id"], JsonValue::Null);
+
+            // Notifications are not replied to, not even in a batch. IDs
+            // too large to be represented exactly are invalid.
+            let notif = r#"{"jsonrpc":"2.0","method":"echo","params":[]}"#;
+            writer.write_all(format!("{}\r\n", notif).as_bytes()).await?;
+            writer.write_all(format!("[{},{}]\r\n", notif, notif).as_bytes()).await?;
+            let batch = format!(
+                r#"[{},{{"jsonrpc":"2.0","id":"b","method":"echo","params":[]}},{{"jsonrpc":"2.0","id":{},"method":"echo","params":[]}}]"#,
+                notif,
+                MAX_SAFE_ID + 2,
+            );
+            writer.write_all(format!("{}\r\n", batch).as_bytes()).await?;
+            let mut line = String::new();
+            reader.read_line(&mut line).await?;
+            let rep: JsonValue = line.trim().parse()?;
+            let JsonValue::Array(replies) = rep else { panic!() };
+            assert_eq!(replies.len(), 2);
+            assert_eq!(replies[0]["id"], JsonValue::String("b".to_string()));
+            assert_eq!(replies[1]["error"]["code"], JsonValue::Number(-32600.0));
+
+            server_task.stop().await;
+            Ok(())
+        }))
+    }
+
+    #[test]
+    fn access_control() -> Result<()> {
+        let executor = Arc::new(Executor::new());
+
+        smol::block_on(executor.run(async {
+            let settings = AccessSettings::new("public", &["admin:secret".to_string()])?;
+            let (_, endpoint, server_task) = spawn_test_server(settings, executor.clone()).await?;
+
+            let rpc_client = RpcClient::new(endpoint, executor.clone()).await?;
+            let unauthorized = ErrorCode::Unauthorized.code();
+
+            // Only public methods are callable before authenticating
+            let req = JsonRequest::new("echo", JsonValue::Array(vec![]));
+            assert_eq!(rpc_client.request(req).await?, JsonValue::Array(vec![]));
+            let req = JsonRequest::new("ping", JsonValue::Array(vec![]));
+            let rep = rpc_client.request(req).await;
+            assert!(matches!(rep, Err(Error::JsonRpcError((c, _))) if c == unauthorized));
+
+            let token = |t: &str| JsonValue::Array(vec![JsonValue::String(t.to_string())]);
+            let req = JsonRequest::new(AUTH_METHOD, token("guess"));
+            let rep = rpc_client.request(req).await;
+            assert!(matches!(rep, Err(Error::JsonRpcError((c, _))) if c == unauthorized));
+
+            // A valid token grants its level for the rest of the connection
+            let req = JsonRequest::new(AUTH_METHOD, token("secret"));
+            assert_eq!(rpc_client.request(req).await?, JsonValue::String("admin".to_string()));
+            let req = JsonRequest::new("ping", JsonValue::Array(vec![]));
+            assert_eq!(rpc_client.request(req).await?, JsonValue::String("pong".to_string()));
+
+            rpc_client.stop().await;
+            server_task.stop().await;
+            Ok(())
+        }))
+    }
+
+    #[test]
+    fn method_registry() -> Result<()> {
+        let executor = Arc::new(Executor::new());
+
+        smol::block_on(executor.run(async {
+            let settings = AccessSettings::new("public", &["admin:secret".to_string()])?;
+            let (_, endpoint, server_task) = spawn_test_server(settings, executor.clone()).await?;
+
+            let rpc_client = RpcClient::new(endpoint, executor.clone()).await?;
+            let method_names = |doc: JsonValue| -> Vec<String> {
+                let JsonValue::Array(methods) = &doc["methods"] else { panic!() };
+                methods.iter().map(|m| m["name"].get::<String>().unwrap().clone()).collect()
+            };
+
+            // Discovery only lists the methods callable on the connection
+            let req = JsonRequest::new(DISCOVER_METHOD, JsonValue::Array(vec![]));
+            assert_eq!(method_names(rpc_client.request(req).await?), vec!["echo"]);
+
+            let token = JsonValue::Array(vec![JsonValue::String("secret".to_string())]);
+            rpc_client.request(JsonRequest::new(AUTH_METHOD, token)).await?;
+            let req = JsonRequest::new(DISCOVER_METHOD, JsonValue::Array(vec![]));
+            assert_eq!(method_names(rpc_client.request(req).await?), vec!["ping", "echo"]);
+
+            // Params may be given by name, handlers get them by position
+            let one = JsonValue::Number(1.0);
+            let params = JsonValue::Object(HashMap::from([("value".to_string(), one.clone())]));
+            let rep = rpc_client.request(JsonRequest::new("echo", params)).await?;
+            assert_eq!(rep, JsonValue::Array(vec![one.clone()]));
+
+            // Malformed params never reach the handler
+            let invalid_params = ErrorCode::InvalidParams.code();
+            let params = JsonValue::Array(vec![one.clone(), one.clone()]);
+            let rep = rpc_client.request(JsonRequest::new("echo", params)).await;
+            assert!(matches!(rep, Err(Error::JsonRpcError((c, _))) if c == invalid_params));
+            let params = JsonValue::Object(HashMap::from([("other".to_string(), one)]));
+            let rep = rpc_client.request(JsonRequest::new("echo", params)).await;
+            assert!(matches!(rep, Err(Error::JsonRpcError((c, _))) if c == invalid_params));
+
+            rpc_client.stop().await;
+            server_task.stop().await;
+            Ok(())
+        }))
+    }
 }
@@ -19,7 +19,8 @@
 use std::collections::HashMap;
 
 pub use tinyjson::JsonValue::{
-    self, Array as JsonArray, Number as JsonNum, Object as JsonObj, String as JsonStr,
+    self, Array as JsonArray, Null as JsonNull, Number as JsonNum, Object as JsonObj,
+    String as JsonStr,
 };
 
 // helper functions
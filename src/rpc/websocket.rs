@@ -0,0 +1,197 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Minimal WebSocket (RFC 6455) framing for the HTTP front-end of the
+//! JSON-RPC server. Only the server side is implemented: frames from the
+//! client must be masked, our frames are not, and no extensions are used.
+
+use sha1::{Digest, Sha1};
+use smol::{
+    io::{AsyncReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf},
+    lock::Mutex,
+};
+
+use super::common::MAX_BUF_SIZE;
+use crate::{error::RpcError, net::transport::PtStream, util::encoding::base64, Result};
+
+/// GUID the client key is concatenated with in the opening handshake
+const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Largest payload allowed in a control frame
+const MAX_CONTROL_LEN: usize = 125;
+
+/// Compute the `Sec-WebSocket-Accept` handshake header from the client's
+/// `Sec-WebSocket-Key` header.
+pub(super) fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.trim().as_bytes());
+    hasher.update(WS_GUID.as_bytes());
+    base64::encode(&hasher.finalize())
+}
+
+/// WebSocket frame opcodes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Opcode {
+    Continuation = 0x0,
+    Text = 0x1,
+    Binary = 0x2,
+    Close = 0x8,
+    Ping = 0x9,
+    Pong = 0xa,
+}
+
+impl TryFrom<u8> for Opcode {
+    type Error = RpcError;
+
+    fn try_from(value: u8) -> std::result::Result<Self, Self::Error> {
+        match value {
+            0x0 => Ok(Self::Continuation),
+            0x1 => Ok(Self::Text),
+            0x2 => Ok(Self::Binary),
+            0x8 => Ok(Self::Close),
+            0x9 => Ok(Self::Ping),
+            0xa => Ok(Self::Pong),
+            _ => Err(RpcError::MalformedWebSocket(format!("Unknown opcode {:#x}", value))),
+        }
+    }
+}
+
+/// Write a single, unfragmented frame to the stream.
+pub(super) async fn write_frame(
+    writer: &mut WriteHalf<Box<dyn PtStream>>,
+    opcode: Opcode,
+    payload: &[u8],
+) -> Result<()> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode as u8);
+
+    match payload.len() {
+        n if n < 126 => frame.push(n as u8),
+        n if n <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        n => {
+            frame.push(127);
+            frame.extend_from_slice(&(n as u64).to_be_bytes());
+        }
+    }
+
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame).await?;
+    Ok(())
+}
+
+/// Read a single frame from the stream, returning whether it is the final
+/// fragment of its message, its opcode and its unmasked payload.
+async fn read_frame(
+    reader: &mut BufReader<ReadHalf<Box<dyn PtStream>>>,
+) -> Result<(bool, Opcode, Vec<u8>)> {
+    let mut head = [0_u8; 2];
+    reader.read_exact(&mut head).await?;
+
+    let fin = head[0] & 0x80 != 0;
+    if head[0] & 0x70 != 0 {
+        return Err(RpcError::MalformedWebSocket("Reserved bits set".to_string()).into())
+    }
+    let opcode = Opcode::try_from(head[0] & 0x0f)?;
+
+    if head[1] & 0x80 == 0 {
+        return Err(RpcError::MalformedWebSocket("Unmasked client frame".to_string()).into())
+    }
+
+    let len = match head[1] & 0x7f {
+        126 => {
+            let mut len = [0_u8; 2];
+            reader.read_exact(&mut len).await?;
+            u16::from_be_bytes(len) as u64
+        }
+        127 => {
+            let mut len = [0_u8; 8];
+            reader.read_exact(&mut len).await?;
+            u64::from_be_bytes(len)
+        }
+        n => n as u64,
+    };
+
+    if len > MAX_BUF_SIZE as u64 {
+        return Err(RpcError::MalformedWebSocket("Frame too large".to_string()).into())
+    }
+
+    let is_control = matches!(opcode, Opcode::Close | Opcode::Ping | Opcode::Pong);
+    if is_control && (!fin || len > MAX_CONTROL_LEN as u64) {
+        return Err(RpcError::MalformedWebSocket("Invalid control frame".to_string()).into())
+    }
+
+    let mut mask = [0_u8; 4];
+    reader.read_exact(&mut mask).await?;
+
+    let mut payload = vec![0_u8; len as usize];
+    reader.read_exact(&mut payload).await?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+
+    Ok((fin, opcode, payload))
+}
+
+/// Read the next text or binary message from the stream, reassembling it
+/// from its fragments. Pings are answered along the way. Returns `None`
+/// once the client closes the connection.
+pub(super) async fn read_message(
+    reader: &mut BufReader<ReadHalf<Box<dyn PtStream>>>,
+    writer: &Mutex<WriteHalf<Box<dyn PtStream>>>,
+) -> Result<Option<Vec<u8>>> {
+    let mut message: Option<Vec<u8>> = None;
+
+    loop {
+        let (fin, opcode, payload) = read_frame(reader).await?;
+
+        match opcode {
+            Opcode::Ping => {
+                write_frame(&mut *writer.lock().await, Opcode::Pong, &payload).await?;
+                continue
+            }
+
+            Opcode::Pong => continue,
+
+            Opcode::Close => {
+                // Echo the status code back, which completes the closing handshake
+                let status = &payload[..payload.len().min(2)];
+                write_frame(&mut *writer.lock().await, Opcode::Close, status).await?;
+                return Ok(None)
+            }
+
+            Opcode::Text | Opcode::Binary if message.is_none() => message = Some(payload),
+
+            Opcode::Continuation if message.is_some() => {
+                let message = message.as_mut().unwrap();
+                if message.len() + payload.len() > MAX_BUF_SIZE {
+                    return Err(RpcError::MalformedWebSocket("Message too large".to_string()).into())
+                }
+                message.extend_from_slice(&payload);
+            }
+
+            _ => return Err(RpcError::MalformedWebSocket("Unexpected fragment".to_string()).into()),
+        }
+
+        if fin {
+            return Ok(message)
+        }
+    }
+}
@@ -0,0 +1,374 @@
+/* This file is part of DarkFi (https://dark.fi)
+ *
+ * Copyright (C) 2020-2023 Dyne.org foundation
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Process-wide metrics registry, rendered in the Prometheus text
+//! exposition format.
+//!
+//! Metrics are created on first use and live in the global [`registry()`].
+//! Every metric is identified by its name and a set of labels:
+//! ```
+//! use darkfi::util::metrics::registry;
+//!
+//! let requests = registry().counter("requests_total", "Requests handled", &[("method", "ping")]);
+//! requests.inc();
+//! assert!(registry().render().contains("requests_total{method=\"ping\"} 1"));
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering::Relaxed},
+        Arc, OnceLock, RwLock,
+    },
+    time::Duration,
+};
+
+/// Default histogram buckets, in seconds
+pub const DEFAULT_BUCKETS: [f64; 11] =
+    [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Monotonically increasing counter
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1)
+    }
+
+    pub fn add(&self, v: u64) {
+        self.0.fetch_add(v, Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Relaxed)
+    }
+}
+
+/// Value that can go up and down
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub fn inc(&self) {
+        self.add(1)
+    }
+
+    pub fn dec(&self) {
+        self.add(-1)
+    }
+
+    pub fn add(&self, v: i64) {
+        self.0.fetch_add(v, Relaxed);
+    }
+
+    pub fn set(&self, v: i64) {
+        self.0.store(v, Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Relaxed)
+    }
+}
+
+/// Distribution of observed values over a fixed set of buckets
+#[derive(Debug)]
+pub struct Histogram {
+    /// Upper bounds of the buckets
+    bounds: Vec<f64>,
+    /// Number of observations per bucket (not cumulative)
+    buckets: Vec<AtomicU64>,
+    /// Sum of all observations, stored as `f64` bits
+    sum: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &[f64]) -> Self {
+        Self {
+            bounds: bounds.to_vec(),
+            buckets: bounds.iter().map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0f64.to_bits()),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, v: f64) {
+        if let Some(i) = self.bounds.iter().position(|bound| v <= *bound) {
+            self.buckets[i].fetch_add(1, Relaxed);
+        }
+
+        let _ = self
+            .sum
+            .fetch_update(Relaxed, Relaxed, |sum| Some((f64::from_bits(sum) + v).to_bits()));
+        self.count.fetch_add(1, Relaxed);
+    }
+
+    /// Observe a duration, in seconds
+    pub fn observe_duration(&self, d: Duration) {
+        self.observe(d.as_secs_f64())
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Relaxed)
+    }
+
+    pub fn sum(&self) -> f64 {
+        f64::from_bits(self.sum.load(Relaxed))
+    }
+}
+
+#[derive(Clone)]
+enum Metric {
+    Counter(Arc<Counter>),
+    Gauge(Arc<Gauge>),
+    Histogram(Arc<Histogram>),
+}
+
+impl Metric {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Counter(_) => "counter",
+            Self::Gauge(_) => "gauge",
+            Self::Histogram(_) => "histogram",
+        }
+    }
+}
+
+type Labels = Vec<(String, String)>;
+
+/// All metrics sharing a name
+struct Family {
+    help: String,
+    series: BTreeMap<Labels, Metric>,
+}
+
+/// Collection of metrics
+#[derive(Default)]
+pub struct Registry {
+    families: RwLock<BTreeMap<String, Family>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get or create the counter with the given name and labels
+    pub fn counter(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Arc<Counter> {
+        match self.get_or_insert(name, help, labels, || Metric::Counter(Default::default())) {
+            Metric::Counter(c) => c,
+            m => panic!("Metric {} is registered as a {}", name, m.kind()),
+        }
+    }
+
+    /// Get or create the gauge with the given name and labels
+    pub fn gauge(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Arc<Gauge> {
+        match self.get_or_insert(name, help, labels, || Metric::Gauge(Default::default())) {
+            Metric::Gauge(g) => g,
+            m => panic!("Metric {} is registered as a {}", name, m.kind()),
+        }
+    }
+
+    /// Get or create the histogram with the given name and labels, using
+    /// [`DEFAULT_BUCKETS`].
+    pub fn histogram(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Arc<Histogram> {
+        let new = || Metric::Histogram(Arc::new(Histogram::new(&DEFAULT_BUCKETS)));
+        match self.get_or_insert(name, help, labels, new) {
+            Metric::Histogram(h) => h,
+            m => panic!("Metric {} is registered as a {}", name, m.kind()),
+        }
+    }
+
+    /// Export the given gauge under the given name and labels, replacing
+    /// the one registered there before, if any. This is for gauges owned
+    /// by an object rather than by the registry.
+    pub fn insert_gauge(&self, name: &str, help: &str, labels: &[(&str, &str)], gauge: Arc<Gauge>) {
+        let mut families = self.families.write().unwrap();
+        let family = families
+            .entry(name.to_string())
+            .or_insert_with(|| Family { help: help.to_string(), series: BTreeMap::new() });
+
+        if let Some(first) = family.series.values().next() {
+            if first.kind() != "gauge" {
+                panic!("Metric {} is registered as a {}", name, first.kind());
+            }
+        }
+
+        family.series.insert(to_labels(labels), Metric::Gauge(gauge));
+    }
+
+    /// Remove the metric with the given name and labels, e.g. once the
+    /// object it describes goes away.
+    pub fn remove(&self, name: &str, labels: &[(&str, &str)]) {
+        let mut families = self.families.write().unwrap();
+        if let Some(family) = families.get_mut(name) {
+            family.series.remove(&to_labels(labels));
+            if family.series.is_empty() {
+                families.remove(name);
+            }
+        }
+    }
+
+    fn get_or_insert(
+        &self,
+        name: &str,
+        help: &str,
+        labels: &[(&str, &str)],
+        new: impl FnOnce() -> Metric,
+    ) -> Metric {
+        let labels = to_labels(labels);
+
+        if let Some(family) = self.families.read().unwrap().get(name) {
+            if let Some(metric) = family.series.get(&labels) {
+                return metric.clone()
+            }
+        }
+
+        let mut families = self.families.write().unwrap();
+        let family = families
+            .entry(name.to_string())
+            .or_insert_with(|| Family { help: help.to_string(), series: BTreeMap::new() });
+
+        if let Some(first) = family.series.values().next() {
+            let metric = new();
+            if first.kind() != metric.kind() {
+                panic!("Metric {} is registered as a {}", name, first.kind());
+            }
+        }
+
+        family.series.entry(labels).or_insert_with(new).clone()
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        for (name, family) in self.families.read().unwrap().iter() {
+            let Some(first) = family.series.values().next() else { continue };
+            let _ = writeln!(out, "# HELP {} {}", name, escape(&family.help, false));
+            let _ = writeln!(out, "# TYPE {} {}", name, first.kind());
+
+            for (labels, metric) in family.series.iter() {
+                match metric {
+                    Metric::Counter(c) => {
+                        let _ = writeln!(out, "{}{} {}", name, fmt_labels(labels, None), c.get());
+                    }
+                    Metric::Gauge(g) => {
+                        let _ = writeln!(out, "{}{} {}", name, fmt_labels(labels, None), g.get());
+                    }
+                    Metric::Histogram(h) => {
+                        let mut cumulative = 0;
+                        for (bound, bucket) in h.bounds.iter().zip(h.buckets.iter()) {
+                            cumulative += bucket.load(Relaxed);
+                            let le = bound.to_string();
+                            let labels = fmt_labels(labels, Some(&le));
+                            let _ = writeln!(out, "{}_bucket{} {}", name, labels, cumulative);
+                        }
+                        let labels_inf = fmt_labels(labels, Some("+Inf"));
+                        let labels = fmt_labels(labels, None);
+                        let _ = writeln!(out, "{}_bucket{} {}", name, labels_inf, h.count());
+                        let _ = writeln!(out, "{}_sum{} {}", name, labels, h.sum());
+                        let _ = writeln!(out, "{}_count{} {}", name, labels, h.count());
+                    }
+                }
+            }
+        }
+
+        out
+    }
+}
+
+/// Returns the process-wide metrics registry
+pub fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(Registry::new)
+}
+
+fn to_labels(labels: &[(&str, &str)]) -> Labels {
+    let mut labels: Labels = labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    labels.sort();
+    labels
+}
+
+fn escape(s: &str, quotes: bool) -> String {
+    let mut ret = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            '"' if quotes => ret.push_str("\\\""),
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+fn fmt_labels(labels: &Labels, le: Option<&str>) -> String {
+    let mut pairs: Vec<String> =
+        labels.iter().map(|(k, v)| format!("{}=\"{}\"", k, escape(v, true))).collect();
+
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{}\"", le));
+    }
+
+    if pairs.is_empty() {
+        return String::new()
+    }
+
+    format!("{{{}}}", pairs.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics_render() {
+        let registry = Registry::new();
+
+        let c = registry.counter("test_total", "A counter", &[("cmd", "ping")]);
+        c.add(3);
+        registry.counter("test_total", "A counter", &[("cmd", "ping")]).inc();
+        registry.gauge("test_gauge", "A gauge", &[]).set(-2);
+
+        let h = registry.histogram("test_seconds", "A histogram", &[("m", "a\"b")]);
+        h.observe(0.02);
+        h.observe(20.0);
+
+        let out = registry.render();
+        assert!(out.contains("# TYPE test_total counter\ntest_total{cmd=\"ping\"} 4\n"));
+        assert!(out.contains("test_gauge -2\n"));
+        assert!(out.contains("test_seconds_bucket{m=\"a\\\"b\",le=\"0.01\"} 0\n"));
+        assert!(out.contains("test_seconds_bucket{m=\"a\\\"b\",le=\"0.025\"} 1\n"));
+        assert!(out.contains("test_seconds_bucket{m=\"a\\\"b\",le=\"+Inf\"} 2\n"));
+        assert!(out.contains("test_seconds_count{m=\"a\\\"b\"} 2\n"));
+
+        registry.remove("test_gauge", &[]);
+        assert!(!registry.render().contains("test_gauge"));
+
+        // Gauges owned elsewhere replace the registered ones
+        let gauge = Arc::new(Gauge::default());
+        gauge.set(7);
+        registry.gauge("test_owned", "Owned gauge", &[]).set(3);
+        registry.insert_gauge("test_owned", "Owned gauge", &[], gauge.clone());
+        gauge.inc();
+        assert!(registry.render().contains("test_owned 8"));
+    }
+}
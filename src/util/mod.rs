@@ -31,6 +31,9 @@ pub mod parse;
 /// Filesystem path utilities
 pub mod path;
 
+/// Metrics registry with Prometheus text rendering
+pub mod metrics;
+
 /// Time utilities
 pub mod time;
 
@@ -16,6 +16,8 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use std::time::Instant;
+
 use darkfi_sdk::{
     blockchain::{expected_reward, PidOutput, PreviousSlot, Slot, POS_START},
     crypto::SecretKey,
@@ -27,7 +29,10 @@ use log::{debug, error, info};
 use crate::{
     blockchain::{BlockInfo, Blockchain, BlockchainOverlay, BlockchainOverlayPtr, Header},
     tx::Transaction,
-    util::time::{TimeKeeper, Timestamp},
+    util::{
+        metrics::registry,
+        time::{TimeKeeper, Timestamp},
+    },
     validator::{
         pid::slot_pid_output, pow::PoWModule, utils::block_rank, verify_block, verify_proposal,
         verify_transactions,
@@ -206,7 +211,25 @@ impl Consensus {
     /// If the proposal extends the canonical blockchain, a new fork chain is created.
     pub async fn append_proposal(&mut self, proposal: &Proposal) -> Result<()> {
         info!(target: "validator::consensus::append_proposal", "Appending proposal {}", proposal.hash);
+        let start = Instant::now();
+        let result = self.verify_and_append_proposal(proposal).await;
+
+        // Rejected proposals are timed as well, since verifying them
+        // costs just as much.
+        let labels = [("result", if result.is_ok() { "appended" } else { "rejected" })];
+        registry()
+            .histogram(
+                "darkfi_validator_append_proposal_seconds",
+                "Time spent verifying and appending block proposals",
+                &labels,
+            )
+            .observe_duration(start.elapsed());
+
+        result
+    }
 
+    /// Verify the proposal and append it to the fork it extends.
+    async fn verify_and_append_proposal(&mut self, proposal: &Proposal) -> Result<()> {
         // Verify proposal and grab corresponding fork
         let (mut fork, index) = verify_proposal(self, proposal).await?;
 